@@ -2,9 +2,14 @@ use crate::data::DataArgs;
 use crate::evaluate::EvaluateArgs;
 use crate::export::ExportArgs;
 use crate::inference::InferenceArgs;
+use crate::inspect::InspectArgs;
+use crate::pack::PackArgs;
+use crate::prune::PruneVocabArgs;
 use crate::train::TrainArgs;
 use crate::vocab::VocabArgs;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use cortex_rust::{BitLlamaConfig, RuntimeConfig, TuneMode};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about = "Bit-TTT Unified Toolchain", long_about = None)]
@@ -30,9 +35,144 @@ pub enum Commands {
     /// Export model to .bitt format
     Export(ExportArgs),
 
+    /// Show (and --verify) the header and sections of a .bitt file
+    Inspect(InspectArgs),
+
+    /// Write a checkpoint with 2-bit packed weights (memory-mapped at load)
+    Pack(PackArgs),
+
+    /// Keep only the used part of the vocabulary (embedding, lm_head, tokenizer)
+    PruneVocab(PruneVocabArgs),
+
     /// Run inference
     Inference(InferenceArgs),
 
     /// Evaluate model (Perplexity)
     Evaluate(EvaluateArgs),
 }
+
+/// Worker pool of the engine (train sizes rayon's global pool with it)
+#[derive(Args, Debug, Clone, Default)]
+pub struct PoolArgs {
+    /// Worker threads (default: one per pinned core, or per logical CPU)
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Pin workers to these cores, e.g. "0-7" or "0,2,4,6"
+    #[arg(long)]
+    pub pin_cores: Option<String>,
+}
+
+impl PoolArgs {
+    pub fn config(&self) -> anyhow::Result<RuntimeConfig> {
+        let pin_cores = match &self.pin_cores {
+            Some(spec) => RuntimeConfig::parse_cores(spec)?,
+            None => Vec::new(),
+        };
+        Ok(RuntimeConfig {
+            threads: self.threads,
+            pin_cores,
+            ..RuntimeConfig::default()
+        })
+    }
+}
+
+/// Kernel autotuning of `activation_quant = "auto"` models (inference, evaluate, pack)
+#[derive(Args, Debug, Clone, Default)]
+pub struct AutotuneArgs {
+    /// Autotune cache use: cached, retune or off (default: config.json, then BIT_TTT_AUTOTUNE)
+    #[arg(long)]
+    pub autotune: Option<TuneMode>,
+
+    /// Autotune cache file (default: BIT_TTT_AUTOTUNE_CACHE, then ~/.cache/bit-ttt/autotune.json)
+    #[arg(long)]
+    pub autotune_cache: Option<PathBuf>,
+}
+
+impl AutotuneArgs {
+    /// Override the model config with --autotune and point the process-wide
+    /// tuner at --autotune-cache
+    pub fn apply(&self, config: &mut BitLlamaConfig) {
+        if let Some(mode) = self.autotune {
+            config.autotune = Some(mode);
+        }
+        if let Some(path) = &self.autotune_cache {
+            cortex_rust::kernels::autotune::configure(None, Some(path.clone()));
+        }
+    }
+}
+
+/// Worker threads of the engine, per phase (inference, evaluate)
+#[derive(Args, Debug, Clone, Default)]
+pub struct RuntimeArgs {
+    #[command(flatten)]
+    pub pool: PoolArgs,
+
+    /// Worker threads for the prompt (default: --threads)
+    #[arg(long)]
+    pub prefill_threads: Option<usize>,
+
+    /// Worker threads for generated tokens (default: --threads)
+    #[arg(long)]
+    pub decode_threads: Option<usize>,
+}
+
+impl RuntimeArgs {
+    pub fn config(&self) -> anyhow::Result<RuntimeConfig> {
+        Ok(RuntimeConfig {
+            prefill_threads: self.prefill_threads,
+            decode_threads: self.decode_threads,
+            ..self.pool.config()?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_train_rejects_per_phase_threads() {
+        let cli = Cli::try_parse_from(["bit_llama", "train", "--data", "d", "--threads", "4"]);
+        match cli.map(|cli| cli.command) {
+            Ok(Some(Commands::Train(args))) => assert_eq!(args.runtime.threads, Some(4)),
+            _ => panic!("train --threads should parse"),
+        }
+        for flag in ["--prefill-threads", "--decode-threads"] {
+            assert!(Cli::try_parse_from(["bit_llama", "train", "--data", "d", flag, "2"]).is_err());
+        }
+
+        let cli = Cli::try_parse_from([
+            "bit_llama",
+            "inference",
+            "--decode-threads",
+            "2",
+            "--threads",
+            "4",
+        ]);
+        match cli.map(|cli| cli.command) {
+            Ok(Some(Commands::Inference(args))) => {
+                let config = args.runtime.config().unwrap();
+                assert_eq!((config.threads, config.decode_threads), (Some(4), Some(2)));
+            }
+            _ => panic!("inference --decode-threads should parse"),
+        }
+    }
+
+    #[test]
+    fn test_autotune_flag_overrides_config() {
+        let cli =
+            Cli::try_parse_from(["bit_llama", "evaluate", "--data", "d", "--autotune", "off"]);
+        let args = match cli.map(|cli| cli.command) {
+            Ok(Some(Commands::Evaluate(args))) => args.autotune,
+            _ => panic!("evaluate --autotune should parse"),
+        };
+        let mut config = BitLlamaConfig::new(16, 32, 1, 0.1, None);
+        config.autotune = Some(TuneMode::Retune);
+        args.apply(&mut config);
+        assert_eq!(config.autotune, Some(TuneMode::Off));
+
+        let bad = ["bit_llama", "pack", "--autotune", "sometimes"];
+        assert!(Cli::try_parse_from(bad).is_err());
+    }
+}
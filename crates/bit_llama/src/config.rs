@@ -67,6 +67,14 @@ pub struct ProjectConfig {
     pub mock: bool,
     #[serde(default)]
     pub lm_head_cpu: bool,
+
+    // Mixture of Experts (None = dense SwiGLU)
+    #[serde(default)]
+    pub n_experts: Option<usize>,
+    #[serde(default = "cortex_rust::model::config::default_experts_per_tok")]
+    pub n_experts_per_tok: usize,
+    #[serde(default = "cortex_rust::model::config::default_router_aux_loss_coef")]
+    pub router_aux_loss_coef: f64,
 }
 
 fn default_input_pattern() -> String {
@@ -127,6 +135,9 @@ impl Default for ProjectConfig {
             rope_theta: default_rope(),
             max_position_embeddings: default_max_pos(),
             lm_head_cpu: false, // Default to GPU
+            n_experts: None,
+            n_experts_per_tok: cortex_rust::model::config::default_experts_per_tok(),
+            router_aux_loss_coef: cortex_rust::model::config::default_router_aux_loss_coef(),
         }
     }
 }
@@ -164,6 +175,9 @@ impl ProjectConfig {
             rope_theta: default_rope(),
             max_position_embeddings: args.context_len.max(2048),
             lm_head_cpu: false,
+            n_experts: args.experts,
+            n_experts_per_tok: args.experts_per_tok,
+            router_aux_loss_coef: args.router_aux_loss_coef,
         }
     }
 
@@ -181,6 +195,21 @@ impl ProjectConfig {
             rope_theta: self.rope_theta,
             max_position_embeddings: self.max_position_embeddings,
             lm_head_cpu: self.lm_head_cpu,
+            n_experts: self.n_experts,
+            n_experts_per_tok: self.n_experts_per_tok,
+            router_aux_loss_coef: self.router_aux_loss_coef,
+            quantized_kv_attention: false,
+            kv_cache_dtype: cortex_rust::KvCacheDtype::default(),
+            activation_quant: cortex_rust::ActivationQuant::default(),
+            autotune: None,
+            weight_scale: cortex_rust::WeightScale::default(),
+            weight_scale_group: 128,
+            storage_dtype: cortex_rust::FloatDtype::default(),
+            compute_dtype: cortex_rust::FloatDtype::default(),
+            lm_head_dtype: None,
+            fused_projections: true,
+            embedding_quant: cortex_rust::TableQuant::default(),
+            lm_head_quant: cortex_rust::TableQuant::default(),
         }
     }
 
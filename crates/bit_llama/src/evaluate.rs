@@ -4,6 +4,7 @@ use clap::Args;
 use cortex_rust::Llama;
 // use memmap2::MmapOptions; // Removed
 // use std::fs::File; // Removed
+use crate::cli::{AutotuneArgs, RuntimeArgs};
 use crate::loader::BitLoader;
 use std::io::{self, Write};
 use tracing::{info, warn};
@@ -24,6 +25,17 @@ pub struct EvaluateArgs {
 
     #[arg(long)]
     pub limit: Option<usize>,
+
+    /// Compare the perplexity of every embedding / lm_head quantization
+    /// (dense, int8, ternary) instead of evaluating the model as configured
+    #[arg(long)]
+    pub table_quant_report: bool,
+
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+
+    #[command(flatten)]
+    pub autotune: AutotuneArgs,
 }
 
 pub fn run(args: EvaluateArgs) -> Result<()> {
@@ -32,7 +44,10 @@ pub fn run(args: EvaluateArgs) -> Result<()> {
     info!("Data:  {}", args.data);
 
     let mut llama = Llama::load_auto(&args.model)?;
-    llama.model.precompute_packed()?;
+    llama.set_runtime(&args.runtime.config()?)?;
+    args.autotune.apply(&mut llama.model.config);
+    let runtime = llama.runtime.clone();
+    runtime.prefill(|| llama.model.precompute_packed())?;
     info!("Model loaded successfully on {:?}", llama.device);
 
     let mut loader = BitLoader::new(&args.data)?;
@@ -41,6 +56,10 @@ pub fn run(args: EvaluateArgs) -> Result<()> {
 
     info!("Data loaded. Total tokens: {}", loader.data_len);
 
+    if args.table_quant_report {
+        return table_quant_report(&args, &mut llama, &mut loader);
+    }
+
     let mut total_nll = 0.0;
     let mut total_tokens = 0;
     let mut batch_count = 0;
@@ -76,7 +95,8 @@ pub fn run(args: EvaluateArgs) -> Result<()> {
                         let target_id = target_vec[b][t];
 
                         let inp_t = Tensor::new(&[token_id], &llama.device)?;
-                        let logits = llama.model.forward_one(&inp_t, &mut w_states)?;
+                        let logits =
+                            runtime.decode(|| llama.model.forward_one(&inp_t, &mut w_states))?;
                         if b == 0 && t == 0 {
                             eprintln!(
                                 "🚀 [DEBUG] Starting loop. Logits shape: {:?}",
@@ -149,3 +169,56 @@ pub fn run(args: EvaluateArgs) -> Result<()> {
 
     Ok(())
 }
+
+/// Perplexity of the 3x3 embedding / lm_head quantization grid on the first
+/// `limit` tokens (default: one batch)
+fn table_quant_report(
+    args: &EvaluateArgs,
+    llama: &mut Llama,
+    loader: &mut BitLoader,
+) -> Result<()> {
+    let limit = args.limit.unwrap_or(args.batch_size * args.context_len);
+    let mut sequences: Vec<Vec<u32>> = Vec::new();
+    let mut total_tokens = 0;
+    while total_tokens < limit {
+        let Ok((input, target)) =
+            loader.next_batch(args.batch_size, args.context_len, &llama.device)
+        else {
+            break;
+        };
+        for (mut seq, target) in input
+            .to_vec2::<u32>()?
+            .into_iter()
+            .zip(target.to_vec2::<u32>()?)
+        {
+            // Targets are the inputs shifted by one: append the last one
+            seq.extend(target.last());
+            total_tokens += seq.len() - 1;
+            sequences.push(seq);
+        }
+    }
+    if sequences.is_empty() {
+        warn!("No data processed.");
+        return Ok(());
+    }
+    info!("Table quant report on {} tokens...", total_tokens);
+
+    let runtime = llama.runtime.clone();
+    let reports = runtime.prefill(|| llama.model.table_quant_report(&sequences))?;
+    println!(
+        "{:<10} {:<10} {:>12} {:>12} {:>8}",
+        "embedding", "lm_head", "size (MiB)", "perplexity", "ratio"
+    );
+    for r in reports {
+        println!(
+            "{:<10} {:<10} {:>12.2} {:>12.3} {:>8.4}",
+            format!("{:?}", r.embedding).to_lowercase(),
+            format!("{:?}", r.lm_head).to_lowercase(),
+            r.bytes as f64 / (1024.0 * 1024.0),
+            r.perplexity,
+            r.ratio
+        );
+    }
+    println!("Set `embedding_quant` / `lm_head_quant` in config.json to use a row.");
+    Ok(())
+}
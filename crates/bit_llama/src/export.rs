@@ -1,8 +1,12 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
-use serde_json::Value;
+use cortex_rust::model::bitt::{
+    SECTION_CHAT_TEMPLATE, SECTION_CONFIG, SECTION_GENERATION_CONFIG, SECTION_QUANTIZATION,
+    SECTION_SOUL, SECTION_TOKENIZER, SECTION_WEIGHTS,
+};
+use cortex_rust::{BitLlamaConfig, BittFile, BittMetadata, PackedCheckpoint};
+use memmap2::Mmap;
 use std::fs::File;
-use std::io::Write;
 
 #[derive(Args, Debug, Clone)]
 pub struct ExportArgs {
@@ -14,36 +18,146 @@ pub struct ExportArgs {
     pub model: String,
     #[arg(long, default_value = "bit-llama.bitt")]
     pub output: String,
+    /// generation_config.json to embed (sampling defaults)
+    #[arg(long)]
+    pub generation_config: Option<String>,
+    /// Chat template (Jinja text) to embed
+    #[arg(long)]
+    pub chat_template: Option<String>,
+    /// TTT memory snapshot (.soul) restored when the model is loaded
+    #[arg(long)]
+    pub soul: Option<String>,
+    /// Training step of the weights (metadata)
+    #[arg(long)]
+    pub step: Option<u64>,
+    /// Provenance of the weights (metadata, default: the --model path)
+    #[arg(long)]
+    pub source: Option<String>,
 }
 
 pub fn run(args: ExportArgs) -> Result<()> {
     println!("📦 Packaging into custom format: {}", args.output);
 
-    let config_str = std::fs::read_to_string(&args.config)?;
-    let tokenizer_str = std::fs::read_to_string(&args.tokenizer)?;
+    let config_bytes = std::fs::read(&args.config)?;
+    let config: BitLlamaConfig =
+        serde_json::from_slice(&config_bytes).with_context(|| args.config.clone())?;
+    let tokenizer_bytes = std::fs::read(&args.tokenizer)?;
+    serde_json::from_slice::<serde_json::Value>(&tokenizer_bytes)
+        .with_context(|| args.tokenizer.clone())?;
+    let weights = unsafe { Mmap::map(&File::open(&args.model)?)? };
 
-    let header_json = serde_json::json!({
-        "config": serde_json::from_str::<Value>(&config_str)?,
-        "tokenizer": serde_json::from_str::<Value>(&tokenizer_str)?
-    });
-    let header_bytes = serde_json::to_vec(&header_json)?;
-    let header_len = header_bytes.len() as u64;
+    // How the weights are stored, for `bit_llama inspect`
+    let quantization = serde_json::to_vec(&serde_json::json!({
+        "packed": PackedCheckpoint::open(&args.model)?.is_packed(),
+        "weight_scale": config.weight_scale,
+        "weight_scale_group": config.weight_scale_group,
+        "activation_quant": config.activation_quant,
+        "storage_dtype": config.storage_dtype,
+        "embedding_quant": config.embedding_quant,
+        "lm_head_quant": config.lm_head_quant,
+    }))?;
+    let generation_config = match &args.generation_config {
+        Some(path) => {
+            let bytes = std::fs::read(path)?;
+            serde_json::from_slice::<serde_json::Value>(&bytes)
+                .with_context(|| path.to_string())?;
+            Some(bytes)
+        }
+        None => None,
+    };
+    let chat_template = args.chat_template.as_ref().map(std::fs::read).transpose()?;
+    let soul = args.soul.as_ref().map(std::fs::read).transpose()?;
 
-    let mut model_file = File::open(&args.model)?;
-    let model_len = model_file.metadata()?.len();
+    let mut sections: Vec<(&str, &[u8])> = vec![
+        (SECTION_CONFIG, &config_bytes),
+        (SECTION_TOKENIZER, &tokenizer_bytes),
+        (SECTION_QUANTIZATION, &quantization),
+    ];
+    if let Some(bytes) = &generation_config {
+        sections.push((SECTION_GENERATION_CONFIG, bytes));
+    }
+    if let Some(bytes) = &chat_template {
+        sections.push((SECTION_CHAT_TEMPLATE, bytes));
+    }
+    if let Some(bytes) = &soul {
+        sections.push((SECTION_SOUL, bytes));
+    }
+    // Weights last: nothing but padding in front of them
+    sections.push((SECTION_WEIGHTS, &weights));
 
-    let mut output = File::create(&args.output)?;
+    let metadata = BittMetadata {
+        training_step: args.step,
+        source: Some(args.source.clone().unwrap_or_else(|| args.model.clone())),
+        created: Some(chrono::Local::now().to_rfc3339()),
+        exporter: Some(format!("bit_llama {}", env!("CARGO_PKG_VERSION"))),
+    };
+    BittFile::write(&args.output, &sections, &metadata)?;
 
-    output.write_all(b"BITT")?;
-    output.write_all(&header_len.to_le_bytes())?;
-    output.write_all(&header_bytes)?;
+    println!(
+        "✅ Created .bitt v{} file!",
+        cortex_rust::model::bitt::BITT_VERSION
+    );
+    for (name, data) in &sections {
+        println!("   {:<18} {} bytes", name, data.len());
+    }
+    Ok(())
+}
 
-    std::io::copy(&mut model_file, &mut output)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{DType, Device};
+    use candle_nn::{VarBuilder, VarMap};
+    use cortex_rust::model::bitt::BITT_ALIGN;
+    use cortex_rust::BitLlama;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::Tokenizer;
 
-    println!("✅ Created .bitt file!");
-    println!("   Magic: BITT");
-    println!("   Header: {} bytes (Config + Tokenizer)", header_len);
-    println!("   Body:   {} bytes (Weights)", model_len);
+    #[test]
+    fn test_export_writes_verified_v2() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
 
-    Ok(())
+        let cfg = BitLlamaConfig::new(16, 32, 1, 0.1, None);
+        let varmap = VarMap::new();
+        BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        varmap.save(path("model.safetensors"))?;
+        std::fs::write(path("config.json"), serde_json::to_string(&cfg)?)?;
+        let vocab = (0..16).map(|i| (format!("t{}", i), i)).collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("t0".into())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        Tokenizer::new(model)
+            .save(path("tokenizer.json"), false)
+            .map_err(anyhow::Error::msg)?;
+        std::fs::write(path("template.jinja"), "{{ messages }}")?;
+
+        run(ExportArgs {
+            config: path("config.json"),
+            tokenizer: path("tokenizer.json"),
+            model: path("model.safetensors"),
+            output: path("model.bitt"),
+            generation_config: None,
+            chat_template: Some(path("template.jinja")),
+            soul: None,
+            step: Some(42),
+            source: None,
+        })?;
+
+        let bitt = BittFile::open(path("model.bitt"))?;
+        bitt.verify()?;
+        assert_eq!(bitt.metadata.training_step, Some(42));
+        assert_eq!(bitt.metadata.source, Some(path("model.safetensors")));
+        assert_eq!(bitt.quantization()?.unwrap()["packed"], false);
+        assert_eq!(bitt.chat_template()?.as_deref(), Some("{{ messages }}"));
+        assert_eq!(bitt.body(), std::fs::read(path("model.safetensors"))?);
+        let weights = bitt.sections.iter().find(|s| s.name == SECTION_WEIGHTS);
+        assert_eq!(weights.unwrap().offset as usize % BITT_ALIGN, 0);
+        Ok(())
+    }
 }
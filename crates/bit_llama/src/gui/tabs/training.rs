@@ -200,6 +200,15 @@ pub fn show(
                         accum,
                     ];
 
+                    if let Some(experts) = project.config.n_experts {
+                        cmd_args.push("--experts".to_string());
+                        cmd_args.push(experts.to_string());
+                        cmd_args.push("--experts-per-tok".to_string());
+                        cmd_args.push(project.config.n_experts_per_tok.to_string());
+                        cmd_args.push("--router-aux-loss-coef".to_string());
+                        cmd_args.push(project.config.router_aux_loss_coef.to_string());
+                    }
+
                     if project.config.mock {
                         cmd_args.push("--mock".to_string());
                     }
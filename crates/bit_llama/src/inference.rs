@@ -1,3 +1,4 @@
+use crate::cli::{AutotuneArgs, RuntimeArgs};
 use crate::memory::MemorySystem;
 use anyhow::Result;
 use clap::Args;
@@ -8,6 +9,7 @@ use std::thread;
 
 #[derive(Args, Debug, Clone)]
 pub struct InferenceArgs {
+    /// Model directory (config.json + model.safetensors + tokenizer.json) or .bitt file
     #[arg(short, long, default_value = ".")]
     pub model: String,
 
@@ -23,6 +25,16 @@ pub struct InferenceArgs {
     /// Path to load initial TTT memory (.soul file)
     #[arg(long)]
     pub memory: Option<String>,
+
+    /// LoRA adapter (.safetensors) applied on top of the base weights
+    #[arg(long)]
+    pub lora: Option<String>,
+
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+
+    #[command(flatten)]
+    pub autotune: AutotuneArgs,
 }
 
 pub fn run(args: InferenceArgs) -> Result<()> {
@@ -50,12 +62,23 @@ pub fn run(args: InferenceArgs) -> Result<()> {
 
     let mut llama = Llama::load_auto(&args.model).map_err(|e| {
         anyhow::anyhow!(
-            "Failed to load model: {}\nEnsure directory contains config.json etc.",
+            "Failed to load model: {}\nEnsure directory contains config.json etc. (or pass a .bitt file)",
             e
         )
     })?;
 
-    llama.model.precompute_packed()?;
+    llama.set_runtime(&args.runtime.config()?)?;
+    args.autotune.apply(&mut llama.model.config);
+    let runtime = llama.runtime.clone();
+    runtime.prefill(|| llama.model.precompute_packed())?;
+
+    if let Some(lora_path) = &args.lora {
+        let cfg = llama.model.load_lora(lora_path)?;
+        println!(
+            "🧩 LoRA adapter loaded: {} (rank={}, alpha={})",
+            lora_path, cfg.rank, cfg.alpha
+        );
+    }
 
     // Load initial memory if specified
     if let Some(mem_path) = &args.memory {
@@ -240,6 +263,21 @@ pub fn run(args: InferenceArgs) -> Result<()> {
                     continue;
                 }
 
+                if let Some(path) = prompt.strip_prefix("/lora ") {
+                    let path = path.trim();
+                    if path == "off" {
+                        llama.model.clear_lora();
+                        println!("🧩 LoRA adapter removed.");
+                    } else {
+                        llama.model.clear_lora();
+                        match llama.model.load_lora(path) {
+                            Ok(cfg) => println!("🧩 LoRA adapter swapped in (rank={})", cfg.rank),
+                            Err(e) => println!("❌ Failed to load LoRA adapter: {}", e),
+                        }
+                    }
+                    continue;
+                }
+
                 if let Some(stripped) = prompt.strip_prefix("/temp ") {
                     if let Ok(v) = stripped.parse::<f64>() {
                         current_temp = v;
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use clap::Args;
+use cortex_rust::model::bitt::BITT_ALIGN;
+use cortex_rust::BittFile;
+
+#[derive(Args, Debug, Clone)]
+pub struct InspectArgs {
+    /// .bitt file
+    pub file: String,
+
+    /// Check the SHA-256 of every section (v2 files)
+    #[arg(long)]
+    pub verify: bool,
+}
+
+pub fn run(args: InspectArgs) -> Result<()> {
+    let bitt = BittFile::open(&args.file)?;
+    let cfg = &bitt.config;
+
+    println!("📦 {} (.bitt v{})", args.file, bitt.version);
+    println!(
+        "   Model:    {:?}, {} layers, hidden {}, vocab {}",
+        cfg.arch, cfg.num_layers, cfg.hidden_dim, cfg.vocab_size
+    );
+    let metadata = &bitt.metadata;
+    let fields = [
+        ("Step", metadata.training_step.map(|s| s.to_string())),
+        ("Source", metadata.source.clone()),
+        ("Created", metadata.created.clone()),
+        ("Exporter", metadata.exporter.clone()),
+    ];
+    for (label, value) in fields {
+        if let Some(value) = value {
+            println!("   {:<9} {}", format!("{}:", label), value);
+        }
+    }
+    if let Some(quant) = bitt.quantization()? {
+        println!("   Quant:    {}", quant);
+    }
+
+    println!();
+    println!(
+        "   {:<18} {:>12} {:>14}  sha256",
+        "section", "offset", "bytes"
+    );
+    for section in &bitt.sections {
+        let aligned = if (section.offset as usize).is_multiple_of(BITT_ALIGN) {
+            ""
+        } else {
+            " (unaligned)"
+        };
+        println!(
+            "   {:<18} {:>12} {:>14}  {}{}",
+            section.name,
+            section.offset,
+            section.length,
+            section.sha256.as_deref().unwrap_or("-"),
+            aligned
+        );
+    }
+
+    if args.verify {
+        bitt.verify()?;
+        println!("✅ All {} section checksums match", bitt.sections.len());
+    }
+    Ok(())
+}
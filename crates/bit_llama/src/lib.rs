@@ -5,8 +5,11 @@ pub mod evaluate;
 pub mod export;
 pub mod gui;
 pub mod inference;
+pub mod inspect;
 pub mod loader;
 pub mod memory;
+pub mod pack;
+pub mod prune;
 pub mod state;
 pub mod train;
 pub mod vocab;
@@ -82,6 +82,24 @@ impl BitLoader {
         self.cursor = 0;
     }
 
+    /// Occurrences of every token id below `vocab_size` in the whole file
+    pub fn token_counts(&self, vocab_size: usize) -> Result<Vec<u64>> {
+        let mut counts = vec![0u64; vocab_size];
+        let elem_size = if self.is_u32 { 4 } else { 2 };
+        for c in self.mmap[..self.data_len * elem_size].chunks_exact(elem_size) {
+            let id = if self.is_u32 {
+                u32::from_le_bytes([c[0], c[1], c[2], c[3]])
+            } else {
+                u16::from_le_bytes([c[0], c[1]]) as u32
+            };
+            match counts.get_mut(id as usize) {
+                Some(count) => *count += 1,
+                None => anyhow::bail!("Token {} is outside the vocabulary ({})", id, vocab_size),
+            }
+        }
+        Ok(counts)
+    }
+
     pub fn next_batch(
         &mut self,
         batch_size: usize,
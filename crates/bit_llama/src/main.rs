@@ -3,7 +3,7 @@
 
 use anyhow::Result;
 use bit_llama::cli::{Cli, Commands};
-use bit_llama::{data, evaluate, export, gui, inference, train, vocab};
+use bit_llama::{data, evaluate, export, gui, inference, inspect, pack, prune, train, vocab};
 use clap::Parser;
 
 fn main() -> Result<()> {
@@ -96,6 +96,9 @@ fn main() -> Result<()> {
         Some(Commands::Data(args)) => data::run(args)?,
         Some(Commands::Vocab(args)) => vocab::run(args)?,
         Some(Commands::Export(args)) => export::run(args)?,
+        Some(Commands::Inspect(args)) => inspect::run(args)?,
+        Some(Commands::Pack(args)) => pack::run(args)?,
+        Some(Commands::PruneVocab(args)) => prune::run(args)?,
         Some(Commands::Inference(args)) => inference::run(args)?,
         Some(Commands::Evaluate(args)) => evaluate::run(args)?,
     }
@@ -0,0 +1,38 @@
+use crate::cli::AutotuneArgs;
+use anyhow::Result;
+use clap::Args;
+use cortex_rust::Llama;
+use tracing::info;
+
+#[derive(Args, Debug, Clone)]
+pub struct PackArgs {
+    /// Model directory (config.json + model.safetensors)
+    #[arg(short, long, default_value = ".")]
+    pub model: String,
+
+    /// Output safetensors file with 2-bit packed weights
+    #[arg(short, long, default_value = "model.packed.safetensors")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub autotune: AutotuneArgs,
+}
+
+pub fn run(args: PackArgs) -> Result<()> {
+    info!("--- Bit-Llama Pack (2-bit checkpoint) ---");
+    info!("Model:  {}", args.model);
+
+    let mut llama = Llama::load_auto(&args.model)?;
+    args.autotune.apply(&mut llama.model.config);
+    llama.model.precompute_packed()?;
+    llama.model.save_packed(&args.output)?;
+
+    let size = std::fs::metadata(&args.output)?.len();
+    println!("✅ Packed checkpoint written: {}", args.output);
+    println!(
+        "   Scales: {:?}, CPU kernel: {:?}",
+        llama.model.config.weight_scale, llama.model.config.activation_quant
+    );
+    println!("   Size:   {} bytes", size);
+    Ok(())
+}
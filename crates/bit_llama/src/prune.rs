@@ -0,0 +1,746 @@
+//! prune-vocab - keep only the tokens a deployment uses
+//!
+//! Slices the rows of the embedding table and lm_head, rewrites
+//! `tokenizer.json` with the remapped ids and updates `vocab_size` and the
+//! bos/eos/pad ids in config.json (and generation_config.json). Special
+//! tokens are always kept, as are the parts of every kept BPE token, so it
+//! can still be produced by the merges. Kept tokens stay in their original
+//! order; the logits of the kept tokens are unchanged and the dropped ones
+//! simply can't be generated. `vocab_map.json` lists the original id of
+//! every new id.
+
+use anyhow::{Context, Result};
+use candle_core::{Device, Tensor};
+use clap::Args;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::loader::BitLoader;
+
+/// Embedding table and lm_head tensors (HF and legacy names)
+const VOCAB_TENSORS: [&str; 3] = [
+    "model.embed_tokens.weight",
+    "embed.weight",
+    "lm_head.weight",
+];
+
+/// Token ids in config.json / generation_config.json (a number, a list or null)
+const SPECIAL_ID_KEYS: [&str; 4] = [
+    "bos_token_id",
+    "eos_token_id",
+    "pad_token_id",
+    "decoder_start_token_id",
+];
+
+#[derive(Args, Debug, Clone)]
+pub struct PruneVocabArgs {
+    /// Model directory (config.json + model.safetensors + tokenizer.json)
+    #[arg(short, long, default_value = ".")]
+    pub model: String,
+
+    /// Output model directory
+    #[arg(short, long, required = true)]
+    pub output: String,
+
+    /// Token corpus (.u32 / .u16 with the model's ids) to count token usage
+    #[arg(long)]
+    pub corpus: Option<String>,
+
+    /// Keep corpus tokens seen at least this often
+    #[arg(long, default_value_t = 1)]
+    pub min_count: u64,
+
+    /// Keep-list: one token id or token string (as in tokenizer.json) per line
+    #[arg(long)]
+    pub keep: Option<String>,
+
+    /// Largest pruned vocabulary; the most used corpus tokens fill what the
+    /// special, single-character and keep-list tokens leave
+    #[arg(long)]
+    pub max_vocab: Option<usize>,
+
+    /// Drop unused single-character / byte tokens too (text outside the
+    /// corpus may then no longer be encodable)
+    #[arg(long)]
+    pub drop_chars: bool,
+}
+
+pub fn run(args: PruneVocabArgs) -> Result<()> {
+    info!("--- Bit-Llama Vocabulary Pruning ---");
+    info!("Model:  {}", args.model);
+
+    let dir = Path::new(&args.model);
+    let mut config: Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("config.json"))?)?;
+    let vocab_size = config["vocab_size"]
+        .as_u64()
+        .context("config.json has no vocab_size")? as usize;
+    let tokenizer_path = dir.join("tokenizer.json");
+    let tokenizer = Tokenizer::from_file(&tokenizer_path).map_err(anyhow::Error::msg)?;
+    let generation_config_path = dir.join("generation_config.json");
+    let mut generation_config: Option<Value> = if generation_config_path.exists() {
+        Some(serde_json::from_str(&std::fs::read_to_string(
+            &generation_config_path,
+        )?)?)
+    } else {
+        None
+    };
+
+    let mut specials = special_ids(&config)?;
+    if let Some(generation_config) = &generation_config {
+        specials.extend(special_ids(generation_config)?);
+    }
+    let keep = select_tokens(&args, &tokenizer, vocab_size, &specials)?;
+    info!("Keeping {} of {} tokens", keep.len(), vocab_size);
+
+    // Weights
+    let mut model_path = dir.join("model.safetensors");
+    if !model_path.exists() {
+        model_path = dir.join("weight.safetensors");
+    }
+    let mut tensors = candle_core::safetensors::load(&model_path, &Device::Cpu)
+        .with_context(|| format!("Could not load weights from {:?}", dir))?;
+    prune_tensors(&mut tensors, &keep, vocab_size)?;
+
+    // Tokenizer, checked against the original
+    let mut tokenizer_json: Value =
+        serde_json::from_str(&std::fs::read_to_string(&tokenizer_path)?)?;
+    prune_tokenizer(&mut tokenizer_json, &keep)?;
+    let tokenizer_str = serde_json::to_string_pretty(&tokenizer_json)?;
+    let pruned = Tokenizer::from_str(&tokenizer_str).map_err(anyhow::Error::msg)?;
+    for (new_id, &old_id) in keep.iter().enumerate() {
+        let token = tokenizer.id_to_token(old_id);
+        if token.is_some() && pruned.id_to_token(new_id as u32) != token {
+            anyhow::bail!("Token {:?} did not survive the tokenizer rewrite", token);
+        }
+    }
+
+    config["vocab_size"] = keep.len().into();
+    remap_special_ids(&mut config, &keep).context("config.json")?;
+    if let Some(generation_config) = &mut generation_config {
+        remap_special_ids(generation_config, &keep).context("generation_config.json")?;
+    }
+
+    let out = Path::new(&args.output);
+    std::fs::create_dir_all(out)?;
+    candle_core::safetensors::save(&tensors, out.join("model.safetensors"))?;
+    std::fs::write(out.join("tokenizer.json"), tokenizer_str)?;
+    std::fs::write(
+        out.join("config.json"),
+        serde_json::to_string_pretty(&config)?,
+    )?;
+    if let Some(generation_config) = &generation_config {
+        std::fs::write(
+            out.join("generation_config.json"),
+            serde_json::to_string_pretty(generation_config)?,
+        )?;
+    }
+    let vocab_map = serde_json::json!({ "source": args.model, "kept": keep });
+    std::fs::write(
+        out.join("vocab_map.json"),
+        serde_json::to_string(&vocab_map)?,
+    )?;
+
+    println!("✅ Pruned model written: {}", args.output);
+    println!("   Vocabulary: {} -> {} tokens", vocab_size, keep.len());
+    Ok(())
+}
+
+/// Original ids to keep, ascending (the new id of a token is its index).
+/// `specials` are kept along with the tokenizer's own special tokens; every
+/// kept BPE token brings the tokens it is merged from.
+pub fn select_tokens(
+    args: &PruneVocabArgs,
+    tokenizer: &Tokenizer,
+    vocab_size: usize,
+    specials: &[u32],
+) -> Result<Vec<u32>> {
+    if args.corpus.is_none() && args.keep.is_none() {
+        anyhow::bail!("Give a --corpus to count token usage, a --keep list, or both");
+    }
+    let tokenizer_json: Value =
+        serde_json::from_str(&tokenizer.to_string(false).map_err(anyhow::Error::msg)?)?;
+    let parents = merge_parents(&tokenizer_json);
+    // `id` and everything it is merged from
+    let with_parents = |id: u32| {
+        let mut ids = BTreeSet::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if ids.insert(id) {
+                stack.extend(parents.get(&id).into_iter().flatten());
+            }
+        }
+        ids
+    };
+
+    let mut keep: BTreeSet<u32> = specials.iter().copied().collect();
+    keep.extend(tokenizer.get_added_tokens_decoder().keys());
+    let model = &tokenizer_json["model"];
+    if let Some(unk_id) = model["unk_id"].as_u64() {
+        keep.insert(unk_id as u32);
+    }
+    if let Some(unk) = model["unk_token"].as_str() {
+        keep.extend(tokenizer.token_to_id(unk));
+    }
+    if !args.drop_chars {
+        keep.extend(
+            tokenizer
+                .get_vocab(false)
+                .into_iter()
+                .filter(|(token, _)| is_base_token(token))
+                .map(|(_, id)| id),
+        );
+    }
+    if let Some(path) = &args.keep {
+        keep.extend(read_keep_list(path, tokenizer)?);
+    }
+    let mut keep: BTreeSet<u32> = keep.into_iter().flat_map(with_parents).collect();
+    if let Some(&id) = keep.range(vocab_size as u32..).next() {
+        anyhow::bail!("Token {} is outside the vocabulary ({})", id, vocab_size);
+    }
+
+    if let Some(path) = &args.corpus {
+        let counts = BitLoader::new(path)?.token_counts(vocab_size)?;
+        let mut used: Vec<u32> = (0..vocab_size as u32)
+            .filter(|&id| counts[id as usize] >= args.min_count.max(1))
+            .collect();
+        // Most used first, ties by id
+        used.sort_by_key(|&id| (std::cmp::Reverse(counts[id as usize]), id));
+        let limit = args.max_vocab.unwrap_or(usize::MAX);
+        for id in used {
+            let mut new = with_parents(id);
+            new.retain(|id| !keep.contains(id));
+            if keep.len() + new.len() <= limit {
+                keep.extend(new);
+            }
+        }
+    }
+    if let Some(max_vocab) = args.max_vocab {
+        if keep.len() > max_vocab {
+            anyhow::bail!(
+                "{} tokens are always kept (special, single-character, keep-list and their BPE parts), more than --max-vocab {}",
+                keep.len(),
+                max_vocab
+            );
+        }
+    }
+    Ok(keep.into_iter().collect())
+}
+
+/// Ids referenced by the `SPECIAL_ID_KEYS` of a config
+fn special_ids(config: &Value) -> Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    for key in SPECIAL_ID_KEYS {
+        match &config[key] {
+            Value::Null => {}
+            Value::Array(list) => {
+                for id in list {
+                    ids.push(
+                        id.as_u64()
+                            .with_context(|| format!("{} is not an id", key))?
+                            as u32,
+                    );
+                }
+            }
+            id => ids.push(
+                id.as_u64()
+                    .with_context(|| format!("{} is not an id", key))? as u32,
+            ),
+        }
+    }
+    Ok(ids)
+}
+
+/// Rewrite the `SPECIAL_ID_KEYS` of a config to the new ids
+fn remap_special_ids(config: &mut Value, keep: &[u32]) -> Result<()> {
+    let new_id = |key: &str, id: &mut Value| -> Result<()> {
+        let old = id
+            .as_u64()
+            .with_context(|| format!("{} is not an id", key))?;
+        match keep.binary_search(&(old as u32)) {
+            Ok(new) => *id = new.into(),
+            Err(_) => anyhow::bail!("{} {} was dropped from the vocabulary", key, old),
+        }
+        Ok(())
+    };
+    for key in SPECIAL_ID_KEYS {
+        match config.get_mut(key) {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(list)) => {
+                for id in list.iter_mut() {
+                    new_id(key, id)?;
+                }
+            }
+            Some(id) => new_id(key, id)?,
+        }
+    }
+    Ok(())
+}
+
+/// The two parts of a BPE merge: "a b" (older files) or ["a", "b"]
+fn merge_parts(merge: &Value) -> Option<(&str, &str)> {
+    match merge {
+        Value::String(s) => s.split_once(' '),
+        Value::Array(p) => p.first()?.as_str().zip(p.get(1)?.as_str()),
+        _ => None,
+    }
+}
+
+/// Token produced by merging `a` and `b`
+fn merged_token(a: &str, b: &str, prefix: &str) -> String {
+    format!("{}{}", a, b.strip_prefix(prefix).unwrap_or(b))
+}
+
+/// BPE token id -> ids of the tokens it is merged from
+fn merge_parents(tokenizer: &Value) -> HashMap<u32, Vec<u32>> {
+    let model = &tokenizer["model"];
+    let (Some(vocab), Some(merges)) = (model["vocab"].as_object(), model["merges"].as_array())
+    else {
+        return HashMap::new();
+    };
+    let prefix = model["continuing_subword_prefix"]
+        .as_str()
+        .unwrap_or_default();
+    let id = |token: &str| vocab.get(token).and_then(Value::as_u64).map(|id| id as u32);
+    let mut parents: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (a, b) in merges.iter().filter_map(merge_parts) {
+        if let (Some(merged), Some(a), Some(b)) = (id(&merged_token(a, b, prefix)), id(a), id(b)) {
+            parents.entry(merged).or_default().extend([a, b]);
+        }
+    }
+    parents
+}
+
+/// Single characters and byte-fallback tokens (`<0x41>`): with them any text
+/// stays encodable
+fn is_base_token(token: &str) -> bool {
+    token.chars().count() == 1
+        || (token.len() == 6 && token.starts_with("<0x") && token.ends_with('>'))
+}
+
+fn read_keep_list(path: &str, tokenizer: &Tokenizer) -> Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        let id = match entry.parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => tokenizer
+                .token_to_id(entry)
+                .with_context(|| format!("Unknown token '{}' in {}", entry, path))?,
+        };
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Keep the `keep` rows of the embedding table and lm_head
+pub fn prune_tensors(
+    tensors: &mut HashMap<String, Tensor>,
+    keep: &[u32],
+    vocab_size: usize,
+) -> Result<()> {
+    let index = Tensor::new(keep, &Device::Cpu)?;
+    let mut pruned = 0;
+    for name in VOCAB_TENSORS {
+        let Some(tensor) = tensors.get_mut(name) else {
+            continue;
+        };
+        if tensor.dim(0)? != vocab_size {
+            anyhow::bail!(
+                "{} has {} rows, config.json says vocab_size {}",
+                name,
+                tensor.dim(0)?,
+                vocab_size
+            );
+        }
+        *tensor = tensor.index_select(&index, 0)?;
+        pruned += 1;
+    }
+    if pruned < 2 {
+        anyhow::bail!(
+            "Expected an embedding table and an lm_head ({:?})",
+            VOCAB_TENSORS
+        );
+    }
+    Ok(())
+}
+
+/// Rewrite a tokenizer.json for the kept ids: model vocabulary (BPE /
+/// WordPiece / WordLevel maps, Unigram piece lists), BPE merges whose parts
+/// all survive, added tokens and the special ids of the post-processor
+pub fn prune_tokenizer(tokenizer: &mut Value, keep: &[u32]) -> Result<()> {
+    let new_id = |old: &Value| -> Result<Value> {
+        let old = old.as_u64().context("Token id is not a number")?;
+        match keep.binary_search(&(old as u32)) {
+            Ok(id) => Ok(id.into()),
+            Err(_) => anyhow::bail!("Token {} is referenced by the tokenizer but not kept", old),
+        }
+    };
+    let kept = |old: &Value| {
+        old.as_u64()
+            .is_some_and(|id| keep.binary_search(&(id as u32)).is_ok())
+    };
+
+    let model = tokenizer
+        .get_mut("model")
+        .context("tokenizer.json has no model")?;
+    let mut tokens = HashSet::new();
+    match model.get_mut("vocab") {
+        Some(Value::Object(vocab)) => {
+            let mut pruned = serde_json::Map::new();
+            for (token, id) in vocab.iter().filter(|(_, id)| kept(id)) {
+                pruned.insert(token.clone(), new_id(id)?);
+                tokens.insert(token.clone());
+            }
+            *vocab = pruned;
+        }
+        // Unigram: [piece, score] at index id
+        Some(Value::Array(pieces)) => {
+            *pieces = keep
+                .iter()
+                .filter_map(|&id| pieces.get(id as usize).cloned())
+                .collect();
+        }
+        _ => anyhow::bail!("tokenizer.json model has no vocab"),
+    }
+    if let Some(unk_id) = model.get_mut("unk_id").filter(|id| !id.is_null()) {
+        *unk_id = new_id(unk_id)?;
+    }
+    let prefix = model["continuing_subword_prefix"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if let Some(Value::Array(merges)) = model.get_mut("merges") {
+        merges.retain(|merge| {
+            merge_parts(merge).is_some_and(|(a, b)| {
+                tokens.contains(a)
+                    && tokens.contains(b)
+                    && tokens.contains(&merged_token(a, b, &prefix))
+            })
+        });
+    }
+
+    if let Some(Value::Array(added)) = tokenizer.get_mut("added_tokens") {
+        for token in added.iter_mut() {
+            token["id"] = new_id(&token["id"])?;
+        }
+    }
+    if let Some(processor) = tokenizer.get_mut("post_processor") {
+        remap_post_processor(processor, &new_id)?;
+    }
+    Ok(())
+}
+
+fn remap_post_processor(
+    processor: &mut Value,
+    new_id: &impl Fn(&Value) -> Result<Value>,
+) -> Result<()> {
+    match processor["type"].as_str() {
+        Some("TemplateProcessing") => {
+            if let Some(Value::Object(specials)) = processor.get_mut("special_tokens") {
+                for special in specials.values_mut() {
+                    if let Some(Value::Array(ids)) = special.get_mut("ids") {
+                        for id in ids.iter_mut() {
+                            *id = new_id(id)?;
+                        }
+                    }
+                }
+            }
+        }
+        // ["[SEP]", id]
+        Some("BertProcessing") | Some("RobertaProcessing") => {
+            for key in ["sep", "cls"] {
+                if let Some(Value::Array(pair)) = processor.get_mut(key) {
+                    if let Some(id) = pair.get_mut(1) {
+                        *id = new_id(id)?;
+                    }
+                }
+            }
+        }
+        Some("Sequence") => {
+            if let Some(Value::Array(processors)) = processor.get_mut("processors") {
+                for p in processors.iter_mut() {
+                    remap_post_processor(p, new_id)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::DType;
+    use candle_nn::{VarBuilder, VarMap};
+    use cortex_rust::{BitLlama, BitLlamaConfig, Llama};
+    use tokenizers::models::bpe::{Vocab, BPE};
+    use tokenizers::models::unigram::Unigram;
+
+    const PIECES: [&str; 12] = [
+        "<unk>", "<s>", "</s>", "a", "b", "c", "ab", "bc", "abc", "x", "y", "xy",
+    ];
+
+    /// Small model directory with a Unigram tokenizer over `PIECES`
+    fn write_model(dir: &Path) -> Result<()> {
+        let pieces = PIECES
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.to_string(), -(i as f64) / 4.0))
+            .collect();
+        let unigram = Unigram::from(pieces, Some(0), false).map_err(anyhow::Error::msg)?;
+        write_model_with(dir, Tokenizer::new(unigram), PIECES.len())
+    }
+
+    /// Model directory for `tokenizer`; <s> = 1 and </s> = 2 in the configs
+    fn write_model_with(dir: &Path, tokenizer: Tokenizer, vocab_size: usize) -> Result<()> {
+        tokenizer
+            .save(dir.join("tokenizer.json"), false)
+            .map_err(anyhow::Error::msg)?;
+
+        let cfg = BitLlamaConfig::new(vocab_size, 32, 1, 0.1, None);
+        let varmap = VarMap::new();
+        BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        varmap.save(dir.join("model.safetensors"))?;
+        let mut config = serde_json::to_value(cfg)?;
+        config["bos_token_id"] = 1.into();
+        config["eos_token_id"] = 2.into();
+        std::fs::write(dir.join("config.json"), serde_json::to_string(&config)?)?;
+        let generation_config = serde_json::json!({ "eos_token_id": [2], "pad_token_id": null });
+        std::fs::write(
+            dir.join("generation_config.json"),
+            serde_json::to_string(&generation_config)?,
+        )?;
+        Ok(())
+    }
+
+    /// Last-position logits of `ids`
+    fn logits(llama: &Llama, ids: &[u32]) -> Result<Vec<f32>> {
+        let mut seq = llama.model.new_sequence()?;
+        let mut logits = Vec::new();
+        for &id in ids {
+            logits = llama
+                .model
+                .forward_batch(&[id], &mut [&mut seq])?
+                .flatten_all()?
+                .to_vec1::<f32>()?;
+        }
+        Ok(logits)
+    }
+
+    #[test]
+    fn test_prune_vocab_keeps_logits_and_tokens() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+        std::fs::create_dir_all(&src)?;
+        write_model(&src)?;
+        let keep_path = dir.path().join("keep.txt");
+        std::fs::write(&keep_path, "# kept\nabc\n11\n")?;
+
+        let args = PruneVocabArgs {
+            model: src.to_string_lossy().to_string(),
+            output: out.to_string_lossy().to_string(),
+            corpus: None,
+            min_count: 1,
+            keep: Some(keep_path.to_string_lossy().to_string()),
+            max_vocab: None,
+            drop_chars: false,
+        };
+        run(args)?;
+
+        // Special ids, single characters and the keep-list; "ab" and "bc" are gone
+        let kept: Vec<u32> = vec![0, 1, 2, 3, 4, 5, 8, 9, 10, 11];
+        let read = |name: &str| -> Result<Value> {
+            Ok(serde_json::from_str(&std::fs::read_to_string(
+                out.join(name),
+            )?)?)
+        };
+        assert_eq!(read("vocab_map.json")?["kept"], serde_json::json!(kept));
+        assert_eq!(read("config.json")?["eos_token_id"], 2);
+        assert_eq!(
+            read("generation_config.json")?["eos_token_id"],
+            serde_json::json!([2])
+        );
+
+        let original = Llama::load_auto(&src)?;
+        let pruned = Llama::load_auto(&out)?;
+        assert_eq!(pruned.model.config.vocab_size, kept.len());
+        for (new_id, &old_id) in kept.iter().enumerate() {
+            assert_eq!(
+                pruned.tokenizer.id_to_token(new_id as u32),
+                original.tokenizer.id_to_token(old_id)
+            );
+        }
+
+        // Text still encodes (without the dropped pieces) and decodes
+        let encoding = pruned
+            .tokenizer
+            .encode("abcab", false)
+            .map_err(anyhow::Error::msg)?;
+        assert_eq!(encoding.get_tokens(), ["abc", "a", "b"]);
+        let decoded = pruned
+            .tokenizer
+            .decode(encoding.get_ids(), false)
+            .map_err(anyhow::Error::msg)?;
+        assert_eq!(decoded.replace(' ', ""), "abcab");
+
+        // Kept tokens get the same logits
+        let old_ids = [1u32, 8, 3, 11];
+        let new_ids: Vec<u32> = old_ids
+            .iter()
+            .map(|id| kept.binary_search(id).unwrap() as u32)
+            .collect();
+        let (expected, got) = (logits(&original, &old_ids)?, logits(&pruned, &new_ids)?);
+        for (new_id, &old_id) in kept.iter().enumerate() {
+            assert!((got[new_id] - expected[old_id as usize]).abs() < 1e-4);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_tokens_ranks_corpus_usage() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write_model(dir.path())?;
+        let corpus = dir.path().join("corpus.u32");
+        let ids: Vec<u8> = [11u32, 6, 11, 7, 6, 11, 3]
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        std::fs::write(&corpus, ids)?;
+        let tokenizer =
+            Tokenizer::from_file(dir.path().join("tokenizer.json")).map_err(anyhow::Error::msg)?;
+
+        let mut args = PruneVocabArgs {
+            model: dir.path().to_string_lossy().to_string(),
+            output: String::new(),
+            corpus: Some(corpus.to_string_lossy().to_string()),
+            min_count: 1,
+            keep: None,
+            max_vocab: Some(5),
+            drop_chars: true,
+        };
+        // Special ids, then the most used tokens
+        assert_eq!(
+            select_tokens(&args, &tokenizer, PIECES.len(), &[1, 2])?,
+            [0, 1, 2, 6, 11]
+        );
+        args.max_vocab = None;
+        args.min_count = 2;
+        assert_eq!(
+            select_tokens(&args, &tokenizer, PIECES.len(), &[1, 2])?,
+            [0, 1, 2, 6, 11]
+        );
+        args.drop_chars = false;
+        args.max_vocab = Some(6);
+        assert!(select_tokens(&args, &tokenizer, PIECES.len(), &[1, 2]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_tokenizer_remaps_bpe_merges_and_specials() -> Result<()> {
+        let mut tokenizer = serde_json::json!({
+            "added_tokens": [{ "id": 5, "content": "<eos>" }],
+            "post_processor": {
+                "type": "TemplateProcessing",
+                "special_tokens": { "<eos>": { "id": "<eos>", "ids": [5], "tokens": ["<eos>"] } }
+            },
+            "model": {
+                "type": "BPE",
+                "vocab": { "a": 0, "b": 1, "ab": 2, "c": 3, "abc": 4, "<eos>": 5 },
+                "merges": ["a b", ["ab", "c"]]
+            }
+        });
+        prune_tokenizer(&mut tokenizer, &[0, 1, 3, 4, 5])?;
+        assert_eq!(
+            tokenizer["model"]["vocab"],
+            serde_json::json!({ "a": 0, "b": 1, "c": 2, "abc": 3, "<eos>": 4 })
+        );
+        // Both merges need "ab"
+        assert_eq!(tokenizer["model"]["merges"], serde_json::json!([]));
+        assert_eq!(tokenizer["added_tokens"][0]["id"], 4);
+        assert_eq!(
+            tokenizer["post_processor"]["special_tokens"]["<eos>"]["ids"],
+            serde_json::json!([4])
+        );
+        assert!(prune_tokenizer(&mut tokenizer, &[0, 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_vocab_keeps_bpe_merge_parts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+        std::fs::create_dir_all(&src)?;
+        let vocab: Vocab = [
+            "<unk>", "<s>", "</s>", "a", "b", "c", "ab", "abc", "x", "y", "xy", "d",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.to_string(), i as u32))
+        .collect();
+        let merges = [("a", "b"), ("ab", "c"), ("x", "y")]
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect();
+        let bpe = BPE::builder()
+            .vocab_and_merges(vocab.clone(), merges)
+            .unk_token("<unk>".into())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        write_model_with(&src, Tokenizer::new(bpe), vocab.len())?;
+        let keep_path = dir.path().join("keep.txt");
+        std::fs::write(&keep_path, "abc\n")?;
+
+        run(PruneVocabArgs {
+            model: src.to_string_lossy().to_string(),
+            output: out.to_string_lossy().to_string(),
+            corpus: None,
+            min_count: 1,
+            keep: Some(keep_path.to_string_lossy().to_string()),
+            max_vocab: None,
+            drop_chars: true,
+        })?;
+
+        // "abc" brings "ab", "c" and in turn "a" and "b"
+        let map: Value =
+            serde_json::from_str(&std::fs::read_to_string(out.join("vocab_map.json"))?)?;
+        assert_eq!(map["kept"], serde_json::json!([0, 1, 2, 3, 4, 5, 6, 7]));
+        let pruned = Llama::load_auto(&out)?;
+        for (text, tokens) in [("abc", vec!["abc"]), ("abcab", vec!["abc", "ab"])] {
+            let encoding = pruned
+                .tokenizer
+                .encode(text, false)
+                .map_err(anyhow::Error::msg)?;
+            assert_eq!(encoding.get_tokens(), tokens);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_remap_special_ids_rejects_dropped() -> Result<()> {
+        let mut config = serde_json::json!({ "bos_token_id": 5, "eos_token_id": [7, 9] });
+        remap_special_ids(&mut config, &[0, 5, 7, 9])?;
+        assert_eq!(
+            config,
+            serde_json::json!({ "bos_token_id": 1, "eos_token_id": [2, 3] })
+        );
+        let err = remap_special_ids(&mut config, &[0, 1, 2]).unwrap_err();
+        assert!(
+            err.to_string().contains("eos_token_id 3 was dropped"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 //! Training Arguments - CLI configuration for training
 
+use crate::cli::PoolArgs;
 use clap::Args;
 
 /// Training configuration from command line arguments
@@ -52,4 +53,31 @@ pub struct TrainArgs {
 
     #[arg(long, action)]
     pub mock: bool,
+
+    /// MoE experts per block (unset = dense SwiGLU)
+    #[arg(long)]
+    pub experts: Option<usize>,
+
+    /// Experts routed per token
+    #[arg(long, default_value_t = cortex_rust::model::config::default_experts_per_tok())]
+    pub experts_per_tok: usize,
+
+    /// Weight of the MoE load-balancing loss
+    #[arg(long, default_value_t = cortex_rust::model::config::default_router_aux_loss_coef())]
+    pub router_aux_loss_coef: f64,
+
+    /// LoRA rank (0 = perturb every model variable)
+    #[arg(long, default_value_t = 0)]
+    pub lora_rank: usize,
+
+    #[arg(long, default_value_t = 16.0)]
+    pub lora_alpha: f64,
+
+    /// Comma separated: q,k,v,o,gate,up,down,ttt (or "all")
+    #[arg(long, default_value = "q,v,ttt")]
+    pub lora_targets: String,
+
+    // Training runs on rayon's global pool: --threads/--pin-cores only
+    #[command(flatten)]
+    pub runtime: PoolArgs,
 }
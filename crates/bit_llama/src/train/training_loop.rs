@@ -11,7 +11,7 @@ use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use cortex_rust::BitLlama;
+use cortex_rust::{BitLlama, LoraConfig, LoraTarget};
 use tokenizers::Tokenizer;
 use tracing::{error, info, warn};
 
@@ -29,6 +29,22 @@ fn save_securely(varmap: &VarMap, path: &str) -> Result<()> {
     Ok(())
 }
 
+/// Save adapter-only weights plus the `LoraConfig` sidecar
+fn save_adapter_securely(varmap: &VarMap, cfg: &LoraConfig, path: &str) -> Result<()> {
+    save_securely(varmap, path)?;
+    cortex_rust::model::lora::write_lora_config(Path::new(path), cfg)?;
+    Ok(())
+}
+
+/// Adapter file next to a checkpoint path (`x.safetensors` -> `x.lora.safetensors`),
+/// so LoRA runs never overwrite the base weights
+fn adapter_path(path: &str) -> String {
+    match path.strip_suffix(".safetensors") {
+        Some(stem) => format!("{}.lora.safetensors", stem),
+        None => format!("{}.lora", path),
+    }
+}
+
 /// `MeZO`: Perturb weights using a deterministic seed.
 /// vars: List of model variables
 /// seed: Random seed (u64)
@@ -99,11 +115,68 @@ fn perturb_weights(vars: &[Var], seed: u64, scale: f64) -> Result<()> {
     Ok(())
 }
 
+/// `MeZO` loss of one perturbed forward pass: masked cross entropy plus
+/// `router_aux_loss_coef` times the MoE load-balancing loss (MoE blocks only).
+/// Starts from fresh TTT states.
+fn mezo_loss(
+    model: &BitLlama,
+    args: &TrainArgs,
+    inputs: &Tensor,
+    targets: &Tensor,
+    mask: Option<&Tensor>,
+) -> Result<f32> {
+    let d_small = args.dim / 4;
+    let mut w_states = Vec::new();
+    for _ in 0..args.layers {
+        w_states.push(Tensor::zeros(
+            (args.batch_size, d_small, d_small),
+            DType::F32,
+            inputs.device(),
+        )?);
+    }
+    let chunk_size = 32;
+    let (logits, aux) = model.forward_chunkwise_with_aux(inputs, &mut w_states, chunk_size)?;
+    let logits_flat =
+        logits.reshape((args.batch_size * args.context_len, model.config.vocab_size))?;
+    let targets_flat = targets.reshape(args.batch_size * args.context_len)?;
+
+    // Manual cross_entropy to ensure element-wise loss (for masking)
+    let log_sm = ops::log_softmax(&logits_flat, candle_core::D::Minus1)?;
+    let loss_vec = log_sm
+        .gather(&targets_flat.unsqueeze(1)?, candle_core::D::Minus1)?
+        .squeeze(candle_core::D::Minus1)?
+        .neg()?;
+
+    let loss = if let Some(m) = mask {
+        let m_flat = m.reshape(loss_vec.shape())?;
+        let masked_loss = (loss_vec * m_flat.clone())?;
+        let sum_loss = masked_loss.sum_all()?.to_scalar::<f32>()?;
+        let sum_mask = m_flat.sum_all()?.to_scalar::<f32>()?;
+        if sum_mask == 0.0 {
+            0.0
+        } else {
+            sum_loss / sum_mask
+        }
+    } else {
+        loss_vec.mean_all()?.to_scalar::<f32>()?
+    };
+
+    // MoE load-balancing term
+    Ok(match aux {
+        Some(aux) => {
+            let aux = aux.to_dtype(DType::F32)?.to_scalar::<f32>()?;
+            loss + (model.config.router_aux_loss_coef as f32) * aux
+        }
+        None => loss,
+    })
+}
+
 /// Main training function
 pub fn run(args: TrainArgs) -> Result<()> {
     // ============================================================
     // Section 1: Initialization
     // ============================================================
+    args.runtime.config()?.init_global()?;
     info!("--- Bit-Llama Training (MeZO - Memory Efficient) ---");
     info!(
         "Config: Dim={}, Layers={}, Context={}, Batch={}",
@@ -212,7 +285,7 @@ pub fn run(args: TrainArgs) -> Result<()> {
 
     let mut varmap = VarMap::new();
     let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
-    let model = BitLlama::load(config, vb)?;
+    let mut model = BitLlama::load(config, vb)?;
 
     let base_dir = if Path::new("bit_llama_checkpoint.safetensors").exists() {
         "".to_string()
@@ -258,7 +331,68 @@ pub fn run(args: TrainArgs) -> Result<()> {
         varmap.data().lock().expect("Failed to lock VarMap").len()
     );
 
-    let optim_vars = varmap.all_vars();
+    // LoRA: freeze the base model and only perturb/save adapter variables
+    let mut lora_varmap = VarMap::new();
+    let lora_cfg = if args.lora_rank > 0 {
+        let cfg = LoraConfig {
+            rank: args.lora_rank,
+            alpha: args.lora_alpha,
+            targets: LoraTarget::parse_list(&args.lora_targets)?,
+        };
+        let lora_vb = VarBuilder::from_varmap(&lora_varmap, DType::F32, &device);
+        let n = model.attach_lora(&cfg, lora_vb)?;
+        info!(
+            "🧩 LoRA enabled: rank={} alpha={} targets={:?} ({} modules)",
+            cfg.rank, cfg.alpha, cfg.targets, n
+        );
+
+        // Resume the adapters saved at shutdown (the base came from the checkpoint above)
+        let resume = adapter_path(&format!("{}bit_llama_checkpoint.safetensors", base_dir));
+        if Path::new(&resume).exists() {
+            let sidecar = cortex_rust::model::lora::lora_config_path(Path::new(&resume));
+            let saved: Option<LoraConfig> = std::fs::read_to_string(&sidecar)
+                .ok()
+                .and_then(|s| serde_json::from_str(&s).ok());
+            let matches =
+                saved.is_some_and(|saved| saved.rank == cfg.rank && saved.targets == cfg.targets);
+            if !matches {
+                warn!(
+                    "⚠️ Adapters in '{}' don't match rank={} targets={:?}. Starting from fresh adapters.",
+                    resume, cfg.rank, cfg.targets
+                );
+            } else if let Err(e) = lora_varmap.load(&resume) {
+                warn!("⚠️ Failed to load adapters '{}': {}", resume, e);
+                warn!("⚠️ Starting from fresh adapters instead.");
+            } else {
+                info!("✅ LoRA adapters resumed from {}", resume);
+            }
+        }
+        Some(cfg)
+    } else {
+        None
+    };
+
+    let optim_vars = if lora_cfg.is_some() {
+        lora_varmap.all_vars()
+    } else {
+        varmap.all_vars()
+    };
+    info!("Trainable variables: {}", optim_vars.len());
+
+    // Returns the file written (the adapter file in LoRA mode)
+    let save_checkpoint = |path: &str| -> Result<String> {
+        match &lora_cfg {
+            Some(cfg) => {
+                let path = adapter_path(path);
+                save_adapter_securely(&lora_varmap, cfg, &path)?;
+                Ok(path)
+            }
+            None => {
+                save_securely(&varmap, path)?;
+                Ok(path.to_string())
+            }
+        }
+    };
 
     // RNG for MeZO noise (Step Seed)
     let mut step_rng = StdRng::from_entropy();
@@ -351,10 +485,7 @@ pub fn run(args: TrainArgs) -> Result<()> {
         if Path::new("stop_signal").exists() {
             info!("\n🛑 Stop signal detected (Start of Loop)! Saving and exiting...");
             let _ = std::fs::remove_file("stop_signal");
-            save_securely(
-                &varmap,
-                &format!("{}bit_llama_checkpoint.safetensors", base_dir),
-            )?;
+            save_checkpoint(&format!("{}bit_llama_checkpoint.safetensors", base_dir))?;
             let state = serde_json::json!({ "step": step });
             if let Ok(file) = File::create(&state_path) {
                 serde_json::to_writer(file, &state)?;
@@ -388,86 +519,14 @@ pub fn run(args: TrainArgs) -> Result<()> {
         perturb_weights(&optim_vars, seed, epsilon)?;
 
         // Forward (+ loop)
-        let loss_pos = {
-            let d_small = args.dim / 4;
-            let mut w_states = Vec::new(); // Reset states
-            for _ in 0..args.layers {
-                w_states.push(Tensor::zeros(
-                    (args.batch_size, d_small, d_small),
-                    DType::F32,
-                    &device,
-                )?);
-            }
-            let chunk_size = 32;
-            let logits = model.forward_chunkwise(&inputs, &mut w_states, chunk_size)?;
-            let logits_flat =
-                logits.reshape((args.batch_size * args.context_len, config.vocab_size))?;
-            let targets_flat = targets.reshape(args.batch_size * args.context_len)?;
-
-            // Manual cross_entropy to ensure element-wise loss (for masking)
-            let log_sm = ops::log_softmax(&logits_flat, candle_core::D::Minus1)?;
-            let loss_vec = log_sm
-                .gather(&targets_flat.unsqueeze(1)?, candle_core::D::Minus1)?
-                .squeeze(candle_core::D::Minus1)?
-                .neg()?;
-
-            if let Some(ref m) = mask_tensor {
-                let m_flat = m.reshape(loss_vec.shape())?;
-                let masked_loss = (loss_vec * m_flat.clone())?;
-                let sum_loss = masked_loss.sum_all()?.to_scalar::<f32>()?;
-                let sum_mask = m_flat.sum_all()?.to_scalar::<f32>()?;
-                if sum_mask == 0.0 {
-                    0.0
-                } else {
-                    sum_loss / sum_mask
-                }
-            } else {
-                loss_vec.mean_all()?.to_scalar::<f32>()?
-            }
-        };
+        let loss_pos = mezo_loss(&model, &args, &inputs, &targets, mask_tensor.as_ref())?;
 
         // 2. Perturb (-)
         // theta = (theta + epsilon * Z) - 2 * epsilon * Z = theta - epsilon * Z
         perturb_weights(&optim_vars, seed, -2.0 * epsilon)?;
 
-        // Forward (- loop)
-        let loss_neg = {
-            let d_small = args.dim / 4;
-            let mut w_states = Vec::new(); // Reset states (Independent forward)
-            for _ in 0..args.layers {
-                w_states.push(Tensor::zeros(
-                    (args.batch_size, d_small, d_small),
-                    DType::F32,
-                    &device,
-                )?);
-            }
-            let chunk_size = 32;
-            let logits = model.forward_chunkwise(&inputs, &mut w_states, chunk_size)?;
-            let logits_flat =
-                logits.reshape((args.batch_size * args.context_len, config.vocab_size))?;
-            let targets_flat = targets.reshape(args.batch_size * args.context_len)?;
-
-            // Manual cross_entropy to ensure element-wise loss (for masking)
-            let log_sm = ops::log_softmax(&logits_flat, candle_core::D::Minus1)?;
-            let loss_vec = log_sm
-                .gather(&targets_flat.unsqueeze(1)?, candle_core::D::Minus1)?
-                .squeeze(candle_core::D::Minus1)?
-                .neg()?;
-
-            if let Some(ref m) = mask_tensor {
-                let m_flat = m.reshape(loss_vec.shape())?;
-                let masked_loss = (loss_vec * m_flat.clone())?;
-                let sum_loss = masked_loss.sum_all()?.to_scalar::<f32>()?;
-                let sum_mask = m_flat.sum_all()?.to_scalar::<f32>()?;
-                if sum_mask == 0.0 {
-                    0.0
-                } else {
-                    sum_loss / sum_mask
-                }
-            } else {
-                loss_vec.mean_all()?.to_scalar::<f32>()?
-            }
-        };
+        // Forward (- loop, independent states)
+        let loss_neg = mezo_loss(&model, &args, &inputs, &targets, mask_tensor.as_ref())?;
 
         // 3. Restore
         // theta = (theta - epsilon * Z) + epsilon * Z = theta
@@ -514,10 +573,7 @@ pub fn run(args: TrainArgs) -> Result<()> {
             if step > 0 && loss_pos < best_loss {
                 best_loss = loss_pos;
                 info!("🌟 New Best Loss: {:.4}", best_loss);
-                save_securely(
-                    &varmap,
-                    &format!("{}model-best.safetensors", effective_output_dir),
-                )?;
+                save_checkpoint(&format!("{}model-best.safetensors", effective_output_dir))?;
             }
         }
 
@@ -529,12 +585,9 @@ pub fn run(args: TrainArgs) -> Result<()> {
             let filename_no_ext = format!("{}checkpoint_step_{}", effective_output_dir, step);
             let safetensors_path = format!("{}.safetensors", filename_no_ext);
 
-            save_securely(&varmap, &safetensors_path)?;
+            let written = save_checkpoint(&safetensors_path)?;
             // Also save as "latest"
-            save_securely(
-                &varmap,
-                &format!("{}model-latest.safetensors", effective_output_dir),
-            )?;
+            save_checkpoint(&format!("{}model-latest.safetensors", effective_output_dir))?;
 
             save_training_state(
                 &effective_output_dir,
@@ -544,21 +597,22 @@ pub fn run(args: TrainArgs) -> Result<()> {
             )?;
 
             // Rotate
-            checkpoint_history.push(safetensors_path);
+            checkpoint_history.push(written);
             if checkpoint_history.len() > 3 {
                 let old = checkpoint_history.remove(0);
                 if Path::new(&old).exists() {
                     let _ = std::fs::remove_file(&old);
                 }
+                if lora_cfg.is_some() {
+                    let sidecar = cortex_rust::model::lora::lora_config_path(Path::new(&old));
+                    let _ = std::fs::remove_file(sidecar);
+                }
             }
         }
 
         if !running.load(Ordering::SeqCst) {
             info!("[Shutdown] Saving checkpoint at step {}...", step);
-            save_securely(
-                &varmap,
-                &format!("{}bit_llama_checkpoint.safetensors", base_dir),
-            )?;
+            save_checkpoint(&format!("{}bit_llama_checkpoint.safetensors", base_dir))?;
             let state = serde_json::json!({ "step": step });
             if let Ok(file) = File::create(&state_path) {
                 serde_json::to_writer(file, &state)?;
@@ -572,10 +626,67 @@ pub fn run(args: TrainArgs) -> Result<()> {
     // Final save logic...
     if let Some(ref output_dir) = args.output_dir {
         let model_path = format!("{}/model.safetensors", output_dir);
-        save_securely(&varmap, &model_path)?;
+        save_checkpoint(&model_path)?;
     } else {
-        save_securely(&varmap, &format!("{}bit_llama_v1.safetensors", base_dir))?;
+        save_checkpoint(&format!("{}bit_llama_v1.safetensors", base_dir))?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Commands};
+    use clap::Parser;
+
+    fn train_args(extra: &[&str]) -> TrainArgs {
+        let mut argv = vec![
+            "bit_llama",
+            "train",
+            "--data",
+            "d",
+            "--dim",
+            "32",
+            "--layers",
+            "1",
+            "--context-len",
+            "8",
+            "--batch-size",
+            "2",
+        ];
+        argv.extend_from_slice(extra);
+        match Cli::try_parse_from(argv).map(|cli| cli.command) {
+            Ok(Some(Commands::Train(args))) => args,
+            _ => panic!("train args should parse"),
+        }
+    }
+
+    /// `mezo_loss` difference between `router_aux_loss_coef` = 1 and 0
+    fn aux_term(args: &TrainArgs) -> Result<f32> {
+        let mut project_config = crate::config::ProjectConfig::from_args(args);
+        project_config.vocab_size = 16;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let mut model = BitLlama::load(project_config.to_bit_llama_config(0.1), vb)?;
+
+        let ids: Vec<u32> = (0..16).map(|i| (i * 5 % 16) as u32).collect();
+        let inputs = Tensor::from_vec(ids.clone(), (2, 8), &Device::Cpu)?;
+        let targets = Tensor::from_vec(ids, (2, 8), &Device::Cpu)?;
+
+        let with_aux = mezo_loss(&model, args, &inputs, &targets, None)?;
+        model.config.router_aux_loss_coef = 0.0;
+        let without = mezo_loss(&model, args, &inputs, &targets, None)?;
+        Ok(with_aux - without)
+    }
+
+    #[test]
+    fn test_mezo_loss_applies_router_aux_loss() -> Result<()> {
+        let moe = train_args(&["--experts", "4", "--router-aux-loss-coef", "1.0"]);
+        assert!(aux_term(&moe)? > 0.0, "MoE aux loss missing from MeZO loss");
+
+        let dense = train_args(&["--router-aux-loss-coef", "1.0"]);
+        assert_eq!(aux_term(&dense)?, 0.0);
+        Ok(())
+    }
+}
@@ -1,16 +1,74 @@
 from typing import List, Optional
 
+class KvCacheDtype:
+    F32: "KvCacheDtype"
+    F16: "KvCacheDtype"
+    Q8: "KvCacheDtype"
+    Q4: "KvCacheDtype"
+    Fp8: "KvCacheDtype"
+    Kivi: "KvCacheDtype"
+
+class ActivationQuant:
+    F32: "ActivationQuant"
+    Int8: "ActivationQuant"
+    Lut: "ActivationQuant"
+    Auto: "ActivationQuant"
+
+class TuneMode:
+    Cached: "TuneMode"
+    Retune: "TuneMode"
+    Off: "TuneMode"
+
+class FloatDtype:
+    F32: "FloatDtype"
+    F16: "FloatDtype"
+    BF16: "FloatDtype"
+
+class WeightScale:
+    Tensor: "WeightScale"
+    Channel: "WeightScale"
+    Group: "WeightScale"
+
+class TableQuant:
+    Dense: "TableQuant"
+    Int8: "TableQuant"
+    Ternary: "TableQuant"
+
 class BitLlamaConfig:
     vocab_size: int
     hidden_dim: int
     num_layers: int
     inner_lr: float
     n_gpu_layers: Optional[int]
+    n_experts: Optional[int]
+    n_experts_per_tok: int
+    router_aux_loss_coef: float
+    quantized_kv_attention: bool
+    kv_cache_dtype: KvCacheDtype
+    activation_quant: ActivationQuant
+    autotune: Optional[TuneMode]
+    weight_scale: WeightScale
+    weight_scale_group: int
+    storage_dtype: FloatDtype
+    compute_dtype: FloatDtype
+    lm_head_dtype: Optional[FloatDtype]
+    fused_projections: bool
+    embedding_quant: TableQuant
+    lm_head_quant: TableQuant
 
     def __init__(self, vocab_size: int, hidden_dim: int, num_layers: int, inner_lr: float) -> None: ...
 
 class BitLlama:
-    def __init__(self, config: BitLlamaConfig, checkpoint_path: str, device: Optional[str] = None) -> None: ...
+    def __init__(
+        self,
+        config: Optional[BitLlamaConfig],
+        checkpoint_path: str,
+        device: Optional[str] = None,
+        threads: Optional[int] = None,
+        prefill_threads: Optional[int] = None,
+        decode_threads: Optional[int] = None,
+        pin_cores: Optional[List[int]] = None,
+    ) -> None: ...
     def forward(self, token_id: int) -> List[float]: ...
     def generate(self, prompt: str, max_tokens: int) -> str: ...
     def generate_tokens(self, start_tokens: List[int], max_new_tokens: int) -> List[int]: ...
@@ -1,8 +1,8 @@
-//! Benchmark for BitLinearCpu
-//! Measures throughput in GB/s and GOps/s
+//! Benchmark for BitLinearCpu (per-output / tiled f32, int8 activations, LUT)
+//! Measures GFLOP-equivalents and GB/s for M = 1, 16 and 512
 
 use candle_core::{Device, Tensor};
-use cortex_rust::kernels::cpu::BitLinearCpu;
+use cortex_rust::kernels::cpu::{BitLinearCpu, CpuSimd};
 use cortex_rust::kernels::packing::PackedTensor;
 use std::time::Instant;
 
@@ -11,27 +11,18 @@ fn main() -> anyhow::Result<()> {
 
     // Config: Simulate a typical layer (e.g. Llama-70B dimension)
     // Hidden Dim = 8192 (for 70B)
-    // Batch Size = 1 (Inference)
-    let m = 1;
+    // M = 1 (decode), 16 (batched decode), 512 (prefill)
     let k = 8192;
     let n = 8192;
-
-    // Warmup & Stability
-    let iterations = 100;
+    let batch_sizes = [1, 16, 512];
 
     // Device: CPU
     let device = Device::Cpu;
 
     println!("Configuration:");
-    println!("  M (Batch): {}", m);
+    println!("  M (Batch): {:?}", batch_sizes);
     println!("  K (Hidden): {}", k);
     println!("  N (Output): {}", n);
-    println!("  Iterations: {}", iterations);
-
-    // 1. Prepare Data
-    println!("Preparing data (random float generation)...");
-    let x_data = vec![0.5f32; m * k];
-    let x = Tensor::from_vec(x_data, (m, k), &device)?;
 
     // Weights: Random {-1, 0, 1} pattern
     // We create a dummy PackedTensor directly to save setup time
@@ -48,53 +39,85 @@ fn main() -> anyhow::Result<()> {
 
     let w_shape = candle_core::Shape::from((n, k));
     let packed_weights = PackedTensor::new(w_data, w_shape, 1.0, &device)?;
+    let int8_weights = packed_weights.clone().with_int8_layout()?;
+    let lut_weights = packed_weights.clone().with_lut_layout()?;
+    let simd = CpuSimd::detect();
+    println!("  SIMD: {:?}", simd);
+
+    let mut results = Vec::new();
+    for m in batch_sizes {
+        // Warmup & Stability: keep each case to a few seconds
+        let iterations = (100 / m).max(2);
+        let x_data: Vec<f32> = (0..m * k)
+            .map(|i| ((i % 97) as f32 - 48.0) / 48.0)
+            .collect();
+        let x = Tensor::from_vec(x_data, (m, k), &device)?;
+
+        println!("\n--- M = {} ({} iterations) ---", m, iterations);
+        // Per-output loop (re-streams W per row; too slow to run at prefill sizes)
+        if m <= 16 {
+            let sec = bench("f32 per-output", iterations, || {
+                BitLinearCpu::forward_with_simd(&x, &packed_weights, simd)
+            })?;
+            results.push((m, "f32 per-output", sec));
+        }
+        if m > 1 {
+            let sec = bench("f32 tiled", iterations, || {
+                BitLinearCpu::forward_tiled(&x, &packed_weights)
+            })?;
+            results.push((m, "f32 tiled", sec));
+        }
+        let sec = bench("int8", iterations, || {
+            BitLinearCpu::forward_int8(&x, &int8_weights)
+        })?;
+        results.push((m, "int8", sec));
+        let sec = bench("lut", iterations, || {
+            BitLinearCpu::forward_lut(&x, &lut_weights)
+        })?;
+        results.push((m, "lut", sec));
+    }
+
+    // Report
+    // GFLOP-equivalents: 2 * M * N * K (one add/sub per weight counted as a MAC)
+    // Memory Bandwidth: X (M*K*4 bytes) + W (N*K/4 bytes) + Y (M*N*4 bytes)
+    println!("\n=== Results ===");
+    println!(
+        "{:>5} {:>16} {:>12} {:>10} {:>9}",
+        "M", "kernel", "ms / call", "GFLOP/s", "GB/s"
+    );
+    for (m, name, avg_sec) in results {
+        let flops = 2.0 * (m as f64) * (n as f64) * (k as f64);
+        let bytes = (m * k * 4 + packed_len + m * n * 4) as f64;
+        println!(
+            "{:>5} {:>16} {:>12.3} {:>10.2} {:>9.2}",
+            m,
+            name,
+            avg_sec * 1000.0,
+            flops / avg_sec / 1e9,
+            bytes / avg_sec / 1e9
+        );
+    }
+
+    Ok(())
+}
 
-    // 2. Warmup
-    println!("Warming up...");
-    for _ in 0..10 {
-        let _ = BitLinearCpu::forward(&x, &packed_weights)?;
+/// Warm up, then return the average seconds per call
+fn bench(
+    name: &str,
+    iterations: usize,
+    f: impl Fn() -> candle_core::Result<Tensor>,
+) -> anyhow::Result<f64> {
+    println!("Warming up ({})...", name);
+    for _ in 0..iterations.min(10) {
+        let _ = f()?;
     }
 
-    // 3. Benchmark
-    println!("Running benchmark...");
+    println!("Running benchmark ({})...", name);
     let start = Instant::now();
     for _ in 0..iterations {
-        // Use black_box to prevent compiler optimization (not strictly needed since result is used, but good practice if available)
-        // Here we just accumulate result? No, just run.
-        let out = BitLinearCpu::forward(&x, &packed_weights)?;
-        // Force evaluation? Candle is lazy? Tensor ops are eager usually, except if graph.
-        // BitLinearCpu::forward returns a Tensor. Data is computed eagerly in our CPU implementation.
-        // But to be sure we touch memory?
+        // BitLinearCpu computes eagerly; touch the output anyway
+        let out = f()?;
         let _vec = out.flatten_all()?.to_vec1::<f32>()?;
     }
-    let duration = start.elapsed();
-
-    // 4. Report
-    let total_secs = duration.as_secs_f64();
-    let avg_sec = total_secs / iterations as f64;
-    let avg_ms = avg_sec * 1000.0;
-
-    // Ops: M * N * K * 2 (Add/Sub?)
-    // Technically BitNet is Add/Sub, so 1 Op per weight access? Or 2 (Unpack + Add)?
-    // Let's count "Effective MACs" = 2 * M * N * K
-    let macs = (m as f64) * (n as f64) * (k as f64);
-    let flops = macs / avg_sec; // "FLOPS" equivalent (actually IOPS)
-    let gflops = flops / 1e9;
-
-    // Memory Bandwidth:
-    // Reads: X (M*K*4 bytes) + W (N*K/4 bytes)
-    // Writes: Y (M*N*4 bytes)
-    let bytes_read_x = (m * k * 4) as f64;
-    let bytes_read_w = (packed_len) as f64; // 1 byte per 4 weights
-    let bytes_write_y = (m * n * 4) as f64;
-    let total_bytes = bytes_read_x + bytes_read_w + bytes_write_y;
-    let gb_per_sec = (total_bytes / avg_sec) / 1e9;
-
-    println!("\n=== Results ===");
-    println!("Total Time: {:.4} s", total_secs);
-    println!("Avg Latency: {:.4} ms / kernel", avg_ms);
-    println!("Throughput: {:.2} GOps/s (Effective)", gflops);
-    println!("Bandwidth:  {:.2} GB/s", gb_per_sec);
-
-    Ok(())
+    Ok(start.elapsed().as_secs_f64() / iterations as f64)
 }
@@ -0,0 +1,119 @@
+//! Benchmark for QuantizedKVCache decode cost
+//! Compares the previous cat-per-token cache against the preallocated ring buffer
+//! (append + attention) at increasing context lengths, with f32 views and with
+//! attention fused over the quantized cache.
+
+use candle_core::{DType, Device, Tensor};
+use cortex_rust::kernels::attention_cpu::FlashAttentionCpu;
+use cortex_rust::layers::QuantizedKVCache;
+use std::time::Instant;
+
+/// Phase 5.2 behaviour: `Tensor::cat` the whole cache, then dequantize all of it
+struct CatKVCache {
+    k: Option<(Tensor, Tensor)>,
+    v: Option<(Tensor, Tensor)>,
+}
+
+impl CatKVCache {
+    fn append(&mut self, k: &Tensor, v: &Tensor) -> candle_core::Result<(Tensor, Tensor)> {
+        fn step(slot: &mut Option<(Tensor, Tensor)>, x: &Tensor) -> candle_core::Result<Tensor> {
+            let scale = (x.abs()?.max_keepdim(3)? / 127.0)?;
+            let q = (x.broadcast_div(&scale)?.round()? + 128.0)?.to_dtype(DType::U8)?;
+            let (q, scale) = match slot.take() {
+                Some((cq, cs)) => (Tensor::cat(&[&cq, &q], 2)?, Tensor::cat(&[&cs, &scale], 2)?),
+                None => (q, scale),
+            };
+            let out = (q.to_dtype(DType::F32)? - 128.0)?.broadcast_mul(&scale)?;
+            *slot = Some((q, scale));
+            Ok(out)
+        }
+        Ok((step(&mut self.k, k)?, step(&mut self.v, v)?))
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    println!("=== KV Cache Decode Benchmark ===");
+
+    // TinyLlama-like attention layer
+    let n_heads = 32;
+    let n_kv_heads = 4;
+    let head_dim = 64;
+    let context_lengths = [256usize, 1024, 4096];
+    let measured_tokens = 64;
+    let device = Device::Cpu;
+
+    println!("Configuration:");
+    println!("  Heads: {} (KV Heads: {})", n_heads, n_kv_heads);
+    println!("  Head Dim: {}", head_dim);
+    println!("  Measured decode steps: {}", measured_tokens);
+
+    let q = Tensor::ones((1, n_heads, 1, head_dim), DType::F32, &device)?;
+    let token = |i: usize| {
+        let data: Vec<f32> = (0..n_kv_heads * head_dim)
+            .map(|j| ((i * 31 + j) as f32 * 0.01).sin())
+            .collect();
+        Tensor::from_vec(data, (1, n_kv_heads, 1, head_dim), &device)
+    };
+    let scale = 1.0 / (head_dim as f64).sqrt();
+
+    println!();
+    println!(
+        "{:>8} | {:>14} | {:>14} | {:>14} | {:>8}",
+        "Context", "cat (ms/tok)", "ring (ms/tok)", "q8 (ms/tok)", "Speedup"
+    );
+    println!("{}", "-".repeat(71));
+
+    for &ctx in &context_lengths {
+        let max_len = ctx + measured_tokens;
+
+        // Prefill both caches to `ctx` tokens
+        let mut cat_cache = CatKVCache { k: None, v: None };
+        let mut ring_cache = QuantizedKVCache::new(max_len);
+        let prefill = Tensor::cat(
+            &(0..ctx)
+                .map(token)
+                .collect::<candle_core::Result<Vec<_>>>()?,
+            2,
+        )?;
+        cat_cache.append(&prefill, &prefill)?;
+        ring_cache.append(&prefill, &prefill)?;
+        let mut q8_cache = QuantizedKVCache::new(max_len);
+        q8_cache.append_quantized(&prefill, &prefill)?;
+
+        // Decode: append one token + attention over the whole cache
+        let start = Instant::now();
+        for i in 0..measured_tokens {
+            let t = token(ctx + i)?;
+            let (k, v) = cat_cache.append(&t, &t)?;
+            let _ = FlashAttentionCpu::forward(&q, &k, &v, scale)?;
+        }
+        let cat_ms = start.elapsed().as_secs_f64() * 1000.0 / measured_tokens as f64;
+
+        let start = Instant::now();
+        for i in 0..measured_tokens {
+            let t = token(ctx + i)?;
+            let (k, v) = ring_cache.append(&t, &t)?;
+            let _ = FlashAttentionCpu::forward(&q, &k, &v, scale)?;
+        }
+        let ring_ms = start.elapsed().as_secs_f64() * 1000.0 / measured_tokens as f64;
+
+        let start = Instant::now();
+        for i in 0..measured_tokens {
+            let t = token(ctx + i)?;
+            let kv = q8_cache.append_quantized(&t, &t)?;
+            let _ = FlashAttentionCpu::forward_quantized(&q, &kv, scale)?;
+        }
+        let q8_ms = start.elapsed().as_secs_f64() * 1000.0 / measured_tokens as f64;
+
+        println!(
+            "{:>8} | {:>14.3} | {:>14.3} | {:>14.3} | {:>7.2}x",
+            ctx,
+            cat_ms,
+            ring_ms,
+            q8_ms,
+            cat_ms / ring_ms
+        );
+    }
+
+    Ok(())
+}
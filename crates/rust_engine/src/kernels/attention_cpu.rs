@@ -0,0 +1,642 @@
+//! FlashAttentionCpu - Tiled online-softmax attention for CPU
+//!
+//! Streams K/V in blocks and keeps only a running max / sum / accumulator per
+//! query row, so memory stays O(T * D) instead of O(T * K) for the score
+//! matrix. GQA maps each query head onto its KV head by index (no repeat_kv
+//! copy) and the causal mask is applied by bounding the key range per row.
+//!
+//! `forward_quantized` runs the same loop directly over the stored KV cache
+//! formats, decoding one row at a time. For the symmetric integer formats
+//! Q·K uses `scale_k * (Σ q·k_q - zero·Σ q)` and softmax·V folds the V scale
+//! into the probability, so no f32 copy of K/V is ever made.
+
+use std::ops::Range;
+use std::sync::RwLockReadGuard;
+
+use candle_core::{DType, Device, Layout, Result, Storage, Tensor, WithDType};
+use half::f16;
+
+use crate::layers::kv_cache::{KvView, QuantizedKV};
+use crate::layers::kv_quant::{fp8_table, KIVI_GROUP, Q4_ZERO};
+use rayon::prelude::*;
+
+/// Query rows processed together (share each K/V block while it is in cache)
+const BLOCK_Q: usize = 16;
+/// Keys per streamed K/V block
+const BLOCK_K: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct FlashAttentionCpu;
+
+impl FlashAttentionCpu {
+    /// softmax(Q K^T * scale + causal) V
+    ///
+    /// - q: [B, H, T, D] (f32, CPU)
+    /// - k, v: [B, KV_H, K, D] (f32 or f16) with H % KV_H == 0 and K >= T
+    /// - Query row `i` attends to keys `0..=(K - T) + i` (all keys when T == 1)
+    ///
+    /// Returns [B, H, T, D]
+    pub fn forward(q: &Tensor, k: &Tensor, v: &Tensor, scale: f64) -> Result<Tensor> {
+        let kv = QuantizedKV {
+            k: KvView::Float(k.clone()),
+            v: KvView::Float(v.clone()),
+        };
+        Self::forward_quantized(q, &kv, scale)
+    }
+
+    /// Same as [`FlashAttentionCpu::forward`] over K/V views in any
+    /// `KvCacheDtype` storage format.
+    pub fn forward_quantized(q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+        let dims = check_shapes(q, kv)?;
+        Self::attend_views(q, kv, &dims, scale, None)
+    }
+
+    /// Attention over the first `len` tokens of a sequence stored in a
+    /// `PagedKVCache` pool (`kv` = `pool_view`, batch 1): logical token `j`
+    /// lives in slot `blocks[j / block_size] * block_size + j % block_size`.
+    pub fn forward_paged(
+        q: &Tensor,
+        kv: &QuantizedKV,
+        blocks: &[usize],
+        block_size: usize,
+        len: usize,
+        scale: f64,
+    ) -> Result<Tensor> {
+        let mut dims = check_shapes(q, kv)?;
+        let pool_blocks = dims.k_len / block_size.max(1);
+        if dims.b_sz != 1
+            || len < dims.seq_len
+            || len > blocks.len() * block_size
+            || blocks.iter().any(|&b| b >= pool_blocks)
+        {
+            candle_core::bail!(
+                "FlashAttentionCpu: invalid paged read ({} tokens, {} blocks of {}, pool of {})",
+                len,
+                blocks.len(),
+                block_size,
+                pool_blocks
+            );
+        }
+        dims.k_len = len;
+        let pages = Pages { blocks, block_size };
+        Self::attend_views(q, kv, &dims, scale, Some(pages))
+    }
+
+    fn attend_views(
+        q: &Tensor,
+        kv: &QuantizedKV,
+        dims: &AttnDims,
+        scale: f64,
+        pages: Option<Pages>,
+    ) -> Result<Tensor> {
+        if dims.seq_len == 0 || dims.head_dim == 0 {
+            return Tensor::zeros(q.dims(), q.dtype(), &Device::Cpu);
+        }
+
+        // K/V are usually strided views of a preallocated cache: read them in place
+        let (k_tensors, v_tensors) = (view_tensors(&kv.k)?, view_tensors(&kv.v)?);
+        let k_locked: Vec<_> = k_tensors.iter().map(Tensor::storage_and_layout).collect();
+        let v_locked: Vec<_> = v_tensors.iter().map(Tensor::storage_and_layout).collect();
+        let k_heads = HeadRows::new(&kv.k, &k_locked)?;
+        let v_heads = HeadRows::new(&kv.v, &v_locked)?;
+
+        run(q, dims, scale, |b, kvh| KvPair {
+            k: k_heads.rows(b, kvh),
+            v: v_heads.rows(b, kvh),
+            d: dims.head_dim,
+            pages,
+        })
+    }
+}
+
+struct AttnDims {
+    b_sz: usize,
+    n_heads: usize,
+    n_kv_heads: usize,
+    seq_len: usize,
+    k_len: usize,
+    head_dim: usize,
+}
+
+fn check_shapes(q: &Tensor, kv: &QuantizedKV) -> Result<AttnDims> {
+    let (b_sz, n_heads, seq_len, head_dim) = q.dims4()?;
+    let k_dims = kv.k.dims4()?;
+    let (kb, n_kv_heads, k_len, kd) = k_dims;
+    if kb != b_sz || kd != head_dim || kv.v.dims4()? != k_dims {
+        candle_core::bail!(
+            "FlashAttentionCpu: shape mismatch q {:?} k {:?} v {:?}",
+            q.dims(),
+            k_dims,
+            kv.v.dims4()?
+        );
+    }
+    if n_kv_heads == 0 || n_heads % n_kv_heads != 0 {
+        candle_core::bail!(
+            "FlashAttentionCpu: {} heads not divisible by {} KV heads",
+            n_heads,
+            n_kv_heads
+        );
+    }
+    if k_len < seq_len {
+        candle_core::bail!("FlashAttentionCpu: {} keys for {} queries", k_len, seq_len);
+    }
+    Ok(AttnDims {
+        b_sz,
+        n_heads,
+        n_kv_heads,
+        seq_len,
+        k_len,
+        head_dim,
+    })
+}
+
+/// Parallel driver: (batch, head) x query tiles, `rows(b, kv_head)` selects K/V
+fn run<R: KvRows>(
+    q: &Tensor,
+    dims: &AttnDims,
+    scale: f64,
+    rows: impl Fn(usize, usize) -> R + Sync,
+) -> Result<Tensor> {
+    let AttnDims {
+        b_sz,
+        n_heads,
+        n_kv_heads,
+        seq_len,
+        k_len,
+        head_dim,
+    } = *dims;
+    let n_rep = n_heads / n_kv_heads;
+    let past_len = k_len - seq_len;
+    let scale = scale as f32;
+
+    let q = q.contiguous()?;
+    let (q_storage, q_layout) = q.storage_and_layout();
+    let q_s = cpu_slice(&q_storage, q_layout)?;
+
+    let head_stride = seq_len * head_dim;
+    let mut output = vec![0.0f32; b_sz * n_heads * head_stride];
+
+    // Parallel over (batch, head), then over query tiles
+    output
+        .par_chunks_mut(head_stride)
+        .enumerate()
+        .for_each(|(bh, out_head)| {
+            let b = bh / n_heads;
+            let h = bh % n_heads;
+            let q_head = &q_s[bh * head_stride..(bh + 1) * head_stride];
+            let kv = rows(b, h / n_rep);
+
+            out_head
+                .par_chunks_mut(BLOCK_Q * head_dim)
+                .enumerate()
+                .for_each(|(tile, out_tile)| {
+                    let q0 = tile * BLOCK_Q;
+                    let rows = out_tile.len() / head_dim;
+                    attend_tile(
+                        &q_head[q0 * head_dim..(q0 + rows) * head_dim],
+                        &kv,
+                        out_tile,
+                        TileSpec {
+                            head_dim,
+                            k_len,
+                            // Last visible key (exclusive) for the tile's first row
+                            first_limit: if seq_len == 1 {
+                                k_len
+                            } else {
+                                past_len + q0 + 1
+                            },
+                            scale,
+                        },
+                    );
+                });
+        });
+
+    Tensor::from_vec(output, (b_sz, n_heads, seq_len, head_dim), &Device::Cpu)
+}
+
+/// Key/value rows of one KV head
+trait KvRows: Sync {
+    /// q · k_j (`q_sum` = Σ q, for zero-point correction)
+    fn dot_key(&self, j: usize, q: &[f32], q_sum: f32) -> f32;
+    /// acc += p * v_j; returns the amount to subtract per element later
+    /// (`p * scale * zero_point`, 0 for formats without a zero point)
+    fn add_value(&self, j: usize, p: f32, acc: &mut [f32]) -> f32;
+}
+
+/// Block table of a paged sequence (logical token -> pool slot)
+#[derive(Clone, Copy)]
+struct Pages<'a> {
+    blocks: &'a [usize],
+    block_size: usize,
+}
+
+struct KvPair<'a> {
+    k: Rows<'a>,
+    v: Rows<'a>,
+    d: usize,
+    pages: Option<Pages<'a>>,
+}
+
+impl KvPair<'_> {
+    #[inline]
+    fn slot(&self, j: usize) -> usize {
+        match self.pages {
+            Some(p) => p.blocks[j / p.block_size] * p.block_size + j % p.block_size,
+            None => j,
+        }
+    }
+}
+
+impl KvRows for KvPair<'_> {
+    #[inline]
+    fn dot_key(&self, j: usize, q: &[f32], q_sum: f32) -> f32 {
+        self.k.dot(self.slot(j), q, q_sum, self.d)
+    }
+
+    #[inline]
+    fn add_value(&self, j: usize, p: f32, acc: &mut [f32]) -> f32 {
+        self.v.axpy(self.slot(j), p, acc, self.d)
+    }
+}
+
+/// Zero point of the Q8 cache
+const Q8_ZERO: f32 = 128.0;
+
+/// Rows of K or V for one (batch, KV head), in the cache's storage format
+enum Rows<'a> {
+    F32(&'a [f32]),
+    F16(&'a [f16]),
+    Q8 {
+        data: &'a [u8],
+        scale: &'a [f32],
+    },
+    Q4 {
+        data: &'a [u8],
+        scale: &'a [f32],
+    },
+    Fp8 {
+        data: &'a [u8],
+        scale: &'a [f32],
+    },
+    Q4Channel {
+        data: &'a [u8],
+        scale: &'a [f32],
+        min: &'a [f32],
+        residual: &'a [f32],
+        residual_slots: Range<usize>,
+    },
+}
+
+impl Rows<'_> {
+    /// x · row_j
+    #[inline]
+    fn dot(&self, j: usize, x: &[f32], x_sum: f32, d: usize) -> f32 {
+        match self {
+            Self::F32(k) => dot(x, &k[j * d..(j + 1) * d]),
+            Self::F16(k) => x
+                .iter()
+                .zip(&k[j * d..(j + 1) * d])
+                .map(|(&a, b)| a * b.to_f32())
+                .sum(),
+            Self::Q8 { data, scale } => {
+                let raw: f32 = x
+                    .iter()
+                    .zip(&data[j * d..(j + 1) * d])
+                    .map(|(&a, &k)| a * k as f32)
+                    .sum();
+                (raw - Q8_ZERO * x_sum) * scale[j]
+            }
+            Self::Q4 { data, scale } => {
+                let w = d / 2;
+                let raw: f32 = x
+                    .chunks_exact(2)
+                    .zip(&data[j * w..(j + 1) * w])
+                    .map(|(a, &k)| a[0] * (k & 0xF) as f32 + a[1] * (k >> 4) as f32)
+                    .sum();
+                (raw - Q4_ZERO * x_sum) * scale[j]
+            }
+            Self::Fp8 { data, scale } => {
+                let table = fp8_table();
+                let raw: f32 = x
+                    .iter()
+                    .zip(&data[j * d..(j + 1) * d])
+                    .map(|(&a, &k)| a * table[k as usize])
+                    .sum();
+                raw * scale[j]
+            }
+            Self::Q4Channel {
+                data,
+                scale,
+                min,
+                residual,
+                residual_slots,
+            } => {
+                if residual_slots.contains(&j) {
+                    let r = j % KIVI_GROUP;
+                    return dot(x, &residual[r * d..(r + 1) * d]);
+                }
+                let g = j / KIVI_GROUP;
+                let (s, m) = (&scale[g * d..(g + 1) * d], &min[g * d..(g + 1) * d]);
+                let w = d / 2;
+                let mut acc = 0.0;
+                for (i, &k) in data[j * w..(j + 1) * w].iter().enumerate() {
+                    let (i0, i1) = (2 * i, 2 * i + 1);
+                    acc += x[i0] * ((k & 0xF) as f32 * s[i0] + m[i0]);
+                    acc += x[i1] * ((k >> 4) as f32 * s[i1] + m[i1]);
+                }
+                acc
+            }
+        }
+    }
+
+    /// acc += p * row_j; returns the zero-point term to subtract later
+    #[inline]
+    fn axpy(&self, j: usize, p: f32, acc: &mut [f32], d: usize) -> f32 {
+        match self {
+            Self::F32(v) => {
+                for (a, &x) in acc.iter_mut().zip(&v[j * d..(j + 1) * d]) {
+                    *a += p * x;
+                }
+                0.0
+            }
+            Self::F16(v) => {
+                for (a, x) in acc.iter_mut().zip(&v[j * d..(j + 1) * d]) {
+                    *a += p * x.to_f32();
+                }
+                0.0
+            }
+            Self::Q8 { data, scale } => {
+                let w = p * scale[j];
+                for (a, &x) in acc.iter_mut().zip(&data[j * d..(j + 1) * d]) {
+                    *a += w * x as f32;
+                }
+                w * Q8_ZERO
+            }
+            Self::Q4 { data, scale } => {
+                let w = p * scale[j];
+                let width = d / 2;
+                for (a, &x) in acc
+                    .chunks_exact_mut(2)
+                    .zip(&data[j * width..(j + 1) * width])
+                {
+                    a[0] += w * (x & 0xF) as f32;
+                    a[1] += w * (x >> 4) as f32;
+                }
+                w * Q4_ZERO
+            }
+            Self::Fp8 { data, scale } => {
+                let table = fp8_table();
+                let w = p * scale[j];
+                for (a, &x) in acc.iter_mut().zip(&data[j * d..(j + 1) * d]) {
+                    *a += w * table[x as usize];
+                }
+                0.0
+            }
+            Self::Q4Channel {
+                data,
+                scale,
+                min,
+                residual,
+                residual_slots,
+            } => {
+                if residual_slots.contains(&j) {
+                    let r = j % KIVI_GROUP;
+                    for (a, &x) in acc.iter_mut().zip(&residual[r * d..(r + 1) * d]) {
+                        *a += p * x;
+                    }
+                    return 0.0;
+                }
+                let g = j / KIVI_GROUP;
+                let (s, m) = (&scale[g * d..(g + 1) * d], &min[g * d..(g + 1) * d]);
+                let w = d / 2;
+                for (i, &x) in data[j * w..(j + 1) * w].iter().enumerate() {
+                    let (i0, i1) = (2 * i, 2 * i + 1);
+                    acc[i0] += p * ((x & 0xF) as f32 * s[i0] + m[i0]);
+                    acc[i1] += p * ((x >> 4) as f32 * s[i1] + m[i1]);
+                }
+                0.0
+            }
+        }
+    }
+}
+
+struct TileSpec {
+    head_dim: usize,
+    k_len: usize,
+    first_limit: usize,
+    scale: f32,
+}
+
+/// Online softmax for a tile of query rows against one KV head
+fn attend_tile<R: KvRows>(q: &[f32], kv: &R, out: &mut [f32], spec: TileSpec) {
+    let d = spec.head_dim;
+    let rows = q.len() / d;
+    let mut row_max = vec![f32::NEG_INFINITY; rows];
+    let mut row_sum = vec![0.0f32; rows];
+    // Σ p·scale·zero_point per row, subtracted from every element at the end
+    let mut row_offset = vec![0.0f32; rows];
+    let q_sums: Vec<f32> = q.chunks(d).map(|r| r.iter().sum()).collect();
+    let mut scores = [0.0f32; BLOCK_K];
+
+    // Row r sees keys [0, limit(r)); the tile never needs keys past the last row's limit
+    let limit = |r: usize| (spec.first_limit + r).min(spec.k_len);
+    let tile_limit = limit(rows - 1);
+
+    let mut k0 = 0;
+    while k0 < tile_limit {
+        let k1 = (k0 + BLOCK_K).min(tile_limit);
+        for r in 0..rows {
+            let end = limit(r).min(k1);
+            if end <= k0 {
+                continue;
+            }
+            let q_row = &q[r * d..(r + 1) * d];
+            let n = end - k0;
+
+            let mut block_max = f32::NEG_INFINITY;
+            for (j, s) in scores[..n].iter_mut().enumerate() {
+                *s = kv.dot_key(k0 + j, q_row, q_sums[r]) * spec.scale;
+                block_max = block_max.max(*s);
+            }
+
+            let new_max = row_max[r].max(block_max);
+            let correction = (row_max[r] - new_max).exp();
+            let acc = &mut out[r * d..(r + 1) * d];
+            if correction != 1.0 {
+                acc.iter_mut().for_each(|a| *a *= correction);
+            }
+            let mut sum = row_sum[r] * correction;
+            let mut offset = row_offset[r] * correction;
+            for (j, &s) in scores[..n].iter().enumerate() {
+                let p = (s - new_max).exp();
+                sum += p;
+                offset += kv.add_value(k0 + j, p, acc);
+            }
+            row_max[r] = new_max;
+            row_sum[r] = sum;
+            row_offset[r] = offset;
+        }
+        k0 = k1;
+    }
+
+    for r in 0..rows {
+        let inv = 1.0 / row_sum[r];
+        let offset = row_offset[r];
+        out[r * d..(r + 1) * d]
+            .iter_mut()
+            .for_each(|a| *a = (*a - offset) * inv);
+    }
+}
+
+#[inline]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Borrow the f32 data of a contiguous CPU tensor
+fn cpu_slice<'a>(storage: &'a Storage, layout: &Layout) -> Result<&'a [f32]> {
+    let start = layout.start_offset();
+    Ok(&cpu_storage::<f32>(storage)?[start..start + layout.shape().elem_count()])
+}
+
+fn cpu_storage<T: WithDType>(storage: &Storage) -> Result<&[T]> {
+    match storage {
+        Storage::Cpu(s) => s.as_slice::<T>(),
+        _ => candle_core::bail!("FlashAttentionCpu: tensors must be on CPU"),
+    }
+}
+
+/// Keep `x` as is if every (batch, head) block of [B, H, K, D] is contiguous
+/// (e.g. a `narrow` along K of a capacity buffer), otherwise copy.
+fn head_major(x: &Tensor) -> Result<Tensor> {
+    let (_, _, k_len, d) = x.dims4()?;
+    let stride = x.stride();
+    if stride[3] == 1 && (stride[2] == d || k_len <= 1) {
+        Ok(x.clone())
+    } else {
+        x.contiguous()
+    }
+}
+
+/// Per-(batch, head) contiguous blocks of a [B, H, K, D] tensor
+struct HeadView<'a, T> {
+    data: &'a [T],
+    start: usize,
+    stride_b: usize,
+    stride_h: usize,
+    len: usize,
+}
+
+impl<'a, T> HeadView<'a, T> {
+    fn new(data: &'a [T], layout: &Layout) -> Self {
+        let (stride, dims) = (layout.stride(), layout.dims());
+        Self {
+            data,
+            start: layout.start_offset(),
+            stride_b: stride[0],
+            stride_h: stride[1],
+            len: dims[2] * dims[3],
+        }
+    }
+
+    fn head(&self, b: usize, h: usize) -> &'a [T] {
+        let off = self.start + b * self.stride_b + h * self.stride_h;
+        &self.data[off..off + self.len]
+    }
+}
+
+/// Storage guard + layout of one tensor
+type Locked<'a> = (RwLockReadGuard<'a, Storage>, &'a Layout);
+
+/// Head-major tensors behind a view (data first, then its parameters)
+fn view_tensors(view: &KvView) -> Result<Vec<Tensor>> {
+    let tensors = match view {
+        KvView::Float(x) => vec![x],
+        KvView::Q8 { data, scale } | KvView::Q4 { data, scale } | KvView::Fp8 { data, scale } => {
+            vec![data, scale]
+        }
+        KvView::Q4Channel {
+            data,
+            scale,
+            min,
+            residual,
+            ..
+        } => vec![data, scale, min, residual],
+    };
+    tensors.into_iter().map(head_major).collect()
+}
+
+fn head_view<'a, T: WithDType>(locked: &'a Locked<'_>) -> Result<HeadView<'a, T>> {
+    Ok(HeadView::new(cpu_storage::<T>(&locked.0)?, locked.1))
+}
+
+/// Per-(batch, KV head) `Rows` of one view, borrowing its locked storages
+enum HeadRows<'a> {
+    F32(HeadView<'a, f32>),
+    F16(HeadView<'a, f16>),
+    Q8(HeadView<'a, u8>, HeadView<'a, f32>),
+    Q4(HeadView<'a, u8>, HeadView<'a, f32>),
+    Fp8(HeadView<'a, u8>, HeadView<'a, f32>),
+    Q4Channel {
+        data: HeadView<'a, u8>,
+        scale: HeadView<'a, f32>,
+        min: HeadView<'a, f32>,
+        residual: HeadView<'a, f32>,
+        residual_slots: Range<usize>,
+    },
+}
+
+impl<'a> HeadRows<'a> {
+    /// `locked` holds the storages of `view_tensors(view)`
+    fn new(view: &KvView, locked: &'a [Locked<'_>]) -> Result<Self> {
+        Ok(match view {
+            KvView::Float(x) => match x.dtype() {
+                DType::F32 => Self::F32(head_view(&locked[0])?),
+                DType::F16 => Self::F16(head_view(&locked[0])?),
+                dtype => candle_core::bail!("FlashAttentionCpu: unsupported K/V dtype {:?}", dtype),
+            },
+            KvView::Q8 { .. } => Self::Q8(head_view(&locked[0])?, head_view(&locked[1])?),
+            KvView::Q4 { .. } => Self::Q4(head_view(&locked[0])?, head_view(&locked[1])?),
+            KvView::Fp8 { .. } => Self::Fp8(head_view(&locked[0])?, head_view(&locked[1])?),
+            KvView::Q4Channel { residual_slots, .. } => Self::Q4Channel {
+                data: head_view(&locked[0])?,
+                scale: head_view(&locked[1])?,
+                min: head_view(&locked[2])?,
+                residual: head_view(&locked[3])?,
+                residual_slots: residual_slots.clone(),
+            },
+        })
+    }
+
+    fn rows(&self, b: usize, h: usize) -> Rows<'a> {
+        match self {
+            Self::F32(x) => Rows::F32(x.head(b, h)),
+            Self::F16(x) => Rows::F16(x.head(b, h)),
+            Self::Q8(data, scale) => Rows::Q8 {
+                data: data.head(b, h),
+                scale: scale.head(b, h),
+            },
+            Self::Q4(data, scale) => Rows::Q4 {
+                data: data.head(b, h),
+                scale: scale.head(b, h),
+            },
+            Self::Fp8(data, scale) => Rows::Fp8 {
+                data: data.head(b, h),
+                scale: scale.head(b, h),
+            },
+            Self::Q4Channel {
+                data,
+                scale,
+                min,
+                residual,
+                residual_slots,
+            } => Rows::Q4Channel {
+                data: data.head(b, h),
+                scale: scale.head(b, h),
+                min: min.head(b, h),
+                residual: residual.head(b, h),
+                residual_slots: residual_slots.clone(),
+            },
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Kernel autotuning - the fastest CPU kernel per weight shape
+//!
+//! With `activation_quant = "auto"`, `BitLinear::precompute_packed` asks
+//! `choose` which kernel (f32 FMA, int8 or LUT activations) to pack each
+//! weight for. Every distinct (in, out) shape is micro-benchmarked once on a
+//! single token (decode) and on a `PREFILL_ROWS` batch (prefill, where the
+//! f32 layout runs the blocked GEMM); the kernel with the lowest per-token
+//! time of both wins. Winners are cached in a JSON file keyed by CPU model
+//! and kernel backend, so later loads only read the file.
+//!
+//! `BitLlamaConfig::autotune` (`bit_llama --autotune`) sets how the cache is
+//! used: "cached" (default), "retune" (measure again and overwrite) or "off"
+//! (no measuring, f32 everywhere, for reproducible benchmarks).
+//! `configure` (`bit_llama --autotune-cache`) sets the cache file (default
+//! `~/.cache/bit-ttt/autotune.json`). Unset, they fall back to the
+//! `BIT_TTT_AUTOTUNE` / `BIT_TTT_AUTOTUNE_CACHE` environment variables.
+
+use candle_core::{Device, Result, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::kernels::backend::{self, TernaryKernel};
+use crate::kernels::cpu::ActivationQuant;
+use crate::kernels::packing::PackedTensor;
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+/// Environment variable selecting the `TuneMode`
+pub const MODE_ENV: &str = "BIT_TTT_AUTOTUNE";
+/// Environment variable naming the cache file
+pub const CACHE_ENV: &str = "BIT_TTT_AUTOTUNE_CACHE";
+
+/// Kernels measured per shape
+const CANDIDATES: [ActivationQuant; 3] = [
+    ActivationQuant::F32,
+    ActivationQuant::Int8,
+    ActivationQuant::Lut,
+];
+/// Timed runs per kernel: at least `MIN_RUNS`, then until `MIN_TIME` has passed
+const MIN_RUNS: usize = 3;
+const MAX_RUNS: usize = 50;
+const MIN_TIME: Duration = Duration::from_millis(20);
+/// Rows of the prefill batch measured next to a single decode token
+const PREFILL_ROWS: usize = 32;
+
+/// How `choose` uses the cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum TuneMode {
+    /// Reuse cached winners, measure unknown shapes
+    #[serde(rename = "cached")]
+    #[default]
+    Cached,
+    /// Measure every shape again (once per tuner) and overwrite the cache
+    #[serde(rename = "retune")]
+    Retune,
+    /// Never measure: f32 activations everywhere
+    #[serde(rename = "off")]
+    Off,
+}
+
+impl std::str::FromStr for TuneMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cached" | "on" | "1" => Ok(Self::Cached),
+            "retune" | "force" => Ok(Self::Retune),
+            "off" | "0" => Ok(Self::Off),
+            other => Err(format!(
+                "unknown autotune mode '{}' (expected cached, retune or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// Winners per CPU model, then per "{in}x{out}" shape
+type Table = BTreeMap<String, BTreeMap<String, ActivationQuant>>;
+
+/// Benchmarks kernels per weight shape and remembers the winners
+#[derive(Debug)]
+pub struct Autotuner {
+    /// Cache file (None: in memory only)
+    path: Option<PathBuf>,
+    cpu: String,
+    mode: TuneMode,
+    table: Table,
+    /// Shapes benchmarked by this tuner
+    measured: HashSet<(usize, usize)>,
+}
+
+impl Autotuner {
+    /// Tuner backed by `path`, starting from its cached winners
+    pub fn new(path: Option<PathBuf>, mode: TuneMode) -> Self {
+        let table = path
+            .as_ref()
+            .filter(|p| p.exists())
+            .and_then(|p| {
+                let parsed = std::fs::read_to_string(p)
+                    .map_err(candle_core::Error::wrap)
+                    .and_then(|s| serde_json::from_str(&s).map_err(candle_core::Error::wrap));
+                parsed
+                    .map_err(|e| tracing::warn!("autotune: ignoring cache {:?}: {}", p, e))
+                    .ok()
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            cpu: cpu_model(),
+            mode,
+            table,
+            measured: HashSet::new(),
+        }
+    }
+
+    /// Tuner configured by `BIT_TTT_AUTOTUNE` / `BIT_TTT_AUTOTUNE_CACHE`
+    pub fn from_env() -> Self {
+        Self::new(env_cache_path(), env_mode())
+    }
+
+    /// Cache key of the running machine
+    pub fn cpu(&self) -> &str {
+        &self.cpu
+    }
+
+    pub fn mode(&self) -> TuneMode {
+        self.mode
+    }
+
+    /// Number of shapes benchmarked so far
+    pub fn measurements(&self) -> usize {
+        self.measured.len()
+    }
+
+    /// Cached winner for a weight of `in_dim` -> `out_dim`
+    pub fn cached(&self, in_dim: usize, out_dim: usize) -> Option<ActivationQuant> {
+        let shapes = self.table.get(&self.cpu)?;
+        shapes.get(&shape_key(in_dim, out_dim)).copied()
+    }
+
+    /// Kernel to pack `weights` for (benchmarking its shape if needed)
+    pub fn choose(&mut self, weights: &PackedTensor) -> Result<ActivationQuant> {
+        let (out_dim, in_dim) = weights.shape.dims2()?;
+        let fresh = self.measured.contains(&(in_dim, out_dim));
+        match (self.mode, self.cached(in_dim, out_dim)) {
+            (TuneMode::Off, _) => return Ok(ActivationQuant::F32),
+            (TuneMode::Cached, Some(winner)) => return Ok(winner),
+            (TuneMode::Retune, Some(winner)) if fresh => return Ok(winner),
+            _ => {}
+        }
+
+        let timings = benchmark(weights)?;
+        let Some(&(winner, _)) = timings.iter().min_by_key(|(_, t)| *t) else {
+            candle_core::bail!("autotune: no kernel runs a {}x{} weight", in_dim, out_dim);
+        };
+        tracing::info!(
+            "autotune {}x{}: {:?} ({:?})",
+            in_dim,
+            out_dim,
+            winner,
+            timings
+        );
+        self.table
+            .entry(self.cpu.clone())
+            .or_default()
+            .insert(shape_key(in_dim, out_dim), winner);
+        self.measured.insert((in_dim, out_dim));
+        if let Err(e) = self.save() {
+            tracing::warn!("autotune: can't write cache {:?}: {}", self.path, e);
+        }
+        Ok(winner)
+    }
+
+    /// Write the winners to the cache file (if any)
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(&self.table).map_err(candle_core::Error::wrap)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Per-token time of each kernel that can run `weights` through the CPU
+/// backend: the best decode token plus the best `PREFILL_ROWS` batch divided
+/// by its rows. Kernels whose layout the weights don't allow are skipped
+pub fn benchmark(weights: &PackedTensor) -> Result<Vec<(ActivationQuant, Duration)>> {
+    let (_, in_dim) = weights.shape.dims2()?;
+    let decode = Tensor::randn(0f32, 1.0, (1, in_dim), &Device::Cpu)?;
+    let prefill = Tensor::randn(0f32, 1.0, (PREFILL_ROWS, in_dim), &Device::Cpu)?;
+    let kernel = backend::for_device(&Device::Cpu)?;
+    let plain = PackedTensor {
+        int8_layout: None,
+        lut_layout: None,
+        ..weights.clone()
+    };
+
+    let mut timings = Vec::new();
+    for candidate in CANDIDATES {
+        let packed = match candidate {
+            ActivationQuant::Int8 => plain.clone().with_int8_layout(),
+            ActivationQuant::Lut => plain.clone().with_lut_layout(),
+            _ => Ok(plain.clone()),
+        };
+        let Ok(packed) = packed else {
+            continue;
+        };
+        let per_token = best_time(kernel.as_ref(), &decode, &packed)?
+            + best_time(kernel.as_ref(), &prefill, &packed)? / PREFILL_ROWS as u32;
+        timings.push((candidate, per_token));
+    }
+    Ok(timings)
+}
+
+/// Fastest of at least `MIN_RUNS` timed x W^T (then until `MIN_TIME` has passed)
+fn best_time(kernel: &dyn TernaryKernel, x: &Tensor, packed: &PackedTensor) -> Result<Duration> {
+    // Warm-up (page faults, thread pool)
+    kernel.matmul(x, packed)?;
+    let (mut best, mut total, mut runs) = (Duration::MAX, Duration::ZERO, 0);
+    while runs < MIN_RUNS || (total < MIN_TIME && runs < MAX_RUNS) {
+        let start = Instant::now();
+        kernel.matmul(x, packed)?;
+        let elapsed = start.elapsed();
+        best = best.min(elapsed);
+        total += elapsed;
+        runs += 1;
+    }
+    Ok(best)
+}
+
+/// Process-wide tuner of `choose` (`Autotuner::from_env` until `configure`)
+static TUNER: Mutex<Option<Autotuner>> = Mutex::new(None);
+
+/// Set mode and cache file of the process-wide tuner (None keeps the current
+/// setting, initially from the environment). The tuner is only rebuilt when
+/// either changes.
+pub fn configure(mode: Option<TuneMode>, path: Option<PathBuf>) {
+    let mut tuner = TUNER.lock().unwrap();
+    let current = tuner.get_or_insert_with(Autotuner::from_env);
+    let mode = mode.unwrap_or(current.mode);
+    let path = path.or_else(|| current.path.clone());
+    if current.mode != mode || current.path != path {
+        *tuner = Some(Autotuner::new(path, mode));
+    }
+}
+
+/// `Autotuner::choose` on the process-wide tuner
+pub fn choose(weights: &PackedTensor) -> Result<ActivationQuant> {
+    let mut tuner = TUNER.lock().unwrap();
+    tuner
+        .get_or_insert_with(Autotuner::from_env)
+        .choose(weights)
+}
+
+/// Mode of the process-wide tuner
+pub fn mode() -> TuneMode {
+    let tuner = TUNER.lock().unwrap();
+    tuner.as_ref().map_or_else(env_mode, Autotuner::mode)
+}
+
+fn env_mode() -> TuneMode {
+    match std::env::var(MODE_ENV) {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            tracing::warn!("{}: {}", MODE_ENV, e);
+            TuneMode::default()
+        }),
+        Err(_) => TuneMode::default(),
+    }
+}
+
+fn env_cache_path() -> Option<PathBuf> {
+    std::env::var_os(CACHE_ENV)
+        .map(PathBuf::from)
+        .or_else(default_cache_path)
+}
+
+fn shape_key(in_dim: usize, out_dim: usize) -> String {
+    format!("{}x{}", in_dim, out_dim)
+}
+
+fn default_cache_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("bit-ttt").join("autotune.json"))
+}
+
+/// CPU model name, SIMD level, kernel backend and thread count (winners depend on all four)
+fn cpu_model() -> String {
+    let name = std::fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|info| {
+            info.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                let key = key.trim();
+                (key == "model name" || key == "Hardware" || key == "Model")
+                    .then(|| value.trim().to_string())
+            })
+        });
+    format!(
+        "{} ({:?}, {} backend, {} threads)",
+        name.unwrap_or_else(|| std::env::consts::ARCH.to_string()),
+        crate::kernels::cpu::CpuSimd::detect(),
+        backend::selected(),
+        rayon::current_num_threads()
+    )
+}
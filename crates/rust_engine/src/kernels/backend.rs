@@ -0,0 +1,675 @@
+//! Kernel backends - pluggable implementations of the ternary ops
+//!
+//! Layers never call a kernel directly: they ask `for_device` for the backend
+//! of their input's device and run `matmul` (packed ternary weights),
+//! `adaptive_matmul` (multi-base codes), `int8_rows_into` (int8 vocabulary
+//! tables) or `attention` on it.
+//!
+//! Built-in backends:
+//! - `scalar`: reference loops and materialized attention (the cross-check baseline)
+//! - `portable`: tiled GEMM and flash attention without explicit SIMD
+//! - `avx2` / `neon`: explicit SIMD kernels, available where the CPU has them
+//! - `cuda`: CUDA devices
+//!
+//! The CPU backend is the best available one ("auto") unless chosen with
+//! `select` or the `BIT_TTT_KERNEL` environment variable. With
+//! `BIT_TTT_KERNEL_CHECK=1` (or `set_cross_check(true)`) every CPU call is
+//! also run on `scalar` and fails if the results disagree.
+
+use candle_core::{DType, Device, Result, Tensor, D};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::kernels::attention_cpu::FlashAttentionCpu;
+use crate::kernels::cpu::{self, BitLinearCpu, CpuSimd, Epilogue, KernelScratch, RmsNormIn};
+use crate::kernels::cuda::BitLinearCuda;
+use crate::kernels::packing::{MultiBasePacked, PackedTensor};
+use crate::layers::{KvView, QuantizedKV};
+
+/// Environment variable naming the CPU backend ("auto", "scalar", "portable", "avx2", ...)
+pub const KERNEL_ENV: &str = "BIT_TTT_KERNEL";
+/// Environment variable enabling the reference cross-check ("1" / "true")
+pub const CHECK_ENV: &str = "BIT_TTT_KERNEL_CHECK";
+
+/// Largest `max|got - ref|` accepted by the cross-check, relative to `max|ref|`
+const CHECK_TOLERANCE: f32 = 1e-3;
+
+/// Paged read of a KV pool: logical token `j` lives in slot
+/// `blocks[j / block_size] * block_size + j % block_size`
+#[derive(Debug, Clone, Copy)]
+pub struct PagedRead<'a> {
+    pub blocks: &'a [usize],
+    pub block_size: usize,
+    pub len: usize,
+}
+
+/// One implementation of the ternary inference ops
+///
+/// Shapes follow `BitLinearCpu` and `FlashAttentionCpu`: X [M, K] -> [M, N],
+/// q [B, H, T, D] with K/V [B, KV_H, K, D] -> [B, H, T, D].
+pub trait TernaryKernel: Send + Sync {
+    /// Name used by `select` / `BIT_TTT_KERNEL`
+    fn name(&self) -> &'static str;
+
+    /// Whether the running machine can execute this backend
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Whether this backend computes on `device`
+    fn supports(&self, device: &Device) -> bool;
+
+    /// Y = X * W^T (the packed layout selects f32, int8 or LUT activations)
+    fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor>;
+
+    /// Y = X * (sum_b scale_b * T_b)^T
+    fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor>;
+
+    /// `matmul` on caller buffers: X [M * K] -> `out` [M * N], `scratch` reused
+    /// across calls (the allocation-free decode of `model::decode`).
+    /// The default goes through `matmul` (allocating).
+    fn matmul_into(
+        &self,
+        x: &[f32],
+        w: &PackedTensor,
+        _scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let k = w.shape.dims2()?.1;
+        let x = Tensor::from_slice(x, (x.len() / k.max(1), k), &Device::Cpu)?;
+        copy_out(&self.matmul(&x, w)?, out)
+    }
+
+    /// `adaptive_matmul` on caller buffers (default: through `adaptive_matmul`)
+    fn adaptive_matmul_into(&self, x: &[f32], w: &MultiBasePacked, out: &mut [f32]) -> Result<()> {
+        let k = w.shape.dims2()?.1;
+        let x = Tensor::from_slice(x, (x.len() / k.max(1), k), &Device::Cpu)?;
+        copy_out(&self.adaptive_matmul(&x, w)?, out)
+    }
+
+    /// Int8 weight rows W [N, K_pad] times int8-quantized X [M * K] -> `out`
+    /// [M * N] (`cpu::int8_rows_into`). The default runs the scalar loops.
+    fn int8_rows_into(
+        &self,
+        x: &[f32],
+        w: &[i8],
+        w_scales: &[f32],
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        cpu::int8_rows_into(CpuSimd::Scalar, x, w, w_scales, scratch, out)
+    }
+
+    /// Optional RMSNorm, one activation quantization and `matmul_into` over
+    /// stacked projections, then the epilogue (`BitLinearCpu::fused_into`).
+    /// The default runs the steps one after another (allocating).
+    fn fused_matmul_into(
+        &self,
+        x: &[f32],
+        norm: Option<RmsNormIn>,
+        w: &PackedTensor,
+        epilogue: Epilogue,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let normed;
+        let x = match norm {
+            Some(norm) => {
+                let mut buf = vec![0.0; x.len()];
+                cpu::rms_norm_into(x, norm, &mut buf)?;
+                normed = buf;
+                &normed
+            }
+            None => x,
+        };
+        match epilogue {
+            Epilogue::Linear => self.matmul_into(x, w, scratch, out),
+            Epilogue::SwiGlu => {
+                let n = w.shape.dims2()?.0 / 2;
+                let mut y = vec![0.0; out.len() * 2];
+                self.matmul_into(x, w, scratch, &mut y)?;
+                for (out, y) in out.chunks_mut(n.max(1)).zip(y.chunks(2 * n.max(1))) {
+                    let (gate, up) = y.split_at(n);
+                    for ((o, &g), &u) in out.iter_mut().zip(gate).zip(up) {
+                        *o = cpu::silu(g) * u;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Causal softmax(Q K^T * scale) V over K/V in any cache format
+    fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor>;
+
+    /// `attention` over the first `read.len` tokens of a paged pool (batch 1)
+    fn paged_attention(
+        &self,
+        q: &Tensor,
+        kv: &QuantizedKV,
+        read: PagedRead,
+        scale: f64,
+    ) -> Result<Tensor>;
+}
+
+/// Reference: scalar loops, materialized attention
+#[derive(Debug, Clone, Copy)]
+pub struct ScalarKernel;
+
+impl TernaryKernel for ScalarKernel {
+    fn name(&self) -> &'static str {
+        "scalar"
+    }
+
+    fn supports(&self, device: &Device) -> bool {
+        device.is_cpu()
+    }
+
+    fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor> {
+        if w.lut_layout.is_some() {
+            BitLinearCpu::forward_lut_with_simd(x, w, CpuSimd::Scalar)
+        } else if w.int8_layout.is_some() {
+            BitLinearCpu::forward_int8_with_simd(x, w, CpuSimd::Scalar)
+        } else {
+            BitLinearCpu::forward_with_simd(x, w, CpuSimd::Scalar)
+        }
+    }
+
+    fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor> {
+        BitLinearCpu::forward_multibase_with_simd(x, w, CpuSimd::Scalar)
+    }
+
+    fn matmul_into(
+        &self,
+        x: &[f32],
+        w: &PackedTensor,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        BitLinearCpu::matmul_into(x, w, CpuSimd::Scalar, scratch, out)
+    }
+
+    fn adaptive_matmul_into(&self, x: &[f32], w: &MultiBasePacked, out: &mut [f32]) -> Result<()> {
+        BitLinearCpu::forward_multibase_into(x, w, CpuSimd::Scalar, out)
+    }
+
+    fn fused_matmul_into(
+        &self,
+        x: &[f32],
+        norm: Option<RmsNormIn>,
+        w: &PackedTensor,
+        epilogue: Epilogue,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        BitLinearCpu::fused_into(x, norm, w, epilogue, CpuSimd::Scalar, scratch, out)
+    }
+
+    fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+        let (k, v) = kv.dequantize()?;
+        reference_attention(q, &k, &v, scale)
+    }
+
+    fn paged_attention(
+        &self,
+        q: &Tensor,
+        kv: &QuantizedKV,
+        read: PagedRead,
+        scale: f64,
+    ) -> Result<Tensor> {
+        let slots: Vec<u32> = (0..read.len)
+            .map(|j| {
+                let block = read.blocks.get(j / read.block_size.max(1)).copied();
+                block.map(|b| (b * read.block_size + j % read.block_size) as u32)
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| candle_core::Error::Msg("paged read past the block table".into()))?;
+        let slots = Tensor::new(slots.as_slice(), q.device())?;
+        let (k, v) = kv.dequantize()?;
+        let k = k.index_select(&slots, 2)?;
+        let v = v.index_select(&slots, 2)?;
+        reference_attention(q, &k, &v, scale)
+    }
+}
+
+/// `BitLinearCpu` on one instruction set, the tiled GEMM for M > 1 and flash attention
+#[derive(Debug, Clone, Copy)]
+pub struct SimdKernel(pub CpuSimd);
+
+impl TernaryKernel for SimdKernel {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            CpuSimd::Scalar => "portable",
+            CpuSimd::Avx2 => "avx2",
+            CpuSimd::Neon => "neon",
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.0.is_available()
+    }
+
+    fn supports(&self, device: &Device) -> bool {
+        device.is_cpu()
+    }
+
+    fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor> {
+        if w.lut_layout.is_some() {
+            BitLinearCpu::forward_lut_with_simd(x, w, self.0)
+        } else if w.int8_layout.is_some() {
+            BitLinearCpu::forward_int8_with_simd(x, w, self.0)
+        } else if x.dims2()?.0 > 1 {
+            BitLinearCpu::forward_tiled(x, w)
+        } else {
+            BitLinearCpu::forward_with_simd(x, w, self.0)
+        }
+    }
+
+    fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor> {
+        if x.dims2()?.0 > 1 {
+            return BitLinearCpu::forward_multibase(x, w);
+        }
+        BitLinearCpu::forward_multibase_with_simd(x, w, self.0)
+    }
+
+    /// Per-output kernels for every M (`into` calls are decode steps)
+    fn matmul_into(
+        &self,
+        x: &[f32],
+        w: &PackedTensor,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        BitLinearCpu::matmul_into(x, w, self.0, scratch, out)
+    }
+
+    fn adaptive_matmul_into(&self, x: &[f32], w: &MultiBasePacked, out: &mut [f32]) -> Result<()> {
+        BitLinearCpu::forward_multibase_into(x, w, self.0, out)
+    }
+
+    fn int8_rows_into(
+        &self,
+        x: &[f32],
+        w: &[i8],
+        w_scales: &[f32],
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        cpu::int8_rows_into(self.0, x, w, w_scales, scratch, out)
+    }
+
+    fn fused_matmul_into(
+        &self,
+        x: &[f32],
+        norm: Option<RmsNormIn>,
+        w: &PackedTensor,
+        epilogue: Epilogue,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        BitLinearCpu::fused_into(x, norm, w, epilogue, self.0, scratch, out)
+    }
+
+    fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+        FlashAttentionCpu::forward_quantized(q, kv, scale)
+    }
+
+    fn paged_attention(
+        &self,
+        q: &Tensor,
+        kv: &QuantizedKV,
+        read: PagedRead,
+        scale: f64,
+    ) -> Result<Tensor> {
+        FlashAttentionCpu::forward_paged(q, kv, read.blocks, read.block_size, read.len, scale)
+    }
+}
+
+/// CUDA devices: dequantized matmuls and materialized attention on the GPU
+#[derive(Debug, Clone, Copy)]
+pub struct CudaKernel;
+
+impl TernaryKernel for CudaKernel {
+    fn name(&self) -> &'static str {
+        "cuda"
+    }
+
+    fn is_available(&self) -> bool {
+        candle_core::utils::cuda_is_available()
+    }
+
+    fn supports(&self, device: &Device) -> bool {
+        device.is_cuda()
+    }
+
+    fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor> {
+        BitLinearCuda::forward(x, w)
+    }
+
+    fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor> {
+        x.matmul(&w.unpack(x.device())?.t()?)
+    }
+
+    fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+        ScalarKernel.attention(q, kv, scale)
+    }
+
+    fn paged_attention(
+        &self,
+        q: &Tensor,
+        kv: &QuantizedKV,
+        read: PagedRead,
+        scale: f64,
+    ) -> Result<Tensor> {
+        ScalarKernel.paged_attention(q, kv, read, scale)
+    }
+}
+
+/// Runs every call on `kernel` and on the scalar reference, failing on mismatch
+struct CrossChecked {
+    kernel: Arc<dyn TernaryKernel>,
+}
+
+impl CrossChecked {
+    fn compare(&self, op: &str, got: Tensor, expected: Tensor) -> Result<Tensor> {
+        if got.dims() != expected.dims() {
+            candle_core::bail!(
+                "kernel {} {}: shape {:?} vs reference {:?}",
+                self.kernel.name(),
+                op,
+                got.dims(),
+                expected.dims()
+            );
+        }
+        let max_abs = |t: &Tensor| -> Result<f32> { t.abs()?.max_all()?.to_scalar::<f32>() };
+        let diff = max_abs(&(&got - &expected)?)?;
+        let norm = max_abs(&expected)?;
+        if diff.is_nan() || diff > CHECK_TOLERANCE * norm.max(f32::MIN_POSITIVE) {
+            candle_core::bail!(
+                "kernel {} {}: max |diff| {} vs reference (max |ref| {})",
+                self.kernel.name(),
+                op,
+                diff,
+                norm
+            );
+        }
+        Ok(got)
+    }
+}
+
+impl TernaryKernel for CrossChecked {
+    fn name(&self) -> &'static str {
+        self.kernel.name()
+    }
+
+    fn is_available(&self) -> bool {
+        self.kernel.is_available()
+    }
+
+    fn supports(&self, device: &Device) -> bool {
+        self.kernel.supports(device)
+    }
+
+    fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor> {
+        let got = self.kernel.matmul(x, w)?;
+        self.compare("matmul", got, ScalarKernel.matmul(x, w)?)
+    }
+
+    fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor> {
+        let got = self.kernel.adaptive_matmul(x, w)?;
+        self.compare("adaptive_matmul", got, ScalarKernel.adaptive_matmul(x, w)?)
+    }
+
+    fn int8_rows_into(
+        &self,
+        x: &[f32],
+        w: &[i8],
+        w_scales: &[f32],
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        self.kernel.int8_rows_into(x, w, w_scales, scratch, out)?;
+        let mut expected = vec![0.0; out.len()];
+        let mut reference_scratch = KernelScratch::default();
+        ScalarKernel.int8_rows_into(x, w, w_scales, &mut reference_scratch, &mut expected)?;
+        let got = Tensor::from_slice(out, out.len(), &Device::Cpu)?;
+        let expected = Tensor::from_vec(expected, out.len(), &Device::Cpu)?;
+        self.compare("int8_rows", got, expected)?;
+        Ok(())
+    }
+
+    fn fused_matmul_into(
+        &self,
+        x: &[f32],
+        norm: Option<RmsNormIn>,
+        w: &PackedTensor,
+        epilogue: Epilogue,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        self.kernel
+            .fused_matmul_into(x, norm, w, epilogue, scratch, out)?;
+        let mut expected = vec![0.0; out.len()];
+        let mut reference_scratch = KernelScratch::default();
+        ScalarKernel.fused_matmul_into(
+            x,
+            norm,
+            w,
+            epilogue,
+            &mut reference_scratch,
+            &mut expected,
+        )?;
+        let got = Tensor::from_slice(out, out.len(), &Device::Cpu)?;
+        let expected = Tensor::from_vec(expected, out.len(), &Device::Cpu)?;
+        self.compare("fused_matmul", got, expected)?;
+        Ok(())
+    }
+
+    fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+        let got = self.kernel.attention(q, kv, scale)?;
+        self.compare("attention", got, ScalarKernel.attention(q, kv, scale)?)
+    }
+
+    fn paged_attention(
+        &self,
+        q: &Tensor,
+        kv: &QuantizedKV,
+        read: PagedRead,
+        scale: f64,
+    ) -> Result<Tensor> {
+        let got = self.kernel.paged_attention(q, kv, read, scale)?;
+        let expected = ScalarKernel.paged_attention(q, kv, read, scale)?;
+        self.compare("paged_attention", got, expected)
+    }
+}
+
+/// Wrap `kernel` so that each call is verified against the scalar reference
+pub fn cross_checked(kernel: Arc<dyn TernaryKernel>) -> Arc<dyn TernaryKernel> {
+    Arc::new(CrossChecked { kernel })
+}
+
+/// Materialized causal attention: repeat_kv + full score matrix + mask.
+/// Query row `i` attends to keys `0..=(K - T) + i`.
+fn reference_attention(q: &Tensor, k: &Tensor, v: &Tensor, scale: f64) -> Result<Tensor> {
+    let (b, h, t, d) = q.dims4()?;
+    let (_, kv_h, k_len, _) = k.dims4()?;
+    if kv_h == 0 || h / kv_h * kv_h != h || k_len < t {
+        candle_core::bail!(
+            "attention: {} heads / {} KV heads, {} queries / {} keys",
+            h,
+            kv_h,
+            t,
+            k_len
+        );
+    }
+    let expand = |x: &Tensor| -> Result<Tensor> {
+        x.to_dtype(DType::F32)?
+            .unsqueeze(2)?
+            .expand((b, kv_h, h / kv_h, k_len, d))?
+            .reshape((b, h, k_len, d))
+    };
+    let (k, v) = (expand(k)?, expand(v)?);
+    let scores = (q.contiguous()?.matmul(&k.t()?.contiguous()?)? * scale)?;
+    let past = k_len - t;
+    let mask: Vec<f32> = (0..t * k_len)
+        .map(|idx| {
+            if idx % k_len <= past + idx / k_len {
+                0.0
+            } else {
+                f32::NEG_INFINITY
+            }
+        })
+        .collect();
+    let mask = Tensor::from_vec(mask, (1, 1, t, k_len), q.device())?;
+    candle_nn::ops::softmax(&scores.broadcast_add(&mask)?, D::Minus1)?.matmul(&v)
+}
+
+struct Registry {
+    kernels: Vec<Arc<dyn TernaryKernel>>,
+    /// Backend of CPU tensors
+    cpu: Arc<dyn TernaryKernel>,
+    check: bool,
+}
+
+impl Registry {
+    fn builtin() -> Self {
+        let kernels: Vec<Arc<dyn TernaryKernel>> = vec![
+            Arc::new(ScalarKernel),
+            Arc::new(SimdKernel(CpuSimd::Scalar)),
+            Arc::new(SimdKernel(CpuSimd::Avx2)),
+            Arc::new(SimdKernel(CpuSimd::Neon)),
+            Arc::new(CudaKernel),
+        ];
+        let mut registry = Self {
+            cpu: Arc::new(ScalarKernel),
+            kernels,
+            check: false,
+        };
+        registry.cpu = registry.auto();
+
+        if let Ok(name) = std::env::var(KERNEL_ENV) {
+            match registry.get(&name) {
+                Ok(kernel) => registry.cpu = kernel,
+                Err(e) => tracing::warn!("{}: {} (using {})", KERNEL_ENV, e, registry.cpu.name()),
+            }
+        }
+        if let Ok(check) = std::env::var(CHECK_ENV) {
+            registry.check = matches!(check.to_ascii_lowercase().as_str(), "1" | "true" | "on");
+        }
+        registry
+    }
+
+    /// Best built-in CPU backend of the running machine
+    fn auto(&self) -> Arc<dyn TernaryKernel> {
+        Arc::new(SimdKernel(CpuSimd::detect()))
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<dyn TernaryKernel>> {
+        let name = name.trim().to_ascii_lowercase();
+        if name == "auto" {
+            return Ok(self.auto());
+        }
+        let Some(kernel) = self.kernels.iter().find(|k| k.name() == name) else {
+            candle_core::bail!(
+                "unknown kernel '{}' (expected auto, {})",
+                name,
+                self.kernels
+                    .iter()
+                    .map(|k| k.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+        if !kernel.is_available() {
+            candle_core::bail!("kernel '{}' is not available on this machine", name);
+        }
+        Ok(kernel.clone())
+    }
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Registry::builtin()))
+}
+
+/// Add a backend (replacing a registered one of the same name)
+pub fn register(kernel: Arc<dyn TernaryKernel>) {
+    let mut registry = registry().write().unwrap();
+    registry.kernels.retain(|k| k.name() != kernel.name());
+    registry.kernels.push(kernel);
+}
+
+/// Names of the registered backends this machine can run
+pub fn available() -> Vec<&'static str> {
+    let registry = registry().read().unwrap();
+    registry
+        .kernels
+        .iter()
+        .filter(|k| k.is_available())
+        .map(|k| k.name())
+        .collect()
+}
+
+/// Registered backend by name ("auto" = best built-in CPU backend)
+pub fn get(name: &str) -> Result<Arc<dyn TernaryKernel>> {
+    registry().read().unwrap().get(name)
+}
+
+/// Use the named backend for CPU tensors
+pub fn select(name: &str) -> Result<()> {
+    let mut registry = registry().write().unwrap();
+    let kernel = registry.get(name)?;
+    if !kernel.supports(&Device::Cpu) {
+        candle_core::bail!("kernel '{}' does not run on the CPU", kernel.name());
+    }
+    registry.cpu = kernel;
+    Ok(())
+}
+
+/// Name of the backend used for CPU tensors
+pub fn selected() -> &'static str {
+    registry().read().unwrap().cpu.name()
+}
+
+/// Verify every CPU kernel call against the scalar reference
+pub fn set_cross_check(enabled: bool) {
+    registry().write().unwrap().check = enabled;
+}
+
+/// Backend for tensors on `device`
+pub fn for_device(device: &Device) -> Result<Arc<dyn TernaryKernel>> {
+    let registry = registry().read().unwrap();
+    if device.is_cpu() {
+        let kernel = registry.cpu.clone();
+        return Ok(if registry.check {
+            cross_checked(kernel)
+        } else {
+            kernel
+        });
+    }
+    match registry
+        .kernels
+        .iter()
+        .find(|k| k.is_available() && k.supports(device))
+    {
+        Some(kernel) => Ok(kernel.clone()),
+        None => candle_core::bail!("no kernel backend for device {:?}", device),
+    }
+}
+
+/// Copy a kernel result into a caller buffer of the same size
+fn copy_out(y: &Tensor, out: &mut [f32]) -> Result<()> {
+    let y = y.flatten_all()?.to_vec1::<f32>()?;
+    if y.len() != out.len() {
+        candle_core::bail!("kernel output has {} values, buffer {}", y.len(), out.len());
+    }
+    out.copy_from_slice(&y);
+    Ok(())
+}
+
+/// `KvView` pair of plain f32 / f16 K and V
+pub fn float_kv(k: &Tensor, v: &Tensor) -> QuantizedKV {
+    QuantizedKV {
+        k: KvView::Float(k.clone()),
+        v: KvView::Float(v.clone()),
+    }
+}
@@ -1,12 +1,90 @@
-use crate::kernels::packing::PackedTensor;
+use crate::kernels::gemm_cpu::{multibase_gemm, ternary_gemm, GroupScales};
+use crate::kernels::packing::{MultiBasePacked, PackedScales, PackedTensor, INT8_BLOCK, LUT_ROWS};
 use candle_core::{Result, Tensor};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
+/// Activation precision of the CPU ternary matmul
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum ActivationQuant {
+    /// f32 activations x ternary weights (float FMA, reference accuracy)
+    #[serde(rename = "f32")]
+    #[default]
+    F32,
+    /// Per-token absmax int8 activations x ternary weights (integer dot products)
+    #[serde(rename = "int8")]
+    Int8,
+    /// int8 activations through per-pair lookup tables indexed by weight codes (TL1-style)
+    #[serde(rename = "lut")]
+    Lut,
+    /// Fastest of the above per weight shape, measured at load (`kernels::autotune`)
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+impl std::str::FromStr for ActivationQuant {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" => Ok(Self::F32),
+            "int8" | "i8" => Ok(Self::Int8),
+            "lut" | "tl1" => Ok(Self::Lut),
+            "auto" => Ok(Self::Auto),
+            other => Err(format!(
+                "unknown activation_quant '{}' (expected f32, int8, lut or auto)",
+                other
+            )),
+        }
+    }
+}
+
+/// SIMD instruction set used by `BitLinearCpu`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuSimd {
+    /// Portable reference loop
+    Scalar,
+    /// x86_64 AVX2 + FMA
+    Avx2,
+    /// AArch64 Advanced SIMD
+    Neon,
+}
+
+impl CpuSimd {
+    /// Best instruction set of the running CPU (runtime detection)
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                return Self::Avx2;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return Self::Neon;
+            }
+        }
+        Self::Scalar
+    }
+
+    /// Whether the running CPU can execute this instruction set
+    pub fn is_available(self) -> bool {
+        self == Self::Scalar || self == Self::detect()
+    }
+}
+
 /// CPU Optimized Kernel for BitNet MatMul
-/// Uses explicit SIMD (AVX2/AVX-512) if available, or auto-vectorized loop.
+/// Uses explicit SIMD (AVX2 on x86_64, NEON on AArch64) if available, or a scalar loop.
 #[derive(Debug, Clone)]
 pub struct BitLinearCpu;
 
@@ -14,117 +92,1007 @@ impl BitLinearCpu {
     /// Forward: Y = X * W^T
     /// X: [M, K] (Float32)
     /// W: [N, K/4] (Packed 1.58-bit)
+    ///
+    /// M == 1 (decode) streams each weight row once (`forward_with_simd`);
+    /// M > 1 (prefill, batches) runs the cache-blocked GEMM (`forward_tiled`).
     pub fn forward(input: &Tensor, weights: &PackedTensor) -> Result<Tensor> {
-        // Validation
-        let (m, k) = input.dims2()?;
-        let (n, k_w) = weights.shape.dims2()?;
+        if input.dims2()?.0 > 1 {
+            return Self::forward_tiled(input, weights);
+        }
+        Self::forward_with_simd(input, weights, CpuSimd::detect())
+    }
 
-        if k != k_w {
-            candle_core::bail!(
-                "Shape mismatch: Input [{}, {}] vs Weight [{}, {}]",
-                m,
-                k,
-                n,
-                k_w
-            );
+    /// Forward through the tiled GEMM (`kernels::gemm_cpu`): weight tiles are
+    /// unpacked once per task and shared by all rows of X
+    pub fn forward_tiled(input: &Tensor, weights: &PackedTensor) -> Result<Tensor> {
+        let (m, k, n) = check_shapes(input, &weights.shape)?;
+        let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
+
+        let w_bytes = weights.data.slice()?;
+        let w_slice = w_bytes.as_slice()?;
+
+        // Per-tensor/per-channel scales factor out of the dot products;
+        // group scales are applied to the weight tiles while unpacking
+        let output = match &weights.scales {
+            PackedScales::Tensor(scale) => {
+                let mut output = ternary_gemm(&x_vec, m, k, w_slice, n, None);
+                output.iter_mut().for_each(|v| *v *= scale);
+                output
+            }
+            PackedScales::Channel(scales) => {
+                let mut output = ternary_gemm(&x_vec, m, k, w_slice, n, None);
+                for row in output.chunks_exact_mut(n) {
+                    for (v, scale) in row.iter_mut().zip(scales) {
+                        *v *= scale;
+                    }
+                }
+                output
+            }
+            PackedScales::Group { size, scales } => {
+                let groups = GroupScales {
+                    values: scales,
+                    size: *size,
+                };
+                ternary_gemm(&x_vec, m, k, w_slice, n, Some(groups))
+            }
+        };
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
+
+    /// `forward` on an explicit instruction set (`CpuSimd::Scalar` = reference)
+    pub fn forward_with_simd(
+        input: &Tensor,
+        weights: &PackedTensor,
+        simd: CpuSimd,
+    ) -> Result<Tensor> {
+        let (m, _, n) = check_shapes(input, &weights.shape)?;
+        let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
+        let mut output = vec![0.0f32; m * n];
+        Self::forward_into(&x_vec, weights, simd, &mut output)?;
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
+
+    /// `forward_with_simd` on caller buffers: X [M * K] -> `out` [M * N]
+    pub fn forward_into(
+        x_vec: &[f32],
+        weights: &PackedTensor,
+        simd: CpuSimd,
+        out: &mut [f32],
+    ) -> Result<()> {
+        // Validation
+        if !simd.is_available() {
+            candle_core::bail!("BitLinearCpu: {:?} is not supported by this CPU", simd);
         }
+        let (_, k, n) = check_slices(x_vec, out, &weights.shape)?;
 
         // Ideally we do this without allocating a huge full-float weight matrix.
         // But for "Step 1" correctness, we can unpack row-by-row to L1 cache and compute.
         // This is "Streaming Dequantization".
 
-        // 1. Flatten Input to Vec<f32>
+        // Fetch Packed Weights (Zero-Copy!)
+        // Borrow the tensor storage or the checkpoint mapping to avoid 16MB copy per call.
+        let w_bytes = weights.data.slice()?;
+        let w_slice = w_bytes.as_slice()?;
+
+        // Parallelize over all output elements (M * N)
+        // This scales perfectly regardless of M or N sizes.
+
+        // Note: x_vec and w_vec are read-only and shared across threads.
+        // Rust's borrow checker allows this with Rayon.
+
+        out.par_iter_mut()
+            .enumerate()
+            .for_each(|(global_idx, out_val)| {
+                let i = global_idx / n; // Row Index (Batch)
+                let j = global_idx % n; // Col Index (Output Feature)
+
+                let x_row = &x_vec[i * k..(i + 1) * k];
+                *out_val = row_f32(simd, x_row, weights, w_slice, j);
+            });
+        Ok(())
+    }
+
+    /// Forward with int8 activations: Y = dequant(Q(X) * W^T)
+    /// X: [M, K] (Float32), quantized per row (absmax -> ±127)
+    /// W: packed with `PackedTensor::with_int8_layout`
+    pub fn forward_int8(input: &Tensor, weights: &PackedTensor) -> Result<Tensor> {
+        Self::forward_int8_with_simd(input, weights, CpuSimd::detect())
+    }
+
+    /// `forward_int8` on an explicit instruction set (`CpuSimd::Scalar` = reference)
+    pub fn forward_int8_with_simd(
+        input: &Tensor,
+        weights: &PackedTensor,
+        simd: CpuSimd,
+    ) -> Result<Tensor> {
+        let (m, _, n) = check_shapes(input, &weights.shape)?;
         let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
+        let mut output = vec![0.0f32; m * n];
+        let mut scratch = KernelScratch::default();
+        Self::forward_int8_into(&x_vec, weights, simd, &mut scratch, &mut output)?;
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
+
+    /// `forward_int8_with_simd` on caller buffers (`scratch` holds Q(X))
+    pub fn forward_int8_into(
+        x_vec: &[f32],
+        weights: &PackedTensor,
+        simd: CpuSimd,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if !simd.is_available() {
+            candle_core::bail!("BitLinearCpu: {:?} is not supported by this CPU", simd);
+        }
+        let (m, k, n) = check_slices(x_vec, out, &weights.shape)?;
+        let Some(layout) = &weights.int8_layout else {
+            candle_core::bail!("BitLinearCpu: weights have no int8 layout (use with_int8_layout)");
+        };
 
-        // 2. Fetch Packed Weights (Zero-Copy!)
-        // Access storage directly to avoid 16MB copy per call.
+        let k_pad = k.div_ceil(INT8_BLOCK) * INT8_BLOCK;
+        let (x_q, x_scales) = scratch.quantize(x_vec, m, k, k_pad);
+
+        let w_bytes = layout.slice()?;
+        let w_slice = w_bytes.as_slice()?;
+
+        out.par_iter_mut()
+            .enumerate()
+            .for_each(|(global_idx, out_val)| {
+                let i = global_idx / n;
+                let j = global_idx % n;
+                let x_row = &x_q[i * k_pad..(i + 1) * k_pad];
+                *out_val = row_int8(simd, x_row, weights, w_slice, j) * x_scales[i];
+            });
+        Ok(())
+    }
+
+    /// Forward through activation lookup tables (TL1-style): Y = dequant(Q(X) * W^T)
+    /// X: [M, K] (Float32), quantized per row like `forward_int8`
+    /// W: packed with `PackedTensor::with_lut_layout`
+    ///
+    /// Each pair of int8 activations becomes a 16-entry table of its signed
+    /// sums (one entry per pair of 2-bit codes); the kernel then replaces the
+    /// multiply-accumulate with one table lookup per two weights, 32 output
+    /// rows at a time.
+    pub fn forward_lut(input: &Tensor, weights: &PackedTensor) -> Result<Tensor> {
+        Self::forward_lut_with_simd(input, weights, CpuSimd::detect())
+    }
+
+    /// `forward_lut` on an explicit instruction set (`CpuSimd::Scalar` = reference)
+    pub fn forward_lut_with_simd(
+        input: &Tensor,
+        weights: &PackedTensor,
+        simd: CpuSimd,
+    ) -> Result<Tensor> {
+        let (m, _, n) = check_shapes(input, &weights.shape)?;
+        let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
+        let mut output = vec![0.0f32; m * n];
+        let mut scratch = KernelScratch::default();
+        Self::forward_lut_into(&x_vec, weights, simd, &mut scratch, &mut output)?;
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
+
+    /// `forward_lut_with_simd` on caller buffers (`scratch` holds Q(X) and its tables)
+    pub fn forward_lut_into(
+        x_vec: &[f32],
+        weights: &PackedTensor,
+        simd: CpuSimd,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if !simd.is_available() {
+            candle_core::bail!("BitLinearCpu: {:?} is not supported by this CPU", simd);
+        }
+        let (m, k, n) = check_slices(x_vec, out, &weights.shape)?;
+        let Some(layout) = &weights.lut_layout else {
+            candle_core::bail!("BitLinearCpu: weights have no LUT layout (use with_lut_layout)");
+        };
+
+        let k_pad = k.div_ceil(4) * 4;
+        let row_tables = k_pad / 2 * LUT_TABLE_BYTES;
+        let (x_scales, tables) = scratch.quantize_lut(x_vec, m, k, k_pad);
+
+        let w_bytes = layout.slice()?;
+        let w_slice = w_bytes.as_slice()?;
+        let tile_bytes = k_pad / 4 * LUT_ROWS;
+
+        out.par_chunks_mut(n)
+            .zip(tables.par_chunks(row_tables))
+            .zip(x_scales.par_iter())
+            .for_each(|((out_row, tables), &x_scale)| {
+                out_row
+                    .par_chunks_mut(LUT_ROWS)
+                    .zip(w_slice.par_chunks(tile_bytes))
+                    .enumerate()
+                    .for_each(|(tile, (out_tile, w_tile))| {
+                        lut_tile_into(simd, tables, weights, w_tile, tile, x_scale, out_tile);
+                    });
+            });
+        Ok(())
+    }
+
+    /// Forward on multi-base adaptive weights: Y = X * (sum_b scale_b * T_b)^T
+    /// X: [M, K] (Float32)
+    /// W: `MultiBasePacked` ([N, K/4, num_bases] interleaved codes)
+    ///
+    /// The bases are combined per 4-column group while streaming the packed
+    /// bytes, so no dense copy of W is ever built. M > 1 runs the tiled GEMM.
+    pub fn forward_multibase(input: &Tensor, weights: &MultiBasePacked) -> Result<Tensor> {
+        let (m, k, n) = check_shapes(input, &weights.shape)?;
+        if m == 1 {
+            return Self::forward_multibase_with_simd(input, weights, CpuSimd::detect());
+        }
+        let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
         let (w_storage, w_layout) = weights.data.storage_and_layout();
         let w_slice = match &*w_storage {
             candle_core::Storage::Cpu(storage) => storage.as_slice::<u8>()?,
             _ => candle_core::bail!("BitLinearCpu: Weights must be on CPU storage"),
         };
+        if !w_layout.is_contiguous() {
+            candle_core::bail!("BitLinearCpu: Weights must be contiguous");
+        }
+
+        let output = multibase_gemm(&x_vec, m, k, w_slice, n, &weights.scales);
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
 
+    /// Per-output `forward_multibase` on an explicit instruction set (`CpuSimd::Scalar` = reference)
+    pub fn forward_multibase_with_simd(
+        input: &Tensor,
+        weights: &MultiBasePacked,
+        simd: CpuSimd,
+    ) -> Result<Tensor> {
+        let (m, _, n) = check_shapes(input, &weights.shape)?;
+        let x_vec = input.flatten_all()?.to_vec1::<f32>()?;
+        let mut output = vec![0.0f32; m * n];
+        Self::forward_multibase_into(&x_vec, weights, simd, &mut output)?;
+        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+    }
+
+    /// `forward_multibase_with_simd` on caller buffers: X [M * K] -> `out` [M * N]
+    pub fn forward_multibase_into(
+        x_vec: &[f32],
+        weights: &MultiBasePacked,
+        simd: CpuSimd,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if !simd.is_available() {
+            candle_core::bail!("BitLinearCpu: {:?} is not supported by this CPU", simd);
+        }
+        let (_, k, n) = check_slices(x_vec, out, &weights.shape)?;
+        let (w_storage, w_layout) = weights.data.storage_and_layout();
+        let w_slice = match &*w_storage {
+            candle_core::Storage::Cpu(storage) => storage.as_slice::<u8>()?,
+            _ => candle_core::bail!("BitLinearCpu: Weights must be on CPU storage"),
+        };
         if !w_layout.is_contiguous() {
             candle_core::bail!("BitLinearCpu: Weights must be contiguous");
         }
+        let row_bytes = k / 4 * weights.num_bases();
+        let scales = &weights.scales;
+
+        out.par_iter_mut()
+            .enumerate()
+            .for_each(|(global_idx, out_val)| {
+                let i = global_idx / n;
+                let j = global_idx % n;
+                let x_row = &x_vec[i * k..(i + 1) * k];
+                let w_row = &w_slice[j * row_bytes..(j + 1) * row_bytes];
+
+                *out_val = match simd {
+                    #[cfg(target_arch = "x86_64")]
+                    CpuSimd::Avx2 => unsafe { multibase_row_avx2(x_row, w_row, scales) },
+                    #[cfg(target_arch = "aarch64")]
+                    CpuSimd::Neon => unsafe { multibase_row_neon(x_row, w_row, scales) },
+                    _ => multibase_row_scalar(x_row, w_row, scales),
+                };
+            });
+        Ok(())
+    }
+
+    /// Packed matmul on caller buffers, the kernel picked by the layout
+    /// (LUT, int8 or f32 activations) like `TernaryKernel::matmul`
+    pub fn matmul_into(
+        x_vec: &[f32],
+        weights: &PackedTensor,
+        simd: CpuSimd,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if weights.lut_layout.is_some() {
+            Self::forward_lut_into(x_vec, weights, simd, scratch, out)
+        } else if weights.int8_layout.is_some() {
+            Self::forward_int8_into(x_vec, weights, simd, scratch, out)
+        } else {
+            Self::forward_into(x_vec, weights, simd, out)
+        }
+    }
+
+    /// Fused `RMSNorm -> activation quantization -> X * W^T -> epilogue` on
+    /// caller buffers. The input rows are normalized (if `norm` is given) and
+    /// quantized once for all rows of W, which may stack several projections
+    /// of the same input (`PackedTensor::concat_rows`).
+    pub fn fused_into(
+        x_vec: &[f32],
+        norm: Option<RmsNormIn>,
+        weights: &PackedTensor,
+        epilogue: Epilogue,
+        simd: CpuSimd,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut normed = std::mem::take(&mut scratch.normed);
+        let x = match norm {
+            Some(norm) => {
+                grow(&mut normed, x_vec.len());
+                rms_norm_into(x_vec, norm, &mut normed[..x_vec.len()])?;
+                &normed[..x_vec.len()]
+            }
+            None => x_vec,
+        };
+        let result = match epilogue {
+            Epilogue::Linear => Self::matmul_into(x, weights, simd, scratch, out),
+            Epilogue::SwiGlu => Self::swiglu_into(x, weights, simd, scratch, out),
+        };
+        scratch.normed = normed;
+        result
+    }
+
+    /// `silu(X * G^T) * (X * U^T)` for W = [G; U] (`PackedTensor::concat_rows`):
+    /// each output is written as soon as its pair of dot products is done.
+    /// With the LUT layout G must hold whole tiles (N / 2 a multiple of
+    /// `LUT_ROWS`). X [M * K] -> `out` [M * N / 2]
+    pub fn swiglu_into(
+        x_vec: &[f32],
+        weights: &PackedTensor,
+        simd: CpuSimd,
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if !simd.is_available() {
+            candle_core::bail!("BitLinearCpu: {:?} is not supported by this CPU", simd);
+        }
+        let (rows, k) = weights.shape.dims2()?;
+        let n = rows / 2;
+        let m = x_vec.len() / k.max(1);
+        if n * 2 != rows || m * k != x_vec.len() || m * n != out.len() {
+            candle_core::bail!(
+                "Shape mismatch: {} inputs / {} outputs vs gate/up Weight [{}, {}]",
+                x_vec.len(),
+                out.len(),
+                rows,
+                k
+            );
+        }
+
+        if let Some(layout) = &weights.lut_layout {
+            if !n.is_multiple_of(LUT_ROWS) {
+                candle_core::bail!(
+                    "BitLinearCpu: LUT gate/up needs a multiple of {} gate rows (got {})",
+                    LUT_ROWS,
+                    n
+                );
+            }
+            let k_pad = k.div_ceil(4) * 4;
+            let row_tables = k_pad / 2 * LUT_TABLE_BYTES;
+            let (x_scales, tables) = scratch.quantize_lut(x_vec, m, k, k_pad);
+            let w_bytes = layout.slice()?;
+            let w_slice = w_bytes.as_slice()?;
+            let tile_bytes = k_pad / 4 * LUT_ROWS;
+            let (gate_tiles, up_tiles) = w_slice.split_at(n / LUT_ROWS * tile_bytes);
+
+            // Gate tile `t` pairs with up tile `t` (rows n + t * LUT_ROWS ..)
+            out.par_chunks_mut(n)
+                .zip(tables.par_chunks(row_tables))
+                .zip(x_scales.par_iter())
+                .for_each(|((out_row, tables), &x_scale)| {
+                    out_row
+                        .par_chunks_mut(LUT_ROWS)
+                        .zip(gate_tiles.par_chunks(tile_bytes))
+                        .zip(up_tiles.par_chunks(tile_bytes))
+                        .enumerate()
+                        .for_each(|(tile, ((out_tile, gate_tile), up_tile))| {
+                            let (mut gate, mut up) = ([0.0f32; LUT_ROWS], [0.0f32; LUT_ROWS]);
+                            let up_index = n / LUT_ROWS + tile;
+                            lut_tile_into(
+                                simd, tables, weights, gate_tile, tile, x_scale, &mut gate,
+                            );
+                            lut_tile_into(
+                                simd, tables, weights, up_tile, up_index, x_scale, &mut up,
+                            );
+                            for ((o, &g), &u) in out_tile.iter_mut().zip(&gate).zip(&up) {
+                                *o = silu(g) * u;
+                            }
+                        });
+                });
+        } else if let Some(layout) = &weights.int8_layout {
+            let k_pad = k.div_ceil(INT8_BLOCK) * INT8_BLOCK;
+            let (x_q, x_scales) = scratch.quantize(x_vec, m, k, k_pad);
+            let w_bytes = layout.slice()?;
+            let w_slice = w_bytes.as_slice()?;
+
+            out.par_iter_mut().enumerate().for_each(|(idx, o)| {
+                let (i, j) = (idx / n, idx % n);
+                let x_row = &x_q[i * k_pad..(i + 1) * k_pad];
+                let gate = row_int8(simd, x_row, weights, w_slice, j) * x_scales[i];
+                let up = row_int8(simd, x_row, weights, w_slice, n + j) * x_scales[i];
+                *o = silu(gate) * up;
+            });
+        } else {
+            let w_bytes = weights.data.slice()?;
+            let w_slice = w_bytes.as_slice()?;
+
+            out.par_iter_mut().enumerate().for_each(|(idx, o)| {
+                let (i, j) = (idx / n, idx % n);
+                let x_row = &x_vec[i * k..(i + 1) * k];
+                let gate = row_f32(simd, x_row, weights, w_slice, j);
+                let up = row_f32(simd, x_row, weights, w_slice, n + j);
+                *o = silu(gate) * up;
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Output stage of `BitLinearCpu::fused_into`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Epilogue {
+    /// The matmul result as is
+    #[default]
+    Linear,
+    /// W = [gate; up] (N rows each): output `j` is `silu(gate_j) * up_j`
+    SwiGlu,
+}
+
+/// RMSNorm applied to the input rows of a fused matmul
+#[derive(Debug, Clone, Copy)]
+pub struct RmsNormIn<'a> {
+    /// f32 weight [K]
+    pub weight: &'a [f32],
+    pub eps: f32,
+}
+
+/// `RMSNorm::forward` of the rows of X [M * K] into `out`
+pub fn rms_norm_into(x: &[f32], norm: RmsNormIn, out: &mut [f32]) -> Result<()> {
+    let k = norm.weight.len();
+    if k == 0 || x.len() / k * k != x.len() || out.len() != x.len() {
+        candle_core::bail!(
+            "RMSNorm: {} inputs / {} outputs for a weight of {}",
+            x.len(),
+            out.len(),
+            k
+        );
+    }
+    for (out, x) in out.chunks_exact_mut(k).zip(x.chunks_exact(k)) {
+        let mean = x.iter().map(|v| v * v).sum::<f32>() / k as f32;
+        let inv = 1.0 / (mean + norm.eps).sqrt();
+        for ((o, &x), &w) in out.iter_mut().zip(x).zip(norm.weight) {
+            *o = x * inv * w;
+        }
+    }
+    Ok(())
+}
+
+/// SiLU (x * sigmoid(x)), as `candle_nn::ops::silu`
+pub fn silu(x: f32) -> f32 {
+    x / (1.0 + (-x).exp())
+}
+
+/// One output of the f32 kernel: row `j` of W (`w` = its packed codes) with `x`.
+/// One partial dot product per scale group (one group per row unless the
+/// weights carry per-group scales).
+fn row_f32(simd: CpuSimd, x: &[f32], weights: &PackedTensor, w: &[u8], j: usize) -> f32 {
+    let k = x.len();
+    let w_row = &w[(j * k.div_ceil(4)).min(w.len())..];
+    let (row_scales, group) = weights.scales.row(j, k);
+    scale_segments(row_scales, group, k)
+        .map(|(cols, scale)| dot_f32(simd, &x[cols.clone()], &w_row[cols.start / 4..]) * scale)
+        .sum()
+}
+
+/// One output of the int8 kernel before the activation scale: row `j` of the
+/// int8 layout `w` with the quantized row `x` ([K_pad])
+fn row_int8(simd: CpuSimd, x: &[i8], weights: &PackedTensor, w: &[u8], j: usize) -> f32 {
+    let (k, k_pad) = (weights.shape.dims()[1], x.len());
+    let row_bytes = k_pad / 4;
+    let w_row = &w[j * row_bytes..(j + 1) * row_bytes];
+
+    // Scale groups are whole blocks (checked by `with_int8_layout`)
+    let (row_scales, group) = weights.scales.row(j, k);
+    scale_segments(row_scales, group, k_pad)
+        .map(|(cols, scale)| {
+            let (x, w) = (&x[cols.clone()], &w_row[cols.start / 4..cols.end / 4]);
+            let dot = match simd {
+                #[cfg(target_arch = "x86_64")]
+                CpuSimd::Avx2 => unsafe { dot_int8_avx2(x, w) },
+                #[cfg(target_arch = "aarch64")]
+                CpuSimd::Neon => unsafe { dot_int8_neon(x, w) },
+                _ => dot_int8_scalar(x, w),
+            };
+            dot as f32 * scale
+        })
+        .sum()
+}
+
+/// Outputs of LUT tile `tile` (`out.len()` <= LUT_ROWS rows) for one activation
+/// row given as `tables`, scaled by its `x_scale`
+fn lut_tile_into(
+    simd: CpuSimd,
+    tables: &[u8],
+    weights: &PackedTensor,
+    w_tile: &[u8],
+    tile: usize,
+    x_scale: f32,
+    out: &mut [f32],
+) {
+    let k = weights.shape.dims()[1];
+    let k_pad = k.div_ceil(4) * 4;
+    // All rows share the group boundaries: one tile pass per group
+    let group = weights.scales.group_size(k);
+    let (first_row, _) = weights.scales.row(tile * LUT_ROWS, k);
+    for (g, (cols, _)) in scale_segments(first_row, group, k_pad).enumerate() {
+        let blocks = cols.start / 4..cols.end / 4;
+        let tables = &tables[blocks.start * 2 * LUT_TABLE_BYTES..blocks.end * 2 * LUT_TABLE_BYTES];
+        let w_tile = &w_tile[blocks.start * LUT_ROWS..blocks.end * LUT_ROWS];
+        let dots = match simd {
+            #[cfg(target_arch = "x86_64")]
+            CpuSimd::Avx2 => unsafe { lut_tile_avx2(tables, w_tile) },
+            #[cfg(target_arch = "aarch64")]
+            CpuSimd::Neon => unsafe { lut_tile_neon(tables, w_tile) },
+            _ => lut_tile_scalar(tables, w_tile),
+        };
+        for (r, (out_val, dot)) in out.iter_mut().zip(dots).enumerate() {
+            let scale = weights.scales.row(tile * LUT_ROWS + r, k).0[g];
+            let v = dot as f32 * scale;
+            *out_val = if g == 0 { v } else { *out_val + v };
+        }
+    }
+    for out_val in out.iter_mut() {
+        *out_val *= x_scale;
+    }
+}
+
+/// Activation buffers of the fused, int8 and LUT kernels, reused across `*_into`
+/// calls (they only grow, so a steady stream of same-shaped calls never allocates)
+#[derive(Debug, Default)]
+pub struct KernelScratch {
+    normed: Vec<f32>,
+    x_q: Vec<i8>,
+    x_scales: Vec<f32>,
+    tables: Vec<u8>,
+}
+
+impl KernelScratch {
+    /// `quantize_activations` of M rows into the scratch buffers
+    fn quantize(&mut self, x: &[f32], m: usize, k: usize, k_pad: usize) -> (&[i8], &[f32]) {
+        grow(&mut self.x_q, m * k_pad);
+        grow(&mut self.x_scales, m);
+        let (q, scales) = (&mut self.x_q[..m * k_pad], &mut self.x_scales[..m]);
+        quantize_activations_into(x, k, k_pad, q, scales);
+        (q, scales)
+    }
+
+    /// `quantize`, then the LUT tables of each row: (scales, tables)
+    fn quantize_lut(&mut self, x: &[f32], m: usize, k: usize, k_pad: usize) -> (&[f32], &[u8]) {
+        self.quantize(x, m, k, k_pad);
+        let row_tables = k_pad / 2 * LUT_TABLE_BYTES;
+        grow(&mut self.tables, m * row_tables);
+        self.tables[..m * row_tables]
+            .par_chunks_mut(row_tables)
+            .zip(self.x_q[..m * k_pad].par_chunks(k_pad))
+            .for_each(|(tables, x_row)| build_lut_tables_into(x_row, tables));
+        (&self.x_scales[..m], &self.tables[..m * row_tables])
+    }
+}
+
+/// Make `buf` at least `len` long (never shrinks, so the capacity is kept)
+fn grow<T: Clone + Default>(buf: &mut Vec<T>, len: usize) {
+    if buf.len() < len {
+        buf.resize(len, T::default());
+    }
+}
+
+/// (M, K, N) of X [M, K] and W [N, K]
+fn check_shapes(
+    input: &Tensor,
+    weight_shape: &candle_core::Shape,
+) -> Result<(usize, usize, usize)> {
+    let (m, k) = input.dims2()?;
+    let (n, k_w) = weight_shape.dims2()?;
+    if k != k_w {
+        candle_core::bail!(
+            "Shape mismatch: Input [{}, {}] vs Weight [{}, {}]",
+            m,
+            k,
+            n,
+            k_w
+        );
+    }
+    Ok((m, k, n))
+}
 
-        let output_len = m * n;
-        let mut output = vec![0.0f32; output_len];
+/// (M, K, N) of X [M * K] and `out` [M * N] for W [N, K]
+fn check_slices(
+    x: &[f32],
+    out: &[f32],
+    weight_shape: &candle_core::Shape,
+) -> Result<(usize, usize, usize)> {
+    let (n, k) = weight_shape.dims2()?;
+    let m = x.len() / k.max(1);
+    if m * k != x.len() || m * n != out.len() {
+        candle_core::bail!(
+            "Shape mismatch: {} inputs / {} outputs vs Weight [{}, {}]",
+            x.len(),
+            out.len(),
+            n,
+            k
+        );
+    }
+    Ok((m, k, n))
+}
 
-        // Branchless Optimization (LUT)
-        // 00 -> 0.0
-        // 01 -> 1.0
-        // 10 -> -1.0
-        // 11 -> 0.0
-        const LUT: [f32; 4] = [0.0, 1.0, -1.0, 0.0];
+/// Column ranges of the scale groups of one row with their scales; the last
+/// range runs to `len` (so it also covers the row padding of a kernel layout)
+fn scale_segments(
+    row_scales: &[f32],
+    group: usize,
+    len: usize,
+) -> impl Iterator<Item = (std::ops::Range<usize>, f32)> + '_ {
+    let last = row_scales.len() - 1;
+    row_scales.iter().enumerate().map(move |(g, &scale)| {
+        let end = if g == last { len } else { (g + 1) * group };
+        (g * group..end, scale)
+    })
+}
 
-        // Runtime check for AVX2
+/// Dot product of f32 activations with packed 2-bit weights (`w` starts at
+/// the byte of `x[0]`): SIMD over chunks of 32, scalar remainder
+fn dot_f32(simd: CpuSimd, x: &[f32], w: &[u8]) -> f32 {
+    // Branchless Optimization (LUT)
+    // 00 -> 0.0
+    // 01 -> 1.0
+    // 10 -> -1.0
+    // 11 -> 0.0
+    const LUT: [f32; 4] = [0.0, 1.0, -1.0, 0.0];
+
+    // Process in chunks of 32 (128 bytes of X, 8 bytes of W)
+    let num_chunks = x.len() / 32;
+    let (mut sum, processed) = match simd {
         #[cfg(target_arch = "x86_64")]
-        let has_avx2 = is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma");
-        #[cfg(not(target_arch = "x86_64"))]
-        let has_avx2 = false;
+        CpuSimd::Avx2 => unsafe { (compute_row_avx2(x, w, num_chunks), num_chunks * 32) },
+        #[cfg(target_arch = "aarch64")]
+        CpuSimd::Neon => unsafe { (compute_row_neon(x, w, num_chunks), num_chunks * 32) },
+        _ => (0.0f32, 0),
+    };
 
-        // Parallelize over all output elements (M * N)
-        // This scales perfectly regardless of M or N sizes.
+    // Remainder (Scalar Loop)
+    for (l, &x_val) in x.iter().enumerate().skip(processed) {
+        let Some(&byte) = w.get(l / 4) else {
+            break;
+        };
+        let code = (byte >> ((l % 4) * 2)) & 0b11;
+        sum += x_val * LUT[code as usize];
+    }
+    sum
+}
 
-        // Note: x_vec and w_vec are read-only and shared across threads.
-        // Rust's borrow checker allows this with Rayon.
+/// Per-row absmax quantization to int8 (±127, -128 unused so negation can't overflow)
+///
+/// Returns (rows x k_pad values, zero-padded; per-row dequantization scales)
+pub fn quantize_activations(x: &[f32], k: usize, k_pad: usize) -> (Vec<i8>, Vec<f32>) {
+    let rows = x.len() / k.max(1);
+    let mut q = vec![0i8; rows * k_pad];
+    let mut scales = vec![0.0f32; rows];
+    quantize_activations_into(x, k, k_pad, &mut q, &mut scales);
+    (q, scales)
+}
 
-        output
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(global_idx, out_val)| {
-                let i = global_idx / n; // Row Index (Batch)
-                let j = global_idx % n; // Col Index (Output Feature)
+/// `quantize_activations` into caller buffers (every value, padding included, is written)
+pub fn quantize_activations_into(
+    x: &[f32],
+    k: usize,
+    k_pad: usize,
+    q: &mut [i8],
+    scales: &mut [f32],
+) {
+    q.par_chunks_mut(k_pad)
+        .zip(scales.par_iter_mut())
+        .zip(x.par_chunks(k))
+        .for_each(|((q_row, scale), x_row)| {
+            q_row.fill(0);
+            let amax = x_row.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+            if amax == 0.0 {
+                *scale = 0.0;
+                return;
+            }
+            *scale = amax / 127.0;
+            let inv = 127.0 / amax;
+            for (q, &v) in q_row.iter_mut().zip(x_row) {
+                *q = (v * inv).round().clamp(-127.0, 127.0) as i8;
+            }
+        });
+}
 
-                let mut sum = 0.0f32;
-                let w_row_start = j * k.div_ceil(4);
-                let x_row_start = i * k;
+/// Int8 weight rows times int8-quantized activations (quantized vocabulary
+/// tables): `out[m, j] = x_scale[m] * w_scales[j] * <x_q[m], w[j]>`.
+///
+/// `x` is [M, K], `w` [N, K_pad] (rows zero-padded to whole `INT8_BLOCK`s,
+/// absmax-quantized to ±127), `out` [M, N].
+pub fn int8_rows_into(
+    simd: CpuSimd,
+    x: &[f32],
+    w: &[i8],
+    w_scales: &[f32],
+    scratch: &mut KernelScratch,
+    out: &mut [f32],
+) -> Result<()> {
+    let n = w_scales.len();
+    let k_pad = w.len() / n.max(1);
+    if n == 0 || k_pad * n != w.len() || k_pad / INT8_BLOCK * INT8_BLOCK != k_pad {
+        candle_core::bail!(
+            "int8_rows_into: {} weights are not {} rows of whole {}-blocks",
+            w.len(),
+            n,
+            INT8_BLOCK
+        );
+    }
+    let m = out.len() / n;
+    let k = x.len() / m.max(1);
+    if m * n != out.len() || m * k != x.len() || k > k_pad {
+        candle_core::bail!(
+            "int8_rows_into: x {} / out {} don't match [{}, {}] weights",
+            x.len(),
+            out.len(),
+            n,
+            k_pad
+        );
+    }
+    let (x_q, x_scales) = scratch.quantize(x, m, k, k_pad);
+    out.par_chunks_mut(n)
+        .zip(x_q.par_chunks(k_pad))
+        .zip(x_scales.par_iter())
+        .for_each(|((out, x_row), &x_scale)| {
+            out.par_iter_mut()
+                .zip(w.par_chunks(k_pad))
+                .zip(w_scales.par_iter())
+                .for_each(|((o, w_row), &w_scale)| {
+                    let dot = match simd {
+                        #[cfg(target_arch = "x86_64")]
+                        CpuSimd::Avx2 => unsafe { dot_i8_avx2(x_row, w_row) },
+                        #[cfg(target_arch = "aarch64")]
+                        CpuSimd::Neon => unsafe { dot_i8_neon(x_row, w_row) },
+                        _ => dot_i8_scalar(x_row, w_row),
+                    };
+                    *o = dot as f32 * w_scale * x_scale;
+                });
+        });
+    Ok(())
+}
 
-                // AVX2 Path
-                let mut processed = 0;
-                if has_avx2 {
-                    // Process in chunks of 32 (128 bytes of X, 8 bytes of W)
-                    // 32 weights = 64 bits = 8 bytes.
-                    let chunk_size = 32;
-                    let num_chunks = k / chunk_size;
+/// Dot product of one activation row with one multi-base row (4-column groups
+/// of `scales.len()` code bytes): the bases are summed into one coefficient
+/// vector per group before the multiply
+fn multibase_row_scalar(x: &[f32], w: &[u8], scales: &[f32]) -> f32 {
+    let mut sum = 0.0f32;
+    for (x4, group) in x.chunks_exact(4).zip(w.chunks_exact(scales.len())) {
+        let mut coeffs = [0.0f32; 4];
+        for (&byte, &scale) in group.iter().zip(scales) {
+            for (c, &v) in coeffs.iter_mut().zip(&COEFF_LUT[byte as usize]) {
+                *c += v * scale;
+            }
+        }
+        sum += x4.iter().zip(coeffs).map(|(x, c)| x * c).sum::<f32>();
+    }
+    sum
+}
 
-                    // Unsafe block for AVX intrinsics
-                    #[cfg(target_arch = "x86_64")]
-                    unsafe {
-                        sum += compute_row_avx2(
-                            &x_vec[x_row_start..],
-                            &w_slice[w_row_start..],
-                            num_chunks,
-                        );
-                    }
-                    processed = num_chunks * chunk_size;
-                }
+/// AVX2 multi-base Kernel: two 4-column groups (8 activations) per iteration
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2", enable = "fma")]
+unsafe fn multibase_row_avx2(x: &[f32], w: &[u8], scales: &[f32]) -> f32 {
+    let bases = scales.len();
+    let mut acc = _mm256_setzero_ps();
+    let x_pairs = x.chunks_exact(8);
+    let w_pairs = w.chunks_exact(2 * bases);
+    let (x_rest, w_rest) = (x_pairs.remainder(), w_pairs.remainder());
 
-                // Remainder (Scalar Loop)
-                for l in processed..k {
-                    // Safety: We assume valid shapes from validation check.
-                    // Using get_unchecked for max speed in inner loop.
-                    let x_val = unsafe { *x_vec.get_unchecked(x_row_start + l) };
+    for (x8, groups) in x_pairs.zip(w_pairs) {
+        let mut coeffs = _mm256_setzero_ps();
+        for (b, &scale) in scales.iter().enumerate() {
+            let lo = _mm_loadu_ps(COEFF_LUT[groups[b] as usize].as_ptr());
+            let hi = _mm_loadu_ps(COEFF_LUT[groups[bases + b] as usize].as_ptr());
+            coeffs = _mm256_fmadd_ps(_mm256_set_m128(hi, lo), _mm256_set1_ps(scale), coeffs);
+        }
+        acc = _mm256_fmadd_ps(_mm256_loadu_ps(x8.as_ptr()), coeffs, acc);
+    }
 
-                    let byte_idx = l / 4;
-                    let bit_idx = l % 4;
+    let mut lanes = [0.0f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+    lanes.iter().sum::<f32>() + multibase_row_scalar(x_rest, w_rest, scales)
+}
 
-                    if w_row_start + byte_idx >= w_slice.len() {
-                        break;
-                    }
-                    let byte = unsafe { *w_slice.get_unchecked(w_row_start + byte_idx) };
+/// Bytes per activation-pair table: 16 low bytes, then 16 high bytes of the i16 sums
+const LUT_TABLE_BYTES: usize = 32;
 
-                    let code = (byte >> (bit_idx * 2)) & 0b11;
+/// Weight-pair blocks summed in i16 before widening (64 * 2 * 254 < i16::MAX)
+const LUT_FLUSH: usize = 64;
 
-                    let coeff = unsafe { *LUT.get_unchecked(code as usize) };
-                    sum += x_val * coeff;
-                }
-                *out_val = sum * weights.scale;
-            });
+/// One table per activation pair: entry `c0 | c1 << 2` = s(c0) * x0 + s(c1) * x1,
+/// split into a low-byte and a high-byte plane for 16-way byte shuffles
+fn build_lut_tables_into(x: &[i8], tables: &mut [u8]) {
+    const SIGN: [i16; 4] = [0, 1, -1, 0];
+    for (table, pair) in tables
+        .chunks_exact_mut(LUT_TABLE_BYTES)
+        .zip(x.chunks_exact(2))
+    {
+        let (x0, x1) = (pair[0] as i16, pair[1] as i16);
+        for c in 0..16 {
+            let [lo, hi] = (SIGN[c & 3] * x0 + SIGN[c >> 2] * x1).to_le_bytes();
+            table[c] = lo;
+            table[16 + c] = hi;
+        }
+    }
+}
 
-        Tensor::from_vec(output, (m, n), &candle_core::Device::Cpu)
+/// Integer dot products of one activation row (as tables) with one 32-row weight tile
+fn lut_tile_scalar(tables: &[u8], w: &[u8]) -> [i32; LUT_ROWS] {
+    let mut acc = [0i32; LUT_ROWS];
+    for (t, block) in tables
+        .chunks_exact(2 * LUT_TABLE_BYTES)
+        .zip(w.chunks_exact(LUT_ROWS))
+    {
+        for (sum, &byte) in acc.iter_mut().zip(block) {
+            for (g, idx) in [byte & 0x0f, byte >> 4].into_iter().enumerate() {
+                let table = &t[g * LUT_TABLE_BYTES..];
+                let idx = idx as usize;
+                *sum += i16::from_le_bytes([table[idx], table[16 + idx]]) as i32;
+            }
+        }
+    }
+    acc
+}
+
+/// AVX2 LUT Kernel: one 32-byte block (2 weight pairs x 32 rows) per iteration
+///
+/// Both nibbles of the block index the broadcast low/high table planes with
+/// `shuffle_epi8`; interleaving the planes gives the i16 sums of 16 rows per
+/// register, widened to i32 every `LUT_FLUSH` blocks.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn lut_tile_avx2(tables: &[u8], w: &[u8]) -> [i32; LUT_ROWS] {
+    let mask = _mm256_set1_epi8(0x0f);
+    // Rows 0-7, 8-15, 16-23, 24-31
+    let mut acc = [_mm256_setzero_si256(); 4];
+
+    for (t_chunk, w_chunk) in tables
+        .chunks(LUT_FLUSH * 2 * LUT_TABLE_BYTES)
+        .zip(w.chunks(LUT_FLUSH * LUT_ROWS))
+    {
+        // Rows [0-7 | 16-23] and [8-15 | 24-31] (unpack works per 128-bit lane)
+        let mut sum16 = [_mm256_setzero_si256(); 2];
+        for (t, block) in t_chunk
+            .chunks_exact(2 * LUT_TABLE_BYTES)
+            .zip(w_chunk.chunks_exact(LUT_ROWS))
+        {
+            let packed = _mm256_loadu_si256(block.as_ptr() as *const __m256i);
+            let idx = [
+                _mm256_and_si256(packed, mask),
+                _mm256_and_si256(_mm256_srli_epi16(packed, 4), mask),
+            ];
+            for (g, &idx) in idx.iter().enumerate() {
+                let table = t.as_ptr().add(g * LUT_TABLE_BYTES);
+                let lo_plane =
+                    _mm256_broadcastsi128_si256(_mm_loadu_si128(table as *const __m128i));
+                let hi_plane =
+                    _mm256_broadcastsi128_si256(_mm_loadu_si128(table.add(16) as *const __m128i));
+                let lo = _mm256_shuffle_epi8(lo_plane, idx);
+                let hi = _mm256_shuffle_epi8(hi_plane, idx);
+                sum16[0] = _mm256_add_epi16(sum16[0], _mm256_unpacklo_epi8(lo, hi));
+                sum16[1] = _mm256_add_epi16(sum16[1], _mm256_unpackhi_epi8(lo, hi));
+            }
+        }
+        let widen = |v: __m128i| _mm256_cvtepi16_epi32(v);
+        acc[0] = _mm256_add_epi32(acc[0], widen(_mm256_castsi256_si128(sum16[0])));
+        acc[1] = _mm256_add_epi32(acc[1], widen(_mm256_castsi256_si128(sum16[1])));
+        acc[2] = _mm256_add_epi32(acc[2], widen(_mm256_extracti128_si256::<1>(sum16[0])));
+        acc[3] = _mm256_add_epi32(acc[3], widen(_mm256_extracti128_si256::<1>(sum16[1])));
+    }
+
+    let mut out = [0i32; LUT_ROWS];
+    for (q, &v) in acc.iter().enumerate() {
+        _mm256_storeu_si256(out.as_mut_ptr().add(q * 8) as *mut __m256i, v);
     }
+    out
+}
+
+/// Integer dot product of two int8 rows
+fn dot_i8_scalar(x: &[i8], w: &[i8]) -> i32 {
+    x.iter().zip(w).map(|(&x, &w)| x as i32 * w as i32).sum()
+}
+
+/// AVX2 int8 x int8 Kernel: 32 pairs per iteration
+///
+/// `maddubs` needs one unsigned operand: |x| times w with x's sign applied
+/// (|products| <= 127 * 127, pair sums fit in i16).
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_i8_avx2(x: &[i8], w: &[i8]) -> i32 {
+    let ones_i16 = _mm256_set1_epi16(1);
+    let mut acc = _mm256_setzero_si256();
+    for (x32, w32) in x.chunks_exact(32).zip(w.chunks_exact(32)) {
+        let x8 = _mm256_loadu_si256(x32.as_ptr() as *const __m256i);
+        let w8 = _mm256_loadu_si256(w32.as_ptr() as *const __m256i);
+        let prod = _mm256_maddubs_epi16(_mm256_sign_epi8(x8, x8), _mm256_sign_epi8(w8, x8));
+        acc = _mm256_add_epi32(acc, _mm256_madd_epi16(prod, ones_i16));
+    }
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    let tail = x.len() / 32 * 32;
+    lanes.iter().sum::<i32>() + dot_i8_scalar(&x[tail..], &w[tail..])
+}
+
+/// Integer dot product of one activation row with one interleaved weight row
+fn dot_int8_scalar(x: &[i8], w: &[u8]) -> i32 {
+    let mut sum = 0i32;
+    for (x_block, w_block) in x
+        .chunks_exact(INT8_BLOCK)
+        .zip(w.chunks_exact(INT8_BLOCK / 4))
+    {
+        for (b, &byte) in w_block.iter().enumerate() {
+            for s in 0..4 {
+                let x_val = x_block[s * 32 + b] as i32;
+                sum += match (byte >> (s * 2)) & 0b11 {
+                    1 => x_val,
+                    2 => -x_val,
+                    _ => 0,
+                };
+            }
+        }
+    }
+    sum
+}
+
+/// AVX2 Kernel: one 32-byte weight block (128 weights) per iteration
+///
+/// Codes are expanded to {-1, 0, 1} bytes with a shuffle LUT, applied to the
+/// activations with `sign`, and summed with `maddubs` (u8 ones x i8 products).
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_int8_avx2(x: &[i8], w: &[u8]) -> i32 {
+    let mask = _mm256_set1_epi8(0b11);
+    // 00 -> 0, 01 -> 1, 10 -> -1, 11 -> 0
+    let lut = _mm256_broadcastsi128_si256(_mm_setr_epi8(
+        0, 1, -1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ));
+    let ones_u8 = _mm256_set1_epi8(1);
+    let ones_i16 = _mm256_set1_epi16(1);
+    let mut acc = _mm256_setzero_si256();
+
+    for (x_block, w_block) in x
+        .chunks_exact(INT8_BLOCK)
+        .zip(w.chunks_exact(INT8_BLOCK / 4))
+    {
+        let packed = _mm256_loadu_si256(w_block.as_ptr() as *const __m256i);
+        let codes = [
+            _mm256_and_si256(packed, mask),
+            _mm256_and_si256(_mm256_srli_epi16(packed, 2), mask),
+            _mm256_and_si256(_mm256_srli_epi16(packed, 4), mask),
+            _mm256_and_si256(_mm256_srli_epi16(packed, 6), mask),
+        ];
+        // |pair sums| <= 2 * 127, four of them still fit in i16
+        let mut sum16 = _mm256_setzero_si256();
+        for (s, &code) in codes.iter().enumerate() {
+            let w8 = _mm256_shuffle_epi8(lut, code);
+            let x8 = _mm256_loadu_si256(x_block.as_ptr().add(s * 32) as *const __m256i);
+            let prod = _mm256_sign_epi8(x8, w8);
+            sum16 = _mm256_add_epi16(sum16, _mm256_maddubs_epi16(ones_u8, prod));
+        }
+        acc = _mm256_add_epi32(acc, _mm256_madd_epi16(sum16, ones_i16));
+    }
+
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    lanes.iter().sum()
 }
 
 /// AVX2 Kernel: Processes chunks of 32 (K)
@@ -191,8 +1159,8 @@ unsafe fn compute_row_avx2(x_ptr: &[f32], w_ptr: &[u8], num_chunks: usize) -> f3
 
         for _ in 0..4 {
             // 1. Load 2 bytes (8 weights)
-            // Need to read u16.
-            let w_val = *(w_curr as *const u16);
+            // Need to read u16 (rows of K/4 bytes may start at odd addresses).
+            let w_val = std::ptr::read_unaligned(w_curr as *const u16);
             w_curr = w_curr.add(2);
 
             // Expand 2 bytes to 8 integers?
@@ -237,3 +1205,152 @@ unsafe fn compute_row_avx2(x_ptr: &[f32], w_ptr: &[u8], num_chunks: usize) -> f3
     _mm256_storeu_ps(temp.as_mut_ptr(), sum_vec);
     temp.iter().sum()
 }
+
+/// 2-bit codes of one byte -> 4 coefficients (00 -> 0, 01 -> 1, 10 -> -1, 11 -> 0)
+pub(crate) static COEFF_LUT: [[f32; 4]; 256] = {
+    let mut table = [[0.0; 4]; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut i = 0;
+        while i < 4 {
+            table[byte][i] = match (byte >> (i * 2)) & 0b11 {
+                1 => 1.0,
+                2 => -1.0,
+                _ => 0.0,
+            };
+            i += 1;
+        }
+        byte += 1;
+    }
+    table
+};
+
+/// NEON Kernel: Processes chunks of 32 (K), one byte (4 weights) per FMA
+/// Returns partial sum.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn compute_row_neon(x: &[f32], w: &[u8], num_chunks: usize) -> f32 {
+    // Two accumulators to hide the FMA latency
+    let mut acc = [vdupq_n_f32(0.0), vdupq_n_f32(0.0)];
+    for (x_chunk, w_chunk) in x.chunks_exact(32).zip(w.chunks_exact(8)).take(num_chunks) {
+        for (b, &byte) in w_chunk.iter().enumerate() {
+            let coeffs = vld1q_f32(COEFF_LUT[byte as usize].as_ptr());
+            let x_vec = vld1q_f32(x_chunk.as_ptr().add(b * 4));
+            acc[b & 1] = vfmaq_f32(acc[b & 1], x_vec, coeffs);
+        }
+    }
+    vaddvq_f32(vaddq_f32(acc[0], acc[1]))
+}
+
+/// NEON multi-base Kernel: one 4-column group per iteration
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn multibase_row_neon(x: &[f32], w: &[u8], scales: &[f32]) -> f32 {
+    let mut acc = vdupq_n_f32(0.0);
+    for (x4, group) in x.chunks_exact(4).zip(w.chunks_exact(scales.len())) {
+        let mut coeffs = vdupq_n_f32(0.0);
+        for (&byte, &scale) in group.iter().zip(scales) {
+            coeffs = vfmaq_n_f32(coeffs, vld1q_f32(COEFF_LUT[byte as usize].as_ptr()), scale);
+        }
+        acc = vfmaq_f32(acc, vld1q_f32(x4.as_ptr()), coeffs);
+    }
+    vaddvq_f32(acc)
+}
+
+/// NEON LUT Kernel: one 32-byte block (2 weight pairs x 32 rows) per iteration,
+/// as two 16-row halves through `vqtbl1q` lookups of the low/high table planes
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn lut_tile_neon(tables: &[u8], w: &[u8]) -> [i32; LUT_ROWS] {
+    let mask = vdupq_n_u8(0x0f);
+    // Rows 4q..4q + 4
+    let mut acc = [vdupq_n_s32(0); LUT_ROWS / 4];
+
+    for (t_chunk, w_chunk) in tables
+        .chunks(LUT_FLUSH * 2 * LUT_TABLE_BYTES)
+        .zip(w.chunks(LUT_FLUSH * LUT_ROWS))
+    {
+        // Rows 8q..8q + 8
+        let mut sum16 = [vdupq_n_s16(0); LUT_ROWS / 8];
+        for (t, block) in t_chunk
+            .chunks_exact(2 * LUT_TABLE_BYTES)
+            .zip(w_chunk.chunks_exact(LUT_ROWS))
+        {
+            for half in 0..2 {
+                let packed = vld1q_u8(block.as_ptr().add(half * 16));
+                let idx = [vandq_u8(packed, mask), vshrq_n_u8::<4>(packed)];
+                for (g, &idx) in idx.iter().enumerate() {
+                    let table = t.as_ptr().add(g * LUT_TABLE_BYTES);
+                    let lo = vqtbl1q_u8(vld1q_u8(table), idx);
+                    let hi = vqtbl1q_u8(vld1q_u8(table.add(16)), idx);
+                    let (a, b) = (half * 2, half * 2 + 1);
+                    sum16[a] = vaddq_s16(sum16[a], vreinterpretq_s16_u8(vzip1q_u8(lo, hi)));
+                    sum16[b] = vaddq_s16(sum16[b], vreinterpretq_s16_u8(vzip2q_u8(lo, hi)));
+                }
+            }
+        }
+        for (q, &s) in sum16.iter().enumerate() {
+            acc[2 * q] = vaddw_s16(acc[2 * q], vget_low_s16(s));
+            acc[2 * q + 1] = vaddw_high_s16(acc[2 * q + 1], s);
+        }
+    }
+
+    let mut out = [0i32; LUT_ROWS];
+    for (q, &v) in acc.iter().enumerate() {
+        vst1q_s32(out.as_mut_ptr().add(q * 4), v);
+    }
+    out
+}
+
+/// NEON int8 x int8 Kernel: 16 pairs per iteration, widened to i16 products
+/// and pairwise-accumulated into i32 lanes
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_i8_neon(x: &[i8], w: &[i8]) -> i32 {
+    let mut acc = vdupq_n_s32(0);
+    for (x16, w16) in x.chunks_exact(16).zip(w.chunks_exact(16)) {
+        let (x8, w8) = (vld1q_s8(x16.as_ptr()), vld1q_s8(w16.as_ptr()));
+        acc = vpadalq_s16(acc, vmull_s8(vget_low_s8(x8), vget_low_s8(w8)));
+        acc = vpadalq_s16(acc, vmull_high_s8(x8, w8));
+    }
+    let tail = x.len() / 16 * 16;
+    vaddvq_s32(acc) + dot_i8_scalar(&x[tail..], &w[tail..])
+}
+
+/// NEON Kernel for the int8 layout: one 32-byte weight block (128 weights) per iteration
+///
+/// Codes are expanded to {-1, 0, 1} bytes with a table lookup, multiplied with
+/// the activations and pairwise-accumulated into i16, then i32 lanes.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_int8_neon(x: &[i8], w: &[u8]) -> i32 {
+    // 00 -> 0, 01 -> 1, 10 -> -1, 11 -> 0
+    const LUT: [i8; 16] = [0, 1, -1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let lut = vld1q_s8(LUT.as_ptr());
+    let mask = vdupq_n_u8(0b11);
+    let mut acc = vdupq_n_s32(0);
+
+    for (x_block, w_block) in x
+        .chunks_exact(INT8_BLOCK)
+        .zip(w.chunks_exact(INT8_BLOCK / 4))
+    {
+        // |pair sums| <= 2 * 127, eight of them still fit in i16
+        let mut sum16 = vdupq_n_s16(0);
+        for half in 0..2 {
+            let packed = vld1q_u8(w_block.as_ptr().add(half * 16));
+            let codes = [
+                vandq_u8(packed, mask),
+                vandq_u8(vshrq_n_u8::<2>(packed), mask),
+                vandq_u8(vshrq_n_u8::<4>(packed), mask),
+                vshrq_n_u8::<6>(packed),
+            ];
+            for (s, &code) in codes.iter().enumerate() {
+                let w8 = vqtbl1q_s8(lut, code);
+                let x8 = vld1q_s8(x_block.as_ptr().add(s * 32 + half * 16));
+                sum16 = vpadalq_s8(sum16, vmulq_s8(x8, w8));
+            }
+        }
+        acc = vpadalq_s16(acc, sum16);
+    }
+    vaddvq_s32(acc)
+}
@@ -0,0 +1,287 @@
+//! Ternary GEMM - Cache-blocked Y = X * W^T for M > 1 on CPU
+//!
+//! The per-output loop of `BitLinearCpu::forward` re-streams a packed weight
+//! row and an activation row for every output element. Here:
+//!
+//! - activations are packed once into panels of `MR` rows (`[k][MR]`),
+//! - each task owns the output columns of one `NC` tile and, per `KC` slice of K,
+//!   unpacks its weight tile once into panels of `NR` columns (`[k][NR]`),
+//!   one panel (`KC * NR` f32 = 16 KB) staying in L1 while all row panels pass,
+//!   so every weight is decoded once per call regardless of M,
+//! - an `MR x NR` register tile accumulates in the microkernel (AVX2 + FMA or
+//!   NEON when detected, a portable loop otherwise).
+//!
+//! Multi-base (adaptive) weights go through the same path: the bases of a
+//! 4-column group are summed while unpacking, so the tile holds the effective weights.
+//!
+//! Per-group weight scales (`GroupScales`) are folded into the tile while
+//! unpacking; per-tensor and per-channel scales are applied by the caller.
+//!
+//! Tasks (output tiles) run in parallel.
+
+use rayon::prelude::*;
+
+use super::cpu::CpuSimd;
+
+/// Rows of the register tile
+pub const MR: usize = 4;
+/// Columns of the register tile
+pub const NR: usize = 16;
+/// K slice of one unpacked weight tile
+const KC: usize = 256;
+/// Output columns per task
+const NC: usize = 64;
+
+/// `a_panel [kc][MR] x b_panel [kc][NR]` -> register tile
+type Microkernel = unsafe fn(&[f32], &[f32]) -> [[f32; NR]; MR];
+
+/// Scales of `size` consecutive columns of each weight row: row `r`, column
+/// `c` is scaled by `values[r * k.div_ceil(size) + c / size]` (`size` a multiple of 4)
+#[derive(Clone, Copy, Debug)]
+pub struct GroupScales<'a> {
+    pub values: &'a [f32],
+    pub size: usize,
+}
+
+/// `x [m, k] * W^T` for 2-bit weights `w` (`n` rows of `k.div_ceil(4)` bytes),
+/// unscaled unless per-group scales are given
+pub fn ternary_gemm(
+    x: &[f32],
+    m: usize,
+    k: usize,
+    w: &[u8],
+    n: usize,
+    groups: Option<GroupScales>,
+) -> Vec<f32> {
+    gemm(x, m, k, (w, &[1.0]), n, groups)
+}
+
+/// `x [m, k] * W^T` for multi-base weights `W = sum_b scales[b] * T_b`, whose
+/// codes are interleaved per 4-column group (`n` rows of `k.div_ceil(4) * scales.len()` bytes)
+pub fn multibase_gemm(
+    x: &[f32],
+    m: usize,
+    k: usize,
+    w: &[u8],
+    n: usize,
+    scales: &[f32],
+) -> Vec<f32> {
+    gemm(x, m, k, (w, scales), n, None)
+}
+
+fn gemm(
+    x: &[f32],
+    m: usize,
+    k: usize,
+    (w, scales): (&[u8], &[f32]),
+    n: usize,
+    groups: Option<GroupScales>,
+) -> Vec<f32> {
+    let a_packed = pack_activations(x, m, k);
+    let kernel: Microkernel = match CpuSimd::detect() {
+        #[cfg(target_arch = "x86_64")]
+        CpuSimd::Avx2 => microkernel_avx2,
+        #[cfg(target_arch = "aarch64")]
+        CpuSimd::Neon => microkernel_neon,
+        _ => microkernel,
+    };
+
+    let tiles: Vec<Vec<f32>> = (0..n.div_ceil(NC))
+        .into_par_iter()
+        .map(|t| {
+            let n0 = t * NC;
+            let weights = (w, scales, groups);
+            compute_tile(&a_packed, m, k, weights, n0, (n - n0).min(NC), kernel)
+        })
+        .collect();
+
+    let mut output = vec![0.0f32; m * n];
+    for (t, tile) in tiles.iter().enumerate() {
+        let (n0, nc) = (t * NC, (n - t * NC).min(NC));
+        for (i, row) in tile.chunks_exact(nc).enumerate() {
+            output[i * n + n0..i * n + n0 + nc].copy_from_slice(row);
+        }
+    }
+    output
+}
+
+/// Activations as panels of `MR` rows: panel `p` holds `[k][MR]` (rows past `m` are zero)
+fn pack_activations(x: &[f32], m: usize, k: usize) -> Vec<f32> {
+    let panels = m.div_ceil(MR);
+    let mut packed = vec![0.0f32; panels * k * MR];
+    packed
+        .par_chunks_mut(k * MR)
+        .enumerate()
+        .for_each(|(p, panel)| {
+            for r in 0..MR.min(m - p * MR) {
+                let row = &x[(p * MR + r) * k..(p * MR + r + 1) * k];
+                for (c, &v) in row.iter().enumerate() {
+                    panel[c * MR + r] = v;
+                }
+            }
+        });
+    packed
+}
+
+/// Output tile `[0..m, n0..n0 + nc]`, row-major `m x nc`
+fn compute_tile(
+    a_packed: &[f32],
+    m: usize,
+    k: usize,
+    weights: (&[u8], &[f32], Option<GroupScales>),
+    n0: usize,
+    nc: usize,
+    kernel: Microkernel,
+) -> Vec<f32> {
+    let n_panels = nc.div_ceil(NR);
+    let m_panels = m.div_ceil(MR);
+    let mut tile = vec![0.0f32; m * nc];
+    let mut b_tile = vec![0.0f32; n_panels * KC * NR];
+
+    for k0 in (0..k).step_by(KC) {
+        let kc = (k - k0).min(KC);
+        unpack_weights(weights, k, n0, nc, k0, kc, &mut b_tile);
+
+        for jp in 0..n_panels {
+            let b_panel = &b_tile[jp * KC * NR..jp * KC * NR + kc * NR];
+            for ip in 0..m_panels {
+                let panel = ip * k * MR;
+                let a_panel = &a_packed[panel + k0 * MR..panel + (k0 + kc) * MR];
+
+                // SAFETY: `kernel` matches the detected instruction set
+                let acc = unsafe { kernel(a_panel, b_panel) };
+
+                // Accumulate the valid part of the register tile
+                for (i, acc_row) in acc.iter().enumerate().take(m - ip * MR) {
+                    let row = (ip * MR + i) * nc + jp * NR;
+                    let cols = NR.min(nc - jp * NR);
+                    for (out, &v) in tile[row..row + cols].iter_mut().zip(acc_row) {
+                        *out += v;
+                    }
+                }
+            }
+        }
+    }
+    tile
+}
+
+/// Weights `[n0..n0 + nc, k0..k0 + kc]` as f32 panels of `NR` columns: `[panel][k][NR]`
+/// (columns past `nc` are zero), the bases summed per 4-column group
+///
+/// Per group, the code bytes of the panel's `NR` rows are gathered once and
+/// expanded into 4 contiguous `NR`-wide rows of the panel (vectorizes cleanly).
+/// Group scales, if any, multiply the rows' coefficients of each 4-column group.
+fn unpack_weights(
+    (w, scales, groups): (&[u8], &[f32], Option<GroupScales>),
+    k: usize,
+    n0: usize,
+    nc: usize,
+    k0: usize,
+    kc: usize,
+    b_tile: &mut [f32],
+) {
+    let bases = scales.len();
+    let row_bytes = k.div_ceil(4) * bases;
+    for (jp, panel) in b_tile.chunks_exact_mut(KC * NR).enumerate() {
+        let cols = NR.min(nc.saturating_sub(jp * NR));
+        let first_row = (n0 + jp * NR) * row_bytes;
+        // k0 and KC are multiples of 4: groups never straddle tiles
+        for (g, dst) in panel[..kc * NR].chunks_mut(4 * NR).enumerate() {
+            let group = (k0 / 4 + g) * bases;
+            for (b, &scale) in scales.iter().enumerate() {
+                let mut bytes = [0u8; NR];
+                for (j, byte) in bytes.iter_mut().enumerate().take(cols) {
+                    *byte = w[first_row + j * row_bytes + group + b];
+                }
+                for (e, dst_row) in dst.chunks_exact_mut(NR).enumerate() {
+                    for (d, &byte) in dst_row.iter_mut().zip(&bytes) {
+                        // 00 -> 0, 01 -> 1, 10 -> -1, 11 -> 0
+                        let code = (byte >> (2 * e)) & 0b11;
+                        let v = ((code & 1) as f32 - (code >> 1) as f32) * scale;
+                        *d = if b == 0 { v } else { *d + v };
+                    }
+                }
+            }
+            if let Some(GroupScales { values, size }) = groups {
+                let (per_row, idx) = (k.div_ceil(size), (k0 + 4 * g) / size);
+                let mut row_scales = [0.0f32; NR];
+                for (j, s) in row_scales.iter_mut().enumerate().take(cols) {
+                    *s = values[(n0 + jp * NR + j) * per_row + idx];
+                }
+                for dst_row in dst.chunks_exact_mut(NR) {
+                    for (d, &s) in dst_row.iter_mut().zip(&row_scales) {
+                        *d *= s;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Portable microkernel (auto-vectorized)
+fn microkernel(a_panel: &[f32], b_panel: &[f32]) -> [[f32; NR]; MR] {
+    let mut c = [[0.0f32; NR]; MR];
+    for (a, b) in a_panel.chunks_exact(MR).zip(b_panel.chunks_exact(NR)) {
+        for (c_row, &a_i) in c.iter_mut().zip(a) {
+            for (c_ij, &b_j) in c_row.iter_mut().zip(b) {
+                *c_ij += a_i * b_j;
+            }
+        }
+    }
+    c
+}
+
+/// AVX2 microkernel: 4 rows x 2 YMM accumulators, one broadcast FMA per row and half-panel
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn microkernel_avx2(a_panel: &[f32], b_panel: &[f32]) -> [[f32; NR]; MR] {
+    use std::arch::x86_64::*;
+
+    let mut c = [[_mm256_setzero_ps(); 2]; MR];
+    for (a, b) in a_panel.chunks_exact(MR).zip(b_panel.chunks_exact(NR)) {
+        let b_lo = _mm256_loadu_ps(b.as_ptr());
+        let b_hi = _mm256_loadu_ps(b.as_ptr().add(8));
+        for (c_row, a_i) in c.iter_mut().zip(a) {
+            let a_i = _mm256_broadcast_ss(a_i);
+            c_row[0] = _mm256_fmadd_ps(a_i, b_lo, c_row[0]);
+            c_row[1] = _mm256_fmadd_ps(a_i, b_hi, c_row[1]);
+        }
+    }
+
+    let mut out = [[0.0f32; NR]; MR];
+    for (out_row, c_row) in out.iter_mut().zip(c) {
+        _mm256_storeu_ps(out_row.as_mut_ptr(), c_row[0]);
+        _mm256_storeu_ps(out_row.as_mut_ptr().add(8), c_row[1]);
+    }
+    out
+}
+
+/// NEON microkernel: 4 rows x 4 Q-register accumulators
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn microkernel_neon(a_panel: &[f32], b_panel: &[f32]) -> [[f32; NR]; MR] {
+    use std::arch::aarch64::*;
+
+    let mut c = [[vdupq_n_f32(0.0); NR / 4]; MR];
+    for (a, b) in a_panel.chunks_exact(MR).zip(b_panel.chunks_exact(NR)) {
+        let b = [
+            vld1q_f32(b.as_ptr()),
+            vld1q_f32(b.as_ptr().add(4)),
+            vld1q_f32(b.as_ptr().add(8)),
+            vld1q_f32(b.as_ptr().add(12)),
+        ];
+        for (c_row, &a_i) in c.iter_mut().zip(a) {
+            for (c_q, &b_q) in c_row.iter_mut().zip(&b) {
+                *c_q = vfmaq_n_f32(*c_q, b_q, a_i);
+            }
+        }
+    }
+
+    let mut out = [[0.0f32; NR]; MR];
+    for (out_row, c_row) in out.iter_mut().zip(c) {
+        for (q, c_q) in c_row.into_iter().enumerate() {
+            vst1q_f32(out_row.as_mut_ptr().add(q * 4), c_q);
+        }
+    }
+    out
+}
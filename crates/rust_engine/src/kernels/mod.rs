@@ -1,3 +1,7 @@
+pub mod attention_cpu;
+pub mod autotune;
+pub mod backend;
 pub mod cpu;
 pub mod cuda;
+pub mod gemm_cpu;
 pub mod packing;
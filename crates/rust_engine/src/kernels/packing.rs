@@ -1,8 +1,252 @@
 use candle_core::{Device, Result, Tensor};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::sync::{Arc, RwLockReadGuard};
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+use super::cpu::{ActivationQuant, COEFF_LUT};
 
 /// Epsilon for numerical stability during Scale calculation
 const EPSILON: f32 = 1e-6;
 
+/// Weights per 32-byte block of the int8 kernel layout
+pub const INT8_BLOCK: usize = 128;
+
+/// Output rows interleaved per tile of the LUT kernel layout
+pub const LUT_ROWS: usize = 32;
+
+/// Granularity of the weight scales chosen when packing BitLinear weights
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum WeightScale {
+    /// One absmean scale for the whole matrix (BitNet b1.58)
+    #[serde(rename = "tensor")]
+    #[default]
+    Tensor,
+    /// One scale per output channel (row)
+    #[serde(rename = "channel")]
+    Channel,
+    /// One scale per `weight_scale_group` consecutive input columns of each row
+    #[serde(rename = "group")]
+    Group,
+}
+
+impl std::str::FromStr for WeightScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tensor" | "per-tensor" => Ok(Self::Tensor),
+            "channel" | "per-channel" => Ok(Self::Channel),
+            "group" | "per-group" => Ok(Self::Group),
+            other => Err(format!(
+                "unknown weight_scale '{}' (expected tensor, channel or group)",
+                other
+            )),
+        }
+    }
+}
+
+/// How `precompute_packed` packs the BitLinear weights of a model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackOptions {
+    /// Kernel the weights are laid out for
+    pub activations: ActivationQuant,
+    /// Scale granularity of the packed weights
+    pub weight_scale: WeightScale,
+    /// Columns per scale with `WeightScale::Group`
+    pub group_size: usize,
+    /// Also pack q/k/v and gate/up into one weight each (`layers::FusedProjection`)
+    pub fuse_projections: bool,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            activations: ActivationQuant::default(),
+            weight_scale: WeightScale::default(),
+            group_size: 128,
+            fuse_projections: true,
+        }
+    }
+}
+
+impl From<ActivationQuant> for PackOptions {
+    fn from(activations: ActivationQuant) -> Self {
+        Self {
+            activations,
+            ..Self::default()
+        }
+    }
+}
+
+/// Dequantization scales of a `PackedTensor` (`W ~= scale * T`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackedScales {
+    /// One scale for the whole tensor
+    Tensor(f32),
+    /// One scale per row (`[out_dim]`)
+    Channel(Vec<f32>),
+    /// One scale per `size` columns of each row (`[out_dim, in_dim.div_ceil(size)]`)
+    Group { size: usize, scales: Vec<f32> },
+}
+
+impl PackedScales {
+    /// Scales of one row of a `[_, cols]` matrix and the number of columns each covers
+    pub fn row(&self, row: usize, cols: usize) -> (&[f32], usize) {
+        match self {
+            Self::Tensor(scale) => (std::slice::from_ref(scale), cols),
+            Self::Channel(scales) => (&scales[row..row + 1], cols),
+            Self::Group { size, scales } => {
+                let groups = cols.div_ceil(*size);
+                (&scales[row * groups..(row + 1) * groups], *size)
+            }
+        }
+    }
+
+    /// Columns per scale for a `[_, cols]` matrix
+    pub fn group_size(&self, cols: usize) -> usize {
+        match self {
+            Self::Group { size, .. } => *size,
+            _ => cols,
+        }
+    }
+}
+
+/// Bytes of one tensor inside a memory-mapped file, read in place (never copied)
+#[derive(Debug, Clone)]
+pub struct MappedBytes {
+    map: Arc<memmap2::Mmap>,
+    range: Range<usize>,
+}
+
+impl MappedBytes {
+    pub fn new(map: Arc<memmap2::Mmap>, range: Range<usize>) -> Result<Self> {
+        if range.start > range.end || range.end > map.len() {
+            candle_core::bail!(
+                "MappedBytes: range {:?} outside a {} byte mapping",
+                range,
+                map.len()
+            );
+        }
+        Ok(Self { map, range })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+
+    /// Bytes `range` of this view (same mapping)
+    fn narrow(&self, range: Range<usize>) -> Result<Self> {
+        let start = self.range.start;
+        Self::new(self.map.clone(), start + range.start..start + range.end)
+    }
+}
+
+/// Packed codes (or a kernel layout of them): an owned u8 tensor, or a view
+/// into a memory-mapped packed checkpoint
+#[derive(Debug, Clone)]
+pub enum PackedBytes {
+    Tensor(Tensor),
+    Mapped(MappedBytes),
+}
+
+/// CPU byte view of `PackedBytes`, held for the duration of a kernel call
+pub enum PackedSlice<'a> {
+    Storage {
+        storage: RwLockReadGuard<'a, candle_core::Storage>,
+        range: Range<usize>,
+    },
+    Mapped(&'a [u8]),
+}
+
+impl PackedSlice<'_> {
+    pub fn as_slice(&self) -> Result<&[u8]> {
+        match self {
+            Self::Storage { storage, range } => match &**storage {
+                candle_core::Storage::Cpu(storage) => Ok(&storage.as_slice::<u8>()?[range.clone()]),
+                _ => candle_core::bail!("BitLinearCpu: Weights must be on CPU storage"),
+            },
+            Self::Mapped(bytes) => Ok(bytes),
+        }
+    }
+}
+
+impl PackedBytes {
+    /// Borrow the bytes without copying (CPU tensors and mappings only)
+    pub fn slice(&self) -> Result<PackedSlice<'_>> {
+        match self {
+            Self::Tensor(t) => {
+                let (storage, layout) = t.storage_and_layout();
+                if !layout.is_contiguous() {
+                    candle_core::bail!("BitLinearCpu: Weights must be contiguous");
+                }
+                let start = layout.start_offset();
+                Ok(PackedSlice::Storage {
+                    storage,
+                    range: start..start + layout.shape().elem_count(),
+                })
+            }
+            Self::Mapped(bytes) => Ok(PackedSlice::Mapped(bytes.as_slice())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Tensor(t) => t.elem_count(),
+            Self::Mapped(bytes) => bytes.range.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Self::Mapped(_))
+    }
+
+    /// Bytes `range` as a view sharing this storage (no copy)
+    pub fn narrow(&self, range: Range<usize>) -> Result<Self> {
+        if range.start > range.end || range.end > self.len() {
+            candle_core::bail!(
+                "PackedBytes: range {:?} outside {} bytes",
+                range,
+                self.len()
+            );
+        }
+        match self {
+            Self::Tensor(t) => Ok(Self::Tensor(t.flatten_all()?.narrow(
+                0,
+                range.start,
+                range.len(),
+            )?)),
+            Self::Mapped(bytes) => Ok(Self::Mapped(bytes.narrow(range)?)),
+        }
+    }
+
+    /// Owned copy of the bytes on the CPU
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Tensor(t) => t.to_device(&Device::Cpu)?.flatten_all()?.to_vec1::<u8>(),
+            Self::Mapped(bytes) => Ok(bytes.as_slice().to_vec()),
+        }
+    }
+
+    /// 1D u8 tensor on the CPU (copies a mapping)
+    pub fn to_tensor(&self) -> Result<Tensor> {
+        match self {
+            Self::Tensor(t) => t.to_device(&Device::Cpu)?.flatten_all(),
+            Self::Mapped(bytes) => {
+                Tensor::from_slice(bytes.as_slice(), bytes.range.len(), &Device::Cpu)
+            }
+        }
+    }
+}
+
 /// 1.58-bit Packed Tensor.
 /// Stores weights in a compressed 2-bit format (4 weights per u8).
 ///
@@ -13,11 +257,15 @@ const EPSILON: f32 = 1e-6;
 /// - 11 -> Unused/Padding
 #[derive(Debug, Clone)]
 pub struct PackedTensor {
-    pub data: Tensor, // [out_dim, in_dim/4] (u8)
-    pub scale: f32,
+    pub data: PackedBytes, // [out_dim, in_dim/4] (u8)
+    pub scales: PackedScales,
     pub shape: candle_core::Shape, // Original shape [out_dim, in_dim]
     pub num_elem: usize,
     pub device: Device,
+    /// Rows re-laid out for `BitLinearCpu::forward_int8` (CPU, u8), see `with_int8_layout`
+    pub int8_layout: Option<PackedBytes>,
+    /// Row tiles re-laid out for `BitLinearCpu::forward_lut` (CPU, u8), see `with_lut_layout`
+    pub lut_layout: Option<PackedBytes>,
 }
 
 impl PackedTensor {
@@ -38,11 +286,13 @@ impl PackedTensor {
         let tensor = Tensor::from_vec(data, (capacity,), device)?;
 
         Ok(Self {
-            data: tensor,
-            scale,
+            data: PackedBytes::Tensor(tensor),
+            scales: PackedScales::Tensor(scale),
             shape: shape.clone(),
             num_elem,
             device: device.clone(),
+            int8_layout: None,
+            lut_layout: None,
         })
     }
 
@@ -96,18 +346,428 @@ impl PackedTensor {
             Tensor::from_vec(packed_data, (capacity,), &Device::Cpu)?.to_device(device)?;
 
         Ok(Self {
-            data: data_tensor,
-            scale,
+            data: PackedBytes::Tensor(data_tensor),
+            scales: PackedScales::Tensor(scale),
             shape,
             num_elem,
             device: device.clone(),
+            int8_layout: None,
+            lut_layout: None,
         })
     }
 
+    /// Pack a `[out_dim, in_dim]` weight with scales of the given granularity.
+    /// Each scale is the absmean of the weights it covers; `group_size` (used by
+    /// `WeightScale::Group`) must be a positive multiple of 4.
+    pub fn pack_with(
+        tensor: &Tensor,
+        weight_scale: WeightScale,
+        group_size: usize,
+    ) -> Result<Self> {
+        let (rows, cols) = match weight_scale {
+            WeightScale::Tensor => return Self::pack(tensor),
+            _ => tensor.dims2()?,
+        };
+        let group = match weight_scale {
+            WeightScale::Group => group_size,
+            _ => cols,
+        };
+        if weight_scale == WeightScale::Group && (group == 0 || group / 4 * 4 != group) {
+            candle_core::bail!(
+                "PackedTensor: group size {} must be a positive multiple of 4",
+                group
+            );
+        }
+        let groups = cols.div_ceil(group);
+        let w = tensor
+            .to_dtype(candle_core::DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+
+        let mut codes = vec![0u8; rows * cols];
+        let mut scales = vec![0.0f32; rows * groups];
+        codes
+            .par_chunks_mut(cols)
+            .zip(scales.par_chunks_mut(groups))
+            .zip(w.par_chunks(cols))
+            .for_each(|((codes, scales), w)| {
+                for ((codes, scale), w) in codes
+                    .chunks_mut(group)
+                    .zip(scales.iter_mut())
+                    .zip(w.chunks(group))
+                {
+                    *scale = w.iter().map(|v| v.abs()).sum::<f32>() / w.len() as f32 + EPSILON;
+                    for (code, &v) in codes.iter_mut().zip(w) {
+                        let q = (v / *scale).round();
+                        *code = if q > 0.5 {
+                            1
+                        } else if q < -0.5 {
+                            2
+                        } else {
+                            0
+                        };
+                    }
+                }
+            });
+
+        let packed_data = codes
+            .chunks(4)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &code)| byte | (code << (i * 2)))
+            })
+            .collect();
+        let scales = match weight_scale {
+            WeightScale::Group => PackedScales::Group {
+                size: group,
+                scales,
+            },
+            _ => PackedScales::Channel(scales),
+        };
+        Self::new(packed_data, tensor.shape().clone(), 1.0, tensor.device())?.with_scales(scales)
+    }
+
+    /// Replace the scales (validated against the `[out_dim, in_dim]` shape)
+    pub fn with_scales(mut self, scales: PackedScales) -> Result<Self> {
+        let expected = match &scales {
+            PackedScales::Tensor(_) => None,
+            PackedScales::Channel(values) => Some((values.len(), self.shape.dims2()?.0)),
+            PackedScales::Group { size, scales } => {
+                let (rows, cols) = self.shape.dims2()?;
+                if *size == 0 || size / 4 * 4 != *size {
+                    candle_core::bail!(
+                        "PackedTensor: group size {} must be a positive multiple of 4",
+                        size
+                    );
+                }
+                Some((scales.len(), rows * cols.div_ceil(*size)))
+            }
+        };
+        if let Some((len, expected)) = expected {
+            if len != expected {
+                candle_core::bail!(
+                    "PackedTensor: {} scales for a {:?} weight (expected {})",
+                    len,
+                    self.shape.dims(),
+                    expected
+                );
+            }
+        }
+        self.scales = scales;
+        Ok(self)
+    }
+
+    /// Tensors storing this weight under `prefix`: `{prefix}.packed` (u8 codes),
+    /// `{prefix}.packed_scales` (f32 `[rows, groups]`), `{prefix}.packed_group`
+    /// (u32 columns per scale, 0 = one scale for the whole tensor) and the
+    /// kernel layouts present (`{prefix}.packed_int8`, `{prefix}.packed_lut`)
+    pub fn to_tensors(&self, prefix: &str) -> Result<Vec<(String, Tensor)>> {
+        let (values, rows, group) = match &self.scales {
+            PackedScales::Tensor(scale) => (vec![*scale], 1, 0),
+            PackedScales::Channel(scales) => (scales.clone(), scales.len(), self.shape.dims2()?.1),
+            PackedScales::Group { size, scales } => (scales.clone(), self.shape.dims2()?.0, *size),
+        };
+        let groups = values.len() / rows;
+        let mut tensors = vec![
+            (format!("{}.packed", prefix), self.data.to_tensor()?),
+            (
+                format!("{}.packed_scales", prefix),
+                Tensor::from_vec(values, (rows, groups), &Device::Cpu)?,
+            ),
+            (
+                format!("{}.packed_group", prefix),
+                Tensor::new(&[group as u32], &Device::Cpu)?,
+            ),
+        ];
+        if let Some(layout) = &self.int8_layout {
+            tensors.push((format!("{}.packed_int8", prefix), layout.to_tensor()?));
+        }
+        if let Some(layout) = &self.lut_layout {
+            tensors.push((format!("{}.packed_lut", prefix), layout.to_tensor()?));
+        }
+        Ok(tensors)
+    }
+
+    /// Inverse of `to_tensors` (without the kernel layouts): `get` looks up a
+    /// tensor by name (e.g. from a loaded safetensors file)
+    pub fn from_tensors(
+        prefix: &str,
+        shape: candle_core::Shape,
+        device: &Device,
+        get: impl Fn(&str) -> Result<Tensor>,
+    ) -> Result<Self> {
+        let data = get(&format!("{}.packed", prefix))?
+            .to_dtype(candle_core::DType::U8)?
+            .flatten_all()?
+            .to_device(device)?;
+        let values = get(&format!("{}.packed_scales", prefix))?
+            .to_dtype(candle_core::DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let group = get(&format!("{}.packed_group", prefix))?
+            .to_dtype(candle_core::DType::U32)?
+            .flatten_all()?
+            .to_vec1::<u32>()?;
+        let group = group.first().copied().unwrap_or(0) as usize;
+        Self::from_parts(PackedBytes::Tensor(data), values, group, shape, device)
+    }
+
+    /// Assemble stored codes and scales (`group` as written by `to_tensors`)
+    pub fn from_parts(
+        data: PackedBytes,
+        values: Vec<f32>,
+        group: usize,
+        shape: candle_core::Shape,
+        device: &Device,
+    ) -> Result<Self> {
+        if data.len() != shape.elem_count().div_ceil(4) {
+            candle_core::bail!(
+                "PackedTensor: {} code bytes for a {:?} weight",
+                data.len(),
+                shape.dims()
+            );
+        }
+        let scales = match group {
+            0 => match values.as_slice() {
+                [scale] => PackedScales::Tensor(*scale),
+                _ => candle_core::bail!(
+                    "PackedTensor: {} scales stored for a per-tensor weight",
+                    values.len()
+                ),
+            },
+            g if g >= shape.dims2()?.1 => PackedScales::Channel(values),
+            size => PackedScales::Group {
+                size,
+                scales: values,
+            },
+        };
+        Self {
+            data,
+            scales: PackedScales::Tensor(1.0),
+            num_elem: shape.elem_count(),
+            shape,
+            device: device.clone(),
+            int8_layout: None,
+            lut_layout: None,
+        }
+        .with_scales(scales)
+    }
+
+    /// Add the row layout of the int8 kernel: each row of the `[out, in]`
+    /// matrix is zero-padded to whole blocks of `INT8_BLOCK` weights, and byte
+    /// `b` of a 32-byte block holds weights `b`, `b + 32`, `b + 64` and `b + 96`
+    /// (bits 0-1, 2-3, 4-5, 6-7). One shift + mask then yields the codes of 32
+    /// consecutive weights, matching one 32-byte load of int8 activations.
+    ///
+    /// Per-group scales need groups of whole blocks (multiples of `INT8_BLOCK`).
+    pub fn with_int8_layout(mut self) -> Result<Self> {
+        let (rows, cols) = self.shape.dims2()?;
+        let data = self.data.to_vec()?;
+        let code = |idx: usize| (data[idx / 4] >> ((idx % 4) * 2)) & 0b11;
+
+        let row_bytes = self.int8_layout_len()? / rows.max(1);
+        let mut layout = vec![0u8; rows * row_bytes];
+        for (row, out) in layout.chunks_mut(row_bytes).enumerate() {
+            for (block, out) in out.chunks_mut(INT8_BLOCK / 4).enumerate() {
+                for (b, byte) in out.iter_mut().enumerate() {
+                    for s in 0..4 {
+                        let col = block * INT8_BLOCK + s * 32 + b;
+                        if col < cols {
+                            *byte |= code(row * cols + col) << (s * 2);
+                        }
+                    }
+                }
+            }
+        }
+        let layout = Tensor::from_vec(layout, rows * row_bytes, &Device::Cpu)?;
+        self.int8_layout = Some(PackedBytes::Tensor(layout));
+        Ok(self)
+    }
+
+    /// Add the tile layout of the LUT kernel: rows are grouped into tiles of
+    /// `LUT_ROWS` (zero-padded) and each row to a multiple of 4 weights. Within
+    /// a tile, byte `j` of the 32-byte block `p` is the packed byte of row `j`
+    /// at weights `4p..4p + 4`: its low/high nibble indexes the 16-entry table
+    /// of the weight pair `2p`/`2p + 1`, for all 32 rows of one load.
+    pub fn with_lut_layout(mut self) -> Result<Self> {
+        let (rows, cols) = self.shape.dims2()?;
+        let data = self.data.to_vec()?;
+        let code = |idx: usize| (data[idx / 4] >> ((idx % 4) * 2)) & 0b11;
+
+        let tile_bytes = cols.div_ceil(4) * LUT_ROWS;
+        let mut layout = vec![0u8; self.lut_layout_len()?];
+        for (tile, out) in layout.chunks_mut(tile_bytes).enumerate() {
+            for (p, block) in out.chunks_mut(LUT_ROWS).enumerate() {
+                for (j, byte) in block.iter_mut().enumerate() {
+                    let row = tile * LUT_ROWS + j;
+                    for s in 0..4 {
+                        let col = p * 4 + s;
+                        if row < rows && col < cols {
+                            *byte |= code(row * cols + col) << (s * 2);
+                        }
+                    }
+                }
+            }
+        }
+        let len = layout.len();
+        self.lut_layout = Some(PackedBytes::Tensor(Tensor::from_vec(
+            layout,
+            len,
+            &Device::Cpu,
+        )?));
+        Ok(self)
+    }
+
+    /// Bytes of the int8 kernel layout of this weight (fails if its scale
+    /// groups don't fit the int8 blocks)
+    pub fn int8_layout_len(&self) -> Result<usize> {
+        let (rows, cols) = self.shape.dims2()?;
+        let group = self.scales.group_size(cols);
+        if group < cols && group / INT8_BLOCK * INT8_BLOCK != group {
+            candle_core::bail!(
+                "PackedTensor: int8 layout needs scale groups of a multiple of {} columns (got {})",
+                INT8_BLOCK,
+                group
+            );
+        }
+        Ok(rows * cols.div_ceil(INT8_BLOCK) * INT8_BLOCK / 4)
+    }
+
+    /// Bytes of the LUT kernel layout of this weight
+    pub fn lut_layout_len(&self) -> Result<usize> {
+        let (rows, cols) = self.shape.dims2()?;
+        Ok(rows.div_ceil(LUT_ROWS) * cols.div_ceil(4) * LUT_ROWS)
+    }
+
+    /// Stack the rows of `parts` (all `[_, in_dim]`): `[a; b; ...]`.
+    /// Scales become per-tensor, per-channel or per-group as needed to keep
+    /// every row's own; kernel layouts are not carried over.
+    pub fn concat_rows(parts: &[&PackedTensor]) -> Result<Self> {
+        let mut order = Vec::new();
+        for (p, part) in parts.iter().enumerate() {
+            order.extend((0..part.shape.dims2()?.0).map(|row| (p, row)));
+        }
+        Self::gather_rows(parts, &order)
+    }
+
+    /// `part`, stacked as rows `rows` of this weight (`concat_rows`), with its
+    /// codes and kernel layouts replaced by views of this weight's where they
+    /// line up: codes starting on a byte, int8 rows, LUT tiles starting on a
+    /// tile. Scales stay the part's own.
+    pub fn view_rows(&self, rows: Range<usize>, part: &PackedTensor) -> Result<Self> {
+        let (total, cols) = self.shape.dims2()?;
+        if part.shape.dims2()? != (rows.len(), cols) || rows.end > total {
+            candle_core::bail!(
+                "PackedTensor: {:?} is not rows {:?} of {:?}",
+                part.shape.dims(),
+                rows,
+                self.shape.dims()
+            );
+        }
+        let mut view = part.clone();
+        if (rows.start * cols).is_multiple_of(4) {
+            let start = rows.start * cols / 4;
+            view.data = self.data.narrow(start..start + part.data.len())?;
+        }
+        if let (Some(layout), Some(_)) = (&self.int8_layout, &part.int8_layout) {
+            let row_bytes = cols.div_ceil(INT8_BLOCK) * INT8_BLOCK / 4;
+            view.int8_layout = Some(layout.narrow(rows.start * row_bytes..rows.end * row_bytes)?);
+        }
+        if let (Some(layout), Some(_)) = (&self.lut_layout, &part.lut_layout) {
+            if rows.start.is_multiple_of(LUT_ROWS) {
+                let start = rows.start / LUT_ROWS * cols.div_ceil(4) * LUT_ROWS;
+                view.lut_layout = Some(layout.narrow(start..start + part.lut_layout_len()?)?);
+            }
+        }
+        Ok(view)
+    }
+
+    /// New weight whose row `i` is row `order[i].1` of `parts[order[i].0]`
+    fn gather_rows(parts: &[&PackedTensor], order: &[(usize, usize)]) -> Result<Self> {
+        let Some(first) = parts.first() else {
+            candle_core::bail!("PackedTensor: no weights to combine");
+        };
+        let cols = first.shape.dims2()?.1;
+        // Scale groups of the result: the (common) group size of per-group parts
+        let mut group = None;
+        for part in parts {
+            if part.shape.dims2()?.1 != cols {
+                candle_core::bail!(
+                    "PackedTensor: cannot combine {:?} with {:?}",
+                    first.shape.dims(),
+                    part.shape.dims()
+                );
+            }
+            if let PackedScales::Group { size, .. } = &part.scales {
+                if group.is_some_and(|g| g != *size) {
+                    candle_core::bail!("PackedTensor: cannot combine different scale groups");
+                }
+                group = Some(*size);
+            }
+        }
+
+        let data = parts
+            .iter()
+            .map(|part| part.data.to_vec())
+            .collect::<Result<Vec<_>>>()?;
+        let mut codes = vec![0u8; order.len() * cols];
+        for (out, &(p, row)) in codes.chunks_mut(cols.max(1)).zip(order) {
+            for (col, code) in out.iter_mut().enumerate() {
+                let idx = row * cols + col;
+                *code = (data[p][idx / 4] >> ((idx % 4) * 2)) & 0b11;
+            }
+        }
+        let packed_data = codes
+            .chunks(4)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &code)| byte | (code << (i * 2)))
+            })
+            .collect();
+
+        let scales = match group {
+            Some(size) => {
+                let groups = cols.div_ceil(size);
+                let mut values = Vec::with_capacity(order.len() * groups);
+                for &(p, row) in order {
+                    match parts[p].scales.row(row, cols) {
+                        (scales, g) if g == size => values.extend_from_slice(scales),
+                        (scales, _) => values.extend(std::iter::repeat_n(scales[0], groups)),
+                    }
+                }
+                PackedScales::Group {
+                    size,
+                    scales: values,
+                }
+            }
+            None => match first.scales {
+                PackedScales::Tensor(scale)
+                    if parts
+                        .iter()
+                        .all(|p| p.scales == PackedScales::Tensor(scale)) =>
+                {
+                    PackedScales::Tensor(scale)
+                }
+                _ => PackedScales::Channel(
+                    order
+                        .iter()
+                        .map(|&(p, row)| parts[p].scales.row(row, cols).0[0])
+                        .collect(),
+                ),
+            },
+        };
+        let shape = candle_core::Shape::from((order.len(), cols));
+        Self::new(packed_data, shape, 1.0, &first.device)?.with_scales(scales)
+    }
+
     /// Unpack back to f32 tensor (for verification/fallback)
     pub fn unpack(&self, device: &Device) -> Result<Tensor> {
         // Pull data to CPU to unpack
-        let data_vec = self.data.to_vec1::<u8>()?;
+        let data_vec = self.data.to_vec()?;
         let mut floats = Vec::with_capacity(self.num_elem);
 
         for &byte in &data_vec {
@@ -127,9 +787,90 @@ impl PackedTensor {
         }
 
         // Restore scale
-        // Restore scale
+        let scale = match &self.scales {
+            PackedScales::Tensor(scale) => *scale,
+            scales => {
+                let cols = self.shape.dims2()?.1;
+                floats
+                    .par_chunks_mut(cols)
+                    .enumerate()
+                    .for_each(|(row, w)| {
+                        let (row_scales, group) = scales.row(row, cols);
+                        for (w, &scale) in w.chunks_mut(group).zip(row_scales) {
+                            w.iter_mut().for_each(|v| *v *= scale);
+                        }
+                    });
+                1.0
+            }
+        };
         let t = Tensor::from_vec(floats, self.shape.clone(), device)?;
-        (t * self.scale as f64)?.to_dtype(candle_core::DType::F32)
+        (t * scale as f64)?.to_dtype(candle_core::DType::F32)
+    }
+}
+
+/// Multi-base ternary weights of the adaptive format: `W = sum_b scales[b] * T_b`.
+///
+/// Byte `[row, g, b]` holds the 2-bit codes of columns `4g..4g + 4` of base `b`,
+/// so all bases of a 4-column group sit next to each other (`[out, in/4, num_bases]`).
+#[derive(Debug, Clone)]
+pub struct MultiBasePacked {
+    pub data: Tensor, // [out_dim, in_dim/4, num_bases] (u8, CPU)
+    pub scales: Vec<f32>,
+    pub shape: candle_core::Shape, // Original shape [out_dim, in_dim]
+}
+
+impl MultiBasePacked {
+    /// Wrap interleaved codes `[out_dim, in_dim/4, scales.len()]` (u8) on the CPU
+    pub fn new(data: Tensor, scales: Vec<f32>, out_dim: usize, in_dim: usize) -> Result<Self> {
+        if in_dim / 4 * 4 != in_dim || scales.is_empty() {
+            candle_core::bail!(
+                "MultiBasePacked: in_dim {} must be a multiple of 4 with at least one base",
+                in_dim
+            );
+        }
+        let expected = (out_dim, in_dim / 4, scales.len());
+        if data.dims3()? != expected {
+            candle_core::bail!(
+                "MultiBasePacked: codes {:?} do not match {:?}",
+                data.dims(),
+                expected
+            );
+        }
+        Ok(Self {
+            data: data
+                .to_dtype(candle_core::DType::U8)?
+                .to_device(&Device::Cpu)?
+                .contiguous()?,
+            scales,
+            shape: candle_core::Shape::from((out_dim, in_dim)),
+        })
+    }
+
+    pub fn num_bases(&self) -> usize {
+        self.scales.len()
+    }
+
+    /// Dense f32 weight [out, in] (LoRA merges, export, non-CPU devices)
+    pub fn unpack(&self, device: &Device) -> Result<Tensor> {
+        let (out_dim, in_dim) = self.shape.dims2()?;
+        let data = self.data.flatten_all()?.to_vec1::<u8>()?;
+        let mut dense = vec![0.0f32; out_dim * in_dim];
+        dense
+            .par_chunks_mut(in_dim)
+            .zip(data.par_chunks(in_dim / 4 * self.num_bases()))
+            .for_each(|(row_w, row_codes)| {
+                for (w4, group) in row_w
+                    .chunks_exact_mut(4)
+                    .zip(row_codes.chunks_exact(self.num_bases()))
+                {
+                    for (&byte, &scale) in group.iter().zip(&self.scales) {
+                        for (w, &coeff) in w4.iter_mut().zip(&COEFF_LUT[byte as usize]) {
+                            *w += coeff * scale;
+                        }
+                    }
+                }
+            });
+        Tensor::from_vec(dense, (out_dim, in_dim), device)
     }
 }
 
@@ -163,7 +904,10 @@ mod tests {
         let packed = PackedTensor::pack(&tensor)?;
 
         // Scale ~1.0 + EPSILON
-        assert!((packed.scale - 1.0).abs() < 1e-3);
+        let PackedScales::Tensor(scale) = packed.scales else {
+            panic!("pack must use a per-tensor scale");
+        };
+        assert!((scale - 1.0).abs() < 1e-3);
 
         let unpacked = packed.unpack(&Device::Cpu)?;
         let output_data = unpacked.to_vec1::<f32>()?;
@@ -181,7 +925,10 @@ mod tests {
         let packed = PackedTensor::pack(&tensor)?;
 
         // Scale = Sum(|x|) / N = 4 / 8 = 0.5
-        assert!((packed.scale - 0.5).abs() < 1e-3);
+        let PackedScales::Tensor(scale) = packed.scales else {
+            panic!("pack must use a per-tensor scale");
+        };
+        assert!((scale - 0.5).abs() < 1e-3);
 
         let unpacked = packed.unpack(&Device::Cpu)?;
         let output_data = unpacked.to_vec1::<f32>()?;
@@ -232,6 +979,58 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_int8_layout_interleaving() -> Result<()> {
+        // 2 rows x 130 columns: row 0 = +1 except col 33 (-1), row 1 = 0 except col 129 (+1)
+        let mut w = vec![1.0f32; 130];
+        w[33] = -1.0;
+        let mut row1 = vec![0.0f32; 130];
+        row1[129] = 1.0;
+        w.extend(row1);
+        let tensor = Tensor::from_vec(w, (2, 130), &Device::Cpu)?;
+        let packed = PackedTensor::pack(&tensor)?.with_int8_layout()?;
+
+        // Each row padded to 256 weights = 64 bytes
+        let layout = packed.int8_layout.unwrap().to_vec()?;
+        assert_eq!(layout.len(), 2 * 64);
+        // Row 0, byte 1: cols 1, 33, 65, 97 -> +1, -1, +1, +1
+        assert_eq!(layout[1], 0b01_01_10_01);
+        // Row 0, second block: cols 128, 129 only
+        assert_eq!(layout[32], 0b01);
+        assert_eq!(layout[33], 0b01);
+        assert!(layout[34..64].iter().all(|&b| b == 0));
+        // Row 1: only col 129
+        assert_eq!(layout[64 + 33], 0b01);
+        assert_eq!(layout[64..].iter().filter(|&&b| b != 0).count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lut_layout_tiles() -> Result<()> {
+        // 33 rows x 6 columns: row r holds +1 at col r % 6, row 32 also -1 at col 5
+        let mut w = vec![0.0f32; 33 * 6];
+        for r in 0..33 {
+            w[r * 6 + r % 6] = 1.0;
+        }
+        w[32 * 6 + 5] = -1.0;
+        let tensor = Tensor::from_vec(w, (33, 6), &Device::Cpu)?;
+        let packed = PackedTensor::pack(&tensor)?.with_lut_layout()?;
+
+        // 2 tiles x 2 blocks (cols padded to 8) x 32 rows
+        let layout = packed.lut_layout.unwrap().to_vec()?;
+        assert_eq!(layout.len(), 2 * 2 * 32);
+        // Tile 0, block 0, row 1: +1 at col 1 -> bits 2-3
+        assert_eq!(layout[1], 0b01 << 2);
+        // Tile 0, block 1, row 5: +1 at col 5 -> bits 2-3
+        assert_eq!(layout[32 + 5], 0b01 << 2);
+        assert_eq!(layout[5], 0);
+        // Tile 1, row 32 (j = 0): +1 at col 2, -1 at col 5; padding rows stay zero
+        assert_eq!(layout[64], 0b01 << 4);
+        assert_eq!(layout[96], 0b10 << 2);
+        assert_eq!(layout[64..].iter().filter(|&&b| b != 0).count(), 2);
+        Ok(())
+    }
+
     #[test]
     fn test_packing_padding() -> Result<()> {
         // 5 elements -> 2 bytes.
@@ -244,9 +1043,9 @@ mod tests {
         let tensor = Tensor::new(&input_data[..], &Device::Cpu)?;
 
         let packed = PackedTensor::pack(&tensor)?;
-        assert_eq!(packed.data.dims1()?, 2);
+        assert_eq!(packed.data.len(), 2);
 
-        let data = packed.data.to_vec1::<u8>()?;
+        let data = packed.data.to_vec()?;
         assert_eq!(data[0], 85);
         assert_eq!(data[1], 2);
 
@@ -2,27 +2,46 @@
 //!
 //! This module contains the building blocks for the Bit-Llama architecture:
 //! - RMSNorm: Root Mean Square Layer Normalization
+//! - Precision: F32/F16/BF16 storage and compute of dense weights
 //! - BitLinear: 1.58-bit quantized linear layer
+//! - FusedProjection: q/k/v or gate/up packed as one weight (one pass over the input)
 //! - SwiGLU: Gated MLP with SiLU activation
+//! - MoE: Sparse Mixture-of-Experts over SwiGLU experts
+//! - LoRA: Low-rank adapters for parameter-efficient fine-tuning
 //! - TTTLayer: Test-Time Training with online learning
+//! - VocabTable: embedding / lm_head weights, dense or int8 / ternary quantized
 
 use candle_core::{Result, Tensor};
 
 pub mod adaptive_linear;
 pub mod attention;
 pub mod bit_linear;
+pub mod fused;
+pub mod lora;
+pub mod moe;
+pub mod precision;
 pub mod rms_norm;
 pub mod swiglu;
 pub mod ttt;
+pub mod vocab_table;
 
 pub use adaptive_linear::AdaptiveBitLinear;
 pub use attention::{BitAttention, KVCache};
 pub use bit_linear::BitLinear;
+pub use fused::FusedProjection;
+pub use lora::{LoraAdapter, LoraConfig, LoraTarget};
+pub use moe::MoE;
+pub use precision::{DensePrecision, FloatDtype};
 pub use rms_norm::RMSNorm;
 pub use swiglu::SwiGLU;
 pub use ttt::TTTLayer;
+pub use vocab_table::{QuantizedTable, TableQuant, VocabTable};
 pub mod kv_cache;
-pub use kv_cache::QuantizedKVCache;
+pub mod kv_quant;
+pub mod paged_kv_cache;
+pub use kv_cache::{KvView, QuantizedKV, QuantizedKVCache};
+pub use kv_quant::KvCacheDtype;
+pub use paged_kv_cache::{BlockTable, PagedKVCache, PagedLayerSpec};
 
 // --- Helper Trait for Robust Operations ---
 pub(crate) trait TensorExt {
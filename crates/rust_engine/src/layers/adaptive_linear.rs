@@ -1,39 +1,27 @@
-//! AdaptiveBitLinear - Optimized Loading with Rayon & LUT
+//! AdaptiveBitLinear - BitNet or multi-base adaptive weights, computed natively on CPU
 
-use super::BitLinear;
+use super::{BitLinear, DensePrecision, LoraAdapter};
+use crate::kernels::backend;
+use crate::kernels::packing::{MultiBasePacked, PackOptions};
 use candle_core::{Device, Result, Tensor};
 use candle_nn::VarBuilder;
-use rayon::prelude::*; // 並列処理用
-
-// 🔥 高速化の要: 0-255 のバイト値を 4つのf32値に変換する「カンニングペーパー」
-// 計算を一切せず、メモリから値を拾うだけにします。
-static UNPACK_LUT: [[f32; 4]; 256] = {
-    let mut table = [[0.0; 4]; 256];
-    let mut i = 0;
-    while i < 256 {
-        let byte = i as u8;
-        let mut j = 0;
-        while j < 4 {
-            // 2bit: 00=0, 01=1, 10=-1, 11=0
-            let val = (byte >> (j * 2)) & 0b11;
-            table[i][j] = match val {
-                1 => 1.0,
-                2 => -1.0,
-                _ => 0.0,
-            };
-            j += 1;
-        }
-        i += 1;
-    }
-    table
-};
 
 #[derive(Clone)]
 pub struct AdaptiveBitLinear {
     pub legacy_linear: Option<BitLinear>,
+    /// Multi-base codes + scales, the only resident copy on CPU (`TernaryKernel::adaptive_matmul`)
+    pub packed_bases: Option<MultiBasePacked>,
+    /// Dense weight: adaptive layers on non-CPU devices and merged LoRA layers
     pub reconstructed_weight: Option<Tensor>,
+    /// Storage / compute dtypes of `reconstructed_weight`
+    pub precision: DensePrecision,
     pub in_features: usize,
     pub out_features: usize,
+    /// Optional full-precision low-rank adapter added on top of the frozen weights
+    pub lora: Option<LoraAdapter>,
+    /// Per-request adapters, one per row of dim 0 (batched serving).
+    /// When non-empty it overrides `lora`; a `None` row falls back to `lora`.
+    pub lora_rows: Vec<Option<LoraAdapter>>,
 }
 
 impl AdaptiveBitLinear {
@@ -42,9 +30,13 @@ impl AdaptiveBitLinear {
         if let Ok(linear) = BitLinear::load(in_dim, out_dim, vb.clone(), device) {
             return Ok(Self {
                 legacy_linear: Some(linear),
+                packed_bases: None,
                 reconstructed_weight: None,
+                precision: DensePrecision::default(),
                 in_features: in_dim,
                 out_features: out_dim,
+                lora: None,
+                lora_rows: Vec::new(),
             });
         }
 
@@ -53,74 +45,41 @@ impl AdaptiveBitLinear {
             if let Ok(scales) = vb.get((num_bases,), "scales") {
                 let packed = vb.get((out_dim, in_dim / 4, num_bases), "weight_packed")?;
 
-                // CPUに一度持ってくる
-                let packed_cpu = packed.to_device(&Device::Cpu)?;
-                let scales_cpu = scales.to_device(&Device::Cpu)?;
-
                 eprintln!(
                     "🚀 [FAST-LOAD] Loading layer: {}x{} (bases={})",
                     in_dim, out_dim, num_bases
                 );
 
-                // 生データを取得 (Type agnostic handling)
-                let packed_dtype = packed_cpu.dtype();
-                let packed_vec = match packed_dtype {
-                    candle_core::DType::U8 => packed_cpu.flatten_all()?.to_vec1::<u8>()?,
+                // Type agnostic handling
+                let packed = match packed.dtype() {
+                    candle_core::DType::U8 => packed,
                     candle_core::DType::F32 => {
                         eprintln!("⚠️ [FAST-LOAD] Converting F32 packed weights to U8 (Legacy Model Format)");
-                        // Use Candle's native cast (optimized)
-                        packed_cpu
-                            .to_dtype(candle_core::DType::U8)?
-                            .flatten_all()?
-                            .to_vec1::<u8>()?
+                        packed.to_dtype(candle_core::DType::U8)?
                     }
-                    _ => {
-                        candle_core::bail!("Unexpected dtype for weight_packed: {:?}", packed_dtype)
+                    other => {
+                        candle_core::bail!("Unexpected dtype for weight_packed: {:?}", other)
                     }
                 };
+                let scales = scales.to_device(&Device::Cpu)?.to_vec1::<f32>()?;
+                let packed = MultiBasePacked::new(packed, scales, out_dim, in_dim)?;
 
-                let scales_vec = scales_cpu.to_vec1::<f32>()?;
-
-                // 🚀 【ここが高速化の核心】
-                // Rayonを使って「行ごと」に並列処理で解凍・再構築する
-                let packed_row_stride = (in_dim / 4) * num_bases;
-
-                let rows: Vec<Vec<f32>> = (0..out_dim)
-                    .into_par_iter()
-                    .map(|row_idx| {
-                        let mut row_w = vec![0.0f32; in_dim];
-                        let row_start = row_idx * packed_row_stride;
-
-                        for base in 0..num_bases {
-                            let scale = scales_vec[base];
-
-                            for col_pack in 0..(in_dim / 4) {
-                                // LUTを使って一瞬で値を取得
-                                let flat_idx = row_start + (col_pack * num_bases) + base;
-                                let byte_val = packed_vec[flat_idx];
-                                let vals = UNPACK_LUT[byte_val as usize];
-
-                                // 加算
-                                let out_col_base = col_pack * 4;
-                                row_w[out_col_base + 0] += vals[0] * scale;
-                                row_w[out_col_base + 1] += vals[1] * scale;
-                                row_w[out_col_base + 2] += vals[2] * scale;
-                                row_w[out_col_base + 3] += vals[3] * scale;
-                            }
-                        }
-                        row_w
-                    })
-                    .collect();
-
-                // 結合してTensor化
-                let final_flat: Vec<f32> = rows.into_iter().flatten().collect();
-                let w_recon = Tensor::from_vec(final_flat, (out_dim, in_dim), device)?;
+                // CPU: compute on the packed codes directly. Other devices: dense weight
+                let (packed_bases, reconstructed_weight) = if device.is_cpu() {
+                    (Some(packed), None)
+                } else {
+                    (None, Some(packed.unpack(device)?))
+                };
 
                 return Ok(Self {
                     legacy_linear: None,
-                    reconstructed_weight: Some(w_recon),
+                    packed_bases,
+                    reconstructed_weight,
+                    precision: DensePrecision::default(),
                     in_features: in_dim,
                     out_features: out_dim,
+                    lora: None,
+                    lora_rows: Vec::new(),
                 });
             }
         }
@@ -129,18 +88,93 @@ impl AdaptiveBitLinear {
     }
 
     pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.forward_routed(x, None)
+    }
+
+    /// `forward` where row `i` of `x` belongs to batch row `rows[i]`, so row
+    /// adapters follow inputs that are a subset of the batch (MoE experts)
+    pub fn forward_routed(&self, x: &Tensor, rows: Option<&[usize]>) -> Result<Tensor> {
+        let base = self.forward_base(x)?;
+        match self.lora_delta(x, rows)? {
+            Some(delta) => {
+                let delta = if delta.device().same_device(base.device()) {
+                    delta
+                } else {
+                    delta.to_device(base.device())?
+                };
+                base + delta
+            }
+            None => Ok(base),
+        }
+    }
+
+    /// Adapter contribution for `x` (None if no adapter applies to any row)
+    fn lora_delta(&self, x: &Tensor, batch_rows: Option<&[usize]>) -> Result<Option<Tensor>> {
+        if self.lora_rows.is_empty() {
+            return self.lora.as_ref().map(|a| a.forward(x)).transpose();
+        }
+        let (rows, installed) = (x.dim(0)?, self.lora_rows.len());
+        let batch_rows: Vec<usize> = match batch_rows {
+            None if rows == installed => (0..rows).collect(),
+            None => candle_core::bail!(
+                "AdaptiveBitLinear: input has {} rows but {} row adapters are installed",
+                rows,
+                installed
+            ),
+            Some(batch_rows)
+                if batch_rows.len() == rows && batch_rows.iter().all(|&r| r < installed) =>
+            {
+                batch_rows.to_vec()
+            }
+            Some(batch_rows) => candle_core::bail!(
+                "AdaptiveBitLinear: {} input rows routed from batch rows {:?}, {} row adapters installed",
+                rows,
+                batch_rows,
+                installed
+            ),
+        };
+        let slots: Vec<Option<&LoraAdapter>> = batch_rows
+            .iter()
+            .map(|&r| self.lora_rows[r].as_ref().or(self.lora.as_ref()))
+            .collect();
+        if slots.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+
+        let mut out_shape = x.dims().to_vec();
+        *out_shape.last_mut().unwrap() = self.out_features;
+        out_shape[0] = 1;
+        let mut parts = Vec::with_capacity(rows);
+        for (row, slot) in slots.into_iter().enumerate() {
+            let x_row = x.narrow(0, row, 1)?;
+            parts.push(match slot {
+                Some(adapter) => adapter.forward(&x_row)?,
+                None => Tensor::zeros(out_shape.as_slice(), x.dtype(), x.device())?,
+            });
+        }
+        Ok(Some(Tensor::cat(&parts, 0)?))
+    }
+
+    /// Forward through the frozen (ternary, multi-base or reconstructed) weights only
+    pub fn forward_base(&self, x: &Tensor) -> Result<Tensor> {
         if let Some(linear) = &self.legacy_linear {
             return linear.forward(x);
         }
-        if let Some(w_recon) = &self.reconstructed_weight {
-            // 入力次元の調整 [Batch, Seq, In] -> [Batch*Seq, In]
-            let (x_flat, original_shape) = if x.rank() == 3 {
-                let (b, s, _) = x.dims3()?;
-                (x.flatten(0, 1)?, Some((b, s)))
-            } else {
-                (x.clone(), None)
-            };
+        // 入力次元の調整 [Batch, Seq, In] -> [Batch*Seq, In]
+        let (x_flat, original_shape) = if x.rank() == 3 {
+            let (b, s, _) = x.dims3()?;
+            (x.flatten(0, 1)?, Some((b, s)))
+        } else {
+            (x.clone(), None)
+        };
 
+        let result = if let Some(packed) = &self.packed_bases {
+            // Packed codes live on the CPU; bring results back to the caller's device
+            let x_cpu = x_flat.to_device(&Device::Cpu)?;
+            backend::for_device(&Device::Cpu)?
+                .adaptive_matmul(&x_cpu, packed)?
+                .to_device(x_flat.device())?
+        } else if let Some(w_recon) = &self.reconstructed_weight {
             // デバイス整合性チェックと移動
             let w = if w_recon.device().same_device(x_flat.device()) {
                 w_recon.clone()
@@ -148,21 +182,75 @@ impl AdaptiveBitLinear {
                 // ここで転送ログを出すとうるさいので、必要な時だけにする
                 w_recon.to_device(x_flat.device())?
             };
+            self.precision.forward(&x_flat, &w)?
+        } else {
+            candle_core::bail!("AdaptiveBitLinear: Invalid State")
+        };
 
-            let result = x_flat.matmul(&w.t()?)?;
+        if let Some((b, s)) = original_shape {
+            let (_, out_d) = result.dims2()?;
+            return result.reshape((b, s, out_d));
+        }
+        Ok(result)
+    }
 
-            if let Some((b, s)) = original_shape {
-                let (_, out_d) = result.dims2()?;
-                return result.reshape((b, s, out_d));
-            }
-            return Ok(result);
+    pub fn device(&self) -> &Device {
+        match (&self.legacy_linear, &self.reconstructed_weight) {
+            (Some(linear), _) => linear.weight.device(),
+            (None, Some(w)) => w.device(),
+            // Packed multi-base codes (or nothing) stay on the CPU
+            (None, None) => &Device::Cpu,
+        }
+    }
+
+    /// Dense f32 weight [out, in] as the forward pass effectively sees it
+    /// (ternary values times scale for legacy BitLinear layers)
+    pub fn effective_weight(&self) -> Result<Tensor> {
+        if let Some(linear) = &self.legacy_linear {
+            let packed = match &linear.packed_params {
+                Some(p) => p.clone(),
+                None => crate::kernels::packing::PackedTensor::pack(&linear.weight)?,
+            };
+            return packed.unpack(linear.weight.device());
+        }
+        if let Some(packed) = &self.packed_bases {
+            return packed.unpack(&Device::Cpu);
+        }
+        if let Some(w) = &self.reconstructed_weight {
+            return w.to_dtype(candle_core::DType::F32);
         }
         candle_core::bail!("AdaptiveBitLinear: Invalid State")
     }
 
-    pub fn precompute_packed(&mut self) -> Result<()> {
+    /// Use `precision` for the dense path (converting a resident dense weight)
+    pub fn set_precision(&mut self, precision: DensePrecision) -> Result<()> {
+        if let Some(w) = &self.reconstructed_weight {
+            self.reconstructed_weight = Some(precision.store(w)?);
+        }
+        self.precision = precision;
+        Ok(())
+    }
+
+    /// Fold the adapter into the weights: W' = W + scale * B @ A.
+    ///
+    /// The merged layer is no longer ternary (or multi-base), so it switches
+    /// to the dense reconstructed path.
+    pub fn merge_lora(&mut self) -> Result<()> {
+        let Some(adapter) = self.lora.take() else {
+            return Ok(());
+        };
+        let base = self.effective_weight()?;
+        let delta = adapter.delta_weight()?.to_device(base.device())?;
+        let merged = (base + delta)?.detach();
+        self.reconstructed_weight = Some(self.precision.store(&merged)?);
+        self.legacy_linear = None;
+        self.packed_bases = None;
+        Ok(())
+    }
+
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
         if let Some(linear) = &mut self.legacy_linear {
-            linear.precompute_packed()?;
+            linear.precompute_packed(options)?;
         }
         Ok(())
     }
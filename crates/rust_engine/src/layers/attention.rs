@@ -1,5 +1,7 @@
-use super::AdaptiveBitLinear;
-use candle_core::{Device, Result, Tensor};
+use super::{AdaptiveBitLinear, BlockTable, FusedProjection, PagedKVCache};
+use crate::kernels::backend::{self, PagedRead};
+use crate::kernels::packing::PackOptions;
+use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::{ops::softmax, VarBuilder};
 
 /// Rotary Position Embedding for TinyLlama
@@ -95,6 +97,10 @@ pub struct BitAttention {
     pub head_dim: usize,
     pub scaling: f64,
     pub rotary_emb: RotaryEmbedding,
+    /// CPU: compute attention directly over the quantized KV cache
+    pub quantized_kv: bool,
+    /// q/k/v stacked into one packed weight (built by `precompute_packed`)
+    pub fused_qkv: Option<FusedProjection>,
 }
 
 // [Phase 5.2] Use QuantizedKVCache for memory optimization
@@ -143,9 +149,45 @@ impl BitAttention {
             head_dim,
             scaling,
             rotary_emb,
+            quantized_kv: false,
+            fused_qkv: None,
         })
     }
 
+    /// Stack q/k/v into `fused_qkv` if they are packed (the projections
+    /// themselves are not packed here; they stay on the STE path otherwise)
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
+        self.fused_qkv = if options.fuse_projections {
+            let mut parts = [&mut self.q_proj, &mut self.k_proj, &mut self.v_proj];
+            FusedProjection::concat(&mut parts, options)?
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// `fused_qkv` while q/k/v still run on their packed weights alone
+    pub fn qkv_fusion(&self) -> Option<&FusedProjection> {
+        self.fused_qkv
+            .as_ref()
+            .filter(|_| FusedProjection::applies_to(&[&self.q_proj, &self.k_proj, &self.v_proj]))
+    }
+
+    /// q, k, v projections of x [.., Hidden] (one fused kernel call if possible)
+    fn project(&self, x: &Tensor) -> Result<(Tensor, Tensor, Tensor)> {
+        if let Some(fused) = self.qkv_fusion() {
+            let y = fused.forward(x)?;
+            if let [q, k, v] = &fused.split(&y)?[..] {
+                return Ok((q.clone(), k.clone(), v.clone()));
+            }
+        }
+        Ok((
+            self.q_proj.forward(x)?,
+            self.k_proj.forward(x)?,
+            self.v_proj.forward(x)?,
+        ))
+    }
+
     pub fn forward(
         &self,
         x: &Tensor,
@@ -156,9 +198,7 @@ impl BitAttention {
 
         // DEBUG: Unconditional Trace
 
-        let q = self.q_proj.forward(x)?;
-        let k_new = self.k_proj.forward(x)?;
-        let v_new = self.v_proj.forward(x)?;
+        let (q, k_new, v_new) = self.project(x)?;
 
         // DEBUG: Trace Input devices
 
@@ -169,17 +209,50 @@ impl BitAttention {
         let k = k_new
             .reshape((b_sz, seq_len, self.n_kv_heads, self.head_dim))?
             .transpose(1, 2)?;
-        let mut v = v_new
+        let v = v_new
             .reshape((b_sz, seq_len, self.n_kv_heads, self.head_dim))?
             .transpose(1, 2)?;
 
+        let y = self.attend(&q, &k, &v, kv_cache, pos)?;
+
+        // Reassemble: [Batch, Heads, Seq, Dim] -> [Batch, Seq, Heads, Dim] -> [Batch, Seq, Hidden]
+        let y = y.transpose(1, 2)?.reshape((b_sz, seq_len, hidden))?;
+
+        let y = self.o_proj.forward(&y)?;
+
+        Ok(y)
+    }
+
+    /// RoPE + cache update + scaled dot-product attention for one cache.
+    ///
+    /// q: [Batch, Heads, Seq, Dim], k/v: [Batch, KV_Heads, Seq, Dim] (pre-RoPE)
+    /// Returns [Batch, Heads, Seq, Dim]
+    fn attend(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        v: &Tensor,
+        kv_cache: &mut Option<KVCache>,
+        pos: usize,
+    ) -> Result<Tensor> {
+        let (_, _, seq_len, _) = q.dims4()?;
+
         // Apply RoPE to new Q and new K
         // q: [batch, heads, seq_len, dim] -> rotated at pos..pos+seq_len
         // k: [batch, kv_heads, seq_len, dim] -> rotated at pos..pos+seq_len
 
-        let q = self.rotary_emb.apply(&q, pos, seq_len)?;
+        let q = self.rotary_emb.apply(q, pos, seq_len)?;
         // Make k mutable for caching concat later
-        let mut k = self.rotary_emb.apply(&k, pos, seq_len)?;
+        let mut k = self.rotary_emb.apply(k, pos, seq_len)?;
+        let mut v = v.clone();
+
+        // Fused path: attend straight over the stored cache (no f32 copy of K/V)
+        if self.quantized_kv && q.device().is_cpu() && q.dtype() == DType::F32 {
+            if let Some(cache) = kv_cache {
+                let kv = cache.append_quantized(&k, &v)?;
+                return backend::for_device(q.device())?.attention(&q, &kv, self.scaling);
+            }
+        }
 
         // NOW Update Cache
         // [Phase 5.2] Update Cache (Quantized)
@@ -193,6 +266,23 @@ impl BitAttention {
         // DEBUG: Check K/V devices post-cache
 
         // If no cache (e.g. initial prefill without persistent state?), we use k, v as is.
+        self.attention(&q, k, v)
+    }
+
+    /// Scaled dot-product attention with implicit causal mask over
+    /// q: [Batch, Heads, Seq, Dim], k/v: [Batch, KV_Heads, Past + Seq, Dim]
+    fn attention(&self, q: &Tensor, k: Tensor, v: Tensor) -> Result<Tensor> {
+        let (_, _, seq_len, _) = q.dims4()?;
+
+        // CPU: tiled online-softmax kernel (no score matrix, no repeat_kv, implicit mask).
+        // Its output is a fresh tensor, so training (q/k/v in the graph) keeps
+        // the differentiable path below.
+        let needs_grad = q.track_op() || k.track_op() || v.track_op();
+        if q.device().is_cpu() && q.dtype() == DType::F32 && k.dtype() == DType::F32 && !needs_grad
+        {
+            let kv = backend::float_kv(&k, &v);
+            return backend::for_device(q.device())?.attention(q, &kv, self.scaling);
+        }
 
         // GQA handling: Repeat K/V if n_kv_heads < n_heads
         let k = self.repeat_kv(k)?;
@@ -214,14 +304,112 @@ impl BitAttention {
         let att = softmax(&att, candle_core::D::Minus1)?;
 
         // Out = Attn @ V
-        let y = att.matmul(&v)?;
+        att.matmul(&v)
+    }
 
-        // Reassemble: [Batch, Heads, Seq, Dim] -> [Batch, Seq, Heads, Dim] -> [Batch, Seq, Hidden]
-        let y = y.transpose(1, 2)?.reshape((b_sz, seq_len, hidden))?;
+    /// Single decode step for a batch of independent sequences.
+    ///
+    /// x: [Batch, 1, Hidden]; row `b` uses `kv_caches[b]` at `positions[b]`.
+    /// Projections run batched (so per-row adapters apply), attention runs
+    /// against each sequence's own cache.
+    pub fn forward_batch(
+        &self,
+        x: &Tensor,
+        kv_caches: &mut [&mut Option<KVCache>],
+        positions: &[usize],
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len, hidden) = x.dims3()?;
+        if seq_len != 1 || kv_caches.len() != b_sz || positions.len() != b_sz {
+            candle_core::bail!(
+                "forward_batch expects [B, 1, H] with B caches/positions (got {:?}, {} caches, {} positions)",
+                x.dims(),
+                kv_caches.len(),
+                positions.len()
+            );
+        }
 
-        let y = self.o_proj.forward(&y)?;
+        let (q, k, v) = self.project_decode(x)?;
+        let mut rows = Vec::with_capacity(b_sz);
+        for (b, cache) in kv_caches.iter_mut().enumerate() {
+            rows.push(self.attend(
+                &q.narrow(0, b, 1)?,
+                &k.narrow(0, b, 1)?,
+                &v.narrow(0, b, 1)?,
+                cache,
+                positions[b],
+            )?);
+        }
+        let y = Tensor::cat(&rows, 0)?;
 
-        Ok(y)
+        let y = y.transpose(1, 2)?.reshape((b_sz, 1, hidden))?;
+        self.o_proj.forward(&y)
+    }
+
+    /// `forward_batch` over a shared `PagedKVCache`: row `b` is the next token
+    /// of the sequence in `tables[b]` (blocks already reserved).
+    pub fn forward_paged(
+        &self,
+        x: &Tensor,
+        cache: &PagedKVCache,
+        layer: usize,
+        tables: &[&BlockTable],
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len, hidden) = x.dims3()?;
+        if seq_len != 1 || tables.len() != b_sz {
+            candle_core::bail!(
+                "forward_paged expects [B, 1, H] with B block tables (got {:?}, {} tables)",
+                x.dims(),
+                tables.len()
+            );
+        }
+
+        let (q, k, v) = self.project_decode(x)?;
+        let mut rows = Vec::with_capacity(b_sz);
+        for (b, table) in tables.iter().enumerate() {
+            let pos = table.len();
+            let q = self.rotary_emb.apply(&q.narrow(0, b, 1)?, pos, 1)?;
+            let k = self.rotary_emb.apply(&k.narrow(0, b, 1)?, pos, 1)?;
+            cache.write(layer, table, &k, &v.narrow(0, b, 1)?)?;
+
+            // Read straight through the block table on CPU, gather elsewhere
+            let y = if q.device().is_cpu() && q.dtype() == DType::F32 {
+                let read = PagedRead {
+                    blocks: table.blocks(),
+                    block_size: cache.block_size(),
+                    len: pos + 1,
+                };
+                backend::for_device(q.device())?.paged_attention(
+                    &q,
+                    &cache.pool_view(layer)?,
+                    read,
+                    self.scaling,
+                )?
+            } else {
+                let (k, v) = cache.gather(layer, table, pos + 1)?.dequantize()?;
+                self.attention(&q, k.to_dtype(q.dtype())?, v.to_dtype(q.dtype())?)?
+            };
+            rows.push(y);
+        }
+        let y = Tensor::cat(&rows, 0)?;
+
+        let y = y.transpose(1, 2)?.reshape((b_sz, 1, hidden))?;
+        self.o_proj.forward(&y)
+    }
+
+    /// Batched projections of one token per row: q [B, Heads, 1, Dim], k/v [B, KV_Heads, 1, Dim]
+    fn project_decode(&self, x: &Tensor) -> Result<(Tensor, Tensor, Tensor)> {
+        let b_sz = x.dims()[0];
+        let (q, k, v) = self.project(x)?;
+        let q = q
+            .reshape((b_sz, 1, self.n_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let k = k
+            .reshape((b_sz, 1, self.n_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let v = v
+            .reshape((b_sz, 1, self.n_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        Ok((q, k, v))
     }
 
     // GQA handling: Repeat K/V if n_kv_heads < n_heads
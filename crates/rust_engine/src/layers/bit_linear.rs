@@ -4,8 +4,8 @@ use candle_core::{Device, Result, Tensor};
 use candle_nn::VarBuilder;
 
 use super::TensorExt;
-use crate::kernels::packing::PackedTensor;
-use crate::kernels::{cpu::BitLinearCpu, cuda::BitLinearCuda};
+use crate::kernels::packing::{PackOptions, PackedTensor};
+use crate::kernels::{autotune, backend, cpu::ActivationQuant};
 
 /// 1.58-bit/// Standard BitLinear layer (1.58-bit)
 /// Optimized for inference with pre-packed weights.
@@ -18,17 +18,31 @@ pub struct BitLinear {
     pub out_features: usize,
     /// Simply-packed weights for 1.58-bit kernels (Dual Device Support)
     pub packed_params: Option<PackedTensor>,
+    /// Loaded from a packed checkpoint: `weight` is an empty placeholder and
+    /// `packed_params` (attached by `BitLlama::attach_packed`) is the only copy
+    pub prepacked: bool,
 }
 
 impl BitLinear {
     pub fn load(in_dim: usize, out_dim: usize, vb: VarBuilder, device: &Device) -> Result<Self> {
+        // Packed checkpoint: the codes are mapped in later, never loaded as f32
+        if !vb.contains_tensor("weight") && vb.contains_tensor("packed") {
+            return Ok(Self {
+                weight: Tensor::zeros((0, in_dim), candle_core::DType::F32, device)?,
+                in_features: in_dim,
+                out_features: out_dim,
+                packed_params: None,
+                prepacked: true,
+            });
+        }
+
         let init = candle_nn::init::DEFAULT_KAIMING_NORMAL;
         let weight = vb.get_with_hints((out_dim, in_dim), "weight", init)?;
 
         // [Plan B] Explicit Mmap Detachment
+        // (a deep copy that stays in the graph, so VarMap weights still train)
         let weight = if device.is_cpu() {
-            let data = weight.to_vec1::<f32>()?;
-            Tensor::from_vec(data, weight.shape(), device)?
+            weight.to_dtype(candle_core::DType::F32)?.copy()?
         } else {
             weight.to_device(device)?
         };
@@ -37,14 +51,47 @@ impl BitLinear {
             in_features: in_dim,
             out_features: out_dim,
             packed_params: None,
+            prepacked: false,
         })
     }
 
     /// Pre-compute packed weights for optimized inference via Dual Kernels
-    pub fn precompute_packed(&mut self) -> Result<()> {
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
         // This function quantizes the weights and packs them into 2-bit format.
         // It populates `self.packed_params`.
-        let packed = PackedTensor::pack(&self.weight)?;
+        let mut packed = if self.prepacked {
+            // Keep the checkpoint's codes and scales; only missing layouts are built
+            match self.packed_params.take() {
+                Some(packed) => packed,
+                None => candle_core::bail!(
+                    "BitLinear: packed checkpoint weights were not attached (BitLlama::attach_packed)"
+                ),
+            }
+        } else {
+            PackedTensor::pack_with(&self.weight, options.weight_scale, options.group_size)?
+        };
+        // The int8 and LUT kernels are CPU-only and need their own layouts
+        if self.weight.device().is_cpu() {
+            let activations = match options.activations {
+                ActivationQuant::Auto => autotune::choose(&packed)?,
+                activations => activations,
+            };
+            // Keep only the selected kernel's layout (a checkpoint may carry it already)
+            let (int8_layout, lut_layout) = (packed.int8_layout.take(), packed.lut_layout.take());
+            packed = match (activations, int8_layout, lut_layout) {
+                (ActivationQuant::F32 | ActivationQuant::Auto, _, _) => packed,
+                (ActivationQuant::Int8, Some(layout), _) => PackedTensor {
+                    int8_layout: Some(layout),
+                    ..packed
+                },
+                (ActivationQuant::Int8, None, _) => packed.with_int8_layout()?,
+                (ActivationQuant::Lut, _, Some(layout)) => PackedTensor {
+                    lut_layout: Some(layout),
+                    ..packed
+                },
+                (ActivationQuant::Lut, _, None) => packed.with_lut_layout()?,
+            };
+        }
         self.packed_params = Some(packed);
         Ok(())
     }
@@ -63,26 +110,8 @@ impl BitLinear {
 
         // 1. Dual Kernel Path (Fastest, 1.58-bit Native)
         if let Some(packed) = &self.packed_params {
-            // Automatic Dispatch based on device
-            let result = match input.device() {
-                Device::Cpu => {
-                    // Use Optimized CPU Kernel (AVX2)
-                    BitLinearCpu::forward(&input, packed)
-                }
-                Device::Cuda(_) => {
-                    // Use Custom CUDA Kernel (BitNet)
-                    BitLinearCuda::forward(&input, packed)
-                }
-                _ => {
-                    // Fallback to legacy path if kernel not available for device
-                    // But we don't have a fallback return here easily without code dupe or rearranging.
-                    // For now, let's assume if packed exists, we must use kernel or fail.
-                    // Or we can assume packing only happens if supported?
-                    candle_core::bail!(
-                        "Packed params present but Custom Kernel not implemented for this device"
-                    )
-                }
-            }?;
+            // Backend of the input's device (`kernels::backend`)
+            let result = backend::for_device(input.device())?.matmul(&input, packed)?;
 
             // Reshape back if needed
             if let Some(mut dims) = original_shape {
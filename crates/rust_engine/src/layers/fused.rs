@@ -0,0 +1,179 @@
+//! FusedProjection - several packed projections of one input in one kernel call
+
+use candle_core::{Device, Result, Tensor};
+
+use super::AdaptiveBitLinear;
+use crate::kernels::cpu::{ActivationQuant, Epilogue, KernelScratch};
+use crate::kernels::packing::{PackOptions, PackedTensor, LUT_ROWS};
+use crate::kernels::{autotune, backend};
+
+/// Projections sharing one input (q/k/v or gate/up) packed as one CPU weight.
+///
+/// The input is quantized once and the stacked rows are streamed in a single
+/// pass (`TernaryKernel::fused_matmul_into`, which can also apply the RMSNorm
+/// in front). For SwiGLU the kernel epilogue writes `silu(gate) * up` directly.
+///
+/// The separate projections keep working on their own (LoRA, checkpoints and
+/// the unfused paths use them), but their packed weights become views of this
+/// one (`PackedTensor::view_rows`), so fusing keeps a single copy; `applies_to`
+/// tells whether the fused weight may stand in.
+#[derive(Debug, Clone)]
+pub struct FusedProjection {
+    /// Stacked rows: [q; k; v], or [gate; up] for `Epilogue::SwiGlu`
+    pub weight: PackedTensor,
+    pub epilogue: Epilogue,
+    /// Output widths of the fused projections (one entry for SwiGLU)
+    pub splits: Vec<usize>,
+}
+
+impl FusedProjection {
+    /// Stack `parts`, which then run on views of the stacked weight
+    /// (None unless all are packed BitLinear layers on the CPU)
+    pub fn concat(
+        parts: &mut [&mut AdaptiveBitLinear],
+        options: PackOptions,
+    ) -> Result<Option<Self>> {
+        let Some(packed) = parts
+            .iter()
+            .map(|p| plain_packed(p))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(None);
+        };
+        let weight = with_layout(PackedTensor::concat_rows(&packed)?, options)?;
+        share_rows(parts, &weight)?;
+        Ok(Some(Self {
+            weight,
+            epilogue: Epilogue::Linear,
+            splits: parts.iter().map(|p| p.out_features).collect(),
+        }))
+    }
+
+    /// `silu(gate(x)) * up(x)`, gate/up then running on views of the fused
+    /// weight (None unless both are packed BitLinear layers on the CPU, or if
+    /// the LUT layout is chosen and the gate rows don't fill whole tiles)
+    pub fn swiglu(
+        gate: &mut AdaptiveBitLinear,
+        up: &mut AdaptiveBitLinear,
+        options: PackOptions,
+    ) -> Result<Option<Self>> {
+        let (Some(g), Some(u)) = (plain_packed(gate), plain_packed(up)) else {
+            return Ok(None);
+        };
+        let weight = with_layout(PackedTensor::concat_rows(&[g, u])?, options)?;
+        if weight.lut_layout.is_some() && !gate.out_features.is_multiple_of(LUT_ROWS) {
+            return Ok(None);
+        }
+        share_rows(&mut [gate, up], &weight)?;
+        Ok(Some(Self {
+            weight,
+            epilogue: Epilogue::SwiGlu,
+            splits: vec![gate.out_features],
+        }))
+    }
+
+    /// Whether the layers this was built from still run on their packed
+    /// weights alone (no LoRA adapter, not merged into a dense weight)
+    pub fn applies_to(parts: &[&AdaptiveBitLinear]) -> bool {
+        parts.iter().all(|p| plain_packed(p).is_some())
+    }
+
+    pub fn out_features(&self) -> usize {
+        self.splits.iter().sum()
+    }
+
+    /// x [.., In] -> [.., out_features]
+    ///
+    /// Several rows with f32 activations (prefill) take the tiled GEMM over
+    /// the stacked weight instead, with the epilogue applied afterwards.
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let k = self.weight.shape.dims2()?.1;
+        let rows = x.elem_count() / k.max(1);
+        let mut out_shape = x.dims().to_vec();
+        *out_shape.last_mut().unwrap() = self.out_features();
+
+        let x_cpu = x.to_device(&Device::Cpu)?.reshape((rows, k))?;
+        let kernel = backend::for_device(&Device::Cpu)?;
+        let per_row = self.weight.int8_layout.is_some() || self.weight.lut_layout.is_some();
+        let y = if rows > 1 && !per_row {
+            let y = kernel.matmul(&x_cpu, &self.weight)?;
+            match self.epilogue {
+                Epilogue::Linear => y,
+                Epilogue::SwiGlu => {
+                    let n = self.out_features();
+                    let gate = y.narrow(1, 0, n)?;
+                    let up = y.narrow(1, n, n)?;
+                    (candle_nn::ops::silu(&gate)? * up)?
+                }
+            }
+        } else {
+            let x_vec = x_cpu.flatten_all()?.to_vec1::<f32>()?;
+            let mut out = vec![0.0f32; rows * self.out_features()];
+            let mut scratch = KernelScratch::default();
+            kernel.fused_matmul_into(
+                &x_vec,
+                None,
+                &self.weight,
+                self.epilogue,
+                &mut scratch,
+                &mut out,
+            )?;
+            Tensor::from_vec(out, (rows, self.out_features()), &Device::Cpu)?
+        };
+        y.reshape(out_shape)?.to_device(x.device())
+    }
+
+    /// Split a `forward` result along the last dim into the fused projections
+    pub fn split(&self, y: &Tensor) -> Result<Vec<Tensor>> {
+        let dim = y.rank() - 1;
+        let mut start = 0;
+        self.splits
+            .iter()
+            .map(|&len| {
+                let part = y.narrow(dim, start, len)?;
+                start += len;
+                Ok(part)
+            })
+            .collect()
+    }
+}
+
+/// Packed CPU weights of `layer` if its forward runs on them alone
+fn plain_packed(layer: &AdaptiveBitLinear) -> Option<&PackedTensor> {
+    if layer.lora.is_some() || !layer.lora_rows.is_empty() {
+        return None;
+    }
+    let packed = layer.legacy_linear.as_ref()?.packed_params.as_ref()?;
+    packed.device.is_cpu().then_some(packed)
+}
+
+/// Point the packed weights of `parts` (stacked in that order in `weight`)
+/// at views of `weight`
+fn share_rows(parts: &mut [&mut AdaptiveBitLinear], weight: &PackedTensor) -> Result<()> {
+    let mut start = 0;
+    for part in parts.iter_mut() {
+        let packed = part
+            .legacy_linear
+            .as_mut()
+            .and_then(|l| l.packed_params.as_mut());
+        if let Some(packed) = packed {
+            let rows = packed.shape.dims2()?.0;
+            *packed = weight.view_rows(start..start + rows, packed)?;
+            start += rows;
+        }
+    }
+    Ok(())
+}
+
+/// Add the kernel layout `options` asks for (as `BitLinear::precompute_packed`)
+pub(crate) fn with_layout(packed: PackedTensor, options: PackOptions) -> Result<PackedTensor> {
+    let activations = match options.activations {
+        ActivationQuant::Auto => autotune::choose(&packed)?,
+        activations => activations,
+    };
+    match activations {
+        ActivationQuant::F32 | ActivationQuant::Auto => Ok(packed),
+        ActivationQuant::Int8 => packed.with_int8_layout(),
+        ActivationQuant::Lut => packed.with_lut_layout(),
+    }
+}
@@ -1,129 +1,731 @@
-use candle_core::{DType, Result, Tensor};
+use std::ops::Range;
 
-/// Quantized Key-Value Cache (Phase 5.2)
+use candle_core::{DType, Device, Result, Tensor};
+
+use super::kv_quant::{self, KvCacheDtype, KvFormat, KIVI_GROUP};
+
+/// Initial token capacity (doubled on demand up to `max_seq_len`)
+const INITIAL_CAPACITY: usize = 256;
+
+/// Quantized Key-Value Cache (Phase 5.3: preallocated ring buffer)
 ///
-/// Stores KV pairs in 8-bit quantized format to reduce VRAM usage.
-/// Supports on-the-fly dequantization during attention calculation.
+/// Stores KV pairs in a compact format (`KvCacheDtype`, Q8 by default) to
+/// reduce memory usage. Supports on-the-fly dequantization during attention
+/// calculation.
 ///
 /// # Architecture
-/// - **Storage**: `u8` tensor for data.
-/// - **Scale**: `f32` tensor for dequantization factor (per-token-head).
-/// - **Zero Point**: Fixed at 128 for symmetric mapping (-127..127 -> 1..255).
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
+/// - **Storage**: codes (`u8`) or raw values (`f32` / `f16`), see `kv_quant`.
+/// - **Scale**: `f32` dequantization factor per token-head (Q8 / Q4 / FP8) or
+///   per channel and group of `KIVI_GROUP` tokens (KIVI keys).
+/// - **Zero Point**: Q8 is symmetric with a fixed offset of 128
+///   (-127..127 -> 1..255), Q4 with an offset of 8.
+/// - **Capacity**: Buffers are preallocated along the sequence axis and
+///   written in place; `append` only quantizes/dequantizes the new tokens and
+///   returns views of the active region. Capacity doubles until it reaches
+///   `max_seq_len` (rounded up to whole KIVI groups), after which the cache
+///   wraps and overwrites the oldest tokens (sliding window).
+/// - **Attention**: `append_quantized` hands the stored data straight to a
+///   fused kernel. `append` dequantizes the active region into a fresh `f32`
+///   tensor per call, which the attention drops again: no `f32` copy of the
+///   cache outlives the step.
+#[derive(Debug)]
 pub struct QuantizedKVCache {
-    k_cache: Option<Tensor>, // Shape: [batch, n_kv_heads, total_seq_len, head_dim] (u8)
-    v_cache: Option<Tensor>, // Shape: [batch, n_kv_heads, total_seq_len, head_dim] (u8)
-
-    k_scale: Option<Tensor>, // Shape: [batch, n_kv_heads, total_seq_len, 1] (f32)
-    v_scale: Option<Tensor>,
+    dtype: KvCacheDtype,
+    k: Option<KVBuffer>,
+    v: Option<KVBuffer>,
 
+    /// Next slot to write (ring position)
+    head: usize,
     current_seq_len: usize,
     max_seq_len: usize,
 }
 
+/// Preallocated storage for K or V: [batch, n_kv_heads, capacity, row_width]
+/// (also the block pool of `PagedKVCache`)
+#[derive(Debug)]
+pub(crate) struct KVBuffer {
+    format: KvFormat,
+    head_dim: usize,
+    data: Tensor,   // u8 codes, or f32 / f16 values
+    params: Params, // dequantization parameters of `data`
+}
+
+#[derive(Debug)]
+enum Params {
+    /// F32 / F16: `data` holds the values
+    None,
+    /// Q8 / Q4 / FP8: one scale per token-head, [batch, n_kv_heads, capacity, 1]
+    PerToken(Tensor),
+    /// KIVI keys: scale / min per channel for every group of `KIVI_GROUP` slots
+    /// ([batch, n_kv_heads, capacity / G, head_dim]) and the f32 values of the
+    /// newest, incomplete group ([batch, n_kv_heads, G, head_dim])
+    PerChannel {
+        scale: Tensor,
+        min: Tensor,
+        residual: Tensor,
+    },
+}
+
+impl Params {
+    fn map(&self, f: impl Fn(&Tensor) -> Result<Tensor>) -> Result<Self> {
+        Ok(match self {
+            Self::None => Self::None,
+            Self::PerToken(s) => Self::PerToken(f(s)?),
+            Self::PerChannel {
+                scale,
+                min,
+                residual,
+            } => Self::PerChannel {
+                scale: f(scale)?,
+                min: f(min)?,
+                residual: f(residual)?,
+            },
+        })
+    }
+}
+
+impl KVBuffer {
+    pub(crate) fn zeros(
+        format: KvFormat,
+        (b, h, cap, d): (usize, usize, usize, usize),
+        device: &Device,
+    ) -> Result<Self> {
+        let data = match format {
+            KvFormat::F32 => Tensor::zeros((b, h, cap, d), DType::F32, device)?,
+            KvFormat::F16 => Tensor::zeros((b, h, cap, d), DType::F16, device)?,
+            _ => Tensor::zeros((b, h, cap, format.row_width(d)), DType::U8, device)?,
+        };
+        let params = match format {
+            KvFormat::F32 | KvFormat::F16 => Params::None,
+            KvFormat::Q4Channel => {
+                let groups = (b, h, cap / KIVI_GROUP, d);
+                Params::PerChannel {
+                    scale: Tensor::zeros(groups, DType::F32, device)?,
+                    min: Tensor::zeros(groups, DType::F32, device)?,
+                    residual: Tensor::zeros((b, h, KIVI_GROUP, d), DType::F32, device)?,
+                }
+            }
+            _ => Params::PerToken(Tensor::zeros((b, h, cap, 1), DType::F32, device)?),
+        };
+        Ok(Self {
+            format,
+            head_dim: d,
+            data,
+            params,
+        })
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.data.dims()[2]
+    }
+
+    pub(crate) fn device(&self) -> &Device {
+        self.data.device()
+    }
+
+    /// Reallocate with a larger capacity, keeping slots 0..capacity
+    fn grow(&self, new_cap: usize) -> Result<Self> {
+        let (b, h, _, _) = self.data.dims4()?;
+        let d = self.head_dim;
+        let grown = Self::zeros(self.format, (b, h, new_cap, d), self.data.device())?;
+        grown.data.slice_set(&self.data, 2, 0)?;
+        match (&self.params, &grown.params) {
+            (Params::PerToken(s), Params::PerToken(g)) => g.slice_set(s, 2, 0)?,
+            (
+                Params::PerChannel {
+                    scale,
+                    min,
+                    residual,
+                },
+                Params::PerChannel {
+                    scale: g_scale,
+                    min: g_min,
+                    residual: g_residual,
+                },
+            ) => {
+                g_scale.slice_set(scale, 2, 0)?;
+                g_min.slice_set(min, 2, 0)?;
+                g_residual.slice_set(residual, 2, 0)?;
+            }
+            _ => {}
+        }
+        Ok(grown)
+    }
+
+    /// Bytes held by the stored data and its dequantization parameters
+    pub(crate) fn bytes(&self) -> usize {
+        let size = |t: &Tensor| t.elem_count() * t.dtype().size_in_bytes();
+        size(&self.data)
+            + match &self.params {
+                Params::None => 0,
+                Params::PerToken(s) => size(s),
+                Params::PerChannel {
+                    scale,
+                    min,
+                    residual,
+                } => size(scale) + size(min) + size(residual),
+            }
+    }
+
+    /// Slots 0..len in storage order (`head` = next slot to write)
+    pub(crate) fn slot_view(&self, len: usize, head: usize) -> Result<KvView> {
+        let data = self.data.narrow(2, 0, len)?;
+        Ok(match (&self.params, self.format) {
+            (Params::None, _) => KvView::Float(data),
+            (Params::PerToken(s), format) => {
+                let scale = s.narrow(2, 0, len)?;
+                match format {
+                    KvFormat::Q8 => KvView::Q8 { data, scale },
+                    KvFormat::Q4 => KvView::Q4 { data, scale },
+                    _ => KvView::Fp8 { data, scale },
+                }
+            }
+            (
+                Params::PerChannel {
+                    scale,
+                    min,
+                    residual,
+                },
+                _,
+            ) => {
+                let groups = len.div_ceil(KIVI_GROUP);
+                KvView::Q4Channel {
+                    data,
+                    scale: scale.narrow(2, 0, groups)?,
+                    min: min.narrow(2, 0, groups)?,
+                    residual: residual.clone(),
+                    residual_slots: head - head % KIVI_GROUP..head,
+                }
+            }
+        })
+    }
+
+    /// Quantize and write `len` tokens starting at `slot` (no wrap)
+    pub(crate) fn write(&self, slot: usize, x: &Tensor) -> Result<()> {
+        let (b, h, len, d) = x.dims4()?;
+        match (&self.params, self.format) {
+            (Params::None, _) => {
+                let x = x.to_dtype(self.data.dtype())?;
+                self.data.slice_set(&x.contiguous()?, 2, slot)?;
+            }
+            (Params::PerToken(scale), KvFormat::Q8) => {
+                let (q, s) = quantize_q8(x)?;
+                let s = s.to_dtype(DType::F32)?;
+                self.data.slice_set(&q.contiguous()?, 2, slot)?;
+                scale.slice_set(&s.contiguous()?, 2, slot)?;
+            }
+            (Params::PerToken(scale), format) => {
+                let values = to_host(x)?;
+                let (codes, scales) = kv_quant::quantize_rows(format, &values, d);
+                let device = self.data.device();
+                let width = format.row_width(d);
+                let codes = Tensor::from_vec(codes, (b, h, len, width), device)?;
+                self.data.slice_set(&codes, 2, slot)?;
+                scale.slice_set(&Tensor::from_vec(scales, (b, h, len, 1), device)?, 2, slot)?;
+            }
+            (Params::PerChannel { residual, .. }, _) => {
+                // Fill the residual window; a completed group gets quantized
+                let mut done = 0;
+                while done < len {
+                    let s = slot + done;
+                    let r = s % KIVI_GROUP;
+                    let n = (KIVI_GROUP - r).min(len - done);
+                    let chunk = x.narrow(2, done, n)?.to_dtype(DType::F32)?.contiguous()?;
+                    residual.slice_set(&chunk, 2, r)?;
+                    if r + n == KIVI_GROUP {
+                        self.quantize_group(s - r)?;
+                    }
+                    done += n;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Per-channel quantization of the (full) residual window into slots
+    /// `start..start + KIVI_GROUP`
+    fn quantize_group(&self, start: usize) -> Result<()> {
+        let Params::PerChannel {
+            scale,
+            min,
+            residual,
+        } = &self.params
+        else {
+            candle_core::bail!("KV cache: {:?} has no channel groups", self.format);
+        };
+        let (b, h, g, d) = residual.dims4()?;
+        let values = to_host(residual)?;
+        let width = self.format.row_width(d);
+        let (mut codes, mut scales, mut mins) = (Vec::new(), Vec::new(), Vec::new());
+        for head_values in values.chunks(g * d) {
+            let (c, s, m) = kv_quant::quantize_channels(head_values, d);
+            codes.extend(c);
+            scales.extend(s);
+            mins.extend(m);
+        }
+
+        let device = self.data.device();
+        self.data.slice_set(
+            &Tensor::from_vec(codes, (b, h, g, width), device)?,
+            2,
+            start,
+        )?;
+        let group = start / KIVI_GROUP;
+        scale.slice_set(&Tensor::from_vec(scales, (b, h, 1, d), device)?, 2, group)?;
+        min.slice_set(&Tensor::from_vec(mins, (b, h, 1, d), device)?, 2, group)?;
+        Ok(())
+    }
+
+    /// Copy the stored slots `src..src + len` to `dst..dst + len`
+    pub(crate) fn copy_slots(&self, src: usize, dst: usize, len: usize) -> Result<()> {
+        // Copy out first: source and destination share one storage
+        let slots = |t: &Tensor| t.narrow(2, src, len)?.copy()?.contiguous();
+        self.data.slice_set(&slots(&self.data)?, 2, dst)?;
+        match &self.params {
+            Params::None => {}
+            Params::PerToken(scale) => {
+                scale.slice_set(&slots(scale)?, 2, dst)?;
+            }
+            Params::PerChannel { .. } => {
+                candle_core::bail!("KV cache: cannot move slots of per-channel groups")
+            }
+        }
+        Ok(())
+    }
+
+    fn deep_copy(&self) -> Result<Self> {
+        Ok(Self {
+            format: self.format,
+            head_dim: self.head_dim,
+            data: self.data.copy()?,
+            params: self.params.map(Tensor::copy)?,
+        })
+    }
+}
+
+/// Stored-format view of K or V over the active cache region
+/// (all tensors [batch, n_kv_heads, len, _], see `QuantizedKVCache::append_quantized`)
+#[derive(Debug, Clone)]
+pub enum KvView {
+    /// Unquantized values (f32 / f16)
+    Float(Tensor),
+    /// u8 codes, `(q - 128) * scale`, scale [.., len, 1]
+    Q8 { data: Tensor, scale: Tensor },
+    /// Two 4-bit codes per byte (low nibble first), `(q - 8) * scale`
+    Q4 { data: Tensor, scale: Tensor },
+    /// e4m3 codes, `fp8(q) * scale`
+    Fp8 { data: Tensor, scale: Tensor },
+    /// KIVI keys in slot order: packed 4-bit codes with `q * scale + min` per
+    /// channel of group `j / KIVI_GROUP` ([.., groups, head_dim]); slots in
+    /// `residual_slots` are read from `residual[j % KIVI_GROUP]` instead
+    Q4Channel {
+        data: Tensor,
+        scale: Tensor,
+        min: Tensor,
+        residual: Tensor,
+        residual_slots: Range<usize>,
+    },
+}
+
+impl KvView {
+    /// Logical shape (batch, n_kv_heads, len, head_dim), after checking that
+    /// the scale tensors fit the data
+    pub fn dims4(&self) -> Result<(usize, usize, usize, usize)> {
+        let (b, h, len, width) = match self {
+            Self::Float(x) => return x.dims4(),
+            Self::Q8 { data, .. }
+            | Self::Q4 { data, .. }
+            | Self::Fp8 { data, .. }
+            | Self::Q4Channel { data, .. } => data.dims4()?,
+        };
+        let (d, params_ok) = match self {
+            Self::Q8 { scale, .. } | Self::Fp8 { scale, .. } => {
+                (width, scale.dims() == [b, h, len, 1])
+            }
+            Self::Q4 { scale, .. } => (width * 2, scale.dims() == [b, h, len, 1]),
+            Self::Q4Channel {
+                scale,
+                min,
+                residual,
+                ..
+            } => {
+                let d = width * 2;
+                let groups = len.div_ceil(KIVI_GROUP);
+                (
+                    d,
+                    scale.dims() == [b, h, groups, d]
+                        && min.dims() == scale.dims()
+                        && residual.dims() == [b, h, KIVI_GROUP, d],
+                )
+            }
+            Self::Float(_) => unreachable!(),
+        };
+        if !params_ok {
+            candle_core::bail!("KV view: scales do not match data {:?}", self);
+        }
+        Ok((b, h, len, d))
+    }
+
+    /// Gather the rows `index` (u32 slots) of a per-token view
+    pub fn index_select(&self, index: &Tensor) -> Result<Self> {
+        let pick = |t: &Tensor| t.index_select(index, 2);
+        Ok(match self {
+            Self::Float(x) => Self::Float(pick(x)?),
+            Self::Q8 { data, scale } => Self::Q8 {
+                data: pick(data)?,
+                scale: pick(scale)?,
+            },
+            Self::Q4 { data, scale } => Self::Q4 {
+                data: pick(data)?,
+                scale: pick(scale)?,
+            },
+            Self::Fp8 { data, scale } => Self::Fp8 {
+                data: pick(data)?,
+                scale: pick(scale)?,
+            },
+            Self::Q4Channel { .. } => {
+                candle_core::bail!("KV view: per-channel groups cannot be gathered by row")
+            }
+        })
+    }
+
+    /// Dequantize to f32 [batch, n_kv_heads, len, head_dim]
+    pub fn dequantize(&self) -> Result<Tensor> {
+        match self {
+            Self::Float(x) => x.to_dtype(DType::F32),
+            Self::Q8 { data, scale } => dequantize_q8(data, scale),
+            Self::Q4 { data, scale } | Self::Fp8 { data, scale } => {
+                let (format, d) = match self {
+                    Self::Q4 { .. } => (KvFormat::Q4, data.dims()[3] * 2),
+                    _ => (KvFormat::Fp8, data.dims()[3]),
+                };
+                let (b, h, len, _) = data.dims4()?;
+                let codes = data.flatten_all()?.to_vec1::<u8>()?;
+                let x = kv_quant::dequantize_rows(format, &codes, &to_host(scale)?, d);
+                Tensor::from_vec(x, (b, h, len, d), data.device())
+            }
+            Self::Q4Channel {
+                data,
+                scale,
+                min,
+                residual,
+                residual_slots,
+            } => {
+                let (b, h, len, width) = data.dims4()?;
+                let groups = scale.dims()[2];
+                let d = scale.dims()[3];
+                let codes = data.flatten_all()?.to_vec1::<u8>()?;
+                let (scale, min, residual) = (to_host(scale)?, to_host(min)?, to_host(residual)?);
+
+                let mut out = Vec::with_capacity(b * h * len * d);
+                for bh in 0..b * h {
+                    for g in 0..groups {
+                        let rows = g * KIVI_GROUP..((g + 1) * KIVI_GROUP).min(len);
+                        let params = (bh * groups + g) * d..(bh * groups + g + 1) * d;
+                        let group_codes =
+                            &codes[(bh * len + rows.start) * width..(bh * len + rows.end) * width];
+                        let mut x = kv_quant::dequantize_channels(
+                            group_codes,
+                            &scale[params.clone()],
+                            &min[params],
+                            d,
+                        );
+                        // The newest partial group is still full precision
+                        for j in rows.clone().filter(|j| residual_slots.contains(j)) {
+                            let r = (bh * KIVI_GROUP + j % KIVI_GROUP) * d;
+                            let dst = (j - rows.start) * d;
+                            x[dst..dst + d].copy_from_slice(&residual[r..r + d]);
+                        }
+                        out.extend(x);
+                    }
+                }
+                Tensor::from_vec(out, (b, h, len, d), data.device())
+            }
+        }
+    }
+}
+
+/// Views of the active cache region in its stored format
+/// (see `QuantizedKVCache::append_quantized`)
+#[derive(Debug, Clone)]
+pub struct QuantizedKV {
+    pub k: KvView,
+    pub v: KvView,
+}
+
+impl QuantizedKV {
+    /// Dequantize to f32 (`QuantizedKVCache::append`, reference / non-CPU fallback)
+    pub fn dequantize(&self) -> Result<(Tensor, Tensor)> {
+        Ok((self.k.dequantize()?, self.v.dequantize()?))
+    }
+}
+
+impl Clone for QuantizedKVCache {
+    /// Deep copy: clones must not share (and overwrite) the same buffers
+    fn clone(&self) -> Self {
+        let copy = |b: &Option<KVBuffer>| b.as_ref().map(|b| b.deep_copy().expect("KV cache copy"));
+        Self {
+            dtype: self.dtype,
+            k: copy(&self.k),
+            v: copy(&self.v),
+            head: self.head,
+            current_seq_len: self.current_seq_len,
+            max_seq_len: self.max_seq_len,
+        }
+    }
+}
+
 impl QuantizedKVCache {
+    /// Q8 cache (the default `KvCacheDtype`)
     pub fn new(max_seq_len: usize) -> Self {
+        Self::with_dtype(max_seq_len, KvCacheDtype::default())
+    }
+
+    pub fn with_dtype(max_seq_len: usize, dtype: KvCacheDtype) -> Self {
         Self {
-            k_cache: None,
-            v_cache: None,
-            k_scale: None,
-            v_scale: None,
+            dtype,
+            k: None,
+            v: None,
+            head: 0,
             current_seq_len: 0,
             max_seq_len,
         }
     }
 
-    /// Reset cache state (for new generation)
+    pub fn dtype(&self) -> KvCacheDtype {
+        self.dtype
+    }
+
+    /// Reset cache state (for new generation). Buffers are kept for reuse.
     pub fn reset(&mut self) {
-        self.k_cache = None;
-        self.v_cache = None;
-        self.k_scale = None;
-        self.v_scale = None;
+        self.head = 0;
         self.current_seq_len = 0;
     }
 
+    /// Number of cached tokens
+    pub fn len(&self) -> usize {
+        self.current_seq_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current_seq_len == 0
+    }
+
+    /// Allocated token slots
+    pub fn capacity(&self) -> usize {
+        self.k.as_ref().map_or(0, KVBuffer::capacity)
+    }
+
+    /// Bytes held by the K and V buffers
+    pub fn bytes(&self) -> usize {
+        [&self.k, &self.v]
+            .into_iter()
+            .flatten()
+            .map(KVBuffer::bytes)
+            .sum()
+    }
+
     /// Append new keys and values to the cache
     ///
-    /// This implementation performs on-the-fly quantization.
-    /// Returns DEQUANTIZED full cache for use in Attention.
+    /// Quantizes only the new tokens, writes them in place and returns the
+    /// active region DEQUANTIZED to f32 for use in Attention. Quantized
+    /// formats are dequantized into new tensors on every call (the cache
+    /// keeps no f32 copy); raw f32 buffers are returned as views.
     pub fn append(&mut self, k: &Tensor, v: &Tensor) -> Result<(Tensor, Tensor)> {
-        let (_b, _h, seq_len, _d) = k.dims4()?;
-
-        // 1. Quantize Inputs (f32/f16 -> u8, f32_scale)
-        let (k_u8, k_s) = self.quantize_q8(k)?;
-        let (v_u8, v_s) = self.quantize_q8(v)?;
-
-        // 2. Concatenate with existing persistent cache
-        // Note: 'cat' creates a new tensor, which is simpler but causes fragmentation.
-        // For Phase 5.2 MVP, we accept 'cat'. 'Vec<Tensor>' optimization is Phase 5.3.
-        let k_next = match &self.k_cache {
-            Some(c) => Tensor::cat(&[c, &k_u8], 2)?,
-            None => k_u8,
-        };
-        let k_scale_next = match &self.k_scale {
-            Some(c) => Tensor::cat(&[c, &k_s], 2)?,
-            None => k_s,
-        };
+        let kv = self.append_quantized(k, v)?;
+        kv.dequantize()
+    }
 
-        let v_next = match &self.v_cache {
-            Some(c) => Tensor::cat(&[c, &v_u8], 2)?,
-            None => v_u8,
-        };
-        let v_scale_next = match &self.v_scale {
-            Some(c) => Tensor::cat(&[c, &v_s], 2)?,
-            None => v_s,
-        };
+    /// Append new keys and values and return views of the active region in
+    /// the STORED format (no f32 copy of the cache is made or kept).
+    pub fn append_quantized(&mut self, k: &Tensor, v: &Tensor) -> Result<QuantizedKV> {
+        let seq_len = self.store(k, v)?;
+        let (kb, vb) = (self.k.as_ref().unwrap(), self.v.as_ref().unwrap());
+        Ok(QuantizedKV {
+            k: self.stored_view(kb, seq_len)?,
+            v: self.stored_view(vb, seq_len)?,
+        })
+    }
 
-        // 3. Update State
-        self.k_cache = Some(k_next.clone());
-        self.v_cache = Some(v_next.clone());
-        self.k_scale = Some(k_scale_next.clone());
-        self.v_scale = Some(v_scale_next.clone());
-        self.current_seq_len += seq_len;
+    /// Quantize and write new tokens in place. Returns the number of new tokens.
+    fn store(&mut self, k: &Tensor, v: &Tensor) -> Result<usize> {
+        let (b, h, seq_len, d) = k.dims4()?;
+        if seq_len == 0 || seq_len > self.max_seq_len {
+            candle_core::bail!(
+                "KV cache: cannot append {} tokens (max_seq_len {})",
+                seq_len,
+                self.max_seq_len
+            );
+        }
+        if d % 2 != 0 && matches!(self.dtype, KvCacheDtype::Q4 | KvCacheDtype::Kivi) {
+            candle_core::bail!(
+                "KV cache: {:?} packs two values per byte, head_dim {} must be even",
+                self.dtype,
+                d
+            );
+        }
 
-        // 4. Dequantize for Return (To be compatible with standard Attention)
-        // This effectively "Reconstructs" the full cache in f16/f32 for computation.
-        // Optimization: In Phase 5.3, we should fuse this into the Attention Kernel.
-        let k_out = self.dequantize_q8(&k_next, &k_scale_next)?;
-        let v_out = self.dequantize_q8(&v_next, &v_scale_next)?;
+        // 1. Ensure capacity (allocate lazily, grow geometrically, wrap at max_seq_len)
+        self.reserve((b, h, seq_len, d), k.device())?;
 
-        Ok((k_out, v_out))
-    }
+        // 2. Quantize only the new tokens and write them in place (split at the ring boundary)
+        let cap = self.capacity();
+        let first = seq_len.min(cap - self.head);
+        let (kb, vb) = (self.k.as_ref().unwrap(), self.v.as_ref().unwrap());
+        let parts = [(0, self.head, first), (first, 0, seq_len - first)];
+        for (src, slot, len) in parts {
+            if len == 0 {
+                continue;
+            }
+            kb.write(slot, &k.narrow(2, src, len)?)?;
+            vb.write(slot, &v.narrow(2, src, len)?)?;
+        }
 
-    /// Quantize a Tensor to Q8 (Symetric + 128 Offset)
-    fn quantize_q8(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
-        // x: [batch, heads, seq, dim]
-        // Scale per token-head: max(abs(x), dim=3) -> [batch, heads, seq, 1]
-        let x_abs = x.abs()?;
-        let max_val = x_abs.max_keepdim(3)?;
-        // Avoid division by zero
-        let scale = (max_val / 127.0)?;
+        // 3. Update State
+        self.head = (self.head + seq_len) % cap;
+        self.current_seq_len = (self.current_seq_len + seq_len).min(cap);
+        Ok(seq_len)
+    }
 
-        // Broadcast scale
-        let scaled = x.broadcast_div(&scale)?;
+    /// Capacity rounded up to whole KIVI groups (group statistics are per slot range)
+    fn round_capacity(&self, n: usize) -> usize {
+        if self.dtype == KvCacheDtype::Kivi {
+            n.div_ceil(KIVI_GROUP) * KIVI_GROUP
+        } else {
+            n
+        }
+    }
 
-        // Quantize: round(x/s) + 128
-        // We use standard rounding.
-        let rounded = scaled.round()?;
+    /// Make room for `seq_len` more tokens of shape (b, h, _, d)
+    fn reserve(
+        &mut self,
+        (b, h, seq_len, d): (usize, usize, usize, usize),
+        device: &Device,
+    ) -> Result<()> {
+        let max_cap = self.round_capacity(self.max_seq_len);
+        let Some(kb) = &self.k else {
+            let cap = self
+                .round_capacity(INITIAL_CAPACITY.max(seq_len))
+                .min(max_cap);
+            let (k_format, v_format) = self.dtype.formats();
+            self.k = Some(KVBuffer::zeros(k_format, (b, h, cap, d), device)?);
+            self.v = Some(KVBuffer::zeros(v_format, (b, h, cap, d), device)?);
+            self.head = 0;
+            return Ok(());
+        };
 
-        // Shift to u8 range [0, 255]. Center is 128.
-        let shifted = (rounded + 128.0)?;
+        let (kb_b, kb_h, cap, _) = kb.data.dims4()?;
+        let kb_d = kb.head_dim;
+        if (kb_b, kb_h, kb_d) != (b, h, d) {
+            candle_core::bail!(
+                "KV cache: append shape [{}, {}, _, {}] does not match cache [{}, {}, _, {}]",
+                b,
+                h,
+                d,
+                kb_b,
+                kb_h,
+                kb_d
+            );
+        }
 
-        // Clamp to ensure safety (though abs/127 should be within range)
-        // Candle's to_dtype(U8) naturally saturates or wraps.
-        // We trust the math: max_val/127 -> range [-127, 127]. +128 -> [1, 255].
-        let quantized = shifted.to_dtype(DType::U8)?;
+        // Grow only while slots are in logical order (not wrapped)
+        let needed = self.current_seq_len + seq_len;
+        let in_order = self.head == self.current_seq_len % cap;
+        if needed > cap && cap < max_cap && in_order {
+            let new_cap = self.round_capacity(needed.max(cap * 2)).min(max_cap);
+            self.k = Some(kb.grow(new_cap)?);
+            self.v = Some(self.v.as_ref().unwrap().grow(new_cap)?);
+            self.head = self.current_seq_len;
+        }
+        Ok(())
+    }
 
-        Ok((quantized, scale))
+    /// Active region of `buf` in its stored format
+    fn stored_view(&self, buf: &KVBuffer, new_tokens: usize) -> Result<KvView> {
+        let cap = buf.capacity();
+        let wrapped = self.current_seq_len == cap && self.head != 0;
+        if !wrapped {
+            return buf.slot_view(self.current_seq_len, self.head);
+        }
+        // Wrapped ring: a single query attends to every key, so slot order is fine
+        let all = buf.slot_view(cap, self.head)?;
+        if new_tokens == 1 {
+            return Ok(all);
+        }
+        let ordered = |t: &Tensor| self.active_view(t, new_tokens);
+        Ok(match all {
+            KvView::Float(x) => KvView::Float(ordered(&x)?),
+            KvView::Q8 { data, scale } => KvView::Q8 {
+                data: ordered(&data)?,
+                scale: ordered(&scale)?,
+            },
+            KvView::Q4 { data, scale } => KvView::Q4 {
+                data: ordered(&data)?,
+                scale: ordered(&scale)?,
+            },
+            KvView::Fp8 { data, scale } => KvView::Fp8 {
+                data: ordered(&data)?,
+                scale: ordered(&scale)?,
+            },
+            // Group statistics are indexed by slot: reorder dequantized values
+            view @ KvView::Q4Channel { .. } => KvView::Float(ordered(&view.dequantize()?)?),
+        })
     }
 
-    /// Dequantize Q8 back to original dtype (f32/f16)
-    fn dequantize_q8(&self, q: &Tensor, s: &Tensor) -> Result<Tensor> {
-        // x = (q - 128) * scale
-        let q_float = q.to_dtype(DType::F32)?;
-        let shifted = (q_float - 128.0)?;
-        let out = shifted.broadcast_mul(s)?;
-        Ok(out)
+    /// Active region in logical (oldest -> newest) order
+    fn active_view(&self, buf: &Tensor, new_tokens: usize) -> Result<Tensor> {
+        let cap = buf.dims()[2];
+        let len = self.current_seq_len;
+        if len < cap || self.head == 0 {
+            return buf.narrow(2, 0, len);
+        }
+        // Wrapped ring: a single query attends to every key, so order is irrelevant
+        if new_tokens == 1 {
+            return Ok(buf.clone());
+        }
+        Tensor::cat(
+            &[
+                &buf.narrow(2, self.head, cap - self.head)?,
+                &buf.narrow(2, 0, self.head)?,
+            ],
+            2,
+        )
     }
 }
+
+/// Quantize a Tensor to Q8 (Symetric + 128 Offset)
+fn quantize_q8(x: &Tensor) -> Result<(Tensor, Tensor)> {
+    // x: [batch, heads, seq, dim]
+    // Scale per token-head: max(abs(x), dim=3) -> [batch, heads, seq, 1]
+    let x_abs = x.abs()?;
+    let max_val = x_abs.max_keepdim(3)?;
+    // Avoid division by zero
+    let scale = (max_val / 127.0)?;
+
+    // Broadcast scale
+    let scaled = x.broadcast_div(&scale)?;
+
+    // Quantize: round(x/s) + 128
+    // We use standard rounding.
+    let rounded = scaled.round()?;
+
+    // Shift to u8 range [0, 255]. Center is 128.
+    let shifted = (rounded + 128.0)?;
+
+    // Clamp to ensure safety (though abs/127 should be within range)
+    // Candle's to_dtype(U8) naturally saturates or wraps.
+    // We trust the math: max_val/127 -> range [-127, 127]. +128 -> [1, 255].
+    let quantized = shifted.to_dtype(DType::U8)?;
+
+    Ok((quantized, scale))
+}
+
+/// Dequantize Q8 back to f32: x = (q - 128) * scale
+fn dequantize_q8(q: &Tensor, s: &Tensor) -> Result<Tensor> {
+    (q.to_dtype(DType::F32)? - 128.0)?.broadcast_mul(s)
+}
+
+/// Flattened f32 copy of `x` on the host
+fn to_host(x: &Tensor) -> Result<Vec<f32>> {
+    x.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()
+}
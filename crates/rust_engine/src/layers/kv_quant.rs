@@ -0,0 +1,277 @@
+//! KV Quant - Storage formats of the KV cache
+//!
+//! Row codecs shared by `QuantizedKVCache` (encode on append, decode for the
+//! f32 views) and `FlashAttentionCpu` (decode inside the attention loop).
+//!
+//! | dtype  | keys                              | values            | bits/value |
+//! |--------|-----------------------------------|-------------------|------------|
+//! | `f32`  | raw                               | raw               | 32         |
+//! | `f16`  | raw                               | raw               | 16         |
+//! | `q8`   | per-token symmetric, zero 128     | same              | 8          |
+//! | `q4`   | per-token symmetric, zero 8       | same              | 4          |
+//! | `fp8`  | per-token scaled FP8 (e4m3)       | same              | 8          |
+//! | `kivi` | per-channel asymmetric 4-bit      | per-token `q4`    | 4          |
+//!
+//! KIVI keys are quantized per channel over groups of [`KIVI_GROUP`] tokens;
+//! the newest, still incomplete group is kept in full precision.
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+/// Storage precision of the KV cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum KvCacheDtype {
+    /// No quantization (debugging / reference)
+    #[serde(rename = "f32")]
+    F32,
+    #[serde(rename = "f16")]
+    F16,
+    #[serde(rename = "q8")]
+    #[default]
+    Q8,
+    #[serde(rename = "q4")]
+    Q4,
+    #[serde(rename = "fp8", alias = "fp8_e4m3", alias = "e4m3")]
+    Fp8,
+    /// Per-channel 4-bit keys with a full-precision residual window, `q4` values
+    #[serde(rename = "kivi")]
+    Kivi,
+}
+
+impl KvCacheDtype {
+    /// Stored bits per key / value (scales not included)
+    pub fn bits_per_value(self) -> usize {
+        match self {
+            Self::F32 => 32,
+            Self::F16 => 16,
+            Self::Q8 | Self::Fp8 => 8,
+            Self::Q4 | Self::Kivi => 4,
+        }
+    }
+
+    /// Storage of keys and values
+    pub(crate) fn formats(self) -> (KvFormat, KvFormat) {
+        match self {
+            Self::F32 => (KvFormat::F32, KvFormat::F32),
+            Self::F16 => (KvFormat::F16, KvFormat::F16),
+            Self::Q8 => (KvFormat::Q8, KvFormat::Q8),
+            Self::Q4 => (KvFormat::Q4, KvFormat::Q4),
+            Self::Fp8 => (KvFormat::Fp8, KvFormat::Fp8),
+            Self::Kivi => (KvFormat::Q4Channel, KvFormat::Q4),
+        }
+    }
+}
+
+impl std::str::FromStr for KvCacheDtype {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" | "off" => Ok(Self::F32),
+            "f16" => Ok(Self::F16),
+            "q8" => Ok(Self::Q8),
+            "q4" => Ok(Self::Q4),
+            "fp8" | "fp8_e4m3" | "e4m3" => Ok(Self::Fp8),
+            "kivi" => Ok(Self::Kivi),
+            other => Err(format!(
+                "unknown kv_cache_dtype '{}' (expected f32, f16, q8, q4, fp8 or kivi)",
+                other
+            )),
+        }
+    }
+}
+
+/// Layout of one side (K or V) of the cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum KvFormat {
+    F32,
+    F16,
+    Q8,
+    Q4,
+    Fp8,
+    Q4Channel,
+}
+
+impl KvFormat {
+    /// Stored elements per token-head row of `d` values
+    pub(crate) fn row_width(self, d: usize) -> usize {
+        match self {
+            Self::Q4 | Self::Q4Channel => d.div_ceil(2),
+            _ => d,
+        }
+    }
+}
+
+/// Tokens per KIVI key group (per-channel statistics are shared by a group)
+pub const KIVI_GROUP: usize = 32;
+
+/// Zero point of the Q4 formats (codes 1..=15 for -7..=7)
+pub(crate) const Q4_ZERO: f32 = 8.0;
+const Q4_MAX: f32 = 7.0;
+/// Largest finite e4m3 value
+const FP8_MAX: f32 = 448.0;
+
+/// e4m3 (bias 7, no infinities, 0x7F/0xFF = NaN) code -> f32
+pub(crate) fn fp8_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0f32; 256];
+        for (code, v) in table.iter_mut().enumerate() {
+            let exp = ((code >> 3) & 0xF) as i32;
+            let mant = (code & 0x7) as f32;
+            let mag = if exp == 0 {
+                mant / 8.0 * 2f32.powi(-6)
+            } else {
+                (1.0 + mant / 8.0) * 2f32.powi(exp - 7)
+            };
+            let mag = if code & 0x7F == 0x7F { f32::NAN } else { mag };
+            *v = if code & 0x80 != 0 { -mag } else { mag };
+        }
+        table
+    })
+}
+
+/// Nearest e4m3 code (saturating at ±448)
+pub(crate) fn fp8_encode(x: f32) -> u8 {
+    // Codes 0x00..=0x7E are the finite non-negative values in increasing order
+    let positive = &fp8_table()[..0x7F];
+    let mag = x.abs().min(FP8_MAX);
+    let hi = positive.partition_point(|&v| v < mag).min(0x7E);
+    let code = if hi > 0 && mag - positive[hi - 1] <= positive[hi] - mag {
+        hi - 1
+    } else {
+        hi
+    } as u8;
+    if x.is_sign_negative() && code != 0 {
+        code | 0x80
+    } else {
+        code
+    }
+}
+
+/// Per-token quantization of `rows x d` values (Q4 / FP8; Q8 runs as tensor ops)
+///
+/// Returns (codes: rows x row_width, scales: rows)
+pub(crate) fn quantize_rows(format: KvFormat, x: &[f32], d: usize) -> (Vec<u8>, Vec<f32>) {
+    let width = format.row_width(d);
+    let rows = x.len() / d.max(1);
+    let mut codes = vec![0u8; rows * width];
+    let mut scales = vec![0.0f32; rows];
+    for ((row, out), scale) in x
+        .chunks(d)
+        .zip(codes.chunks_mut(width))
+        .zip(scales.iter_mut())
+    {
+        let amax = row.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        match format {
+            KvFormat::Q4 => {
+                *scale = amax / Q4_MAX;
+                let inv = if amax > 0.0 { 1.0 / *scale } else { 0.0 };
+                let code = |v: f32| ((v * inv).round().clamp(-Q4_MAX, Q4_MAX) + Q4_ZERO) as u8;
+                for (c, pair) in out.iter_mut().zip(row.chunks(2)) {
+                    let hi = pair.get(1).map_or(Q4_ZERO as u8, |&v| code(v));
+                    *c = code(pair[0]) | (hi << 4);
+                }
+            }
+            KvFormat::Fp8 => {
+                *scale = amax / FP8_MAX;
+                let inv = if amax > 0.0 { 1.0 / *scale } else { 0.0 };
+                for (c, &v) in out.iter_mut().zip(row) {
+                    *c = fp8_encode(v * inv);
+                }
+            }
+            _ => unreachable!("{:?} is not a host-side per-token format", format),
+        }
+    }
+    (codes, scales)
+}
+
+/// Inverse of [`quantize_rows`]
+pub(crate) fn dequantize_rows(
+    format: KvFormat,
+    codes: &[u8],
+    scales: &[f32],
+    d: usize,
+) -> Vec<f32> {
+    let width = format.row_width(d);
+    let mut out = vec![0.0f32; scales.len() * d];
+    for ((row, src), &scale) in out.chunks_mut(d).zip(codes.chunks(width)).zip(scales) {
+        match format {
+            KvFormat::Q4 => {
+                for (pair, &c) in row.chunks_mut(2).zip(src) {
+                    pair[0] = ((c & 0xF) as f32 - Q4_ZERO) * scale;
+                    if let Some(o) = pair.get_mut(1) {
+                        *o = ((c >> 4) as f32 - Q4_ZERO) * scale;
+                    }
+                }
+            }
+            KvFormat::Fp8 => {
+                let table = fp8_table();
+                for (o, &c) in row.iter_mut().zip(src) {
+                    *o = table[c as usize] * scale;
+                }
+            }
+            _ => unreachable!("{:?} is not a host-side per-token format", format),
+        }
+    }
+    out
+}
+
+/// Per-channel asymmetric 4-bit quantization of one group (`rows x d`)
+///
+/// Returns (packed codes: rows x ceil(d/2), scale: d, min: d);
+/// value = code * scale + min.
+pub(crate) fn quantize_channels(x: &[f32], d: usize) -> (Vec<u8>, Vec<f32>, Vec<f32>) {
+    let mut min = vec![f32::INFINITY; d];
+    let mut max = vec![f32::NEG_INFINITY; d];
+    for row in x.chunks(d) {
+        for (i, &v) in row.iter().enumerate() {
+            min[i] = min[i].min(v);
+            max[i] = max[i].max(v);
+        }
+    }
+    let scale: Vec<f32> = min
+        .iter()
+        .zip(&max)
+        .map(|(lo, hi)| (hi - lo) / 15.0)
+        .collect();
+
+    let width = KvFormat::Q4Channel.row_width(d);
+    let mut codes = vec![0u8; x.len() / d.max(1) * width];
+    for (row, out) in x.chunks(d).zip(codes.chunks_mut(width)) {
+        let code = |i: usize| {
+            if scale[i] > 0.0 {
+                ((row[i] - min[i]) / scale[i]).round().clamp(0.0, 15.0) as u8
+            } else {
+                0
+            }
+        };
+        for (p, c) in out.iter_mut().enumerate() {
+            let hi = if 2 * p + 1 < d { code(2 * p + 1) } else { 0 };
+            *c = code(2 * p) | (hi << 4);
+        }
+    }
+    (codes, scale, min)
+}
+
+/// Code of channel `i` in a packed 4-bit row
+#[inline]
+pub(crate) fn q4_code(row: &[u8], i: usize) -> u8 {
+    (row[i / 2] >> ((i & 1) * 4)) & 0xF
+}
+
+/// Inverse of [`quantize_channels`] for one group
+pub(crate) fn dequantize_channels(codes: &[u8], scale: &[f32], min: &[f32], d: usize) -> Vec<f32> {
+    let width = KvFormat::Q4Channel.row_width(d);
+    let mut out = vec![0.0f32; codes.len() / width.max(1) * d];
+    for (row, src) in out.chunks_mut(d).zip(codes.chunks(width)) {
+        for (i, o) in row.iter_mut().enumerate() {
+            *o = q4_code(src, i) as f32 * scale[i] + min[i];
+        }
+    }
+    out
+}
@@ -0,0 +1,192 @@
+//! LoRA - Low-rank adapters on top of frozen ternary projections
+
+use candle_core::{Device, Result, Tensor};
+use candle_nn::VarBuilder;
+use serde::{Deserialize, Serialize};
+
+use super::TensorExt;
+
+/// Projection families that can carry an adapter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoraTarget {
+    Q,
+    K,
+    V,
+    O,
+    Gate,
+    Up,
+    Down,
+    /// TTT inner projections (`ttt.down` / `ttt.up`)
+    Ttt,
+}
+
+impl LoraTarget {
+    pub const ALL: [LoraTarget; 8] = [
+        LoraTarget::Q,
+        LoraTarget::K,
+        LoraTarget::V,
+        LoraTarget::O,
+        LoraTarget::Gate,
+        LoraTarget::Up,
+        LoraTarget::Down,
+        LoraTarget::Ttt,
+    ];
+
+    /// Parse a comma separated list ("q,v", "q_proj,o_proj", "all")
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        let mut targets = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if item.eq_ignore_ascii_case("all") {
+                return Ok(Self::ALL.to_vec());
+            }
+            let t = item.parse::<Self>()?;
+            if !targets.contains(&t) {
+                targets.push(t);
+            }
+        }
+        Ok(targets)
+    }
+}
+
+impl std::str::FromStr for LoraTarget {
+    type Err = candle_core::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_ascii_lowercase();
+        let name = name.strip_suffix("_proj").unwrap_or(&name);
+        Ok(match name {
+            "q" => LoraTarget::Q,
+            "k" => LoraTarget::K,
+            "v" => LoraTarget::V,
+            "o" => LoraTarget::O,
+            "gate" | "w1" => LoraTarget::Gate,
+            "up" | "w3" => LoraTarget::Up,
+            "down" | "w2" => LoraTarget::Down,
+            "ttt" => LoraTarget::Ttt,
+            _ => candle_core::bail!(
+                "Unknown LoRA target '{}' (expected q/k/v/o/gate/up/down/ttt)",
+                s
+            ),
+        })
+    }
+}
+
+/// Adapter hyper-parameters (saved next to adapter weights)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoraConfig {
+    pub rank: usize,
+    pub alpha: f64,
+    pub targets: Vec<LoraTarget>,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            rank: 8,
+            alpha: 16.0,
+            targets: vec![LoraTarget::Q, LoraTarget::V],
+        }
+    }
+}
+
+impl LoraConfig {
+    pub fn scaling(&self) -> f64 {
+        self.alpha / self.rank as f64
+    }
+}
+
+/// Low-rank update `scale * B @ A` kept in full precision
+///
+/// - A: [rank, in_features]
+/// - B: [out_features, rank]
+#[derive(Clone)]
+pub struct LoraAdapter {
+    pub a: Tensor,
+    pub b: Tensor,
+    pub scale: f64,
+}
+
+impl LoraAdapter {
+    pub fn new(a: Tensor, b: Tensor, scale: f64) -> Result<Self> {
+        let (rank_a, _in) = a.dims2()?;
+        let (_out, rank_b) = b.dims2()?;
+        if rank_a != rank_b {
+            candle_core::bail!("LoRA rank mismatch: A {:?} vs B {:?}", a.dims(), b.dims());
+        }
+        Ok(Self { a, b, scale })
+    }
+
+    /// Fail unless A is [rank, in_features] and B [out_features, rank] for
+    /// the projection `name`
+    pub fn check_shape(&self, name: &str, in_features: usize, out_features: usize) -> Result<()> {
+        let (rank, a_in) = self.a.dims2()?;
+        let (b_out, _) = self.b.dims2()?;
+        if a_in != in_features || b_out != out_features {
+            candle_core::bail!(
+                "LoRA {}: A {:?} / B {:?} don't fit a {}x{} projection (expected A [{}, {}], B [{}, {}])",
+                name,
+                self.a.dims(),
+                self.b.dims(),
+                out_features,
+                in_features,
+                rank,
+                in_features,
+                out_features,
+                rank
+            );
+        }
+        Ok(())
+    }
+
+    /// Create trainable A/B from a VarBuilder (A: Kaiming, B: zeros, so the
+    /// adapter starts as an exact no-op)
+    pub fn init(
+        in_features: usize,
+        out_features: usize,
+        cfg: &LoraConfig,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let a = vb.pp("lora_A").get_with_hints(
+            (cfg.rank, in_features),
+            "weight",
+            candle_nn::init::DEFAULT_KAIMING_UNIFORM,
+        )?;
+        let b = vb.pp("lora_B").get_with_hints(
+            (out_features, cfg.rank),
+            "weight",
+            candle_nn::Init::Const(0.0),
+        )?;
+        Self::new(a, b, cfg.scaling())
+    }
+
+    pub fn rank(&self) -> usize {
+        self.a.dims()[0]
+    }
+
+    /// Bytes held by A and B
+    pub fn size_in_bytes(&self) -> usize {
+        (self.a.elem_count() + self.b.elem_count()) * self.a.dtype().size_in_bytes()
+    }
+
+    pub fn to_device(&self, device: &Device) -> Result<Self> {
+        Ok(Self {
+            a: self.a.to_device(device)?,
+            b: self.b.to_device(device)?,
+            scale: self.scale,
+        })
+    }
+
+    /// y = scale * (x @ A^T) @ B^T
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        // [Hybrid Guard] matmul_robust moves A/B next to the input
+        let h = x.matmul_robust(&self.a.t()?)?;
+        let y = h.matmul_robust(&self.b.t()?)?;
+        y * self.scale
+    }
+
+    /// Dense weight delta `scale * B @ A` ([out, in])
+    pub fn delta_weight(&self) -> Result<Tensor> {
+        self.b.matmul(&self.a)? * self.scale
+    }
+}
@@ -0,0 +1,173 @@
+//! MoE - Sparse Mixture-of-Experts MLP with ternary SwiGLU experts
+
+use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_nn::VarBuilder;
+
+use super::SwiGLU;
+use crate::kernels::packing::PackOptions;
+
+/// Sparse MoE block (Mixtral layout: `gate` router + `experts.{i}.w1/w2/w3`)
+///
+/// Each token is routed to its `top_k` highest-scoring experts and only those
+/// experts run, so with packed weights the per-token cost stays at `top_k`
+/// BitLinearCpu passes regardless of `num_experts`.
+pub struct MoE {
+    /// Router: [num_experts, hidden] (kept in full precision)
+    pub gate: candle_nn::Linear,
+    pub experts: Vec<SwiGLU>,
+    pub top_k: usize,
+}
+
+impl MoE {
+    pub fn load(
+        hidden_dim: usize,
+        intermediate_dim: usize,
+        num_experts: usize,
+        top_k: usize,
+        vb: VarBuilder,
+        device: &Device,
+    ) -> Result<Self> {
+        if top_k == 0 || top_k > num_experts {
+            candle_core::bail!(
+                "MoE: top_k ({}) must be in 1..={} (num_experts)",
+                top_k,
+                num_experts
+            );
+        }
+
+        let gate_w = vb.pp("gate").get_with_hints(
+            (num_experts, hidden_dim),
+            "weight",
+            candle_nn::init::DEFAULT_KAIMING_NORMAL,
+        )?;
+
+        // [Plan B] Explicit Mmap Detachment
+        let gate_w = if device.is_cpu() {
+            gate_w.to_dtype(DType::F32)?.copy()?
+        } else {
+            gate_w.to_device(device)?
+        };
+
+        let experts = (0..num_experts)
+            .map(|i| {
+                SwiGLU::load_expert(
+                    hidden_dim,
+                    intermediate_dim,
+                    vb.pp("experts").pp(i.to_string()),
+                    device,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            gate: candle_nn::Linear::new(gate_w, None),
+            experts,
+            top_k,
+        })
+    }
+
+    pub fn num_experts(&self) -> usize {
+        self.experts.len()
+    }
+
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        Ok(self.forward_with_aux(x)?.0)
+    }
+
+    /// Forward pass returning `(output, aux_loss)`.
+    ///
+    /// `aux_loss` is the Switch-Transformer load-balancing term
+    /// `E * sum_i(f_i * P_i)`, where `f_i` is the fraction of routing slots
+    /// assigned to expert `i` and `P_i` its mean router probability.
+    /// It equals 1.0 under perfectly uniform routing.
+    pub fn forward_with_aux(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let dims = x.dims().to_vec();
+        let hidden = dims[dims.len() - 1];
+        let n_tokens = x.elem_count() / hidden;
+        let n_experts = self.num_experts();
+        let k = self.top_k;
+
+        let x_flat = x.reshape((n_tokens, hidden))?;
+        // Batch row of each token (row adapters are installed per batch row)
+        let tokens_per_row = n_tokens / dims[0].max(1);
+
+        // [Hybrid Guard] Router runs on the device of the input
+        let gate = if self.gate.weight().device().same_device(x_flat.device()) {
+            self.gate.clone()
+        } else {
+            candle_nn::Linear::new(self.gate.weight().to_device(x_flat.device())?, None)
+        };
+
+        // 1. Routing probabilities [N, E]
+        let router_logits = gate.forward(&x_flat)?;
+        let probs = candle_nn::ops::softmax(&router_logits, D::Minus1)?;
+
+        // 2. Top-k selection (on host; E is small)
+        let probs_host = probs.to_dtype(DType::F32)?.to_vec2::<f32>()?;
+        let mut topk_idx: Vec<u32> = Vec::with_capacity(n_tokens * k);
+        for row in &probs_host {
+            let mut order: Vec<usize> = (0..n_experts).collect();
+            order.sort_by(|&a, &b| row[b].total_cmp(&row[a]));
+            topk_idx.extend(order[..k].iter().map(|&e| e as u32));
+        }
+
+        // 3. Renormalized routing weights [N*k] (differentiable w.r.t. router)
+        let topk_t = Tensor::from_vec(topk_idx.clone(), (n_tokens, k), x_flat.device())?;
+        let topk_w = probs.gather(&topk_t, 1)?;
+        let topk_w = topk_w.broadcast_div(&topk_w.sum_keepdim(1)?)?;
+        let topk_w = topk_w.flatten_all()?;
+
+        // 4. Dispatch: run each expert only on the tokens routed to it
+        let mut out = x_flat.zeros_like()?;
+        let mut counts = vec![0f32; n_experts];
+        for (e, expert) in self.experts.iter().enumerate() {
+            let mut tokens: Vec<u32> = Vec::new();
+            let mut slots: Vec<u32> = Vec::new();
+            for (slot, &sel) in topk_idx.iter().enumerate() {
+                if sel as usize == e {
+                    tokens.push((slot / k) as u32);
+                    slots.push(slot as u32);
+                }
+            }
+            if tokens.is_empty() {
+                continue;
+            }
+            counts[e] = tokens.len() as f32;
+
+            let n_sel = tokens.len();
+            let rows: Vec<usize> = tokens
+                .iter()
+                .map(|&t| t as usize / tokens_per_row.max(1))
+                .collect();
+            let tok_t = Tensor::from_vec(tokens, (n_sel,), x_flat.device())?;
+            let slot_t = Tensor::from_vec(slots, (n_sel,), x_flat.device())?;
+
+            let x_e = x_flat.index_select(&tok_t, 0)?;
+            let y_e = expert.forward_routed(&x_e, Some(&rows))?;
+            let y_e = if y_e.device().same_device(out.device()) {
+                y_e
+            } else {
+                y_e.to_device(out.device())?
+            };
+            let w_e = topk_w.index_select(&slot_t, 0)?.unsqueeze(1)?;
+            let y_e = y_e.broadcast_mul(&w_e.to_dtype(y_e.dtype())?)?;
+            out = out.index_add(&tok_t, &y_e, 0)?;
+        }
+
+        // 5. Load-balancing loss
+        let total_slots = (n_tokens * k) as f32;
+        let frac: Vec<f32> = counts.iter().map(|c| c / total_slots).collect();
+        let frac = Tensor::from_vec(frac, (n_experts,), probs.device())?;
+        let mean_prob = probs.mean(0)?;
+        let aux_loss = ((mean_prob * frac)?.sum_all()? * n_experts as f64)?;
+
+        Ok((out.reshape(dims)?, aux_loss))
+    }
+
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
+        for expert in self.experts.iter_mut() {
+            expert.precompute_packed(options)?;
+        }
+        Ok(())
+    }
+}
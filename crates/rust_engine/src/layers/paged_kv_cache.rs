@@ -0,0 +1,277 @@
+//! PagedKVCache - Block-pooled KV cache for batched serving
+//!
+//! Keys and values of all sequences live in one preallocated pool per layer,
+//! cut into fixed-size blocks of `block_size` tokens. Each sequence owns a
+//! `BlockTable` (logical block -> physical block), so memory is handed out a
+//! block at a time instead of one contiguous buffer per sequence.
+//!
+//! Blocks are reference counted: `fork` shares every block of a sequence
+//! (e.g. a common prompt prefix) and the partially filled last block is copied
+//! on the first write after a fork (copy-on-write). Storage reuses the
+//! per-token formats of `QuantizedKVCache` (`KvCacheDtype`, except KIVI whose
+//! per-channel groups span a sequence's own tokens).
+
+use candle_core::{Device, Result, Tensor};
+
+use super::kv_cache::{KVBuffer, QuantizedKV};
+use super::kv_quant::KvCacheDtype;
+
+/// Physical blocks of one sequence
+#[derive(Debug, Clone, Default)]
+pub struct BlockTable {
+    blocks: Vec<usize>,
+    len: usize,
+}
+
+impl BlockTable {
+    /// Cached tokens
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Physical block ids, in logical order
+    pub fn blocks(&self) -> &[usize] {
+        &self.blocks
+    }
+}
+
+/// Reference-counted free list of physical blocks
+#[derive(Debug, Clone)]
+struct BlockAllocator {
+    ref_counts: Vec<u32>,
+    free: Vec<usize>,
+}
+
+impl BlockAllocator {
+    fn new(num_blocks: usize) -> Self {
+        Self {
+            ref_counts: vec![0; num_blocks],
+            // Pop from the back -> hand out low ids first
+            free: (0..num_blocks).rev().collect(),
+        }
+    }
+
+    fn alloc(&mut self) -> Result<usize> {
+        let Some(block) = self.free.pop() else {
+            candle_core::bail!(
+                "PagedKVCache: out of blocks (pool of {})",
+                self.ref_counts.len()
+            );
+        };
+        self.ref_counts[block] = 1;
+        Ok(block)
+    }
+
+    fn retain(&mut self, block: usize) {
+        self.ref_counts[block] += 1;
+    }
+
+    fn release(&mut self, block: usize) {
+        self.ref_counts[block] -= 1;
+        if self.ref_counts[block] == 0 {
+            self.free.push(block);
+        }
+    }
+}
+
+/// Pool of one attention layer (None for layers without a KV cache)
+#[derive(Debug)]
+struct LayerPool {
+    k: KVBuffer,
+    v: KVBuffer,
+}
+
+/// Shape of one layer's pool: (n_kv_heads, head_dim, device)
+pub type PagedLayerSpec = Option<(usize, usize, Device)>;
+
+/// Shared block pool + allocator for all layers of a model
+#[derive(Debug)]
+pub struct PagedKVCache {
+    block_size: usize,
+    dtype: KvCacheDtype,
+    allocator: BlockAllocator,
+    layers: Vec<Option<LayerPool>>,
+}
+
+impl PagedKVCache {
+    /// Allocate `num_blocks` blocks of `block_size` tokens for every layer in `layers`
+    pub fn new(
+        layers: &[PagedLayerSpec],
+        num_blocks: usize,
+        block_size: usize,
+        dtype: KvCacheDtype,
+    ) -> Result<Self> {
+        if dtype == KvCacheDtype::Kivi {
+            candle_core::bail!("PagedKVCache: KIVI groups are per sequence, use q8/q4/fp8/f16/f32");
+        }
+        if num_blocks == 0 || block_size == 0 {
+            candle_core::bail!("PagedKVCache: empty pool ({} x {})", num_blocks, block_size);
+        }
+        let (k_format, v_format) = dtype.formats();
+        let layers = layers
+            .iter()
+            .map(|spec| {
+                spec.as_ref()
+                    .map(|(n_kv_heads, head_dim, device)| {
+                        let shape = (1, *n_kv_heads, num_blocks * block_size, *head_dim);
+                        Ok::<_, candle_core::Error>(LayerPool {
+                            k: KVBuffer::zeros(k_format, shape, device)?,
+                            v: KVBuffer::zeros(v_format, shape, device)?,
+                        })
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            block_size,
+            dtype,
+            allocator: BlockAllocator::new(num_blocks),
+            layers,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn dtype(&self) -> KvCacheDtype {
+        self.dtype
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.allocator.ref_counts.len()
+    }
+
+    pub fn free_blocks(&self) -> usize {
+        self.allocator.free.len()
+    }
+
+    pub fn used_blocks(&self) -> usize {
+        self.num_blocks() - self.free_blocks()
+    }
+
+    /// Blocks referenced by more than one sequence
+    pub fn shared_blocks(&self) -> usize {
+        self.allocator.ref_counts.iter().filter(|&&c| c > 1).count()
+    }
+
+    /// Fraction of the pool in use (0.0 ..= 1.0)
+    pub fn utilization(&self) -> f32 {
+        self.used_blocks() as f32 / self.num_blocks() as f32
+    }
+
+    /// Share all blocks of `table` with a new sequence (copy-on-write prefix)
+    pub fn fork(&mut self, table: &BlockTable) -> BlockTable {
+        for &block in &table.blocks {
+            self.allocator.retain(block);
+        }
+        table.clone()
+    }
+
+    /// Return the blocks of a finished sequence to the pool
+    pub fn free(&mut self, table: &mut BlockTable) {
+        for block in table.blocks.drain(..) {
+            self.allocator.release(block);
+        }
+        table.len = 0;
+    }
+
+    /// Make room for `n` more tokens: un-share the partially filled last
+    /// block (copy-on-write) and allocate new blocks as needed.
+    ///
+    /// Fails without allocating anything when the pool is too small.
+    /// Call once per step before writing the layers, then `advance`.
+    pub fn reserve(&mut self, table: &mut BlockTable, n: usize) -> Result<()> {
+        let bs = self.block_size;
+        let partial = table.len % bs;
+        let shared_tail =
+            partial > 0 && self.allocator.ref_counts[table.blocks[table.len / bs]] > 1;
+        let needed = (table.len + n)
+            .div_ceil(bs)
+            .saturating_sub(table.blocks.len());
+        if needed + usize::from(shared_tail) > self.free_blocks() {
+            candle_core::bail!(
+                "PagedKVCache: out of blocks ({} of {} free)",
+                self.free_blocks(),
+                self.num_blocks()
+            );
+        }
+        if shared_tail {
+            let last = table.blocks[table.len / bs];
+            let copy = self.allocator.alloc()?;
+            for pool in self.layers.iter().flatten() {
+                pool.k.copy_slots(last * bs, copy * bs, bs)?;
+                pool.v.copy_slots(last * bs, copy * bs, bs)?;
+            }
+            self.allocator.release(last);
+            table.blocks[table.len / bs] = copy;
+        }
+        while table.blocks.len() * bs < table.len + n {
+            table.blocks.push(self.allocator.alloc()?);
+        }
+        Ok(())
+    }
+
+    /// Count `n` reserved tokens as written (after every layer wrote them)
+    pub fn advance(&self, table: &mut BlockTable, n: usize) {
+        table.len = (table.len + n).min(table.blocks.len() * self.block_size);
+    }
+
+    fn pool(&self, layer: usize) -> Result<&LayerPool> {
+        match self.layers.get(layer) {
+            Some(Some(pool)) => Ok(pool),
+            _ => candle_core::bail!("PagedKVCache: layer {} has no KV pool", layer),
+        }
+    }
+
+    /// Quantize and write k/v [1, n_kv_heads, n, head_dim] at the reserved
+    /// positions `table.len()..table.len() + n` of `layer`
+    pub fn write(&self, layer: usize, table: &BlockTable, k: &Tensor, v: &Tensor) -> Result<()> {
+        let pool = self.pool(layer)?;
+        let n = k.dims4()?.2;
+        let bs = self.block_size;
+        if table.len + n > table.blocks.len() * bs {
+            candle_core::bail!("PagedKVCache: {} tokens written without reserve", n);
+        }
+        let mut done = 0;
+        while done < n {
+            let pos = table.len + done;
+            let run = (bs - pos % bs).min(n - done);
+            let slot = table.blocks[pos / bs] * bs + pos % bs;
+            pool.k.write(slot, &k.narrow(2, done, run)?)?;
+            pool.v.write(slot, &v.narrow(2, done, run)?)?;
+            done += run;
+        }
+        Ok(())
+    }
+
+    /// Whole-pool views of `layer` ([1, n_kv_heads, num_blocks * block_size, _]),
+    /// to be read through a block table
+    pub fn pool_view(&self, layer: usize) -> Result<QuantizedKV> {
+        let pool = self.pool(layer)?;
+        let slots = pool.k.capacity();
+        Ok(QuantizedKV {
+            k: pool.k.slot_view(slots, 0)?,
+            v: pool.v.slot_view(slots, 0)?,
+        })
+    }
+
+    /// First `len` tokens of a sequence gathered in logical order
+    /// (fallback for devices without the paged kernel)
+    pub fn gather(&self, layer: usize, table: &BlockTable, len: usize) -> Result<QuantizedKV> {
+        let pool = self.pool_view(layer)?;
+        let bs = self.block_size;
+        let slots: Vec<u32> = (0..len)
+            .map(|p| (table.blocks[p / bs] * bs + p % bs) as u32)
+            .collect();
+        let index = Tensor::from_vec(slots, len, self.pool(layer)?.k.device())?;
+        Ok(QuantizedKV {
+            k: pool.k.index_select(&index)?,
+            v: pool.v.index_select(&index)?,
+        })
+    }
+}
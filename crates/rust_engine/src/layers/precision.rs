@@ -0,0 +1,146 @@
+//! Dense precision - storage and compute dtypes of the non-ternary weights
+//!
+//! Embeddings, `lm_head`, norms and dense fallback weights may be stored in
+//! F16/BF16 (half the RAM of F32, BF16 HF checkpoints load without a copy).
+//! The hidden stream between layers stays F32: embeddings are widened after
+//! the lookup, RMSNorm computes its statistics in F32 and dense matmuls
+//! return to the caller's dtype.
+
+use candle_core::{DType, Device, Result, Storage, Tensor, WithDType};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+use super::TensorExt;
+
+/// Floating-point format of dense weights or of a dense op
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum FloatDtype {
+    #[serde(rename = "f32")]
+    #[default]
+    F32,
+    #[serde(rename = "f16")]
+    F16,
+    #[serde(rename = "bf16")]
+    BF16,
+}
+
+impl FloatDtype {
+    pub fn dtype(self) -> DType {
+        match self {
+            Self::F32 => DType::F32,
+            Self::F16 => DType::F16,
+            Self::BF16 => DType::BF16,
+        }
+    }
+}
+
+impl std::str::FromStr for FloatDtype {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" | "fp32" | "float32" => Ok(Self::F32),
+            "f16" | "fp16" | "float16" | "half" => Ok(Self::F16),
+            "bf16" | "bfloat16" => Ok(Self::BF16),
+            other => Err(format!(
+                "unknown float dtype '{}' (expected f32, f16 or bf16)",
+                other
+            )),
+        }
+    }
+}
+
+/// Storage and compute dtypes of one dense weight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DensePrecision {
+    pub storage: DType,
+    pub compute: DType,
+}
+
+impl Default for DensePrecision {
+    fn default() -> Self {
+        Self {
+            storage: DType::F32,
+            compute: DType::F32,
+        }
+    }
+}
+
+impl DensePrecision {
+    /// `w` in the storage dtype
+    pub fn store(&self, w: &Tensor) -> Result<Tensor> {
+        w.to_dtype(self.storage)
+    }
+
+    /// x W^T with both operands in the compute dtype, returned in x's dtype.
+    /// F16/BF16 CPU weights with F32 compute are read in place and accumulated
+    /// in F32 (`half_matmul`); other mixed pairs convert W per call.
+    pub fn forward(&self, x: &Tensor, w: &Tensor) -> Result<Tensor> {
+        let on_cpu = x.device().is_cpu() && w.device().is_cpu();
+        // candle's CPU matmul only has F16/F32/F64 kernels (BF16 is rejected),
+        // so BF16 compute accumulates in F32 there
+        let compute = match self.compute {
+            DType::BF16 if on_cpu => DType::F32,
+            dtype => dtype,
+        };
+        if on_cpu && compute == DType::F32 {
+            match w.dtype() {
+                DType::BF16 => return half_matmul(x, w, half::bf16::to_f32),
+                DType::F16 => return half_matmul(x, w, half::f16::to_f32),
+                _ => {}
+            }
+        }
+        let w = w.to_dtype(compute)?.t()?;
+        x.to_dtype(compute)?.matmul_robust(&w)?.to_dtype(x.dtype())
+    }
+}
+
+/// x [.., k] W^T for a half-precision CPU weight W [n, k], accumulated in F32
+/// one widened weight row at a time (no F32 copy of W)
+fn half_matmul<T: WithDType>(x: &Tensor, w: &Tensor, to_f32: fn(T) -> f32) -> Result<Tensor> {
+    let (n, k) = w.dims2()?;
+    if x.dims().last() != Some(&k) {
+        candle_core::bail!(
+            "DensePrecision: input {:?} doesn't match weight [{}, {}]",
+            x.dims(),
+            n,
+            k
+        );
+    }
+    let x_vec = x.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+    let m = x_vec.len() / k.max(1);
+    let w = w.contiguous()?;
+    let (storage, layout) = w.storage_and_layout();
+    let Storage::Cpu(storage) = &*storage else {
+        candle_core::bail!("DensePrecision: weight is not on the CPU");
+    };
+    let start = layout.start_offset();
+    let w_vec = &storage.as_slice::<T>()?[start..start + n * k];
+
+    // [n, m]: one weight row per task, widened once for all m inputs
+    let mut out_t = vec![0.0f32; n * m];
+    out_t
+        .par_chunks_mut(m.max(1))
+        .zip(w_vec.par_chunks(k.max(1)))
+        .for_each_init(
+            || vec![0.0f32; k],
+            |row, (out, w_row)| {
+                row.iter_mut().zip(w_row).for_each(|(r, &w)| *r = to_f32(w));
+                for (o, x_row) in out.iter_mut().zip(x_vec.chunks(k.max(1))) {
+                    *o = row.iter().zip(x_row).map(|(w, x)| w * x).sum();
+                }
+            },
+        );
+    let out = if m == 1 {
+        out_t
+    } else {
+        (0..m * n).map(|i| out_t[(i % n) * m + i / n]).collect()
+    };
+    let mut shape = x.dims().to_vec();
+    *shape.last_mut().unwrap() = n;
+    Tensor::from_vec(out, shape, &Device::Cpu)?.to_dtype(x.dtype())
+}
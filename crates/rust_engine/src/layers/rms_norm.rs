@@ -21,9 +21,9 @@ impl RMSNorm {
 
         // [Plan B] Explicit Mmap Detachment
         // If loading to CPU, we must Deep Copy to allow dropping the Mmap file.
+        // (kept in the VarBuilder's dtype, see `layers::precision`)
         let weight = if device.is_cpu() {
-            let data = weight.to_vec1::<f32>()?;
-            Tensor::from_vec(data, weight.shape(), device)?
+            weight.copy()?
         } else {
             weight.to_device(device)?
         };
@@ -4,13 +4,16 @@ use candle_core::Result;
 use candle_core::Tensor;
 use candle_nn::VarBuilder;
 
-use super::AdaptiveBitLinear;
+use super::{AdaptiveBitLinear, FusedProjection};
+use crate::kernels::packing::PackOptions;
 
 /// SwiGLU MLP block (Gate, Down, Up projections)
 pub struct SwiGLU {
     pub w1: AdaptiveBitLinear, // Gate
     pub w2: AdaptiveBitLinear, // Down
     pub w3: AdaptiveBitLinear, // Up
+    /// Gate/up stacked for the fused SiLU epilogue (built by `precompute_packed`)
+    pub fused_gate_up: Option<FusedProjection>,
 }
 
 impl SwiGLU {
@@ -23,21 +26,65 @@ impl SwiGLU {
         let w1 = AdaptiveBitLinear::load(hidden_dim, intermediate_dim, vb.pp("gate_proj"), device)?;
         let w2 = AdaptiveBitLinear::load(intermediate_dim, hidden_dim, vb.pp("down_proj"), device)?;
         let w3 = AdaptiveBitLinear::load(hidden_dim, intermediate_dim, vb.pp("up_proj"), device)?;
-        Ok(Self { w1, w2, w3 })
+        Ok(Self {
+            w1,
+            w2,
+            w3,
+            fused_gate_up: None,
+        })
+    }
+
+    /// Load a Mixtral-style expert (`w1` = Gate, `w2` = Down, `w3` = Up)
+    pub fn load_expert(
+        hidden_dim: usize,
+        intermediate_dim: usize,
+        vb: VarBuilder,
+        device: &candle_core::Device,
+    ) -> Result<Self> {
+        let w1 = AdaptiveBitLinear::load(hidden_dim, intermediate_dim, vb.pp("w1"), device)?;
+        let w2 = AdaptiveBitLinear::load(intermediate_dim, hidden_dim, vb.pp("w2"), device)?;
+        let w3 = AdaptiveBitLinear::load(hidden_dim, intermediate_dim, vb.pp("w3"), device)?;
+        Ok(Self {
+            w1,
+            w2,
+            w3,
+            fused_gate_up: None,
+        })
     }
 
     pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
-        let x_gate = self.w1.forward(x)?;
-        let x_up = self.w3.forward(x)?;
+        self.forward_routed(x, None)
+    }
+
+    /// `forward` for rows taken from batch rows `rows` (MoE expert inputs),
+    /// see `AdaptiveBitLinear::forward_routed`
+    pub fn forward_routed(&self, x: &Tensor, rows: Option<&[usize]>) -> Result<Tensor> {
+        if let Some(fused) = self.gate_up_fusion() {
+            return self.w2.forward_routed(&fused.forward(x)?, rows);
+        }
+        let x_gate = self.w1.forward_routed(x, rows)?;
+        let x_up = self.w3.forward_routed(x, rows)?;
         let silu_gate = candle_nn::ops::silu(&x_gate)?;
         let hidden = (silu_gate * x_up)?;
-        self.w2.forward(&hidden)
+        self.w2.forward_routed(&hidden, rows)
     }
 
-    pub fn precompute_packed(&mut self) -> Result<()> {
-        self.w1.precompute_packed()?;
-        self.w2.precompute_packed()?;
-        self.w3.precompute_packed()?;
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
+        self.w1.precompute_packed(options)?;
+        self.w2.precompute_packed(options)?;
+        self.w3.precompute_packed(options)?;
+        self.fused_gate_up = if options.fuse_projections {
+            FusedProjection::swiglu(&mut self.w1, &mut self.w3, options)?
+        } else {
+            None
+        };
         Ok(())
     }
+
+    /// `fused_gate_up` while gate/up still run on their packed weights alone
+    pub fn gate_up_fusion(&self) -> Option<&FusedProjection> {
+        self.fused_gate_up
+            .as_ref()
+            .filter(|_| FusedProjection::applies_to(&[&self.w1, &self.w3]))
+    }
 }
@@ -4,6 +4,7 @@ use candle_core::{Result, Tensor};
 use candle_nn::VarBuilder;
 
 use super::AdaptiveBitLinear;
+use crate::kernels::packing::PackOptions;
 
 /// Epsilon for TTT layer normalization
 const TTT_NORM_EPS: f32 = 1e-6;
@@ -36,9 +37,9 @@ impl TTTLayer {
         })
     }
 
-    pub fn precompute_packed(&mut self) -> Result<()> {
-        self.proj_down.precompute_packed()?;
-        self.proj_up.precompute_packed()?;
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
+        self.proj_down.precompute_packed(options)?;
+        self.proj_up.precompute_packed(options)?;
         Ok(())
     }
 
@@ -0,0 +1,327 @@
+//! Vocabulary tables - embedding and lm_head weights, dense or quantized
+//!
+//! Both are `[vocab, hidden]` matrices and often the largest tensors of a
+//! ternary model. Besides the dense storage dtype (`layers::precision`) they
+//! can be held on the CPU as
+//!
+//! | quant     | weights                            | lookup          | logits                       |
+//! |-----------|------------------------------------|-----------------|------------------------------|
+//! | `dense`   | storage dtype, any device          | `index_select`  | dense matmul                 |
+//! | `int8`    | per-row absmax int8 (~1/4 of F32)  | dequantized row | `int8_rows_into` (W8A8)      |
+//! | `ternary` | per-row absmean ternary (~1/16)    | dequantized row | the ternary kernels          |
+//!
+//! `BitLlama::table_quant_report` measures the perplexity cost of each choice.
+
+use candle_core::{DType, Device, Module, Result, Tensor};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+use super::fused::with_layout;
+use super::DensePrecision;
+use crate::kernels::backend;
+use crate::kernels::cpu::{self, KernelScratch};
+use crate::kernels::packing::{PackOptions, PackedScales, PackedTensor, WeightScale, INT8_BLOCK};
+
+/// Storage of the embedding table or the lm_head weight
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "python", pyclass)]
+pub enum TableQuant {
+    /// `storage_dtype` (F32/F16/BF16), on the model's IO device
+    #[serde(rename = "dense", alias = "none")]
+    #[default]
+    Dense,
+    /// Per-row absmax int8 on the CPU
+    #[serde(rename = "int8")]
+    Int8,
+    /// Per-row absmean ternary on the CPU
+    #[serde(rename = "ternary")]
+    Ternary,
+}
+
+impl TableQuant {
+    pub const ALL: [Self; 3] = [Self::Dense, Self::Int8, Self::Ternary];
+}
+
+impl std::str::FromStr for TableQuant {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dense" | "none" | "off" => Ok(Self::Dense),
+            "int8" | "i8" | "q8" => Ok(Self::Int8),
+            "ternary" | "1.58" => Ok(Self::Ternary),
+            other => Err(format!(
+                "unknown table quant '{}' (expected dense, int8 or ternary)",
+                other
+            )),
+        }
+    }
+}
+
+/// Quantized `[vocab, hidden]` matrix (CPU)
+#[derive(Debug, Clone)]
+pub enum QuantizedTable {
+    /// Rows zero-padded to whole `INT8_BLOCK`s, one dequantization scale each
+    Int8 {
+        data: Vec<i8>,
+        scales: Vec<f32>,
+        cols: usize,
+    },
+    /// Per-channel scales, kernel layout of the model's `activation_quant`
+    Ternary(PackedTensor),
+}
+
+impl QuantizedTable {
+    /// Quantize `w` ([rows, cols], any dtype/device); None for `TableQuant::Dense`
+    pub fn quantize(w: &Tensor, quant: TableQuant, options: PackOptions) -> Result<Option<Self>> {
+        let w = w.to_device(&Device::Cpu)?.to_dtype(DType::F32)?;
+        let (rows, cols) = w.dims2()?;
+        match quant {
+            TableQuant::Dense => Ok(None),
+            TableQuant::Int8 => {
+                let values = w.flatten_all()?.to_vec1::<f32>()?;
+                let k_pad = cols.div_ceil(INT8_BLOCK) * INT8_BLOCK;
+                let mut data = vec![0i8; rows * k_pad];
+                let mut scales = vec![0.0f32; rows];
+                cpu::quantize_activations_into(&values, cols, k_pad, &mut data, &mut scales);
+                Ok(Some(Self::Int8 { data, scales, cols }))
+            }
+            TableQuant::Ternary => {
+                let packed = PackedTensor::pack_with(&w, WeightScale::Channel, options.group_size)?;
+                Ok(Some(Self::Ternary(with_layout(packed, options)?)))
+            }
+        }
+    }
+
+    pub fn quant(&self) -> TableQuant {
+        match self {
+            Self::Int8 { .. } => TableQuant::Int8,
+            Self::Ternary(_) => TableQuant::Ternary,
+        }
+    }
+
+    /// (rows, cols)
+    pub fn dims(&self) -> Result<(usize, usize)> {
+        match self {
+            Self::Int8 { scales, cols, .. } => Ok((scales.len(), *cols)),
+            Self::Ternary(packed) => packed.shape.dims2(),
+        }
+    }
+
+    /// Bytes held by the weights (codes, scales and kernel layouts)
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            Self::Int8 { data, scales, .. } => data.len() + scales.len() * 4,
+            Self::Ternary(packed) => {
+                let layouts = [&packed.int8_layout, &packed.lut_layout]
+                    .iter()
+                    .filter_map(|l| l.as_ref().map(|l| l.len()))
+                    .sum::<usize>();
+                let scales = match &packed.scales {
+                    PackedScales::Tensor(_) => 1,
+                    PackedScales::Channel(scales) | PackedScales::Group { scales, .. } => {
+                        scales.len()
+                    }
+                };
+                packed.data.len() + layouts + scales * 4
+            }
+        }
+    }
+
+    /// Dequantized row `row` (embedding lookup) into `out` ([cols])
+    pub fn row_into(&self, row: usize, out: &mut [f32]) -> Result<()> {
+        let (rows, cols) = self.dims()?;
+        if row >= rows || out.len() != cols {
+            candle_core::bail!(
+                "QuantizedTable: row {} of [{}, {}] into {} values",
+                row,
+                rows,
+                cols,
+                out.len()
+            );
+        }
+        match self {
+            Self::Int8 { data, scales, .. } => {
+                let k_pad = data.len() / rows;
+                let q = &data[row * k_pad..row * k_pad + cols];
+                for (o, &q) in out.iter_mut().zip(q) {
+                    *o = q as f32 * scales[row];
+                }
+            }
+            Self::Ternary(packed) => {
+                let data = packed.data.slice()?;
+                let data = data.as_slice()?;
+                let (row_scales, group) = packed.scales.row(row, cols);
+                for (c, o) in out.iter_mut().enumerate() {
+                    let idx = row * cols + c;
+                    *o = match (data[idx / 4] >> ((idx % 4) * 2)) & 0b11 {
+                        1 => row_scales[c / group],
+                        2 => -row_scales[c / group],
+                        _ => 0.0,
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// x W^T for `x` [M, cols] -> `out` [M, rows]
+    pub fn matmul_into(
+        &self,
+        x: &[f32],
+        scratch: &mut KernelScratch,
+        out: &mut [f32],
+    ) -> Result<()> {
+        match self {
+            Self::Int8 { data, scales, .. } => {
+                backend::for_device(&Device::Cpu)?.int8_rows_into(x, data, scales, scratch, out)
+            }
+            Self::Ternary(packed) => {
+                backend::for_device(&Device::Cpu)?.matmul_into(x, packed, scratch, out)
+            }
+        }
+    }
+
+    /// Rows `ids` (any shape) -> [.., cols] F32
+    pub fn embed(&self, ids: &Tensor) -> Result<Tensor> {
+        let cols = self.dims()?.1;
+        let ids_vec = ids.flatten_all()?.to_vec1::<u32>()?;
+        let mut out = vec![0.0f32; ids_vec.len() * cols];
+        for (&id, row) in ids_vec.iter().zip(out.chunks_mut(cols)) {
+            self.row_into(id as usize, row)?;
+        }
+        let mut shape = ids.dims().to_vec();
+        shape.push(cols);
+        Tensor::from_vec(out, shape, &Device::Cpu)
+    }
+
+    /// x [.., cols] -> [.., rows] on x's device
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let (rows, cols) = self.dims()?;
+        let m = x.elem_count() / cols.max(1);
+        let x_vec = x
+            .to_device(&Device::Cpu)?
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let mut out = vec![0.0f32; m * rows];
+        self.matmul_into(&x_vec, &mut KernelScratch::default(), &mut out)?;
+        let mut shape = x.dims().to_vec();
+        *shape.last_mut().unwrap() = rows;
+        Tensor::from_vec(out, shape, &Device::Cpu)?
+            .to_dtype(x.dtype())?
+            .to_device(x.device())
+    }
+
+    /// Dequantized F32 weights
+    pub fn dequantize(&self) -> Result<Tensor> {
+        let (rows, cols) = self.dims()?;
+        match self {
+            Self::Int8 { .. } => {
+                let mut out = vec![0.0f32; rows * cols];
+                for (r, row) in out.chunks_mut(cols).enumerate() {
+                    self.row_into(r, row)?;
+                }
+                Tensor::from_vec(out, (rows, cols), &Device::Cpu)
+            }
+            Self::Ternary(packed) => packed.unpack(&Device::Cpu),
+        }
+    }
+}
+
+/// Embedding table or lm_head weight ([vocab, hidden])
+#[derive(Debug, Clone)]
+pub enum VocabTable {
+    /// Storage dtype, on the IO (embedding) or lm_head device
+    Dense(Tensor),
+    /// CPU (lookups and logits return to the caller's device)
+    Quantized(QuantizedTable),
+}
+
+impl VocabTable {
+    /// Dense weights, None once quantized
+    pub fn dense(&self) -> Option<&Tensor> {
+        match self {
+            Self::Dense(w) => Some(w),
+            Self::Quantized(_) => None,
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        const CPU: &Device = &Device::Cpu;
+        match self {
+            Self::Dense(w) => w.device(),
+            Self::Quantized(_) => CPU,
+        }
+    }
+
+    pub fn dims(&self) -> Result<(usize, usize)> {
+        match self {
+            Self::Dense(w) => w.dims2(),
+            Self::Quantized(q) => q.dims(),
+        }
+    }
+
+    pub fn quant(&self) -> TableQuant {
+        match self {
+            Self::Dense(_) => TableQuant::Dense,
+            Self::Quantized(q) => q.quant(),
+        }
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            Self::Dense(w) => w.elem_count() * w.dtype().size_in_bytes(),
+            Self::Quantized(q) => q.size_bytes(),
+        }
+    }
+
+    /// This table stored as `quant` (a dense table is required to change it)
+    pub fn quantize(&self, quant: TableQuant, options: PackOptions) -> Result<Self> {
+        if quant == self.quant() {
+            return Ok(self.clone());
+        }
+        let Self::Dense(w) = self else {
+            candle_core::bail!(
+                "VocabTable: {:?} table can't be converted to {:?} (reload the dense weights)",
+                self.quant(),
+                quant
+            );
+        };
+        Ok(match QuantizedTable::quantize(w, quant, options)? {
+            Some(q) => Self::Quantized(q),
+            None => self.clone(),
+        })
+    }
+
+    /// Embedding lookup: ids -> [.., hidden] F32
+    pub fn embed(&self, ids: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Dense(w) => {
+                let hidden = w.dim(1)?;
+                candle_nn::Embedding::new(w.clone(), hidden)
+                    .forward(ids)?
+                    .to_dtype(DType::F32)
+            }
+            Self::Quantized(q) => q.embed(ids),
+        }
+    }
+
+    /// Logits: h [.., hidden] -> [.., vocab] (dense matmul in `precision`)
+    pub fn logits(&self, h: &Tensor, precision: DensePrecision) -> Result<Tensor> {
+        match self {
+            Self::Dense(w) => precision.forward(h, w),
+            Self::Quantized(q) => q.forward(h),
+        }
+    }
+
+    /// F32 weights (dequantized if needed)
+    pub fn to_dense(&self) -> Result<Tensor> {
+        match self {
+            Self::Dense(w) => Ok(w.clone()),
+            Self::Quantized(q) => q.dequantize(),
+        }
+    }
+}
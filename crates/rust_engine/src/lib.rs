@@ -12,10 +12,22 @@ pub mod layers;
 pub mod model;
 pub mod optim;
 pub mod python;
+pub mod runtime;
 
 // Primary public API re-exports
-pub use layers::{BitLinear, RMSNorm, SwiGLU, TTTLayer};
-pub use model::{BitLlama, BitLlamaBlock, BitLlamaConfig, LayerDispatch, Llama, ModelArch};
+pub use kernels::autotune::TuneMode;
+pub use kernels::cpu::ActivationQuant;
+pub use kernels::packing::{PackOptions, WeightScale};
+pub use layers::{
+    BitLinear, FloatDtype, KvCacheDtype, LoraConfig, LoraTarget, MoE, RMSNorm, SwiGLU, TTTLayer,
+    TableQuant, VocabTable,
+};
+pub use model::{
+    AdapterInfo, BitLlama, BitLlamaBlock, BitLlamaConfig, BittFile, BittMetadata, DecodeWorkspace,
+    GenerationRequest, LayerDispatch, Llama, MlpDispatch, ModelArch, PackedCheckpoint,
+    TableQuantReport,
+};
+pub use runtime::{EngineRuntime, RuntimeConfig};
 
 // Alias for backward compatibility
 pub use model::TTTLayer as CandleTTTLayer;
@@ -28,12 +40,106 @@ use pyo3::prelude::*;
 #[pymodule]
 fn cortex_rust(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<model::ModelArch>()?;
+    m.add_class::<layers::KvCacheDtype>()?;
+    m.add_class::<kernels::cpu::ActivationQuant>()?;
+    m.add_class::<kernels::autotune::TuneMode>()?;
+    m.add_class::<kernels::packing::WeightScale>()?;
+    m.add_class::<layers::FloatDtype>()?;
+    m.add_class::<layers::TableQuant>()?;
     m.add_class::<model::BitLlamaConfig>()?;
     m.add_class::<python::PyBitLlama>()?;
     m.add_class::<python::PyTrainer>()?;
     Ok(())
 }
 
+#[cfg(test)]
+#[path = "tests/test_util.rs"]
+mod test_util;
+
 #[cfg(test)]
 #[path = "tests/attention_test.rs"]
 mod attention_test;
+
+#[cfg(test)]
+#[path = "tests/moe_test.rs"]
+mod moe_test;
+
+#[cfg(test)]
+#[path = "tests/lora_test.rs"]
+mod lora_test;
+
+#[cfg(test)]
+#[path = "tests/adapter_test.rs"]
+mod adapter_test;
+
+#[cfg(test)]
+#[path = "tests/flash_attention_test.rs"]
+mod flash_attention_test;
+
+#[cfg(test)]
+#[path = "tests/kv_cache_dtype_test.rs"]
+mod kv_cache_dtype_test;
+
+#[cfg(test)]
+#[path = "tests/paged_kv_cache_test.rs"]
+mod paged_kv_cache_test;
+
+#[cfg(test)]
+#[path = "tests/int8_kernel_test.rs"]
+mod int8_kernel_test;
+
+#[cfg(test)]
+#[path = "tests/cpu_simd_test.rs"]
+mod cpu_simd_test;
+
+#[cfg(test)]
+#[path = "tests/gemm_cpu_test.rs"]
+mod gemm_cpu_test;
+
+#[cfg(test)]
+#[path = "tests/lut_kernel_test.rs"]
+mod lut_kernel_test;
+
+#[cfg(test)]
+#[path = "tests/adaptive_kernel_test.rs"]
+mod adaptive_kernel_test;
+
+#[cfg(test)]
+#[path = "tests/weight_scale_test.rs"]
+mod weight_scale_test;
+
+#[cfg(test)]
+#[path = "tests/packed_checkpoint_test.rs"]
+mod packed_checkpoint_test;
+
+#[cfg(test)]
+#[path = "tests/kernel_backend_test.rs"]
+mod kernel_backend_test;
+
+#[cfg(test)]
+#[path = "tests/autotune_test.rs"]
+mod autotune_test;
+
+#[cfg(test)]
+#[path = "tests/precision_test.rs"]
+mod precision_test;
+
+#[cfg(test)]
+#[path = "tests/decode_workspace_test.rs"]
+mod decode_workspace_test;
+
+#[cfg(test)]
+#[path = "tests/fused_projection_test.rs"]
+mod fused_projection_test;
+
+#[cfg(test)]
+#[path = "tests/runtime_test.rs"]
+mod runtime_test;
+
+#[cfg(test)]
+#[path = "tests/vocab_table_test.rs"]
+mod vocab_table_test;
+
+#[cfg(test)]
+#[path = "tests/bitt_test.rs"]
+mod bitt_test;
@@ -5,14 +5,34 @@
 //! - BitLlama: Full model with embedding, layers, and LM head
 //! - BitLlamaConfig: Model configuration
 //! - Llama: High-level API with tokenizer
+//! - lora: LoRA adapter attach/save/load/merge on BitLlama
+//! - packed_checkpoint: Packed (2-bit) checkpoints, memory-mapped zero-copy
+//! - adapters: Named adapter registry for per-request adapter selection
+//! - batch: Batched decoding of independent sequences
+//! - bitt: .bitt containers (config + tokenizer + weights in one file)
+//! - decode: Allocation-free single-token decode through a reusable workspace
+//! - perplexity: Perplexity of token sequences and the embedding/lm_head quant report
 
+pub mod adapters;
+pub mod batch;
+pub mod bitt;
 pub mod block;
 pub mod config;
+pub mod decode;
 pub mod llama;
+pub mod lora;
+pub mod packed_checkpoint;
+pub mod perplexity;
 
-pub use block::{BitLlamaBlock, LayerDispatch};
+pub use adapters::{AdapterInfo, AdapterRegistry};
+pub use batch::{GenerationRequest, SequenceState};
+pub use bitt::{BittFile, BittMetadata, BittSection};
+pub use block::{BitLlamaBlock, LayerDispatch, MlpDispatch};
 pub use config::{BitLlamaConfig, ModelArch};
+pub use decode::DecodeWorkspace;
 pub use llama::{BitLlama, Llama};
+pub use packed_checkpoint::PackedCheckpoint;
+pub use perplexity::TableQuantReport;
 
 // Re-export TTTLayer for backward compatibility alias
 pub use crate::layers::TTTLayer;
@@ -0,0 +1,276 @@
+//! Adapter registry - named LoRA adapters resident next to one base model
+//!
+//! Adapters are loaded once and selected per generation (or per sequence in
+//! `Llama::generate_batch`) without touching the base weights. An optional
+//! byte budget bounds the resident set; the least recently used adapters are
+//! evicted to make room.
+
+use candle_core::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::layers::{LoraAdapter, LoraConfig};
+use crate::model::lora::{adapters_from_tensors, read_lora_config};
+use crate::model::Llama;
+
+/// Summary of a resident adapter
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub rank: usize,
+    pub alpha: f64,
+    /// Number of adapted projections
+    pub modules: usize,
+    pub size_in_bytes: usize,
+}
+
+struct ResidentAdapter {
+    config: LoraConfig,
+    /// Keyed by module prefix (see `BitLlama::linear_modules`)
+    modules: HashMap<String, LoraAdapter>,
+    size_in_bytes: usize,
+    last_used: u64,
+}
+
+/// Named adapters with an optional memory budget (LRU eviction)
+#[derive(Default)]
+pub struct AdapterRegistry {
+    adapters: HashMap<String, ResidentAdapter>,
+    budget_bytes: Option<usize>,
+    clock: u64,
+}
+
+impl AdapterRegistry {
+    pub fn new(budget_bytes: Option<usize>) -> Self {
+        Self {
+            budget_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn budget_bytes(&self) -> Option<usize> {
+        self.budget_bytes
+    }
+
+    /// Change the budget, evicting adapters that no longer fit.
+    /// Returns the evicted names.
+    pub fn set_budget_bytes(&mut self, budget_bytes: Option<usize>) -> Vec<String> {
+        self.budget_bytes = budget_bytes;
+        self.evict_to_fit(0)
+    }
+
+    /// Total bytes held by resident adapters
+    pub fn resident_bytes(&self) -> usize {
+        self.adapters.values().map(|a| a.size_in_bytes).sum()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.adapters.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adapters.is_empty()
+    }
+
+    /// Resident adapters sorted by name
+    pub fn list(&self) -> Vec<AdapterInfo> {
+        let mut infos: Vec<AdapterInfo> = self
+            .adapters
+            .iter()
+            .map(|(name, a)| AdapterInfo {
+                name: name.clone(),
+                rank: a.config.rank,
+                alpha: a.config.alpha,
+                modules: a.modules.len(),
+                size_in_bytes: a.size_in_bytes,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    /// Register (or replace) an adapter. Returns the names evicted to stay
+    /// within the budget.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        config: LoraConfig,
+        modules: HashMap<String, LoraAdapter>,
+    ) -> Result<Vec<String>> {
+        let size_in_bytes: usize = modules.values().map(LoraAdapter::size_in_bytes).sum();
+        if let Some(budget) = self.budget_bytes {
+            if size_in_bytes > budget {
+                candle_core::bail!(
+                    "Adapter '{}' needs {} bytes, exceeding the adapter budget of {} bytes",
+                    name,
+                    size_in_bytes,
+                    budget
+                );
+            }
+        }
+
+        self.adapters.remove(name);
+        let evicted = self.evict_to_fit(size_in_bytes);
+        self.clock += 1;
+        self.adapters.insert(
+            name.to_string(),
+            ResidentAdapter {
+                config,
+                modules,
+                size_in_bytes,
+                last_used: self.clock,
+            },
+        );
+        Ok(evicted)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.adapters.remove(name).is_some()
+    }
+
+    /// Adapter weights for `name`, keyed by module prefix
+    pub fn get(&self, name: &str) -> Option<&HashMap<String, LoraAdapter>> {
+        self.adapters.get(name).map(|a| &a.modules)
+    }
+
+    /// Mark `name` as recently used. Returns false if it is not loaded.
+    pub fn touch(&mut self, name: &str) -> bool {
+        self.clock += 1;
+        match self.adapters.get_mut(name) {
+            Some(a) => {
+                a.last_used = self.clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evict least recently used adapters until `incoming` more bytes fit
+    fn evict_to_fit(&mut self, incoming: usize) -> Vec<String> {
+        let mut evicted = Vec::new();
+        let Some(budget) = self.budget_bytes else {
+            return evicted;
+        };
+        while self.resident_bytes() + incoming > budget {
+            let Some(victim) = self
+                .adapters
+                .iter()
+                .min_by_key(|(_, a)| a.last_used)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            self.adapters.remove(&victim);
+            evicted.push(victim);
+        }
+        evicted
+    }
+}
+
+impl Llama {
+    /// Load an adapter file (plus JSON sidecar) under `name`.
+    ///
+    /// Adapter modules must all exist in this model. Loading may evict the
+    /// least recently used adapters to respect the budget.
+    pub fn load_adapter<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<AdapterInfo> {
+        let path = path.as_ref();
+        let tensors = candle_core::safetensors::load(path, &candle_core::Device::Cpu)?;
+        let cfg = read_lora_config(path, &tensors)?;
+        let mut adapters = adapters_from_tensors(&tensors, &cfg)?;
+
+        let mut modules = HashMap::new();
+        for (module_name, _target, module) in self.model.linear_modules() {
+            if let Some(adapter) = adapters.remove(&module_name) {
+                adapter.check_shape(&module_name, module.in_features, module.out_features)?;
+                modules.insert(module_name, adapter.to_device(module.device())?);
+            }
+        }
+        if !adapters.is_empty() {
+            let mut unknown: Vec<_> = adapters.into_keys().collect();
+            unknown.sort();
+            candle_core::bail!(
+                "Adapter '{}' ({:?}) targets modules missing from this model: {:?}",
+                name,
+                path,
+                unknown
+            );
+        }
+
+        for victim in self.adapters.insert(name, cfg, modules)? {
+            tracing::info!("evicted adapter {:?} (budget)", victim);
+        }
+        Ok(self
+            .adapters
+            .list()
+            .into_iter()
+            .find(|info| info.name == name)
+            .expect("adapter was just inserted"))
+    }
+
+    /// Drop a resident adapter. Returns false if it was not loaded.
+    pub fn unload_adapter(&mut self, name: &str) -> bool {
+        self.adapters.remove(name)
+    }
+
+    pub fn list_adapters(&self) -> Vec<AdapterInfo> {
+        self.adapters.list()
+    }
+
+    /// Set the resident adapter budget in bytes (None = unlimited)
+    pub fn set_adapter_budget(&mut self, budget_bytes: Option<usize>) -> Vec<String> {
+        self.adapters.set_budget_bytes(budget_bytes)
+    }
+
+    /// Streaming generation with a registered adapter (None = base model)
+    pub fn stream_completion_with_adapter<F>(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        temp: f64,
+        adapter: Option<&str>,
+        callback: F,
+    ) -> Result<String>
+    where
+        F: FnMut(&str) -> anyhow::Result<bool>,
+    {
+        let Some(name) = adapter else {
+            return self.stream_completion(prompt, max_tokens, temp, callback);
+        };
+        self.install_row_adapters(&[Some(name)])?;
+        let result = self.stream_completion(prompt, max_tokens, temp, callback);
+        self.clear_row_adapters();
+        result
+    }
+
+    /// Install one adapter per batch row on every projection
+    pub(crate) fn install_row_adapters(&mut self, names: &[Option<&str>]) -> Result<()> {
+        for name in names.iter().flatten() {
+            if !self.adapters.touch(name) {
+                candle_core::bail!("Adapter '{}' is not loaded", name);
+            }
+        }
+        let selected: Vec<_> = names
+            .iter()
+            .map(|name| name.and_then(|n| self.adapters.get(n)))
+            .collect();
+
+        for (module_name, _target, module) in self.model.linear_modules_mut() {
+            let rows: Vec<Option<LoraAdapter>> = selected
+                .iter()
+                .map(|set| set.and_then(|m| m.get(&module_name).cloned()))
+                .collect();
+            module.lora_rows = if rows.iter().all(Option::is_none) {
+                Vec::new()
+            } else {
+                rows
+            };
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clear_row_adapters(&mut self) {
+        for (_name, _target, module) in self.model.linear_modules_mut() {
+            module.lora_rows.clear();
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Batched decoding - independent sequences stepped together
+
+use candle_core::{DType, Device, Result, Tensor};
+
+use crate::layers::{BlockTable, KVCache, PagedKVCache, PagedLayerSpec};
+use crate::model::{BitLlama, BitLlamaBlock, LayerDispatch, Llama};
+
+/// EOS token id (matches `Llama::stream_completion`)
+const EOS_TOKEN: u32 = 2;
+
+/// Decoding state owned by one sequence of a batch
+pub struct SequenceState {
+    /// One cache per layer
+    pub kv_caches: Vec<Option<KVCache>>,
+    /// One TTT state per layer: [D_small, D_small]
+    pub w_states: Vec<Tensor>,
+    /// Tokens consumed so far (RoPE position of the next token)
+    pub pos: usize,
+    /// Blocks of this sequence in a `PagedKVCache` (unused with `kv_caches`)
+    pub block_table: BlockTable,
+}
+
+/// A single prompt in `Llama::generate_batch`
+#[derive(Clone, Debug, Default)]
+pub struct GenerationRequest {
+    pub prompt: String,
+    /// Name of a registered adapter (None = base model)
+    pub adapter: Option<String>,
+}
+
+impl GenerationRequest {
+    pub fn new(prompt: impl Into<String>, adapter: Option<&str>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            adapter: adapter.map(str::to_string),
+        }
+    }
+}
+
+impl BitLlama {
+    /// Fresh state for a new sequence
+    pub fn new_sequence(&self) -> Result<SequenceState> {
+        let d_small = self.config.hidden_dim / 4;
+        let w_states = (0..self.layers.len())
+            .map(|i| Tensor::zeros((d_small, d_small), DType::F32, self.layer_device(i)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SequenceState {
+            kv_caches: vec![
+                Some(KVCache::with_dtype(
+                    self.config.max_position_embeddings,
+                    self.config.kv_cache_dtype
+                ));
+                self.layers.len()
+            ],
+            w_states,
+            pos: 0,
+            block_table: BlockTable::default(),
+        })
+    }
+
+    /// Block pool for `forward_paged`: `num_blocks` blocks of `block_size`
+    /// tokens per attention layer, stored as `config.kv_cache_dtype`
+    pub fn new_paged_cache(&self, num_blocks: usize, block_size: usize) -> Result<PagedKVCache> {
+        let specs: Vec<PagedLayerSpec> = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| match &layer.core {
+                LayerDispatch::Attention(a) => {
+                    Some((a.n_kv_heads, a.head_dim, self.layer_device(i).clone()))
+                }
+                LayerDispatch::TTT(_) => None,
+            })
+            .collect();
+        PagedKVCache::new(&specs, num_blocks, block_size, self.config.kv_cache_dtype)
+    }
+
+    /// Fresh sequence whose keys/values live in a `PagedKVCache`
+    pub fn new_paged_sequence(&self) -> Result<SequenceState> {
+        let mut seq = self.new_sequence()?;
+        seq.kv_caches = vec![None; self.layers.len()];
+        Ok(seq)
+    }
+
+    /// Continue `seq` in a second sequence that shares its cached prefix
+    /// (copy-on-write blocks; TTT states are copied)
+    pub fn fork_sequence(&self, seq: &SequenceState, cache: &mut PagedKVCache) -> SequenceState {
+        SequenceState {
+            kv_caches: vec![None; self.layers.len()],
+            w_states: seq.w_states.clone(),
+            pos: seq.pos,
+            block_table: cache.fork(&seq.block_table),
+        }
+    }
+
+    /// Device of layer `i` (layers 0..n_gpu live on the GPU)
+    fn layer_device(&self, i: usize) -> &Device {
+        if i < self.n_gpu {
+            self.gpu_device.as_ref().unwrap_or(&self.cpu_device)
+        } else {
+            &self.cpu_device
+        }
+    }
+
+    /// Feed one token per sequence and return logits [Batch, Vocab].
+    ///
+    /// Sequences may be at different positions; each advances by one.
+    pub fn forward_batch(&self, tokens: &[u32], seqs: &mut [&mut SequenceState]) -> Result<Tensor> {
+        let positions: Vec<usize> = seqs.iter().map(|s| s.pos).collect();
+        self.decode_step(tokens, seqs, |i, layer, h, w_states, seqs| {
+            let mut caches: Vec<&mut Option<KVCache>> =
+                seqs.iter_mut().map(|s| &mut s.kv_caches[i]).collect();
+            layer.forward_batch(h, w_states, &mut caches, &positions)
+        })
+    }
+
+    /// `forward_batch` with keys/values in a shared block pool
+    /// (sequences from `new_paged_sequence` / `fork_sequence`)
+    pub fn forward_paged(
+        &self,
+        tokens: &[u32],
+        seqs: &mut [&mut SequenceState],
+        cache: &mut PagedKVCache,
+    ) -> Result<Tensor> {
+        for seq in seqs.iter_mut() {
+            cache.reserve(&mut seq.block_table, 1)?;
+        }
+        let logits = self.decode_step(tokens, seqs, |i, layer, h, w_states, seqs| {
+            let tables: Vec<&BlockTable> = seqs.iter().map(|s| &s.block_table).collect();
+            layer.forward_paged(h, w_states, cache, i, &tables)
+        })?;
+        for seq in seqs.iter_mut() {
+            cache.advance(&mut seq.block_table, 1);
+        }
+        Ok(logits)
+    }
+
+    /// Embedding -> layers (`layer_step` per block) -> norm -> lm_head
+    fn decode_step(
+        &self,
+        tokens: &[u32],
+        seqs: &mut [&mut SequenceState],
+        mut layer_step: impl FnMut(
+            usize,
+            &BitLlamaBlock,
+            &Tensor,
+            &[Tensor],
+            &mut [&mut SequenceState],
+        ) -> Result<(Tensor, Vec<Tensor>)>,
+    ) -> Result<Tensor> {
+        let b_sz = tokens.len();
+        if seqs.len() != b_sz {
+            candle_core::bail!(
+                "forward_batch: {} tokens for {} sequences",
+                b_sz,
+                seqs.len()
+            );
+        }
+
+        let x = Tensor::from_vec(tokens.to_vec(), (b_sz, 1), self.embedding.device())?;
+        let mut h = self.embed(&x)?;
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            // [Hybrid Fix] Move hidden state to the layer's device
+            let target_device = self.layer_device(i);
+            if !h.device().same_device(target_device) {
+                h = h.to_device(target_device)?;
+            }
+
+            let w_states: Vec<Tensor> = seqs.iter().map(|s| s.w_states[i].clone()).collect();
+            let (h_new, w_new) = layer_step(i, layer, &h, &w_states, seqs)?;
+
+            for (seq, w) in seqs.iter_mut().zip(w_new) {
+                seq.w_states[i] = w;
+            }
+            h = h_new;
+        }
+
+        // [Hybrid Fix] Final norm / lm_head devices
+        let norm_device = self.norm.weight.device();
+        if !h.device().same_device(norm_device) {
+            h = h.to_device(norm_device)?;
+        }
+        let h_norm = self.norm.forward(&h)?;
+        let lm_head_device = self.lm_head.device();
+        let h_norm = if h_norm.device().same_device(lm_head_device) {
+            h_norm
+        } else {
+            h_norm.to_device(lm_head_device)?
+        };
+        let logits = self.lm_head_forward(&h_norm)?.squeeze(1)?;
+
+        for seq in seqs.iter_mut() {
+            seq.pos += 1;
+        }
+        Ok(logits)
+    }
+}
+
+/// Per-request bookkeeping inside `generate_batch`
+struct BatchSlot {
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    fed: usize,
+    output: String,
+    done: bool,
+    state: SequenceState,
+}
+
+impl Llama {
+    /// Greedy generation for several prompts at once.
+    ///
+    /// Every step feeds one token per unfinished sequence (prompt tokens
+    /// first, then generated ones), so prefill and decode of different
+    /// requests share the same batched projections. Each request runs with
+    /// its own adapter from the registry. Returns prompt + completion per
+    /// request, in order.
+    pub fn generate_batch(
+        &mut self,
+        requests: &[GenerationRequest],
+        max_tokens: usize,
+    ) -> Result<Vec<String>> {
+        let mut slots = Vec::with_capacity(requests.len());
+        for req in requests {
+            if let Some(name) = &req.adapter {
+                if !self.adapters.contains(name) {
+                    candle_core::bail!("Adapter '{}' is not loaded", name);
+                }
+            }
+            let tokens = self
+                .tokenizer
+                .encode(req.prompt.as_str(), true)
+                .map_err(candle_core::Error::wrap)?
+                .get_ids()
+                .to_vec();
+            slots.push(BatchSlot {
+                prompt_len: tokens.len(),
+                done: tokens.is_empty() || max_tokens == 0,
+                tokens,
+                fed: 0,
+                output: req.prompt.clone(),
+                state: self.model.new_sequence()?,
+            });
+        }
+
+        // Steps mix prompt and generated tokens; they run on the prefill pool
+        let runtime = self.runtime.clone();
+        let result = runtime.prefill(|| self.run_batch(requests, &mut slots, max_tokens));
+        self.clear_row_adapters();
+        result?;
+
+        Ok(slots.into_iter().map(|s| s.output).collect())
+    }
+
+    fn run_batch(
+        &mut self,
+        requests: &[GenerationRequest],
+        slots: &mut [BatchSlot],
+        max_tokens: usize,
+    ) -> Result<()> {
+        loop {
+            let active: Vec<usize> = (0..slots.len()).filter(|&i| !slots[i].done).collect();
+            if active.is_empty() {
+                return Ok(());
+            }
+
+            let adapters: Vec<Option<&str>> = active
+                .iter()
+                .map(|&i| requests[i].adapter.as_deref())
+                .collect();
+            self.install_row_adapters(&adapters)?;
+
+            let inputs: Vec<u32> = active
+                .iter()
+                .map(|&i| slots[i].tokens[slots[i].fed])
+                .collect();
+            let mut states: Vec<&mut SequenceState> = slots
+                .iter_mut()
+                .filter(|s| !s.done)
+                .map(|s| &mut s.state)
+                .collect();
+            let logits = self.model.forward_batch(&inputs, &mut states)?;
+            let logits = logits.to_dtype(DType::F32)?.to_vec2::<f32>()?;
+
+            for (row, &i) in active.iter().enumerate() {
+                let slot = &mut slots[i];
+                slot.fed += 1;
+                if slot.fed < slot.tokens.len() {
+                    // Still consuming the prompt
+                    continue;
+                }
+
+                // Greedy
+                let next_token = logits[row]
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(t, _)| t as u32)
+                    .unwrap();
+                slot.tokens.push(next_token);
+
+                let decoded = self
+                    .tokenizer
+                    .decode(&[next_token], true)
+                    .map_err(candle_core::Error::wrap)?;
+                slot.output.push_str(&decoded);
+                self.soul_level += 1;
+
+                if next_token == EOS_TOKEN || slot.tokens.len() - slot.prompt_len >= max_tokens {
+                    slot.done = true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! .bitt container - config, tokenizer and weights in one file
+//!
+//! Version 2 (written by `bit_llama export`):
+//!
+//! | offset | bytes                                                          |
+//! |--------|----------------------------------------------------------------|
+//! | 0      | magic `BITT`                                                   |
+//! | 4      | version (u32 LE, 2)                                            |
+//! | 8      | header length (u64 LE)                                         |
+//! | 16     | header JSON: `metadata` and the `sections` index               |
+//! | ...    | sections, each at a multiple of `BITT_ALIGN` (zero padding)    |
+//!
+//! Every section has an offset, a length and a SHA-256 (`verify`). Required
+//! sections are `config` and `tokenizer` (JSON) and `weights` (a safetensors
+//! file, plain or packed); `generation_config`, `chat_template`,
+//! `quantization` and `soul` (a `.soul` snapshot) are optional. The weights
+//! start page-aligned, so they are read from the mapping in place.
+//!
+//! Version 1 files (magic, header length as u64 LE, JSON header with config
+//! and tokenizer, safetensors up to the end) are still read; they carry no
+//! checksums or metadata.
+
+use candle_core::Result;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+use crate::model::{BitLlama, BitLlamaConfig};
+
+pub const BITT_MAGIC: &[u8; 4] = b"BITT";
+
+/// Version written by `BittFile::write`
+pub const BITT_VERSION: u32 = 2;
+
+/// Alignment of every v2 section (one page)
+pub const BITT_ALIGN: usize = 4096;
+
+/// v1 stores the header length where v2 stores the version; a v1 header
+/// (config + tokenizer JSON) is always longer than this
+const MAX_VERSION: u32 = 255;
+
+pub const SECTION_CONFIG: &str = "config";
+pub const SECTION_TOKENIZER: &str = "tokenizer";
+pub const SECTION_WEIGHTS: &str = "weights";
+pub const SECTION_GENERATION_CONFIG: &str = "generation_config";
+pub const SECTION_CHAT_TEMPLATE: &str = "chat_template";
+pub const SECTION_QUANTIZATION: &str = "quantization";
+pub const SECTION_SOUL: &str = "soul";
+
+/// Provenance of a .bitt (v2)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BittMetadata {
+    /// Optimizer step of the exported weights
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub training_step: Option<u64>,
+    /// Where the weights come from (checkpoint path, base model, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Export time (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// Tool and version that wrote the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exporter: Option<String>,
+}
+
+/// One entry of the section index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BittSection {
+    pub name: String,
+    /// Absolute file offset
+    pub offset: u64,
+    pub length: u64,
+    /// Lowercase hex SHA-256 of the section bytes (None in v1 files)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl BittSection {
+    fn range(&self) -> Range<usize> {
+        self.offset as usize..(self.offset + self.length) as usize
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct HeaderV2 {
+    #[serde(default)]
+    metadata: BittMetadata,
+    sections: Vec<BittSection>,
+}
+
+/// An opened .bitt file
+pub struct BittFile {
+    pub version: u32,
+    pub config: BitLlamaConfig,
+    pub tokenizer: Tokenizer,
+    pub metadata: BittMetadata,
+    /// Section index (v1: the header and the weights, without checksums)
+    pub sections: Vec<BittSection>,
+    map: Arc<Mmap>,
+    /// Bytes of the safetensors body
+    body: Range<usize>,
+}
+
+impl BittFile {
+    /// Whether `path` is a file starting with the .bitt magic
+    pub fn is_bitt<P: AsRef<Path>>(path: P) -> bool {
+        let mut magic = [0u8; 4];
+        std::fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok_and(|_| &magic == BITT_MAGIC)
+    }
+
+    /// Map `path` and parse its header (v1 or v2); checksums are not
+    /// checked here (`verify`)
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        if map.get(..4) != Some(BITT_MAGIC) {
+            candle_core::bail!("{:?} is not a .bitt file (no BITT magic)", path);
+        }
+        let Some(word) = map.get(4..8) else {
+            candle_core::bail!("{:?}: truncated .bitt header", path);
+        };
+        let result = match u32::from_le_bytes(word.try_into().unwrap()) {
+            BITT_VERSION => Self::parse_v2(map),
+            version if version <= MAX_VERSION => {
+                candle_core::bail!("{:?}: unsupported .bitt version {}", path, version)
+            }
+            _ => Self::parse_v1(map),
+        };
+        result.map_err(|e| candle_core::Error::Msg(format!("{:?}: {}", path, e)))
+    }
+
+    fn parse_v1(map: Mmap) -> Result<Self> {
+        let Some(header_len) = map.get(4..12) else {
+            candle_core::bail!("truncated .bitt header");
+        };
+        let header_len = u64::from_le_bytes(header_len.try_into().unwrap()) as usize;
+        let Some(header) = map.get(12..12 + header_len) else {
+            candle_core::bail!("truncated .bitt header");
+        };
+        let mut header: serde_json::Value =
+            serde_json::from_slice(header).map_err(candle_core::Error::wrap)?;
+
+        let config = serde_json::from_value(header["config"].take())
+            .map_err(|e| candle_core::Error::Msg(format!("bad config: {}", e)))?;
+        let tokenizer = parse_tokenizer(header["tokenizer"].to_string().as_bytes())?;
+
+        let body = 12 + header_len..map.len();
+        let sections = vec![
+            BittSection {
+                name: "header".to_string(),
+                offset: 12,
+                length: header_len as u64,
+                sha256: None,
+            },
+            BittSection {
+                name: SECTION_WEIGHTS.to_string(),
+                offset: body.start as u64,
+                length: body.len() as u64,
+                sha256: None,
+            },
+        ];
+        Ok(Self {
+            version: 1,
+            config,
+            tokenizer,
+            metadata: BittMetadata::default(),
+            sections,
+            map: Arc::new(map),
+            body,
+        })
+    }
+
+    fn parse_v2(map: Mmap) -> Result<Self> {
+        let Some(header_len) = map.get(8..16) else {
+            candle_core::bail!("truncated .bitt header");
+        };
+        let header_len = u64::from_le_bytes(header_len.try_into().unwrap()) as usize;
+        let Some(header) = map.get(16..16 + header_len) else {
+            candle_core::bail!("truncated .bitt header");
+        };
+        let header: HeaderV2 = serde_json::from_slice(header).map_err(candle_core::Error::wrap)?;
+        for section in &header.sections {
+            if !(section.offset as usize).is_multiple_of(BITT_ALIGN)
+                || section.range().end > map.len()
+            {
+                candle_core::bail!(
+                    "section {} ({} bytes at {}) is misaligned or runs past the file end",
+                    section.name,
+                    section.length,
+                    section.offset
+                );
+            }
+        }
+        let find = |name: &str| -> Result<Range<usize>> {
+            match header.sections.iter().find(|s| s.name == name) {
+                Some(section) => Ok(section.range()),
+                None => candle_core::bail!("missing section {}", name),
+            }
+        };
+        let config = parse_config(&map[find(SECTION_CONFIG)?])?;
+        let tokenizer = parse_tokenizer(&map[find(SECTION_TOKENIZER)?])?;
+        let body = find(SECTION_WEIGHTS)?;
+        Ok(Self {
+            version: BITT_VERSION,
+            config,
+            tokenizer,
+            metadata: header.metadata,
+            sections: header.sections,
+            map: Arc::new(map),
+            body,
+        })
+    }
+
+    /// The safetensors body
+    pub fn body(&self) -> &[u8] {
+        &self.map[self.body.clone()]
+    }
+
+    /// Bytes of section `name`, if present
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        let section = self.sections.iter().find(|s| s.name == name)?;
+        Some(&self.map[section.range()])
+    }
+
+    /// `generation_config` section (JSON)
+    pub fn generation_config(&self) -> Result<Option<serde_json::Value>> {
+        self.json_section(SECTION_GENERATION_CONFIG)
+    }
+
+    /// `quantization` section (JSON)
+    pub fn quantization(&self) -> Result<Option<serde_json::Value>> {
+        self.json_section(SECTION_QUANTIZATION)
+    }
+
+    /// `chat_template` section (UTF-8 text)
+    pub fn chat_template(&self) -> Result<Option<String>> {
+        self.section(SECTION_CHAT_TEMPLATE)
+            .map(|bytes| String::from_utf8(bytes.to_vec()).map_err(candle_core::Error::wrap))
+            .transpose()
+    }
+
+    fn json_section(&self, name: &str) -> Result<Option<serde_json::Value>> {
+        self.section(name)
+            .map(|bytes| serde_json::from_slice(bytes).map_err(candle_core::Error::wrap))
+            .transpose()
+    }
+
+    /// Recompute the SHA-256 of every section; fails with the names of the
+    /// sections that don't match (and for v1 files, which have none)
+    pub fn verify(&self) -> Result<()> {
+        if self.version < 2 {
+            candle_core::bail!(".bitt v{} files have no checksums", self.version);
+        }
+        let bad: Vec<&str> = self
+            .sections
+            .iter()
+            .filter(|s| s.sha256.as_deref() != Some(sha256_hex(&self.map[s.range()]).as_str()))
+            .map(|s| s.name.as_str())
+            .collect();
+        if !bad.is_empty() {
+            candle_core::bail!("checksum mismatch in section(s): {}", bad.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Load the weights with `cfg` (usually `self.config`); packed weights
+    /// stay in the mapping
+    pub fn load_model(&self, cfg: BitLlamaConfig) -> Result<BitLlama> {
+        BitLlama::load_mapped(cfg, self.map.clone(), self.body.clone())
+    }
+
+    /// Write a v2 file with `sections` (name, bytes) in this order. `config`,
+    /// `tokenizer` and `weights` are required.
+    pub fn write<P: AsRef<Path>>(
+        path: P,
+        sections: &[(&str, &[u8])],
+        metadata: &BittMetadata,
+    ) -> Result<()> {
+        for required in [SECTION_CONFIG, SECTION_TOKENIZER, SECTION_WEIGHTS] {
+            if !sections.iter().any(|(name, _)| *name == required) {
+                candle_core::bail!("BittFile::write: missing section {}", required);
+            }
+        }
+        let checksums: Vec<String> = sections.iter().map(|(_, data)| sha256_hex(data)).collect();
+
+        // The offsets are part of the header: grow the first offset until the
+        // header fits in front of it
+        let mut start = BITT_ALIGN;
+        let (header, start) = loop {
+            let mut offset = start;
+            let index = sections
+                .iter()
+                .zip(&checksums)
+                .map(|((name, data), sha256)| {
+                    let section = BittSection {
+                        name: name.to_string(),
+                        offset: offset as u64,
+                        length: data.len() as u64,
+                        sha256: Some(sha256.clone()),
+                    };
+                    offset = align_up(offset + data.len());
+                    section
+                })
+                .collect();
+            let header = serde_json::to_vec(&HeaderV2 {
+                metadata: metadata.clone(),
+                sections: index,
+            })
+            .map_err(candle_core::Error::wrap)?;
+            let needed = align_up(16 + header.len());
+            if needed <= start {
+                break (header, start);
+            }
+            start = needed;
+        };
+
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        out.write_all(BITT_MAGIC)?;
+        out.write_all(&BITT_VERSION.to_le_bytes())?;
+        out.write_all(&(header.len() as u64).to_le_bytes())?;
+        out.write_all(&header)?;
+        let mut written = 16 + header.len();
+        out.write_all(&vec![0u8; start - written])?;
+        written = start;
+        for (i, (_, data)) in sections.iter().enumerate() {
+            out.write_all(data)?;
+            written += data.len();
+            if i + 1 < sections.len() {
+                let padded = align_up(written);
+                out.write_all(&vec![0u8; padded - written])?;
+                written = padded;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn align_up(offset: usize) -> usize {
+    offset.div_ceil(BITT_ALIGN) * BITT_ALIGN
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn parse_config(bytes: &[u8]) -> Result<BitLlamaConfig> {
+    serde_json::from_slice(bytes).map_err(|e| candle_core::Error::Msg(format!("bad config: {}", e)))
+}
+
+fn parse_tokenizer(bytes: &[u8]) -> Result<Tokenizer> {
+    Tokenizer::from_bytes(bytes)
+        .map_err(|e| candle_core::Error::Msg(format!("bad tokenizer: {}", e)))
+}
@@ -3,7 +3,10 @@
 use candle_core::{Result, Tensor};
 use candle_nn::VarBuilder;
 
-use crate::layers::{KVCache, RMSNorm, SwiGLU, TTTLayer};
+use crate::kernels::packing::PackOptions;
+use crate::layers::{
+    BitAttention, BlockTable, KVCache, MoE, PagedKVCache, RMSNorm, SwiGLU, TTTLayer,
+};
 use crate::model::config::{BitLlamaConfig, ModelArch};
 
 /// Epsilon for RMSNorm
@@ -15,12 +18,45 @@ pub enum LayerDispatch {
     Attention(Box<crate::layers::BitAttention>),
 }
 
+/// Enum to dispatch between dense SwiGLU and sparse MoE MLPs
+pub enum MlpDispatch {
+    SwiGLU(Box<SwiGLU>),
+    MoE(Box<MoE>),
+}
+
+impl MlpDispatch {
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        match self {
+            MlpDispatch::SwiGLU(m) => m.forward(x),
+            MlpDispatch::MoE(m) => m.forward(x),
+        }
+    }
+
+    /// Forward returning the MoE load-balancing loss (None for dense MLPs)
+    pub fn forward_with_aux(&self, x: &Tensor) -> Result<(Tensor, Option<Tensor>)> {
+        match self {
+            MlpDispatch::SwiGLU(m) => Ok((m.forward(x)?, None)),
+            MlpDispatch::MoE(m) => {
+                let (out, aux) = m.forward_with_aux(x)?;
+                Ok((out, Some(aux)))
+            }
+        }
+    }
+
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
+        match self {
+            MlpDispatch::SwiGLU(m) => m.precompute_packed(options),
+            MlpDispatch::MoE(m) => m.precompute_packed(options),
+        }
+    }
+}
+
 /// Single transformer block: TTT/Attn + MLP with residual connections
 pub struct BitLlamaBlock {
     pub norm1: RMSNorm,
     pub core: LayerDispatch,
     pub norm2: RMSNorm,
-    pub mlp: SwiGLU,
+    pub mlp: MlpDispatch,
 }
 
 impl BitLlamaBlock {
@@ -30,18 +66,51 @@ impl BitLlamaBlock {
         device: &candle_core::Device,
     ) -> Result<Self> {
         let dim = cfg.hidden_dim;
-        let norm1 = RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("norm1").pp("model.norm"), device)
-            .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("norm1"), device))
-            .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("input_layernorm"), device))?;
+        // Norm weights in the storage dtype (statistics stay F32)
+        let norm_vb = vb.to_dtype(cfg.storage_dtype.dtype());
+        let norm1 = RMSNorm::load(
+            dim,
+            RMS_NORM_EPS,
+            norm_vb.pp("norm1").pp("model.norm"),
+            device,
+        )
+        .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, norm_vb.pp("norm1"), device))
+        .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, norm_vb.pp("input_layernorm"), device))?;
 
-        let norm2 = RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("norm2").pp("model.norm"), device)
-            .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("norm2"), device))
-            .or_else(|_| {
-                RMSNorm::load(dim, RMS_NORM_EPS, vb.pp("post_attention_layernorm"), device)
-            })?;
+        let norm2 = RMSNorm::load(
+            dim,
+            RMS_NORM_EPS,
+            norm_vb.pp("norm2").pp("model.norm"),
+            device,
+        )
+        .or_else(|_| RMSNorm::load(dim, RMS_NORM_EPS, norm_vb.pp("norm2"), device))
+        .or_else(|_| {
+            RMSNorm::load(
+                dim,
+                RMS_NORM_EPS,
+                norm_vb.pp("post_attention_layernorm"),
+                device,
+            )
+        })?;
 
         let mlp_dim = cfg.intermediate_dim.unwrap_or(dim * 4);
-        let mlp = SwiGLU::load(dim, mlp_dim, vb.pp("mlp"), device)?;
+        let mlp = match cfg.n_experts {
+            Some(n_experts) if n_experts > 1 => {
+                let moe = MoE::load(
+                    dim,
+                    mlp_dim,
+                    n_experts,
+                    cfg.n_experts_per_tok,
+                    vb.pp("block_sparse_moe"),
+                    device,
+                )?;
+                MlpDispatch::MoE(Box::new(moe))
+            }
+            _ => {
+                let mlp = SwiGLU::load(dim, mlp_dim, vb.pp("mlp"), device)?;
+                MlpDispatch::SwiGLU(Box::new(mlp))
+            }
+        };
 
         // Dispatch Layer Loading based on Config
         let core = match cfg.arch {
@@ -50,7 +119,7 @@ impl BitLlamaBlock {
                 LayerDispatch::TTT(Box::new(ttt))
             }
             ModelArch::Llama => {
-                let attn = crate::layers::BitAttention::load(
+                let mut attn = crate::layers::BitAttention::load(
                     dim,
                     cfg.n_heads,
                     cfg.n_kv_heads,
@@ -59,6 +128,7 @@ impl BitLlamaBlock {
                     vb.pp("self_attn"),
                     device,
                 )?;
+                attn.quantized_kv = cfg.quantized_kv_attention;
                 LayerDispatch::Attention(Box::new(attn))
             }
         };
@@ -75,12 +145,13 @@ impl BitLlamaBlock {
         self.norm1.weight.device()
     }
 
-    pub fn precompute_packed(&mut self) -> Result<()> {
+    pub fn precompute_packed(&mut self, options: PackOptions) -> Result<()> {
         match &mut self.core {
-            LayerDispatch::TTT(t) => t.precompute_packed()?,
-            LayerDispatch::Attention(_) => {} // No precompute needed yet
+            LayerDispatch::TTT(t) => t.precompute_packed(options)?,
+            // Projections stay as they are; only q/k/v fusion is prepared
+            LayerDispatch::Attention(a) => a.precompute_packed(options)?,
         }
-        self.mlp.precompute_packed()?;
+        self.mlp.precompute_packed(options)?;
         Ok(())
     }
 
@@ -133,12 +204,96 @@ impl BitLlamaBlock {
         Ok((x_out, w_new))
     }
 
+    /// One decode step for a batch of independent sequences.
+    ///
+    /// x: [Batch, 1, Hidden]; row `b` carries its own TTT state `w_states[b]`
+    /// and KV cache `kv_caches[b]` at `positions[b]`. Returns the block output
+    /// and the updated per-row TTT states.
+    pub fn forward_batch(
+        &self,
+        x: &Tensor,
+        w_states: &[Tensor],
+        kv_caches: &mut [&mut Option<KVCache>],
+        positions: &[usize],
+    ) -> Result<(Tensor, Vec<Tensor>)> {
+        self.forward_batch_with(x, w_states, |a, x_norm| {
+            a.forward_batch(x_norm, kv_caches, positions)
+        })
+    }
+
+    /// `forward_batch` with attention over a shared `PagedKVCache` (`layer` = this block's index)
+    pub fn forward_paged(
+        &self,
+        x: &Tensor,
+        w_states: &[Tensor],
+        cache: &PagedKVCache,
+        layer: usize,
+        tables: &[&BlockTable],
+    ) -> Result<(Tensor, Vec<Tensor>)> {
+        self.forward_batch_with(x, w_states, |a, x_norm| {
+            a.forward_paged(x_norm, cache, layer, tables)
+        })
+    }
+
+    fn forward_batch_with(
+        &self,
+        x: &Tensor,
+        w_states: &[Tensor],
+        attend: impl FnOnce(&BitAttention, &Tensor) -> Result<Tensor>,
+    ) -> Result<(Tensor, Vec<Tensor>)> {
+        let residual = x;
+        let x_norm = self.norm1.forward(x)?;
+
+        let (mixed_out, w_new) = match &self.core {
+            LayerDispatch::TTT(t) => {
+                // Stack per-sequence states: (B, D_small, D_small) with x: (B, Hidden)
+                let w = Tensor::stack(w_states, 0)?;
+                let (out, w_new) = t.forward_update(&w, &x_norm.squeeze(1)?)?;
+                let w_new = (0..w_states.len())
+                    .map(|b| w_new.get(b))
+                    .collect::<Result<Vec<_>>>()?;
+                (out.unsqueeze(1)?, w_new)
+            }
+            LayerDispatch::Attention(a) => (attend(a, &x_norm)?, w_states.to_vec()),
+        };
+
+        // [Hybrid Guard] Ensure mixed output is on same device as residual before adding
+        let mixed_out = if mixed_out.device().same_device(residual.device()) {
+            mixed_out
+        } else {
+            mixed_out.to_device(residual.device())?
+        };
+
+        let x_mid = (residual + mixed_out)?;
+        let mlp_out = self.mlp.forward(&self.norm2.forward(&x_mid)?)?;
+
+        // [Hybrid Guard] Ensure MLP output is on same device as residual before adding
+        let mlp_out = if mlp_out.device().same_device(x_mid.device()) {
+            mlp_out
+        } else {
+            mlp_out.to_device(x_mid.device())?
+        };
+
+        Ok(((x_mid + mlp_out)?, w_new))
+    }
+
     pub fn forward_chunkwise(
         &self,
         x: &Tensor,
         w_state: &Tensor,
         chunk_size: usize,
     ) -> Result<(Tensor, Tensor)> {
+        let (x_out, w_final, _aux) = self.forward_chunkwise_with_aux(x, w_state, chunk_size)?;
+        Ok((x_out, w_final))
+    }
+
+    /// Chunkwise forward that also returns the MoE load-balancing loss (if any)
+    pub fn forward_chunkwise_with_aux(
+        &self,
+        x: &Tensor,
+        w_state: &Tensor,
+        chunk_size: usize,
+    ) -> Result<(Tensor, Tensor, Option<Tensor>)> {
         let residual = x;
         let x_norm = self.norm1.forward(x)?;
 
@@ -157,9 +312,9 @@ impl BitLlamaBlock {
         let x_mid = (residual + mixed_out)?;
         let residual = &x_mid;
         let x_norm2 = self.norm2.forward(&x_mid)?;
-        let mlp_out = self.mlp.forward(&x_norm2)?;
+        let (mlp_out, aux_loss) = self.mlp.forward_with_aux(&x_norm2)?;
         let x_out = (residual + mlp_out)?;
 
-        Ok((x_out, w_final))
+        Ok((x_out, w_final, aux_loss))
     }
 }
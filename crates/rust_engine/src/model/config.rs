@@ -2,6 +2,11 @@
 
 use serde::Deserialize;
 
+use crate::kernels::autotune::TuneMode;
+use crate::kernels::cpu::ActivationQuant;
+use crate::kernels::packing::{PackOptions, WeightScale};
+use crate::layers::{DensePrecision, FloatDtype, KvCacheDtype, TableQuant};
+
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 
@@ -56,6 +61,71 @@ pub struct BitLlamaConfig {
     #[pyo3(get, set)]
     #[serde(default)]
     pub lm_head_cpu: bool,
+    /// Number of MoE experts per block (None or 1 = dense SwiGLU)
+    #[pyo3(get, set)]
+    #[serde(default, alias = "num_local_experts")]
+    pub n_experts: Option<usize>,
+    #[pyo3(get, set)]
+    #[serde(default = "default_experts_per_tok", alias = "num_experts_per_tok")]
+    pub n_experts_per_tok: usize,
+    /// Weight of the MoE load-balancing loss during training
+    #[pyo3(get, set)]
+    #[serde(default = "default_router_aux_loss_coef")]
+    pub router_aux_loss_coef: f64,
+    /// CPU attention reads the quantized KV cache directly instead of an f32 copy
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub quantized_kv_attention: bool,
+    /// Storage precision of the KV cache ("q8", "q4", "fp8", "kivi", "f16", "f32")
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub kv_cache_dtype: KvCacheDtype,
+    /// Activations of the CPU ternary matmul: "f32" (float FMA), "int8" (per-token absmax),
+    /// "lut" (int8 through activation lookup tables) or "auto" (fastest per shape, autotuned)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub activation_quant: ActivationQuant,
+    /// Autotune cache use with `activation_quant = "auto"`: "cached", "retune" or
+    /// "off" (None: keep the process-wide mode, initially `BIT_TTT_AUTOTUNE`)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub autotune: Option<TuneMode>,
+    /// Scale granularity of the packed BitLinear weights: "tensor" (one absmean),
+    /// "channel" (per output row) or "group" (per `weight_scale_group` columns)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub weight_scale: WeightScale,
+    /// Columns per scale with `weight_scale = "group"` (multiple of 4; of 128 for int8)
+    #[pyo3(get, set)]
+    #[serde(default = "default_weight_scale_group")]
+    pub weight_scale_group: usize,
+    /// Storage dtype of embeddings, lm_head, norms and dense fallback weights
+    /// ("f32", "f16" or "bf16"); activations between layers stay F32
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub storage_dtype: FloatDtype,
+    /// Dtype of the dense matmuls (dense fallback layers, and lm_head unless `lm_head_dtype`)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub compute_dtype: FloatDtype,
+    /// Dtype of the lm_head matmul (None: `compute_dtype`)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub lm_head_dtype: Option<FloatDtype>,
+    /// Pack q/k/v and gate/up into one weight each so the CPU normalizes and
+    /// quantizes their input once (the projections then use views of the fused weights)
+    #[pyo3(get, set)]
+    #[serde(default = "default_fused_projections")]
+    pub fused_projections: bool,
+    /// Embedding table storage: "dense" (`storage_dtype`), or "int8" / "ternary"
+    /// (per-row scales, CPU), applied by `BitLlama::precompute_packed`
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub embedding_quant: TableQuant,
+    /// lm_head storage, as `embedding_quant`
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub lm_head_quant: TableQuant,
 }
 
 fn default_rope() -> f64 {
@@ -64,6 +134,48 @@ fn default_rope() -> f64 {
 fn default_max_pos() -> usize {
     2048
 }
+/// Default `n_experts_per_tok` (also used by the trainer's project config)
+pub fn default_experts_per_tok() -> usize {
+    2
+}
+/// Default `router_aux_loss_coef`
+pub fn default_router_aux_loss_coef() -> f64 {
+    0.02
+}
+fn default_weight_scale_group() -> usize {
+    128
+}
+fn default_fused_projections() -> bool {
+    true
+}
+
+impl BitLlamaConfig {
+    /// How `BitLlama::precompute_packed` packs the BitLinear weights
+    pub fn pack_options(&self) -> PackOptions {
+        PackOptions {
+            activations: self.activation_quant,
+            weight_scale: self.weight_scale,
+            group_size: self.weight_scale_group,
+            fuse_projections: self.fused_projections,
+        }
+    }
+
+    /// Storage / compute dtypes of the dense fallback weights
+    pub fn dense_precision(&self) -> DensePrecision {
+        DensePrecision {
+            storage: self.storage_dtype.dtype(),
+            compute: self.compute_dtype.dtype(),
+        }
+    }
+
+    /// Storage / compute dtypes of lm_head
+    pub fn lm_head_precision(&self) -> DensePrecision {
+        DensePrecision {
+            storage: self.storage_dtype.dtype(),
+            compute: self.lm_head_dtype.unwrap_or(self.compute_dtype).dtype(),
+        }
+    }
+}
 
 #[cfg(feature = "python")]
 #[pymethods]
@@ -89,6 +201,21 @@ impl BitLlamaConfig {
             rope_theta: 10000.0,
             max_position_embeddings: 2048,
             lm_head_cpu: lm_head_cpu.unwrap_or(false),
+            n_experts: None,
+            n_experts_per_tok: default_experts_per_tok(),
+            router_aux_loss_coef: default_router_aux_loss_coef(),
+            quantized_kv_attention: false,
+            kv_cache_dtype: KvCacheDtype::default(),
+            activation_quant: ActivationQuant::default(),
+            autotune: None,
+            weight_scale: WeightScale::default(),
+            weight_scale_group: default_weight_scale_group(),
+            storage_dtype: FloatDtype::default(),
+            compute_dtype: FloatDtype::default(),
+            lm_head_dtype: None,
+            fused_projections: default_fused_projections(),
+            embedding_quant: TableQuant::default(),
+            lm_head_quant: TableQuant::default(),
         }
     }
 
@@ -0,0 +1,630 @@
+//! Allocation-free decode - single tokens through a reusable workspace
+//!
+//! `BitLlama::forward_one` builds fresh tensors for every norm, projection,
+//! RoPE rotation and attention step, and the CPU kernels copy their input and
+//! output through new `Vec`s. `DecodeWorkspace` holds one session's decode
+//! state (TTT weights, K/V rows, position) together with every buffer a step
+//! needs; `BitLlama::forward_one_into` writes into them and returns the
+//! logits as a slice. Once the first step has sized the kernel scratch,
+//! decoding does no heap allocation except when the K/V rows grow (doubling
+//! from `INITIAL_CAPACITY` tokens up to `max_len`, as in `QuantizedKVCache`).
+//! Fused q/k/v and gate/up projections
+//! (`layers::FusedProjection`) also take over the RMSNorm in front of them.
+//!
+//! Requires every layer, the norms and lm_head on the CPU in F32 storage
+//! (embedding and lm_head may also be quantized), dense SwiGLU MLPs, and
+//! linears that are packed (`precompute_packed`), multi-base or dense,
+//! without unmerged LoRA adapters. K/V rows are kept in
+//! f32 for up to `max_len` tokens; `BitLlama::decode_window` bounds them by
+//! the memory of the `kv_cache_dtype` caches, and `hand_over` moves a longer
+//! session into those caches to continue with `forward_one`.
+
+use candle_core::{DType, Device, Result, Tensor};
+use rayon::prelude::*;
+
+use crate::kernels::backend::{self, TernaryKernel};
+use crate::kernels::cpu::{self, KernelScratch, RmsNormIn};
+use crate::layers::{
+    AdaptiveBitLinear, BitAttention, FusedProjection, KvCacheDtype, RMSNorm, SwiGLU, TTTLayer,
+    VocabTable,
+};
+use crate::model::{BitLlama, LayerDispatch, MlpDispatch};
+
+/// Epsilon of the TTT feature normalization (as in `layers::ttt`)
+const TTT_NORM_EPS: f32 = 1e-6;
+
+/// Initial K/V capacity in tokens (doubled on demand up to `max_len`)
+const INITIAL_CAPACITY: usize = 256;
+
+/// Decode state of one layer
+enum LayerState {
+    /// TTT inner weights [D_small, D_small], row-major
+    Ttt { w: Vec<f32>, dim: usize },
+    /// Rotated keys and values: one row of [KV_Heads * Dim] per allocated token
+    Attention {
+        k: Vec<f32>,
+        v: Vec<f32>,
+        kv_dim: usize,
+    },
+}
+
+/// Per-step buffers, sized for the largest layer
+struct Buffers {
+    hidden: Vec<f32>,
+    normed: Vec<f32>,
+    /// Output of the mixer / MLP, added to `hidden`
+    mixed: Vec<f32>,
+    feat: Vec<f32>,
+    pred: Vec<f32>,
+    q: Vec<f32>,
+    /// Output of a fused q/k/v projection, [q | k | v]
+    qkv: Vec<f32>,
+    attn: Vec<f32>,
+    /// One row of scores per head, as long as the K/V capacity
+    scores: Vec<f32>,
+    gate: Vec<f32>,
+    up: Vec<f32>,
+    logits: Vec<f32>,
+    scratch: KernelScratch,
+}
+
+/// One decoding session: its state and the buffers of `BitLlama::forward_one_into`
+pub struct DecodeWorkspace {
+    layers: Vec<LayerState>,
+    pos: usize,
+    max_len: usize,
+    /// Tokens the K/V rows and scores have room for
+    capacity: usize,
+    /// Largest number of attention heads of a layer
+    heads: usize,
+    buf: Buffers,
+}
+
+impl DecodeWorkspace {
+    /// Workspace for `model` holding up to `max_len` tokens of K/V, allocated
+    /// as the session grows (fails if the model needs `forward_one`, see the
+    /// module docs)
+    pub fn new(model: &BitLlama, max_len: usize) -> Result<Self> {
+        if max_len == 0 {
+            candle_core::bail!("DecodeWorkspace: max_len must be > 0");
+        }
+        check_table(&model.embedding, "embedding")?;
+        check_io(&model.norm.weight, "final norm")?;
+        check_table(&model.lm_head, "lm_head")?;
+        let (mut feat, mut q, mut qkv, mut heads, mut mlp) = (0, 0, 0, 0, 0);
+        let mut layers = Vec::with_capacity(model.layers.len());
+        for (i, layer) in model.layers.iter().enumerate() {
+            check_io(&layer.norm1.weight, "norm1")?;
+            check_io(&layer.norm2.weight, "norm2")?;
+            layers.push(match &layer.core {
+                LayerDispatch::TTT(t) => {
+                    feat = feat.max(t.d_small);
+                    LayerState::Ttt {
+                        w: vec![0.0; t.d_small * t.d_small],
+                        dim: t.d_small,
+                    }
+                }
+                LayerDispatch::Attention(a) => {
+                    q = q.max(a.n_heads * a.head_dim);
+                    qkv = qkv.max((a.n_heads + 2 * a.n_kv_heads) * a.head_dim);
+                    heads = heads.max(a.n_heads);
+                    LayerState::Attention {
+                        k: Vec::new(),
+                        v: Vec::new(),
+                        kv_dim: a.n_kv_heads * a.head_dim,
+                    }
+                }
+            });
+            match &layer.mlp {
+                MlpDispatch::SwiGLU(m) => mlp = mlp.max(m.w1.out_features),
+                MlpDispatch::MoE(_) => {
+                    candle_core::bail!("DecodeWorkspace: layer {} has a MoE MLP", i)
+                }
+            }
+        }
+
+        for (name, _, module) in model.linear_modules() {
+            check_linear(module)
+                .map_err(|e| candle_core::Error::Msg(format!("{}: {}", name, e)))?;
+        }
+
+        let hidden = model.config.hidden_dim;
+        Ok(Self {
+            layers,
+            pos: 0,
+            max_len,
+            capacity: 0,
+            heads,
+            buf: Buffers {
+                hidden: vec![0.0; hidden],
+                normed: vec![0.0; hidden],
+                mixed: vec![0.0; hidden],
+                feat: vec![0.0; feat],
+                pred: vec![0.0; feat],
+                q: vec![0.0; q],
+                qkv: vec![0.0; qkv],
+                attn: vec![0.0; q],
+                scores: Vec::new(),
+                gate: vec![0.0; mlp],
+                up: vec![0.0; mlp],
+                logits: vec![0.0; model.config.vocab_size],
+                scratch: KernelScratch::default(),
+            },
+        })
+    }
+
+    /// Tokens consumed so far (RoPE position of the next token)
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// K/V capacity in tokens
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Make room for one more token: double the K/V rows (up to `max_len`)
+    fn reserve_next(&mut self) {
+        if self.pos < self.capacity {
+            return;
+        }
+        self.capacity = (self.capacity * 2).max(INITIAL_CAPACITY).min(self.max_len);
+        for layer in &mut self.layers {
+            if let LayerState::Attention { k, v, kv_dim } = layer {
+                k.resize(self.capacity * *kv_dim, 0.0);
+                v.resize(self.capacity * *kv_dim, 0.0);
+            }
+        }
+        self.buf.scores.resize(self.heads * self.capacity, 0.0);
+    }
+
+    /// Continue this session with `BitLlama::forward_one`: its K/V rows go
+    /// into `model`'s caches (stored as `kv_cache_dtype`), the TTT states into
+    /// `w_states` and the position into `model.current_pos`
+    pub fn hand_over(&self, model: &mut BitLlama, w_states: &mut [Tensor]) -> Result<()> {
+        if self.layers.len() != model.layers.len() {
+            candle_core::bail!("DecodeWorkspace: created for a different model");
+        }
+        model.reset_kv_cache();
+        for ((layer, state), cache) in model
+            .layers
+            .iter()
+            .zip(&self.layers)
+            .zip(model.kv_caches.iter_mut())
+        {
+            let (LayerDispatch::Attention(a), LayerState::Attention { k, v, kv_dim }, Some(cache)) =
+                (&layer.core, state, cache)
+            else {
+                continue;
+            };
+            if self.pos == 0 {
+                continue;
+            }
+            // [Pos, KV_Heads * Dim] rows -> [1, KV_Heads, Pos, Dim]
+            let shape = (1, self.pos, a.n_kv_heads, a.head_dim);
+            let rows = |x: &[f32]| {
+                Tensor::from_slice(&x[..self.pos * kv_dim], shape, &Device::Cpu)?
+                    .transpose(1, 2)?
+                    .contiguous()
+            };
+            cache.append(&rows(k)?, &rows(v)?)?;
+        }
+        model.current_pos = self.pos;
+        self.store_w_states(w_states)
+    }
+
+    /// Start a new session (zero TTT states, empty K/V)
+    pub fn reset(&mut self) {
+        for layer in &mut self.layers {
+            if let LayerState::Ttt { w, .. } = layer {
+                w.fill(0.0);
+            }
+        }
+        self.pos = 0;
+    }
+
+    /// Take over TTT states (one per layer, [D_small, D_small] values in any
+    /// shape; entries of attention layers are ignored)
+    pub fn load_w_states(&mut self, states: &[Tensor]) -> Result<()> {
+        if states.len() != self.layers.len() {
+            candle_core::bail!(
+                "DecodeWorkspace: {} states for {} layers",
+                states.len(),
+                self.layers.len()
+            );
+        }
+        for (i, (layer, state)) in self.layers.iter_mut().zip(states).enumerate() {
+            if let LayerState::Ttt { w, dim } = layer {
+                let values = state
+                    .flatten_all()?
+                    .to_dtype(DType::F32)?
+                    .to_vec1::<f32>()?;
+                if values.len() != w.len() {
+                    candle_core::bail!(
+                        "DecodeWorkspace: layer {} TTT state has {} values, expected {}x{}",
+                        i,
+                        values.len(),
+                        dim,
+                        dim
+                    );
+                }
+                w.copy_from_slice(&values);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the TTT states back as CPU tensors, keeping the shape of each
+    /// entry that holds D_small^2 values ([D_small, D_small] otherwise;
+    /// entries of attention layers are left as they are)
+    pub fn store_w_states(&self, states: &mut [Tensor]) -> Result<()> {
+        for (layer, state) in self.layers.iter().zip(states.iter_mut()) {
+            if let LayerState::Ttt { w, dim } = layer {
+                let shape = if state.elem_count() == w.len() {
+                    state.shape().clone()
+                } else {
+                    (*dim, *dim).into()
+                };
+                *state = Tensor::from_slice(w, shape, &Device::Cpu)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BitLlama {
+    /// Tokens a `DecodeWorkspace` should hold: as many as keep its f32 K/V
+    /// within the `kv_cache_dtype` caches of `max_position_embeddings` tokens
+    pub fn decode_window(&self) -> usize {
+        let max_len = self.config.max_position_embeddings;
+        let attention = self
+            .layers
+            .iter()
+            .any(|l| matches!(l.core, LayerDispatch::Attention(_)));
+        if !attention {
+            return max_len;
+        }
+        let bits = self.config.kv_cache_dtype.bits_per_value();
+        (max_len * bits / KvCacheDtype::F32.bits_per_value()).max(1)
+    }
+
+    /// `forward_one` for one token into `ws`: advances the session and
+    /// returns the logits [Vocab], without heap allocation
+    pub fn forward_one_into<'a>(
+        &self,
+        token: u32,
+        ws: &'a mut DecodeWorkspace,
+    ) -> Result<&'a [f32]> {
+        if ws.pos >= ws.max_len {
+            candle_core::bail!(
+                "DecodeWorkspace: all {} positions used (reset it or use a larger max_len)",
+                ws.max_len
+            );
+        }
+        if ws.layers.len() != self.layers.len() {
+            candle_core::bail!("DecodeWorkspace: created for a different model");
+        }
+        ws.reserve_next();
+        let kernel = backend::for_device(&Device::Cpu)?;
+        let kernel = kernel.as_ref();
+        let buf = &mut ws.buf;
+
+        let hidden = buf.hidden.len();
+        let row = token as usize * hidden;
+        match &self.embedding {
+            VocabTable::Dense(table) => with_f32(table, |table| {
+                let Some(embedding) = table.get(row..row + hidden) else {
+                    candle_core::bail!("token {} is out of the vocabulary", token);
+                };
+                buf.hidden.copy_from_slice(embedding);
+                Ok(())
+            })??,
+            VocabTable::Quantized(table) => table.row_into(token as usize, &mut buf.hidden)?,
+        }
+
+        for (layer, state) in self.layers.iter().zip(ws.layers.iter_mut()) {
+            match (&layer.core, state) {
+                (LayerDispatch::TTT(t), LayerState::Ttt { w, .. }) => {
+                    rms_norm_into(&buf.hidden, &layer.norm1, &mut buf.normed)?;
+                    buf.ttt(t, w, kernel)?
+                }
+                (LayerDispatch::Attention(a), LayerState::Attention { k, v, .. }) => {
+                    buf.attention(a, &layer.norm1, k, v, ws.pos, kernel)?
+                }
+                _ => candle_core::bail!("DecodeWorkspace: created for a different model"),
+            }
+            add_into(&mut buf.hidden, &buf.mixed);
+
+            match &layer.mlp {
+                MlpDispatch::SwiGLU(m) => buf.swiglu(m, &layer.norm2, kernel)?,
+                MlpDispatch::MoE(_) => {
+                    candle_core::bail!("DecodeWorkspace: MoE MLPs need forward_one")
+                }
+            }
+            add_into(&mut buf.hidden, &buf.mixed);
+        }
+
+        rms_norm_into(&buf.hidden, &self.norm, &mut buf.normed)?;
+        match &self.lm_head {
+            VocabTable::Dense(w) => with_f32(w, |w| dense_into(&buf.normed, w, &mut buf.logits))??,
+            VocabTable::Quantized(w) => {
+                w.matmul_into(&buf.normed, &mut buf.scratch, &mut buf.logits)?
+            }
+        }
+        ws.pos += 1;
+        Ok(&ws.buf.logits)
+    }
+}
+
+impl Buffers {
+    /// TTT mixer (`TTTLayer::forward_update`): `normed` -> `mixed`, `w` updated in place
+    fn ttt(&mut self, t: &TTTLayer, w: &mut [f32], kernel: &dyn TernaryKernel) -> Result<()> {
+        let d = t.d_small;
+        let (feat, pred) = (&mut self.feat[..d], &mut self.pred[..d]);
+        linear_into(kernel, &t.proj_down, &self.normed, &mut self.scratch, feat)?;
+
+        let norm = feat.iter().map(|v| v * v).sum::<f32>().sqrt() + TTT_NORM_EPS;
+        feat.iter_mut().for_each(|v| *v /= norm);
+
+        // Predict, then one gradient step on |W f - f|^2: W -= lr * (W f - f) f^T
+        for (p, row) in pred.iter_mut().zip(w.chunks_exact(d)) {
+            *p = dot(row, feat);
+        }
+        let lr = t.inner_lr as f32;
+        for ((row, &p), &f) in w.chunks_exact_mut(d).zip(pred.iter()).zip(feat.iter()) {
+            let g = (p - f) * lr;
+            for (w, &f) in row.iter_mut().zip(feat.iter()) {
+                *w -= g * f;
+            }
+        }
+
+        linear_into(kernel, &t.proj_up, pred, &mut self.scratch, &mut self.mixed)
+    }
+
+    /// Attention mixer at `pos`: appends this token's K/V rows, `norm(hidden)` -> `mixed`
+    fn attention(
+        &mut self,
+        a: &BitAttention,
+        norm: &RMSNorm,
+        k: &mut [f32],
+        v: &mut [f32],
+        pos: usize,
+        kernel: &dyn TernaryKernel,
+    ) -> Result<()> {
+        let (hd, n_rep) = (a.head_dim, a.n_heads / a.n_kv_heads);
+        let (q_dim, kv_dim) = (a.n_heads * hd, a.n_kv_heads * hd);
+        let capacity = k.len() / kv_dim;
+        let q = &mut self.q[..q_dim];
+        let rows = pos * kv_dim..(pos + 1) * kv_dim;
+        if let Some(fused) = a.qkv_fusion() {
+            let qkv = &mut self.qkv[..q_dim + 2 * kv_dim];
+            fused_into(kernel, fused, norm, &self.hidden, &mut self.scratch, qkv)?;
+            let (q_new, kv_new) = qkv.split_at(q_dim);
+            q.copy_from_slice(q_new);
+            k[rows.clone()].copy_from_slice(&kv_new[..kv_dim]);
+            v[rows.clone()].copy_from_slice(&kv_new[kv_dim..]);
+        } else {
+            rms_norm_into(&self.hidden, norm, &mut self.normed)?;
+            let x = &self.normed;
+            linear_into(kernel, &a.q_proj, x, &mut self.scratch, q)?;
+            linear_into(
+                kernel,
+                &a.k_proj,
+                x,
+                &mut self.scratch,
+                &mut k[rows.clone()],
+            )?;
+            linear_into(
+                kernel,
+                &a.v_proj,
+                x,
+                &mut self.scratch,
+                &mut v[rows.clone()],
+            )?;
+        }
+        rope_into(a, q, pos)?;
+        rope_into(a, &mut k[rows], pos)?;
+
+        // One head per task; query head h reads KV head h / n_rep (GQA)
+        let (k, v, len, scale) = (&*k, &*v, pos + 1, a.scaling as f32);
+        self.attn[..q_dim]
+            .par_chunks_mut(hd)
+            .zip(q.par_chunks(hd))
+            .zip(self.scores.par_chunks_mut(capacity))
+            .enumerate()
+            .for_each(|(h, ((out, q), scores))| {
+                let offset = h / n_rep * hd;
+                let scores = &mut scores[..len];
+                for (j, s) in scores.iter_mut().enumerate() {
+                    let start = j * kv_dim + offset;
+                    *s = dot(q, &k[start..start + hd]) * scale;
+                }
+                let max = scores.iter().fold(f32::NEG_INFINITY, |m, &s| m.max(s));
+                let mut sum = 0.0;
+                for s in scores.iter_mut() {
+                    *s = (*s - max).exp();
+                    sum += *s;
+                }
+                out.fill(0.0);
+                for (j, &s) in scores.iter().enumerate() {
+                    let start = j * kv_dim + offset;
+                    let p = s / sum;
+                    for (o, &v) in out.iter_mut().zip(&v[start..start + hd]) {
+                        *o += p * v;
+                    }
+                }
+            });
+
+        let attn = &self.attn[..q_dim];
+        linear_into(kernel, &a.o_proj, attn, &mut self.scratch, &mut self.mixed)
+    }
+
+    /// SwiGLU MLP: `norm(hidden)` -> `mixed`
+    fn swiglu(&mut self, m: &SwiGLU, norm: &RMSNorm, kernel: &dyn TernaryKernel) -> Result<()> {
+        let n = m.w1.out_features;
+        let (gate, up) = (&mut self.gate[..n], &mut self.up[..n]);
+        if let Some(fused) = m.gate_up_fusion() {
+            fused_into(kernel, fused, norm, &self.hidden, &mut self.scratch, gate)?;
+        } else {
+            rms_norm_into(&self.hidden, norm, &mut self.normed)?;
+            linear_into(kernel, &m.w1, &self.normed, &mut self.scratch, gate)?;
+            linear_into(kernel, &m.w3, &self.normed, &mut self.scratch, up)?;
+            for (g, &u) in gate.iter_mut().zip(up.iter()) {
+                *g = cpu::silu(*g) * u;
+            }
+        }
+        linear_into(kernel, &m.w2, gate, &mut self.scratch, &mut self.mixed)
+    }
+}
+
+/// Embedding, norm and lm_head weights must be F32 CPU tensors
+fn check_io(t: &Tensor, name: &str) -> Result<()> {
+    if !t.device().is_cpu() || t.dtype() != DType::F32 {
+        candle_core::bail!(
+            "DecodeWorkspace: {} is {:?} on {:?} (needs F32 on the CPU)",
+            name,
+            t.dtype(),
+            t.device()
+        );
+    }
+    Ok(())
+}
+
+/// Embedding / lm_head tables: quantized (CPU) or F32 on the CPU
+fn check_table(table: &VocabTable, name: &str) -> Result<()> {
+    match table {
+        VocabTable::Dense(t) => check_io(t, name),
+        VocabTable::Quantized(_) => Ok(()),
+    }
+}
+
+/// Whether `linear_into` can run `layer`
+fn check_linear(layer: &AdaptiveBitLinear) -> Result<()> {
+    if layer.lora.is_some() || !layer.lora_rows.is_empty() {
+        candle_core::bail!("unmerged LoRA adapters need forward_one (merge them first)");
+    }
+    if let Some(linear) = &layer.legacy_linear {
+        match &linear.packed_params {
+            Some(packed) if packed.device.is_cpu() => return Ok(()),
+            Some(_) => candle_core::bail!("packed weights are not on the CPU"),
+            None => candle_core::bail!("weights are not packed (call precompute_packed)"),
+        }
+    }
+    if let Some(w) = &layer.reconstructed_weight {
+        return check_io(w, "dense weight");
+    }
+    Ok(())
+}
+
+/// `AdaptiveBitLinear::forward` of one row into `out`
+fn linear_into(
+    kernel: &dyn TernaryKernel,
+    layer: &AdaptiveBitLinear,
+    x: &[f32],
+    scratch: &mut KernelScratch,
+    out: &mut [f32],
+) -> Result<()> {
+    check_linear(layer)?;
+    if let Some(packed) = layer
+        .legacy_linear
+        .as_ref()
+        .and_then(|l| l.packed_params.as_ref())
+    {
+        return kernel.matmul_into(x, packed, scratch, out);
+    }
+    if let Some(packed) = &layer.packed_bases {
+        return kernel.adaptive_matmul_into(x, packed, out);
+    }
+    if let Some(w) = &layer.reconstructed_weight {
+        return with_f32(w, |w| dense_into(x, w, out))?;
+    }
+    candle_core::bail!("AdaptiveBitLinear: Invalid State")
+}
+
+/// `FusedProjection` of `norm(x)` for one row into `out` (one kernel call)
+fn fused_into(
+    kernel: &dyn TernaryKernel,
+    fused: &FusedProjection,
+    norm: &RMSNorm,
+    x: &[f32],
+    scratch: &mut KernelScratch,
+    out: &mut [f32],
+) -> Result<()> {
+    with_f32(&norm.weight, |weight| {
+        let norm = RmsNormIn {
+            weight,
+            eps: norm.eps as f32,
+        };
+        kernel.fused_matmul_into(x, Some(norm), &fused.weight, fused.epilogue, scratch, out)
+    })?
+}
+
+/// `RMSNorm::forward` of one row into `out`
+fn rms_norm_into(x: &[f32], norm: &RMSNorm, out: &mut [f32]) -> Result<()> {
+    with_f32(&norm.weight, |weight| {
+        let norm = RmsNormIn {
+            weight,
+            eps: norm.eps as f32,
+        };
+        cpu::rms_norm_into(x, norm, out)
+    })?
+}
+
+/// Rotate the heads of one token row at `pos` in place (`RotaryEmbedding::apply`)
+fn rope_into(a: &BitAttention, x: &mut [f32], pos: usize) -> Result<()> {
+    let half = a.head_dim / 2;
+    let rope = &a.rotary_emb;
+    with_f32(&rope.cos_cache, |cos| {
+        with_f32(&rope.sin_cache, |sin| {
+            let (Some(cos), Some(sin)) = (
+                cos.get(pos * half..(pos + 1) * half),
+                sin.get(pos * half..(pos + 1) * half),
+            ) else {
+                candle_core::bail!("position {} is past the RoPE cache", pos);
+            };
+            for head in x.chunks_exact_mut(a.head_dim) {
+                let (x1, x2) = head.split_at_mut(half);
+                for (((a, b), &c), &s) in x1.iter_mut().zip(x2.iter_mut()).zip(cos).zip(sin) {
+                    (*a, *b) = (*a * c - *b * s, *a * s + *b * c);
+                }
+            }
+            Ok(())
+        })?
+    })?
+}
+
+/// out = W x for a dense row-major W [out.len(), x.len()]
+fn dense_into(x: &[f32], w: &[f32], out: &mut [f32]) -> Result<()> {
+    if w.len() != x.len() * out.len() {
+        candle_core::bail!(
+            "DecodeWorkspace: dense weight has {} values, expected {}x{}",
+            w.len(),
+            out.len(),
+            x.len()
+        );
+    }
+    out.par_iter_mut()
+        .zip(w.par_chunks(x.len()))
+        .for_each(|(o, row)| *o = dot(row, x));
+    Ok(())
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn add_into(x: &mut [f32], y: &[f32]) {
+    x.iter_mut().zip(y).for_each(|(x, y)| *x += y);
+}
+
+/// Run `f` on the contiguous f32 CPU data of `t` (no copy)
+fn with_f32<R>(t: &Tensor, f: impl FnOnce(&[f32]) -> R) -> Result<R> {
+    let (storage, layout) = t.storage_and_layout();
+    let candle_core::Storage::Cpu(storage) = &*storage else {
+        candle_core::bail!("DecodeWorkspace: tensor is not on the CPU");
+    };
+    let Some((start, end)) = layout.contiguous_offsets() else {
+        candle_core::bail!("DecodeWorkspace: tensor is not contiguous");
+    };
+    Ok(f(&storage.as_slice::<f32>()?[start..end]))
+}
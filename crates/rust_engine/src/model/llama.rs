@@ -1,13 +1,19 @@
 //! BitLlama and Llama - Full model implementation
 
-use candle_core::{DType, Device, Module, Result, Tensor};
+use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::VarBuilder;
 // use fs2::FileExt; // Implicitly used? Or compiler bug. Keeping commented to silence warning.
+use std::borrow::Cow;
 use std::path::Path;
 use tokenizers::Tokenizer;
 
-use crate::layers::RMSNorm;
-use crate::model::{BitLlamaBlock, BitLlamaConfig};
+use crate::kernels::autotune;
+use crate::kernels::cpu::ActivationQuant;
+use crate::layers::{RMSNorm, TableQuant, VocabTable};
+use crate::model::adapters::AdapterRegistry;
+use crate::model::bitt::SECTION_SOUL;
+use crate::model::{BitLlamaBlock, BitLlamaConfig, BittFile, DecodeWorkspace};
+use crate::runtime::{EngineRuntime, RuntimeConfig};
 
 /// Epsilon for RMSNorm
 const RMS_NORM_EPS: f64 = 1e-5;
@@ -17,10 +23,12 @@ const TEMP_MIN: f64 = 1e-6;
 
 /// BitLlama model with embedding, layers, and LM head
 pub struct BitLlama {
-    pub embedding: candle_nn::Embedding,
+    /// Token embeddings ([vocab, hidden], dense or quantized, `config.embedding_quant`)
+    pub embedding: VocabTable,
     pub layers: Vec<BitLlamaBlock>,
     pub norm: RMSNorm,
-    pub lm_head: candle_nn::Linear,
+    /// Output projection ([vocab, hidden], dense or quantized, `config.lm_head_quant`)
+    pub lm_head: VocabTable,
     pub kv_caches: Vec<Option<crate::layers::KVCache>>,
     pub current_pos: usize,
     #[allow(dead_code)]
@@ -81,25 +89,23 @@ impl BitLlama {
             io_device
         };
 
+        // Embeddings, final norm and lm_head in the storage dtype (`layers::precision`)
+        let io_vb = vb.to_dtype(cfg.storage_dtype.dtype());
+
         // Support both "model.embed_tokens" (HF) and "embed" (BitLlama Legacy)
-        let embedding_raw =
-            candle_nn::embedding(cfg.vocab_size, cfg.hidden_dim, vb.pp("model.embed_tokens"))
-                .or_else(|_| {
-                    candle_nn::embedding(cfg.vocab_size, cfg.hidden_dim, vb.pp("embed"))
-                })?;
+        let embedding_raw = candle_nn::embedding(
+            cfg.vocab_size,
+            cfg.hidden_dim,
+            io_vb.pp("model.embed_tokens"),
+        )
+        .or_else(|_| candle_nn::embedding(cfg.vocab_size, cfg.hidden_dim, io_vb.pp("embed")))?;
 
         // [Plan B] Explicit Mmap Detachment for Embedding
         // If on CPU, we must Deep Copy. If on GPU, to_device copies automatically.
         let embedding = if io_device.is_cpu() {
-            // Flatten 2D embedding [vocab, hidden] to 1D, then reshape
-            let data = embedding_raw.embeddings().flatten_all()?.to_vec1::<f32>()?;
-            let w = Tensor::from_vec(data, (cfg.vocab_size, cfg.hidden_dim), io_device)?;
-            candle_nn::Embedding::new(w, cfg.hidden_dim)
+            VocabTable::Dense(embedding_raw.embeddings().copy()?)
         } else {
-            candle_nn::Embedding::new(
-                embedding_raw.embeddings().to_device(io_device)?,
-                cfg.hidden_dim,
-            )
+            VocabTable::Dense(embedding_raw.embeddings().to_device(io_device)?)
         };
 
         let mut layers = Vec::new();
@@ -123,30 +129,35 @@ impl BitLlama {
         }
 
         // Support "model.norm" (HF) and "norm_f" (Legacy)
-        let norm = RMSNorm::load(cfg.hidden_dim, RMS_NORM_EPS, vb.pp("model.norm"), io_device)
-            .or_else(|_| RMSNorm::load(cfg.hidden_dim, RMS_NORM_EPS, vb.pp("norm_f"), io_device))?;
+        let norm = RMSNorm::load(
+            cfg.hidden_dim,
+            RMS_NORM_EPS,
+            io_vb.pp("model.norm"),
+            io_device,
+        )
+        .or_else(|_| RMSNorm::load(cfg.hidden_dim, RMS_NORM_EPS, io_vb.pp("norm_f"), io_device))?;
 
         // Load LM Head and move to lm_head_device
         let lm_head_raw =
-            candle_nn::linear_no_bias(cfg.hidden_dim, cfg.vocab_size, vb.pp("lm_head"))?;
+            candle_nn::linear_no_bias(cfg.hidden_dim, cfg.vocab_size, io_vb.pp("lm_head"))?;
 
         // [Hybrid Guard] Move LM Head with Deep Copy if CPU
         let lm_head = if lm_head_device.is_cpu() {
-            // Fix: Flatten 2D tensor to 1D before converting to vector
-            let data = lm_head_raw.weight().flatten_all()?.to_vec1::<f32>()?;
-            let w = Tensor::from_vec(data, (cfg.vocab_size, cfg.hidden_dim), lm_head_device)?;
-            candle_nn::Linear::new(w, None)
+            VocabTable::Dense(lm_head_raw.weight().copy()?)
         } else {
-            candle_nn::Linear::new(lm_head_raw.weight().to_device(lm_head_device)?, None)
+            VocabTable::Dense(lm_head_raw.weight().to_device(lm_head_device)?)
         };
 
-        Ok(Self {
+        let mut model = Self {
             embedding,
             layers,
             norm,
             lm_head,
             kv_caches: vec![
-                Some(crate::layers::KVCache::new(cfg.max_position_embeddings));
+                Some(crate::layers::KVCache::with_dtype(
+                    cfg.max_position_embeddings,
+                    cfg.kv_cache_dtype
+                ));
                 cfg.num_layers
             ],
             current_pos: 0,
@@ -154,32 +165,67 @@ impl BitLlama {
             gpu_device: if n_gpu > 0 { Some(main_device) } else { None },
             cpu_device,
             n_gpu,
-        })
+        };
+        let dense = cfg.dense_precision();
+        for (_name, _target, module) in model.linear_modules_mut() {
+            module.set_precision(dense)?;
+        }
+        Ok(model)
+    }
+
+    /// Token embeddings as F32 activations (the table may be stored in half precision or quantized)
+    pub(crate) fn embed(&self, x: &Tensor) -> Result<Tensor> {
+        self.embedding.embed(x)
+    }
+
+    /// Logits of the final hidden state (dense: the matmul in `config.lm_head_precision()`)
+    pub(crate) fn lm_head_forward(&self, h: &Tensor) -> Result<Tensor> {
+        self.lm_head.logits(h, self.config.lm_head_precision())
     }
 
     /// Helper to get zero states for TTT
     pub fn new_w_states(&self) -> Vec<Tensor> {
-        // TTT State size: [Hidden, Hidden]
+        // TTT State size: [1, 1, D_small, D_small] (batched like the [1, 1, Hidden] decode input)
         // If Attention, we don't need w_states (they are unused), but keep API consistent.
-        let device = self.embedding.embeddings().device();
-        let dim = self.config.hidden_dim;
+        let device = self.embedding.device();
+        let d_small = self.config.hidden_dim / 4;
         // Optimization: Don't allocate if Attention?
         // But forward_one signature requires w_states slice.
         // Allocate zeros.
-        vec![Tensor::zeros((dim, dim), DType::F32, device).unwrap(); self.layers.len()]
+        let shape = (1, 1, d_small, d_small);
+        vec![Tensor::zeros(shape, DType::F32, device).unwrap(); self.layers.len()]
     }
 
+    /// Pack all ternary layers for the inference kernels
+    /// (CPU kernel layout from `config.activation_quant`, scales from `config.weight_scale`)
+    /// and quantize the embedding / lm_head tables (`config.embedding_quant`, `lm_head_quant`)
     pub fn precompute_packed(&mut self) -> Result<()> {
+        let options = self.config.pack_options();
+        if options.activations == ActivationQuant::Auto {
+            autotune::configure(self.config.autotune, None);
+        }
         for layer in self.layers.iter_mut() {
-            layer.precompute_packed()?;
+            layer.precompute_packed(options)?;
         }
+        self.quantize_tables(self.config.embedding_quant, self.config.lm_head_quant)
+    }
+
+    /// Store the embedding table and lm_head as `embedding` / `lm_head`
+    /// (quantized tables move to the CPU; going back to dense needs a reload)
+    pub fn quantize_tables(&mut self, embedding: TableQuant, lm_head: TableQuant) -> Result<()> {
+        let options = self.config.pack_options();
+        self.embedding = self.embedding.quantize(embedding, options)?;
+        self.lm_head = self.lm_head.quantize(lm_head, options)?;
+        self.config.embedding_quant = embedding;
+        self.config.lm_head_quant = lm_head;
         Ok(())
     }
 
     pub fn reset_kv_cache(&mut self) {
         self.kv_caches = vec![
-            Some(crate::layers::KVCache::new(
-                self.config.max_position_embeddings
+            Some(crate::layers::KVCache::with_dtype(
+                self.config.max_position_embeddings,
+                self.config.kv_cache_dtype
             ));
             self.layers.len()
         ];
@@ -205,7 +251,7 @@ impl BitLlama {
         } else {
             x.clone()
         };
-        let mut h = self.embedding.forward(&x)?;
+        let mut h = self.embed(&x)?;
 
         for (i, layer) in self.layers.iter().enumerate() {
             // [Hybrid Fix] Select device based on layer index and stored devices
@@ -256,14 +302,14 @@ impl BitLlama {
         let h_norm = self.norm.forward(&h)?;
 
         // [Hybrid Fix] Ensure input to lm_head is on correct device (may be CPU when lm_head_cpu=true)
-        let lm_head_device = self.lm_head.weight().device();
+        let lm_head_device = self.lm_head.device();
         let h_norm = if h_norm.device().same_device(lm_head_device) {
             h_norm
         } else {
             h_norm.to_device(lm_head_device)?
         };
 
-        let logits = self.lm_head.forward(&h_norm)?;
+        let logits = self.lm_head_forward(&h_norm)?;
 
         // Advance Position
         self.current_pos += 1;
@@ -278,15 +324,37 @@ impl BitLlama {
         w_states: &mut [Tensor],
         chunk_size: usize,
     ) -> Result<Tensor> {
-        let mut h = self.embedding.forward(x)?;
+        Ok(self.forward_chunkwise_with_aux(x, w_states, chunk_size)?.0)
+    }
+
+    /// Forward chunkwise, also returning the summed MoE load-balancing loss.
+    /// The loss is None when no block uses an MoE MLP.
+    pub fn forward_chunkwise_with_aux(
+        &self,
+        x: &Tensor,
+        w_states: &mut [Tensor],
+        chunk_size: usize,
+    ) -> Result<(Tensor, Option<Tensor>)> {
+        let mut h = self.embed(x)?;
+        let mut aux_total: Option<Tensor> = None;
 
         for (i, layer) in self.layers.iter().enumerate() {
             let w_state = &w_states[i];
             // Chunkwise usually implies TTT or specific training mode.
             // Attention implementation of chunkwise is limited in block.rs
-            let (h_new, w_new) = layer.forward_chunkwise(&h, w_state, chunk_size)?;
+            let (h_new, w_new, aux) = layer.forward_chunkwise_with_aux(&h, w_state, chunk_size)?;
             w_states[i] = w_new;
             h = h_new;
+
+            if let Some(aux) = aux {
+                aux_total = Some(match aux_total {
+                    Some(acc) => {
+                        let aux = aux.to_device(acc.device())?;
+                        (acc + aux)?
+                    }
+                    None => aux,
+                });
+            }
         }
 
         // [Hybrid Fix] Ensure input to Final Norm is on the correct device
@@ -300,24 +368,24 @@ impl BitLlama {
         let h_norm = self.norm.forward(&h)?;
 
         // [Hybrid Fix] Ensure input to lm_head is on correct device (may be CPU when lm_head_cpu=true)
-        let lm_head_device = self.lm_head.weight().device();
+        let lm_head_device = self.lm_head.device();
         let h_norm = if h_norm.device().same_device(lm_head_device) {
             h_norm
         } else {
             h_norm.to_device(lm_head_device)?
         };
 
-        let logits = self.lm_head.forward(&h_norm)?;
-        Ok(logits)
+        let logits = self.lm_head_forward(&h_norm)?;
+        Ok((logits, aux_total))
     }
 
     /// Helper for Python to check weights
     pub fn collect_tensors(&self) -> std::collections::HashMap<String, Tensor> {
         let mut tensors = std::collections::HashMap::new();
-        tensors.insert(
-            "embed.weight".to_string(),
-            self.embedding.embeddings().clone(),
-        );
+        // Quantized tables are saved dequantized
+        if let Ok(embedding) = self.embedding.to_dense() {
+            tensors.insert("embed.weight".to_string(), embedding);
+        }
 
         for (i, layer) in self.layers.iter().enumerate() {
             let prefix = format!("layers.{}", i);
@@ -331,7 +399,7 @@ impl BitLlama {
                 if let Some(legacy) = &l.legacy_linear {
                     Some(legacy.weight.clone())
                 } else {
-                    l.reconstructed_weight.clone() // Return reconstructed weight if legacy not found
+                    l.effective_weight().ok() // Dense view of multi-base / reconstructed weights
                 }
             };
 
@@ -365,19 +433,44 @@ impl BitLlama {
                 layer.norm2.weight.clone(),
             );
 
-            if let Some(w) = get_weight(&layer.mlp.w1) {
-                tensors.insert(format!("{}.mlp.gate_proj.weight", prefix), w);
-            }
-            if let Some(w) = get_weight(&layer.mlp.w2) {
-                tensors.insert(format!("{}.mlp.down_proj.weight", prefix), w);
-            }
-            if let Some(w) = get_weight(&layer.mlp.w3) {
-                tensors.insert(format!("{}.mlp.up_proj.weight", prefix), w);
+            match &layer.mlp {
+                crate::model::block::MlpDispatch::SwiGLU(mlp) => {
+                    if let Some(w) = get_weight(&mlp.w1) {
+                        tensors.insert(format!("{}.mlp.gate_proj.weight", prefix), w);
+                    }
+                    if let Some(w) = get_weight(&mlp.w2) {
+                        tensors.insert(format!("{}.mlp.down_proj.weight", prefix), w);
+                    }
+                    if let Some(w) = get_weight(&mlp.w3) {
+                        tensors.insert(format!("{}.mlp.up_proj.weight", prefix), w);
+                    }
+                }
+                crate::model::block::MlpDispatch::MoE(moe) => {
+                    let moe_prefix = format!("{}.block_sparse_moe", prefix);
+                    tensors.insert(
+                        format!("{}.gate.weight", moe_prefix),
+                        moe.gate.weight().clone(),
+                    );
+                    for (e, expert) in moe.experts.iter().enumerate() {
+                        let expert_prefix = format!("{}.experts.{}", moe_prefix, e);
+                        if let Some(w) = get_weight(&expert.w1) {
+                            tensors.insert(format!("{}.w1.weight", expert_prefix), w);
+                        }
+                        if let Some(w) = get_weight(&expert.w2) {
+                            tensors.insert(format!("{}.w2.weight", expert_prefix), w);
+                        }
+                        if let Some(w) = get_weight(&expert.w3) {
+                            tensors.insert(format!("{}.w3.weight", expert_prefix), w);
+                        }
+                    }
+                }
             }
         }
 
         tensors.insert("norm_f.weight".to_string(), self.norm.weight.clone());
-        tensors.insert("lm_head.weight".to_string(), self.lm_head.weight().clone());
+        if let Ok(lm_head) = self.lm_head.to_dense() {
+            tensors.insert("lm_head.weight".to_string(), lm_head);
+        }
 
         tensors
     }
@@ -393,6 +486,12 @@ pub struct Llama {
     pub _lock_file: Option<std::fs::File>,
     /// Accumulated experience (Token Count) - "Soul Level"
     pub soul_level: u64,
+    /// Allocation-free CPU decode of the current session (None: `forward_one`)
+    pub workspace: Option<DecodeWorkspace>,
+    /// Named LoRA adapters selectable per generation
+    pub adapters: AdapterRegistry,
+    /// Worker pools for prefill and decode (default: rayon's global pool)
+    pub runtime: EngineRuntime,
 }
 
 impl Llama {
@@ -401,8 +500,6 @@ impl Llama {
         tokenizer_path: P,
         config: BitLlamaConfig,
     ) -> Result<Self> {
-        let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
-
         // Load Tokenizer
         let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(candle_core::Error::wrap)?;
 
@@ -411,25 +508,53 @@ impl Llama {
         let file = std::fs::File::open(&model_path)?;
         // fs2::FileExt::lock_shared(&file)?; // Optional: file locking
 
-        let vb =
-            unsafe { VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &device)? };
+        // Plain or packed checkpoint (packed weights stay memory-mapped)
+        let model = BitLlama::load_checkpoint(config, &model_path)?;
+        Ok(Self::from_parts(model, tokenizer, file))
+    }
 
-        let model = BitLlama::load(config, vb)?;
-        let w_states = model.new_w_states();
+    /// Load a .bitt container: config and tokenizer from its header, weights
+    /// read from the mapped file (see `BittFile`), `soul` section restored
+    pub fn load_bitt<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref())?;
+        let bitt = BittFile::open(path)?;
+        let model = bitt.load_model(bitt.config)?;
+        let soul = bitt.section(SECTION_SOUL).map(<[u8]>::to_vec);
+        let mut llama = Self::from_parts(model, bitt.tokenizer, file);
+        if let Some(soul) = soul {
+            llama.load_memory_bytes(&soul)?;
+        }
+        Ok(llama)
+    }
 
-        Ok(Self {
+    fn from_parts(model: BitLlama, tokenizer: Tokenizer, file: std::fs::File) -> Self {
+        let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+        let w_states = model.new_w_states();
+        Self {
             model,
             tokenizer,
             device,
             w_states,
             _lock_file: Some(file),
             soul_level: 0,
-        })
+            workspace: None,
+            adapters: AdapterRegistry::default(),
+            runtime: EngineRuntime::default(),
+        }
     }
 
-    /// Load model automatically from directory (or file path)
+    /// Run this instance on its own worker pools (see `RuntimeConfig`)
+    pub fn set_runtime(&mut self, config: &RuntimeConfig) -> Result<()> {
+        self.runtime = config.build()?;
+        Ok(())
+    }
+
+    /// Load model automatically from directory (or file path, .bitt included)
     pub fn load_auto<P: AsRef<Path>>(input_path: P) -> Result<Self> {
         let path = input_path.as_ref();
+        if path.is_file() && BittFile::is_bitt(path) {
+            return Self::load_bitt(path);
+        }
         let dir = if path.is_file() {
             path.parent().unwrap_or(path)
         } else {
@@ -445,7 +570,15 @@ impl Llama {
             // Check for weight.safetensors or others
             model_path = dir.join("weight.safetensors");
             if !model_path.exists() {
-                candle_core::bail!("No model.safetensors found in {:?}", dir);
+                // A directory holding only a .bitt container
+                let bitt = std::fs::read_dir(dir)?
+                    .flatten()
+                    .map(|e| e.path())
+                    .find(|p| p.extension().is_some_and(|x| x == "bitt"));
+                if let Some(bitt) = bitt {
+                    return Self::load_bitt(bitt);
+                }
+                candle_core::bail!("No model.safetensors (or .bitt) found in {:?}", dir);
             }
         }
 
@@ -461,10 +594,9 @@ impl Llama {
         self.model.reset_kv_cache();
         self.soul_level = 0;
         // Reset/Re-init TTT w_states
-        let device = self.device.clone();
-        let dim = self.model.config.hidden_dim;
-        self.w_states =
-            vec![Tensor::zeros((dim, dim), DType::F32, &device)?; self.model.layers.len()];
+        self.w_states = self.model.new_w_states();
+        // Recreated from the fresh state on the next step
+        self.workspace = None;
         Ok(())
     }
 
@@ -490,41 +622,38 @@ impl Llama {
         let mut token_ids = tokens.get_ids().to_vec();
 
         let mut output_str = String::from(prompt);
+        let runtime = self.runtime.clone();
+        self.release_workspace_for_adapters()?;
 
         // 1. Prefill
-        for &id in &token_ids {
-            let input = Tensor::new(&[id], &self.device)?.unsqueeze(0)?;
-            let _ = self.model.forward_one(&input, &mut self.w_states)?;
-        }
+        runtime.prefill(|| token_ids.iter().try_for_each(|&id| self.step(id).map(drop)))?;
 
         // 2. Generate
         let mut last_token = *token_ids.last().unwrap();
         for _ in 0..max_tokens {
-            let input = Tensor::new(&[last_token], &self.device)?.unsqueeze(0)?;
-            let logits = self.model.forward_one(&input, &mut self.w_states)?;
-
-            // Sampling with Temp
-            let logits_v: Vec<f32> = logits.squeeze(0)?.squeeze(0)?.to_vec1()?;
-            let next_token = if temp < TEMP_MIN {
-                // Greedy
-                logits_v
-                    .iter()
-                    .enumerate()
-                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-                    .map(|(i, _)| i as u32)
-                    .unwrap()
-            } else {
-                // Multinomial (Simple implementation or use rand/candle-nn sampler)
-                // For now, let's stick to Greedy-ish or simple Softmax
-                let _prs = candle_nn::ops::softmax(&logits.squeeze(0)?.squeeze(0)?, 0)?;
-                // Mock sampling or just Greedy for now for stability
-                logits_v
-                    .iter()
-                    .enumerate()
-                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-                    .map(|(i, _)| i as u32)
-                    .unwrap()
-            };
+            let next_token = runtime.decode(|| -> Result<u32> {
+                let logits_v = self.step(last_token)?;
+
+                // Sampling with Temp
+                Ok(if temp < TEMP_MIN {
+                    // Greedy
+                    logits_v
+                        .iter()
+                        .enumerate()
+                        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                        .map(|(i, _)| i as u32)
+                        .unwrap()
+                } else {
+                    // Multinomial (Simple implementation or use rand/candle-nn sampler)
+                    // Mock sampling or just Greedy for now for stability
+                    logits_v
+                        .iter()
+                        .enumerate()
+                        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                        .map(|(i, _)| i as u32)
+                        .unwrap()
+                })
+            })?;
 
             token_ids.push(next_token);
             last_token = next_token;
@@ -548,9 +677,69 @@ impl Llama {
                 break;
             }
         }
+        self.sync_w_states()?;
         Ok(output_str)
     }
 
+    /// Feed one token and return its logits [Vocab]. CPU models run through
+    /// the allocation-free `DecodeWorkspace` (created on the first step of a
+    /// session) for `decode_window` tokens; longer sessions, other models or
+    /// models it can't run use `forward_one`.
+    fn step(&mut self, id: u32) -> Result<Cow<'_, [f32]>> {
+        let window_full = self
+            .workspace
+            .as_ref()
+            .is_some_and(|ws| ws.position() >= ws.max_len());
+        if window_full {
+            // Continue in the kv_cache_dtype caches
+            let ws = self.workspace.take().unwrap();
+            ws.hand_over(&mut self.model, &mut self.w_states)?;
+        }
+        if self.workspace.is_none() && self.model.current_pos == 0 {
+            let max_len = self.model.decode_window();
+            match DecodeWorkspace::new(&self.model, max_len) {
+                Ok(mut ws) => {
+                    ws.load_w_states(&self.w_states)?;
+                    self.workspace = Some(ws);
+                }
+                Err(e) => tracing::debug!("decode workspace unavailable: {}", e),
+            }
+        }
+        if let Some(ws) = &mut self.workspace {
+            return Ok(Cow::Borrowed(self.model.forward_one_into(id, ws)?));
+        }
+        let input = Tensor::new(&[id], &self.device)?.unsqueeze(0)?;
+        let logits = self.model.forward_one(&input, &mut self.w_states)?;
+        Ok(Cow::Owned(logits.flatten_all()?.to_vec1()?))
+    }
+
+    /// Unmerged adapters (`attach_lora`, per-request adapters) run on
+    /// `forward_one` only: continue an active workspace session there.
+    /// Checked once per call, adapters can't change while it decodes.
+    fn release_workspace_for_adapters(&mut self) -> Result<()> {
+        if self.workspace.is_none() {
+            return Ok(());
+        }
+        let adapters = self
+            .model
+            .linear_modules()
+            .iter()
+            .any(|(_, _, m)| m.lora.is_some() || !m.lora_rows.is_empty());
+        if adapters {
+            let ws = self.workspace.take().unwrap();
+            ws.hand_over(&mut self.model, &mut self.w_states)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the workspace's TTT states into `w_states` (for `save_memory`)
+    fn sync_w_states(&mut self) -> Result<()> {
+        if let Some(ws) = &self.workspace {
+            ws.store_w_states(&mut self.w_states)?;
+        }
+        Ok(())
+    }
+
     // TTT Training Update (Learn)
     pub fn learn(&mut self, text: &str) -> Result<()> {
         let tokens = self
@@ -563,12 +752,16 @@ impl Llama {
         // Ideally use forward_chunkwise for speed.
 
         // Simple forward pass to update w_states
-        for &id in &token_ids {
-            let input = Tensor::new(&[id], &self.device)?.unsqueeze(0)?;
-            let _ = self.model.forward_one(&input, &mut self.w_states)?;
-            self.soul_level += 1;
-        }
-        Ok(())
+        let runtime = self.runtime.clone();
+        self.release_workspace_for_adapters()?;
+        runtime.prefill(|| {
+            token_ids.iter().try_for_each(|&id| -> Result<()> {
+                self.step(id)?;
+                self.soul_level += 1;
+                Ok(())
+            })
+        })?;
+        self.sync_w_states()
     }
 
     // Memory Persistence
@@ -592,15 +785,31 @@ impl Llama {
 
     pub fn load_memory<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
         let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[path], DType::F32, &self.device)? };
+        self.restore_w_states(vb)
+    }
+
+    /// `load_memory` from the bytes of a memory file (e.g. a .bitt `soul` section)
+    pub fn load_memory_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let vb = VarBuilder::from_slice_safetensors(bytes, DType::F32, &self.device)?;
+        self.restore_w_states(vb)
+    }
 
+    fn restore_w_states(&mut self, vb: VarBuilder) -> Result<()> {
+        let d_small = self.model.config.hidden_dim / 4;
         for i in 0..self.w_states.len() {
-            if let Ok(t) = vb.get(
-                (self.model.config.hidden_dim, self.model.config.hidden_dim),
-                &format!("layer_{}", i),
-            ) {
+            // Older memory files hold [D_small, D_small] states
+            let name = format!("layer_{}", i);
+            let state = vb.get((1, 1, d_small, d_small), &name).or_else(|_| {
+                vb.get((d_small, d_small), &name)?
+                    .reshape((1, 1, d_small, d_small))
+            });
+            if let Ok(t) = state {
                 self.w_states[i] = t;
             }
         }
+        if let Some(ws) = &mut self.workspace {
+            ws.load_w_states(&self.w_states)?;
+        }
         // Restore Soul Level if present
         // if let Ok(sl) = vb.get((1,), "soul_level") {
         //     let v: Vec<f32> = sl.to_vec1()?;
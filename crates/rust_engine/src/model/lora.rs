@@ -0,0 +1,262 @@
+//! LoRA management for BitLlama - attach, save/load adapter-only files, merge
+
+use candle_core::{Result, Tensor};
+use candle_nn::VarBuilder;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::layers::{AdaptiveBitLinear, LoraAdapter, LoraConfig, LoraTarget};
+use crate::model::block::{LayerDispatch, MlpDispatch};
+use crate::model::BitLlama;
+
+/// Sidecar holding the adapter's `LoraConfig` (`adapter.safetensors` -> `adapter.json`)
+pub fn lora_config_path(adapter_path: &Path) -> PathBuf {
+    adapter_path.with_extension("json")
+}
+
+/// Enumerate adaptable projections as (tensor prefix, target family, layer).
+/// Shared by the `&self` and `&mut self` accessors.
+macro_rules! linear_modules {
+    ($self:expr, $iter:ident $(, $mut:tt)?) => {{
+        let mut modules = Vec::new();
+        for (i, layer) in $self.layers.$iter().enumerate() {
+            let prefix = format!("layers.{}", i);
+            match &$($mut)? layer.core {
+                LayerDispatch::TTT(ttt) => {
+                    let ttt = &$($mut)? **ttt;
+                    modules.push((
+                        format!("{}.ttt.down", prefix),
+                        LoraTarget::Ttt,
+                        &$($mut)? ttt.proj_down,
+                    ));
+                    modules.push((
+                        format!("{}.ttt.up", prefix),
+                        LoraTarget::Ttt,
+                        &$($mut)? ttt.proj_up,
+                    ));
+                }
+                LayerDispatch::Attention(attn) => {
+                    let attn = &$($mut)? **attn;
+                    let attn_prefix = format!("{}.self_attn", prefix);
+                    modules.push((
+                        format!("{}.q_proj", attn_prefix),
+                        LoraTarget::Q,
+                        &$($mut)? attn.q_proj,
+                    ));
+                    modules.push((
+                        format!("{}.k_proj", attn_prefix),
+                        LoraTarget::K,
+                        &$($mut)? attn.k_proj,
+                    ));
+                    modules.push((
+                        format!("{}.v_proj", attn_prefix),
+                        LoraTarget::V,
+                        &$($mut)? attn.v_proj,
+                    ));
+                    modules.push((
+                        format!("{}.o_proj", attn_prefix),
+                        LoraTarget::O,
+                        &$($mut)? attn.o_proj,
+                    ));
+                }
+            }
+            match &$($mut)? layer.mlp {
+                MlpDispatch::SwiGLU(mlp) => {
+                    let mlp = &$($mut)? **mlp;
+                    let mlp_prefix = format!("{}.mlp", prefix);
+                    modules.push((
+                        format!("{}.gate_proj", mlp_prefix),
+                        LoraTarget::Gate,
+                        &$($mut)? mlp.w1,
+                    ));
+                    modules.push((
+                        format!("{}.down_proj", mlp_prefix),
+                        LoraTarget::Down,
+                        &$($mut)? mlp.w2,
+                    ));
+                    modules.push((
+                        format!("{}.up_proj", mlp_prefix),
+                        LoraTarget::Up,
+                        &$($mut)? mlp.w3,
+                    ));
+                }
+                MlpDispatch::MoE(moe) => {
+                    for (e, expert) in moe.experts.$iter().enumerate() {
+                        let expert_prefix = format!("{}.block_sparse_moe.experts.{}", prefix, e);
+                        modules.push((
+                            format!("{}.w1", expert_prefix),
+                            LoraTarget::Gate,
+                            &$($mut)? expert.w1,
+                        ));
+                        modules.push((
+                            format!("{}.w2", expert_prefix),
+                            LoraTarget::Down,
+                            &$($mut)? expert.w2,
+                        ));
+                        modules.push((
+                            format!("{}.w3", expert_prefix),
+                            LoraTarget::Up,
+                            &$($mut)? expert.w3,
+                        ));
+                    }
+                }
+            }
+        }
+        modules
+    }};
+}
+
+impl BitLlama {
+    /// All adaptable projections as (tensor prefix, target family, layer)
+    pub fn linear_modules(&self) -> Vec<(String, LoraTarget, &AdaptiveBitLinear)> {
+        linear_modules!(self, iter)
+    }
+
+    /// Mutable variant of [`BitLlama::linear_modules`]
+    pub fn linear_modules_mut(&mut self) -> Vec<(String, LoraTarget, &mut AdaptiveBitLinear)> {
+        linear_modules!(self, iter_mut, mut)
+    }
+
+    /// Create fresh trainable adapters on every targeted projection.
+    ///
+    /// Pass a VarBuilder backed by a dedicated `VarMap` so that the optimizer
+    /// only sees (and only saves) adapter variables. Returns the number of
+    /// adapted modules.
+    pub fn attach_lora(&mut self, cfg: &LoraConfig, vb: VarBuilder) -> Result<usize> {
+        if cfg.rank == 0 {
+            candle_core::bail!("LoRA rank must be > 0");
+        }
+        let mut count = 0;
+        for (name, target, module) in self.linear_modules_mut() {
+            if !cfg.targets.contains(&target) {
+                continue;
+            }
+            let adapter =
+                LoraAdapter::init(module.in_features, module.out_features, cfg, vb.pp(&name))?;
+            module.lora = Some(adapter);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Load an adapter-only safetensors file (and its JSON sidecar) onto the
+    /// matching projections. Returns the adapter config.
+    pub fn load_lora<P: AsRef<Path>>(&mut self, path: P) -> Result<LoraConfig> {
+        let path = path.as_ref();
+        let tensors = candle_core::safetensors::load(path, &candle_core::Device::Cpu)?;
+        let cfg = read_lora_config(path, &tensors)?;
+        let adapters = adapters_from_tensors(&tensors, &cfg)?;
+        let mut applied = 0;
+        for (name, _target, module) in self.linear_modules_mut() {
+            if let Some(adapter) = adapters.get(&name) {
+                adapter.check_shape(&name, module.in_features, module.out_features)?;
+                module.lora = Some(adapter.to_device(module.device())?);
+                applied += 1;
+            }
+        }
+        if applied != adapters.len() {
+            candle_core::bail!(
+                "LoRA adapter {:?} has {} modules but only {} match this model",
+                path,
+                adapters.len(),
+                applied
+            );
+        }
+        Ok(cfg)
+    }
+
+    /// Save only the adapter weights (plus the JSON sidecar)
+    pub fn save_lora<P: AsRef<Path>>(&self, path: P, cfg: &LoraConfig) -> Result<()> {
+        let path = path.as_ref();
+        let mut tensors: HashMap<String, Tensor> = HashMap::new();
+        for (name, _target, module) in self.linear_modules() {
+            if let Some(adapter) = &module.lora {
+                tensors.insert(format!("{}.lora_A.weight", name), adapter.a.clone());
+                tensors.insert(format!("{}.lora_B.weight", name), adapter.b.clone());
+            }
+        }
+        if tensors.is_empty() {
+            candle_core::bail!("No LoRA adapters attached");
+        }
+        candle_core::safetensors::save(&tensors, path)?;
+        write_lora_config(path, cfg)
+    }
+
+    /// Fold all attached adapters into the reconstructed weights
+    pub fn merge_lora(&mut self) -> Result<()> {
+        for (_name, _target, module) in self.linear_modules_mut() {
+            module.merge_lora()?;
+        }
+        Ok(())
+    }
+
+    /// Detach all adapters (base weights are untouched)
+    pub fn clear_lora(&mut self) {
+        for (_name, _target, module) in self.linear_modules_mut() {
+            module.lora = None;
+        }
+    }
+}
+
+/// Write the `LoraConfig` sidecar for an adapter file
+pub fn write_lora_config(adapter_path: &Path, cfg: &LoraConfig) -> Result<()> {
+    let json = serde_json::to_string_pretty(cfg).map_err(candle_core::Error::wrap)?;
+    std::fs::write(lora_config_path(adapter_path), json)?;
+    Ok(())
+}
+
+/// Read the sidecar config, or infer rank from the tensors (alpha = rank) if missing
+pub fn read_lora_config(
+    adapter_path: &Path,
+    tensors: &HashMap<String, Tensor>,
+) -> Result<LoraConfig> {
+    let cfg_path = lora_config_path(adapter_path);
+    if cfg_path.exists() {
+        let s = std::fs::read_to_string(&cfg_path)?;
+        return serde_json::from_str(&s).map_err(candle_core::Error::wrap);
+    }
+    let rank = tensors
+        .iter()
+        .find(|(k, _)| k.ends_with(".lora_A.weight"))
+        .map(|(_, t)| t.dims()[0])
+        .ok_or_else(|| {
+            candle_core::Error::Msg(format!("{:?} has no LoRA tensors", adapter_path))
+        })?;
+    Ok(LoraConfig {
+        rank,
+        alpha: rank as f64,
+        targets: LoraTarget::ALL.to_vec(),
+    })
+}
+
+/// Group `{module}.lora_A.weight` / `{module}.lora_B.weight` pairs by module
+/// prefix (A/B ranks must match `cfg.rank`)
+pub fn adapters_from_tensors(
+    tensors: &HashMap<String, Tensor>,
+    cfg: &LoraConfig,
+) -> Result<HashMap<String, LoraAdapter>> {
+    let mut adapters = HashMap::new();
+    for (key, a) in tensors {
+        let Some(module) = key.strip_suffix(".lora_A.weight") else {
+            continue;
+        };
+        let b = tensors
+            .get(&format!("{}.lora_B.weight", module))
+            .ok_or_else(|| candle_core::Error::Msg(format!("Missing lora_B for {}", module)))?;
+        let rank = a.dims2()?.0;
+        if rank != cfg.rank {
+            candle_core::bail!(
+                "LoRA {}: A {:?} has rank {} but the adapter config says {}",
+                module,
+                a.dims(),
+                rank,
+                cfg.rank
+            );
+        }
+        adapters.insert(
+            module.to_string(),
+            LoraAdapter::new(a.clone(), b.clone(), cfg.scaling())?,
+        );
+    }
+    Ok(adapters)
+}
@@ -0,0 +1,248 @@
+//! Packed checkpoints - BitLinear weights stored as 2-bit codes + scales
+//!
+//! A packed checkpoint is a regular safetensors file in which every packed
+//! BitLinear `{prefix}.weight` (f32) is replaced by the tensors of
+//! `PackedTensor::to_tensors` (u8 codes, scales, optional kernel layouts).
+//! Loading maps the file once; the CPU kernels then read the codes in place,
+//! so nothing is re-quantized or copied at startup.
+
+use candle_core::{DType, Device, Result, Shape, Tensor};
+use candle_nn::VarBuilder;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::kernels::packing::{MappedBytes, PackedBytes, PackedTensor};
+use crate::model::{BitLlama, BitLlamaConfig, BittFile};
+
+/// Location of one tensor in the mapped file
+#[derive(Debug, Clone)]
+struct TensorEntry {
+    dtype: String,
+    range: Range<usize>,
+}
+
+/// Memory-mapped safetensors file giving zero-copy access to packed weights
+#[derive(Debug, Clone)]
+pub struct PackedCheckpoint {
+    map: Arc<Mmap>,
+    tensors: HashMap<String, TensorEntry>,
+}
+
+impl PackedCheckpoint {
+    /// Map a safetensors file and index its header
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref())?;
+        let map = unsafe { Mmap::map(&file)? };
+        let len = map.len();
+        Self::from_map(Arc::new(map), 0..len)
+            .map_err(|e| candle_core::Error::Msg(format!("{} ({:?})", e, path.as_ref())))
+    }
+
+    /// Index a safetensors file held at `body` of a mapping (e.g. a .bitt container)
+    pub fn from_map(map: Arc<Mmap>, body: Range<usize>) -> Result<Self> {
+        if body.end > map.len() || body.start > body.end {
+            candle_core::bail!(
+                "PackedCheckpoint: body {:?} outside a {} byte mapping",
+                body,
+                map.len()
+            );
+        }
+        let Some(header_len) = map
+            .get(body.start..body.start + 8)
+            .filter(|_| body.len() >= 8)
+        else {
+            candle_core::bail!("PackedCheckpoint: safetensors data is too short");
+        };
+        let header_len = u64::from_le_bytes(header_len.try_into().unwrap()) as usize;
+        let base = body.start + 8 + header_len;
+        if base > body.end {
+            candle_core::bail!("PackedCheckpoint: truncated header");
+        }
+        let header: HashMap<String, serde_json::Value> =
+            serde_json::from_slice(&map[body.start + 8..base]).map_err(candle_core::Error::wrap)?;
+
+        let mut tensors = HashMap::new();
+        for (name, info) in header {
+            if name == "__metadata__" {
+                continue;
+            }
+            let dtype = info["dtype"].as_str().unwrap_or_default().to_string();
+            let (Some(start), Some(end)) = (
+                info["data_offsets"][0].as_u64(),
+                info["data_offsets"][1].as_u64(),
+            ) else {
+                candle_core::bail!("PackedCheckpoint: tensor {} has no data_offsets", name);
+            };
+            let range = base + start as usize..base + end as usize;
+            if range.end > body.end {
+                candle_core::bail!("PackedCheckpoint: tensor {} runs past the file end", name);
+            }
+            tensors.insert(name, TensorEntry { dtype, range });
+        }
+
+        Ok(Self { map, tensors })
+    }
+
+    /// Whether the file holds packed BitLinear weights
+    pub fn is_packed(&self) -> bool {
+        self.tensors.keys().any(|name| name.ends_with(".packed"))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tensors.contains_key(name)
+    }
+
+    fn entry(&self, name: &str, dtype: &str) -> Result<&TensorEntry> {
+        match self.tensors.get(name) {
+            Some(entry) if entry.dtype == dtype => Ok(entry),
+            Some(entry) => candle_core::bail!(
+                "PackedCheckpoint: {} is {} (expected {})",
+                name,
+                entry.dtype,
+                dtype
+            ),
+            None => candle_core::bail!("PackedCheckpoint: missing tensor {}", name),
+        }
+    }
+
+    /// Zero-copy view of a u8 tensor
+    pub fn bytes(&self, name: &str) -> Result<MappedBytes> {
+        let entry = self.entry(name, "U8")?;
+        MappedBytes::new(self.map.clone(), entry.range.clone())
+    }
+
+    /// Little-endian 4-byte values of a small tensor (scales, group sizes)
+    fn words(&self, name: &str, dtype: &str) -> Result<Vec<[u8; 4]>> {
+        let entry = self.entry(name, dtype)?;
+        Ok(self.map[entry.range.clone()]
+            .chunks_exact(4)
+            .map(|w| w.try_into().unwrap())
+            .collect())
+    }
+
+    /// Packed weight stored under `prefix`; codes and kernel layouts stay in the mapping
+    pub fn packed_tensor(&self, prefix: &str, shape: Shape) -> Result<PackedTensor> {
+        let data = self.bytes(&format!("{}.packed", prefix))?;
+        let scales = self
+            .words(&format!("{}.packed_scales", prefix), "F32")?
+            .into_iter()
+            .map(f32::from_le_bytes)
+            .collect();
+        let group = self
+            .words(&format!("{}.packed_group", prefix), "U32")?
+            .first()
+            .map_or(0, |w| u32::from_le_bytes(*w) as usize);
+
+        let mut packed = PackedTensor::from_parts(
+            PackedBytes::Mapped(data),
+            scales,
+            group,
+            shape,
+            &Device::Cpu,
+        )?;
+        // Kernels index the layouts without bounds checks: a truncated file or
+        // one packed with other options must fail here
+        let layout = |suffix: &str,
+                      len: fn(&PackedTensor) -> Result<usize>|
+         -> Result<Option<PackedBytes>> {
+            let name = format!("{}.{}", prefix, suffix);
+            if !self.contains(&name) {
+                return Ok(None);
+            }
+            let bytes = self.bytes(&name)?;
+            let expected = len(&packed).map_err(|e| {
+                candle_core::Error::Msg(format!("PackedCheckpoint: {}: {}", name, e))
+            })?;
+            if bytes.as_slice().len() != expected {
+                candle_core::bail!(
+                    "PackedCheckpoint: {} has {} bytes, expected {} for a {:?} weight",
+                    name,
+                    bytes.as_slice().len(),
+                    expected,
+                    packed.shape.dims()
+                );
+            }
+            Ok(Some(PackedBytes::Mapped(bytes)))
+        };
+        let int8_layout = layout("packed_int8", PackedTensor::int8_layout_len)?;
+        let lut_layout = layout("packed_lut", PackedTensor::lut_layout_len)?;
+        packed.int8_layout = int8_layout;
+        packed.lut_layout = lut_layout;
+        Ok(packed)
+    }
+}
+
+impl BitLlama {
+    /// Load a model from a safetensors file, plain or packed, or from the
+    /// weights of a .bitt container.
+    ///
+    /// Packed BitLinear weights are attached as views into the file mapping.
+    pub fn load_checkpoint<P: AsRef<Path>>(cfg: BitLlamaConfig, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if BittFile::is_bitt(path) {
+            return BittFile::open(path)?.load_model(cfg);
+        }
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[path], DType::F32, &Device::Cpu)? };
+        let mut model = Self::load(cfg, vb)?;
+        let checkpoint = PackedCheckpoint::open(path)?;
+        if checkpoint.is_packed() {
+            model.attach_packed(&checkpoint)?;
+        }
+        Ok(model)
+    }
+
+    /// `load_checkpoint` for a safetensors file held at `body` of a mapping
+    /// (the weights of a .bitt container)
+    pub fn load_mapped(cfg: BitLlamaConfig, map: Arc<Mmap>, body: Range<usize>) -> Result<Self> {
+        let checkpoint = PackedCheckpoint::from_map(map.clone(), body.clone())?;
+        let vb = VarBuilder::from_slice_safetensors(&map[body], DType::F32, &Device::Cpu)?;
+        let mut model = Self::load(cfg, vb)?;
+        if checkpoint.is_packed() {
+            model.attach_packed(&checkpoint)?;
+        }
+        Ok(model)
+    }
+
+    /// Attach the packed weights of `checkpoint` to the BitLinear layers loaded
+    /// without an f32 weight. Returns the number of attached layers.
+    pub fn attach_packed(&mut self, checkpoint: &PackedCheckpoint) -> Result<usize> {
+        let mut attached = 0;
+        for (name, _target, module) in self.linear_modules_mut() {
+            let Some(linear) = &mut module.legacy_linear else {
+                continue;
+            };
+            if !linear.prepacked {
+                continue;
+            }
+            let shape = Shape::from((linear.out_features, linear.in_features));
+            linear.packed_params = Some(checkpoint.packed_tensor(&name, shape)?);
+            attached += 1;
+        }
+        Ok(attached)
+    }
+
+    /// Save a packed checkpoint: every tensor of the model, with the BitLinear
+    /// weights as packed codes + scales (and kernel layouts) instead of f32.
+    ///
+    /// Call `precompute_packed` first; the packing options of the current
+    /// config are baked into the file. Layers without packed weights are
+    /// saved as f32.
+    pub fn save_packed<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut tensors: HashMap<String, Tensor> = self.collect_tensors();
+        for (name, _target, module) in self.linear_modules() {
+            let Some(linear) = &module.legacy_linear else {
+                continue;
+            };
+            // Layers that are never packed (e.g. attention) keep their f32 weight
+            let Some(packed) = &linear.packed_params else {
+                continue;
+            };
+            tensors.remove(&format!("{}.weight", name));
+            tensors.extend(packed.to_tensors(&name)?);
+        }
+        candle_core::safetensors::save(&tensors, path.as_ref())
+    }
+}
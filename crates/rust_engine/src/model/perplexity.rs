@@ -0,0 +1,99 @@
+//! Perplexity - next-token NLL of token sequences, and the table quant report
+
+use candle_core::{DType, Result, D};
+
+use crate::layers::TableQuant;
+use crate::model::{BitLlama, SequenceState};
+
+/// One (embedding, lm_head) storage in `BitLlama::table_quant_report`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableQuantReport {
+    pub embedding: TableQuant,
+    pub lm_head: TableQuant,
+    /// Bytes of both tables
+    pub bytes: usize,
+    pub perplexity: f32,
+    /// Perplexity relative to dense/dense (1.0 = no loss)
+    pub ratio: f32,
+}
+
+impl BitLlama {
+    /// exp(mean NLL) of every token after the first of each sequence
+    /// (sequences are decoded together, one token per step, from fresh states)
+    pub fn perplexity(&self, sequences: &[Vec<u32>]) -> Result<f32> {
+        let mut states = sequences
+            .iter()
+            .map(|_| self.new_sequence())
+            .collect::<Result<Vec<SequenceState>>>()?;
+        let steps = sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+        let (mut nll, mut count) = (0.0f64, 0usize);
+        for t in 0..steps.saturating_sub(1) {
+            let active: Vec<usize> = (0..sequences.len())
+                .filter(|&i| t + 1 < sequences[i].len())
+                .collect();
+            let tokens: Vec<u32> = active.iter().map(|&i| sequences[i][t]).collect();
+            let mut seqs: Vec<&mut SequenceState> = states
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| t + 1 < sequences[*i].len())
+                .map(|(_, s)| s)
+                .collect();
+            let logits = self.forward_batch(&tokens, &mut seqs)?;
+            let log_probs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?
+                .to_vec2::<f32>()?;
+            for (row, &i) in log_probs.iter().zip(&active) {
+                let target = sequences[i][t + 1] as usize;
+                let Some(&lp) = row.get(target) else {
+                    candle_core::bail!("token {} is out of the vocabulary", target);
+                };
+                nll -= lp as f64;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            candle_core::bail!("perplexity needs a sequence of at least 2 tokens");
+        }
+        Ok((nll / count as f64).exp() as f32)
+    }
+
+    /// Perplexity of `sequences` for every embedding x lm_head `TableQuant`
+    /// (needs dense tables; they are restored afterwards)
+    pub fn table_quant_report(&mut self, sequences: &[Vec<u32>]) -> Result<Vec<TableQuantReport>> {
+        let (embedding, lm_head) = (self.embedding.clone(), self.lm_head.clone());
+        let (embedding_quant, lm_head_quant) =
+            (self.config.embedding_quant, self.config.lm_head_quant);
+        if embedding.dense().is_none() || lm_head.dense().is_none() {
+            candle_core::bail!("table_quant_report needs the dense embedding and lm_head");
+        }
+        let mut reports = Vec::new();
+        let mut run = || -> Result<()> {
+            for e in TableQuant::ALL {
+                for l in TableQuant::ALL {
+                    self.embedding = embedding.clone();
+                    self.lm_head = lm_head.clone();
+                    self.quantize_tables(e, l)?;
+                    reports.push(TableQuantReport {
+                        embedding: e,
+                        lm_head: l,
+                        bytes: self.embedding.size_bytes() + self.lm_head.size_bytes(),
+                        perplexity: self.perplexity(sequences)?,
+                        ratio: 1.0,
+                    });
+                }
+            }
+            Ok(())
+        };
+        let result = run();
+        self.embedding = embedding;
+        self.lm_head = lm_head;
+        self.config.embedding_quant = embedding_quant;
+        self.config.lm_head_quant = lm_head_quant;
+        result?;
+        // ALL starts with Dense, so the first report is the dense baseline
+        let base = reports[0].perplexity;
+        for report in reports.iter_mut() {
+            report.ratio = report.perplexity / base;
+        }
+        Ok(reports)
+    }
+}
@@ -7,10 +7,12 @@ use candle_core::{DType, IndexOp, Tensor, Var};
 use pyo3::prelude::*;
 
 #[cfg(feature = "python")]
-use crate::model::{BitLlama, BitLlamaConfig};
+use crate::model::{BitLlama, BitLlamaConfig, BittFile};
 #[cfg(feature = "python")]
 use crate::optim::schedule_free::{ParamsScheduleFree, ScheduleFreeOptimizer};
 #[cfg(feature = "python")]
+use crate::runtime::{EngineRuntime, RuntimeConfig};
+#[cfg(feature = "python")]
 use candle_nn::VarMap;
 
 /// Python wrapper for BitLlama model (Inference)
@@ -19,17 +21,34 @@ use candle_nn::VarMap;
 pub struct PyBitLlama {
     inner: BitLlama,
     w_states: Vec<Tensor>,
+    runtime: EngineRuntime,
 }
 
 #[cfg(feature = "python")]
 #[pymethods]
 impl PyBitLlama {
     #[new]
-    #[pyo3(signature = (config, checkpoint_path, device=None))]
+    /// `checkpoint_path` is a safetensors file (plain or packed) or a .bitt
+    /// container; `config=None` takes the config embedded in a .bitt.
+    /// `threads`, `prefill_threads`, `decode_threads` and `pin_cores` give
+    /// this model its own worker pools (see `RuntimeConfig`)
+    #[pyo3(signature = (
+        config,
+        checkpoint_path,
+        device=None,
+        threads=None,
+        prefill_threads=None,
+        decode_threads=None,
+        pin_cores=None
+    ))]
     pub fn new(
-        config: BitLlamaConfig,
+        config: Option<BitLlamaConfig>,
         checkpoint_path: &str,
         device: Option<&str>,
+        threads: Option<usize>,
+        prefill_threads: Option<usize>,
+        decode_threads: Option<usize>,
+        pin_cores: Option<Vec<usize>>,
     ) -> PyResult<Self> {
         let _device = match device {
             Some("cuda") => candle_core::Device::new_cuda(0).map_err(|e| {
@@ -44,30 +63,35 @@ impl PyBitLlama {
             }
         };
 
+        let runtime = RuntimeConfig {
+            threads,
+            prefill_threads,
+            decode_threads,
+            pin_cores: pin_cores.unwrap_or_default(),
+            dedicated_pool: false,
+        }
+        .build()
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+
         // Always load to CPU first, then selectively move to GPU in llama.rs
         // This enables hybrid offloading (n_gpu_layers)
-        let vb = unsafe {
-            candle_nn::VarBuilder::from_mmaped_safetensors(
-                &[checkpoint_path],
-                DType::F32,
-                &candle_core::Device::Cpu,
-            )
-            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?
-        };
-
-        let mut model = BitLlama::load(config, vb)
-            .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
+        // (packed checkpoints are memory-mapped and used in place)
+        let mut model = match config {
+            Some(config) => BitLlama::load_checkpoint(config, checkpoint_path),
+            None => BittFile::open(checkpoint_path).and_then(|bitt| bitt.load_model(bitt.config)),
+        }
+        .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
-        model
-            .precompute_packed()
+        runtime
+            .prefill(|| model.precompute_packed())
             .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
         // w_states should match each layer's device for Hybrid Offloading
-        let d_small = config.hidden_dim / 4;
+        let d_small = model.config.hidden_dim / 4;
         let mut w_states = Vec::new();
         for layer in &model.layers {
             let layer_device = layer.device();
-            let w = Tensor::zeros((d_small, d_small), DType::F32, layer_device)
+            let w = Tensor::zeros((1, 1, d_small, d_small), DType::F32, layer_device)
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
             w_states.push(w);
         }
@@ -75,17 +99,19 @@ impl PyBitLlama {
         Ok(Self {
             inner: model,
             w_states,
+            runtime,
         })
     }
 
     pub fn forward(&mut self, token_id: u32) -> PyResult<Vec<f32>> {
-        let device = self.inner.embedding.embeddings().device();
+        let device = self.inner.embedding.device();
         let input = Tensor::new(&[token_id], device)
             .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
+        let (inner, w_states) = (&mut self.inner, &mut self.w_states);
         let logits = self
-            .inner
-            .forward_one(&input, &mut self.w_states)
+            .runtime
+            .decode(|| inner.forward_one(&input, w_states))
             .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
         let logits_vec = logits
@@ -112,7 +138,7 @@ impl PyBitLlama {
         max_new_tokens: usize,
     ) -> PyResult<Vec<u32>> {
         py.allow_threads(move || {
-            let device = self.inner.embedding.embeddings().device().clone();
+            let device = self.inner.embedding.device().clone();
             let mut current_tokens = start_tokens.clone();
 
             // 1. Prefill
@@ -121,9 +147,10 @@ impl PyBitLlama {
                 .unsqueeze(0) // Batch size 1
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
+            let (inner, w_states) = (&mut self.inner, &mut self.w_states);
             let logits = self
-                .inner
-                .forward(&input, &mut self.w_states)
+                .runtime
+                .prefill(|| inner.forward(&input, w_states))
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
             // Sample first token from last position
@@ -148,9 +175,10 @@ impl PyBitLlama {
                 let input = Tensor::new(&[last_token], &device)
                     .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
+                let (inner, w_states) = (&mut self.inner, &mut self.w_states);
                 let logits = self
-                    .inner
-                    .forward_one(&input, &mut self.w_states)
+                    .runtime
+                    .decode(|| inner.forward_one(&input, w_states))
                     .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
                 let logits_v = logits
@@ -256,7 +284,7 @@ impl PyTrainer {
         py_targets: Vec<u32>,
     ) -> PyResult<f64> {
         py.allow_threads(move || {
-            let device = self.model.embedding.embeddings().device();
+            let device = self.model.embedding.device();
             let input_tensor = Tensor::new(py_input_ids.as_slice(), device)
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?
                 .unsqueeze(0) // Batch dim 1
@@ -283,9 +311,9 @@ impl PyTrainer {
 
             let seq_len = py_input_ids.len();
 
-            let logits = self
+            let (logits, aux_loss) = self
                 .model
-                .forward_chunkwise(&input_tensor, &mut w_states, seq_len)
+                .forward_chunkwise_with_aux(&input_tensor, &mut w_states, seq_len)
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
             // 3. Loss
@@ -300,6 +328,17 @@ impl PyTrainer {
             let loss = candle_nn::loss::cross_entropy(&logits, &targets)
                 .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
 
+            // MoE load-balancing term
+            let loss = match aux_loss {
+                Some(aux) => {
+                    let coef = self.model.config.router_aux_loss_coef;
+                    aux.affine(coef, 0.0)
+                        .and_then(|a| loss.add(&a))
+                        .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?
+                }
+                None => loss,
+            };
+
             // 4. Backward
             let grads_store = loss
                 .backward()
@@ -0,0 +1,185 @@
+//! Runtime - worker threads of the CPU kernels
+//!
+//! The kernels, `AdaptiveBitLinear` reconstruction and the decode workspace
+//! parallelize with rayon, so they run on the pool of the calling thread:
+//! rayon's global pool unless another one is installed. `EngineRuntime`
+//! holds the pools of one `Llama` (prefill and decode can be sized apart),
+//! `RuntimeConfig::init_global` sizes the global pool (training, tools).
+
+use std::sync::Arc;
+
+use candle_core::Result;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Thread count and placement of the engine's workers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeConfig {
+    /// Worker threads (None: one per pinned core, or per logical CPU)
+    pub threads: Option<usize>,
+    /// Prefill workers (None: `threads`)
+    pub prefill_threads: Option<usize>,
+    /// Decode workers (None: `threads`)
+    pub decode_threads: Option<usize>,
+    /// Pin worker i of each pool to core `pin_cores[i % len]` (empty: no pinning)
+    #[serde(default)]
+    pub pin_cores: Vec<usize>,
+    /// Own pools even with default sizes (implied by every other setting)
+    #[serde(default)]
+    pub dedicated_pool: bool,
+}
+
+impl RuntimeConfig {
+    /// Parse a core list such as "0-3,8,10-11"
+    pub fn parse_cores(spec: &str) -> Result<Vec<usize>> {
+        let mut cores = Vec::new();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let parse = |s: &str| {
+                s.trim().parse::<usize>().map_err(|_| {
+                    candle_core::Error::Msg(format!("Invalid core '{}' in '{}'", s, spec))
+                })
+            };
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        candle_core::bail!("Invalid core range '{}'", part);
+                    }
+                    cores.extend(first..=last);
+                }
+                None => cores.push(parse(part)?),
+            }
+        }
+        Ok(cores)
+    }
+
+    /// Whether `build` creates pools instead of using the global one
+    pub fn is_dedicated(&self) -> bool {
+        self.dedicated_pool
+            || self.threads.is_some()
+            || self.prefill_threads.is_some()
+            || self.decode_threads.is_some()
+            || !self.pin_cores.is_empty()
+    }
+
+    /// Pools of one engine; prefill and decode share a pool unless sized apart
+    pub fn build(&self) -> Result<EngineRuntime> {
+        if !self.is_dedicated() {
+            return Ok(EngineRuntime::default());
+        }
+        self.check()?;
+        let (prefill_threads, decode_threads) = (
+            self.prefill_threads.or(self.threads),
+            self.decode_threads.or(self.threads),
+        );
+        let build = |name, threads| {
+            self.builder(name, threads)
+                .build()
+                .map(Arc::new)
+                .map_err(candle_core::Error::wrap)
+        };
+        let prefill = build("prefill", prefill_threads)?;
+        let decode = if decode_threads == prefill_threads {
+            prefill.clone()
+        } else {
+            build("decode", decode_threads)?
+        };
+        Ok(EngineRuntime {
+            prefill: Some(prefill),
+            decode: Some(decode),
+        })
+    }
+
+    /// Size (`threads`) and pin (`pin_cores`) rayon's global pool.
+    /// Fails once the global pool is running, so call it first thing.
+    pub fn init_global(&self) -> Result<()> {
+        self.check()?;
+        self.builder("worker", self.threads)
+            .build_global()
+            .map_err(candle_core::Error::wrap)
+    }
+
+    fn check(&self) -> Result<()> {
+        let counts = [self.threads, self.prefill_threads, self.decode_threads];
+        if counts.contains(&Some(0)) {
+            candle_core::bail!("Thread counts must be at least 1");
+        }
+        if !self.pin_cores.is_empty() {
+            let available = core_affinity::get_core_ids().unwrap_or_default();
+            if let Some(core) = self
+                .pin_cores
+                .iter()
+                .find(|&&c| !available.iter().any(|id| id.id == c))
+            {
+                candle_core::bail!(
+                    "Core {} is not available for pinning ({} usable cores)",
+                    core,
+                    available.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn builder(&self, name: &'static str, threads: Option<usize>) -> ThreadPoolBuilder {
+        let cores = self.pin_cores.clone();
+        // 0 lets rayon pick (one per logical CPU)
+        let threads = threads.unwrap_or(cores.len());
+        let builder = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(move |i| format!("bit-ttt-{}-{}", name, i));
+        if cores.is_empty() {
+            return builder;
+        }
+        builder.start_handler(move |i| {
+            let core = core_affinity::CoreId {
+                id: cores[i % cores.len()],
+            };
+            if !core_affinity::set_for_current(core) {
+                tracing::warn!("Could not pin {} worker {} to core {}", name, i, core.id);
+            }
+        })
+    }
+}
+
+/// Worker pools of one engine (default: rayon's global pool)
+#[derive(Debug, Clone, Default)]
+pub struct EngineRuntime {
+    prefill: Option<Arc<ThreadPool>>,
+    decode: Option<Arc<ThreadPool>>,
+}
+
+impl EngineRuntime {
+    /// Run `op` (prompt processing, batched steps) on the prefill workers
+    pub fn prefill<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        install(self.prefill.as_deref(), op)
+    }
+
+    /// Run `op` (single-token steps) on the decode workers
+    pub fn decode<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        install(self.decode.as_deref(), op)
+    }
+
+    pub fn prefill_threads(&self) -> usize {
+        self.prefill(rayon::current_num_threads)
+    }
+
+    pub fn decode_threads(&self) -> usize {
+        self.decode(rayon::current_num_threads)
+    }
+
+    /// Whether prefill and decode run on separate pools
+    pub fn is_split(&self) -> bool {
+        match (&self.prefill, &self.decode) {
+            (Some(p), Some(d)) => !Arc::ptr_eq(p, d),
+            _ => false,
+        }
+    }
+}
+
+fn install<R: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
@@ -0,0 +1,220 @@
+#[cfg(test)]
+mod tests {
+    use crate::layers::{LoraAdapter, LoraConfig, LoraTarget};
+    use crate::model::{
+        AdapterRegistry, BitLlama, BitLlamaConfig, Llama, ModelArch, SequenceState,
+    };
+    use crate::test_util::{max_abs_diff, small_config};
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+    use std::collections::HashMap;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::Tokenizer;
+
+    fn pattern(rows: usize, cols: usize, seed: f32, device: &Device) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.43).sin() * 0.3)
+            .collect();
+        Tensor::from_vec(data, (rows, cols), device).unwrap()
+    }
+
+    fn lora_config() -> LoraConfig {
+        LoraConfig {
+            rank: 2,
+            alpha: 2.0,
+            targets: LoraTarget::ALL.to_vec(),
+        }
+    }
+
+    /// Non-trivial adapter for every projection of `model`
+    fn adapter_set(model: &BitLlama, seed: f32) -> HashMap<String, LoraAdapter> {
+        let device = Device::Cpu;
+        model
+            .linear_modules()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, _, m))| {
+                let s = seed + i as f32 * 3.0;
+                let a = pattern(2, m.in_features, s, &device);
+                let b = pattern(m.out_features, 2, s + 1.0, &device);
+                (name, LoraAdapter::new(a, b, 1.0).unwrap())
+            })
+            .collect()
+    }
+
+    fn install(model: &mut BitLlama, rows: &[Option<&HashMap<String, LoraAdapter>>]) {
+        for (name, _, module) in model.linear_modules_mut() {
+            module.lora_rows = rows
+                .iter()
+                .map(|set| set.and_then(|s| s.get(&name).cloned()))
+                .collect();
+        }
+    }
+
+    #[test]
+    fn test_registry_budget_evicts_lru() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let one = |seed: f32| {
+            let mut m = HashMap::new();
+            let a = pattern(2, 8, seed, &device);
+            let b = pattern(8, 2, seed, &device);
+            m.insert(
+                "layers.0.ttt.down".to_string(),
+                LoraAdapter::new(a, b, 1.0).unwrap(),
+            );
+            m
+        };
+        // 32 f32 = 128 bytes per adapter; room for two
+        let mut reg = AdapterRegistry::new(Some(256));
+        assert!(reg.insert("a", lora_config(), one(1.0))?.is_empty());
+        assert!(reg.insert("b", lora_config(), one(2.0))?.is_empty());
+        assert_eq!(reg.resident_bytes(), 256);
+
+        // Using "a" makes "b" the eviction victim
+        assert!(reg.touch("a"));
+        assert_eq!(reg.insert("c", lora_config(), one(3.0))?, vec!["b"]);
+        let names: Vec<String> = reg.list().into_iter().map(|i| i.name).collect();
+        assert_eq!(names, vec!["a", "c"]);
+
+        // Shrinking the budget evicts immediately; oversized adapters are rejected
+        assert_eq!(reg.set_budget_bytes(Some(200)), vec!["a"]);
+        assert!(reg
+            .insert("d", lora_config(), {
+                let mut m = one(4.0);
+                m.extend(
+                    one(5.0)
+                        .into_values()
+                        .map(|v| ("layers.1.ttt.down".to_string(), v)),
+                );
+                m
+            })
+            .is_err());
+        assert!(reg.remove("c"));
+        assert!(reg.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_batch_per_row_adapters_match_single() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        // MoE: expert inputs are routed subsets of the batch rows
+        for (arch, n_experts) in [
+            (ModelArch::TTT, None),
+            (ModelArch::Llama, None),
+            (ModelArch::Llama, Some(4)),
+        ] {
+            let mut cfg = BitLlamaConfig::new(24, 32, 2, 0.1, None);
+            cfg.arch = arch;
+            cfg.n_experts = n_experts;
+            cfg.n_heads = 4;
+            cfg.n_kv_heads = 2;
+            cfg.max_position_embeddings = 16;
+            let varmap = VarMap::new();
+            let mut model =
+                BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+
+            let set_a = adapter_set(&model, 1.0);
+            let set_b = adapter_set(&model, 50.0);
+            let rows = [Some(&set_a), None, Some(&set_b)];
+            // Different prompt lengths -> sequences sit at different positions
+            let prompts: [&[u32]; 3] = [&[1, 5, 7], &[3], &[4, 9]];
+
+            // Reference: each sequence alone with its adapter
+            let mut expected = Vec::new();
+            for (set, prompt) in rows.iter().zip(prompts) {
+                install(&mut model, &[*set]);
+                let mut seq = model.new_sequence()?;
+                let mut logits = None;
+                for &t in prompt.iter().chain([2u32, 6].iter()) {
+                    logits = Some(model.forward_batch(&[t], &mut [&mut seq])?);
+                }
+                expected.push(logits.unwrap());
+            }
+
+            // Batched: prefill individually, then decode two steps together
+            let mut seqs: Vec<SequenceState> = (0..3)
+                .map(|_| model.new_sequence())
+                .collect::<candle_core::Result<_>>()?;
+            for ((set, prompt), seq) in rows.iter().zip(prompts).zip(seqs.iter_mut()) {
+                install(&mut model, &[*set]);
+                for &t in prompt {
+                    model.forward_batch(&[t], &mut [seq])?;
+                }
+            }
+            install(&mut model, &rows);
+            let mut logits = None;
+            for t in [2u32, 6] {
+                let mut refs: Vec<&mut SequenceState> = seqs.iter_mut().collect();
+                logits = Some(model.forward_batch(&[t, t, t], &mut refs)?);
+            }
+            let logits = logits.unwrap();
+            assert_eq!(logits.dims(), &[3, 24]);
+            for (b, exp) in expected.iter().enumerate() {
+                let diff = max_abs_diff(&logits.narrow(0, b, 1)?, exp)?;
+                assert!(
+                    diff < 1e-4,
+                    "{:?} experts {:?} row {}: diff {}",
+                    arch,
+                    n_experts,
+                    b,
+                    diff
+                );
+            }
+            // Adapters really change the output
+            assert!(max_abs_diff(&expected[0], &expected[1])? > 1e-4);
+            assert_eq!(seqs[0].pos, 5);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_adapter_generation_after_workspace_session() -> anyhow::Result<()> {
+        let cfg = small_config(ModelArch::Llama);
+        let varmap = VarMap::new();
+        BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        let dir = tempfile_dir("adapter_session");
+        let (weights, tokenizer) = (dir.join("model.safetensors"), dir.join("tokenizer.json"));
+        varmap.save(&weights)?;
+        let vocab = (0..48).map(|i| (format!("t{}", i), i)).collect();
+        let word_level = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("t0".into())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        Tokenizer::new(word_level)
+            .save(&tokenizer, false)
+            .map_err(anyhow::Error::msg)?;
+
+        let mut llama = Llama::load(&weights, &tokenizer, cfg)?;
+        for (_, _, module) in llama.model.linear_modules_mut() {
+            module.precompute_packed(cfg.pack_options())?;
+        }
+        let set = adapter_set(&llama.model, 1.0);
+        llama.adapters.insert("a", lora_config(), set)?;
+
+        // Plain (workspace), adapter (forward_one), plain again: one session
+        llama.stream_completion("t3", 3, 0.0, |_| Ok(true))?;
+        assert!(llama.workspace.is_some());
+        llama.stream_completion_with_adapter("t5", 3, 0.0, Some("a"), |_| Ok(true))?;
+        assert!(llama.workspace.is_none());
+        assert!(llama
+            .model
+            .linear_modules()
+            .iter()
+            .all(|(_, _, m)| m.lora_rows.is_empty()));
+        llama.stream_completion("t7", 3, 0.0, |_| Ok(true))?;
+        assert_eq!(llama.model.current_pos, 12);
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    fn tempfile_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{BitLinearCpu, CpuSimd};
+    use crate::kernels::packing::MultiBasePacked;
+    use crate::layers::AdaptiveBitLinear;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::VarBuilder;
+    use std::collections::HashMap;
+
+    const SCALES: [f32; 3] = [0.5, 0.125, 0.03];
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 5) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    /// Interleaved codes [out, in/4, bases] (all four 2-bit codes, incl. the unused 11)
+    /// and the dense weight they encode
+    fn adaptive_weights(out_dim: usize, in_dim: usize) -> (Vec<u8>, Tensor) {
+        let bases = SCALES.len();
+        let codes: Vec<u8> = (0..out_dim * in_dim / 4 * bases)
+            .map(|i| ((i * 37 + i / 7) % 256) as u8)
+            .collect();
+        let mut dense = vec![0.0f32; out_dim * in_dim];
+        for (i, &byte) in codes.iter().enumerate() {
+            let (group, base) = (i / bases, i % bases);
+            for e in 0..4 {
+                let coeff = match (byte >> (e * 2)) & 0b11 {
+                    1 => 1.0,
+                    2 => -1.0,
+                    _ => 0.0,
+                };
+                dense[group * 4 + e] += coeff * SCALES[base];
+            }
+        }
+        let dense = Tensor::from_vec(dense, (out_dim, in_dim), &Device::Cpu).unwrap();
+        (codes, dense)
+    }
+
+    fn max_rel_diff(got: &Tensor, expected: &Tensor) -> anyhow::Result<f32> {
+        let diff = (got - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        let scale = expected.abs()?.max_all()?.to_scalar::<f32>()?;
+        Ok(diff / scale.max(1.0))
+    }
+
+    #[test]
+    fn test_multibase_kernel_matches_dense_weights() -> anyhow::Result<()> {
+        // Odd group count (AVX2 remainder), decode and batched (tiled GEMM) shapes
+        for (m, k, n) in [(1, 68, 37), (1, 256, 16), (5, 68, 37), (20, 300, 70)] {
+            let (codes, dense) = adaptive_weights(n, k);
+            let data = Tensor::from_vec(codes, (n, k / 4, SCALES.len()), &Device::Cpu)?;
+            let packed = MultiBasePacked::new(data, SCALES.to_vec(), n, k)?;
+            assert_eq!(max_rel_diff(&packed.unpack(&Device::Cpu)?, &dense)?, 0.0);
+
+            let x = pattern(m, k, 1.0);
+            let expected = x.matmul(&dense.t()?)?;
+            let got = BitLinearCpu::forward_multibase(&x, &packed)?;
+            let diff = max_rel_diff(&got, &expected)?;
+            assert!(diff < 1e-5, "{}x{}x{}: diff {}", m, k, n, diff);
+
+            for simd in [CpuSimd::Scalar, CpuSimd::detect()] {
+                let got = BitLinearCpu::forward_multibase_with_simd(&x, &packed, simd)?;
+                let diff = max_rel_diff(&got, &expected)?;
+                assert!(diff < 1e-5, "{:?} {}x{}x{}: diff {}", simd, m, k, n, diff);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_layer_keeps_only_packed_codes() -> anyhow::Result<()> {
+        let (out_dim, in_dim) = (24, 64);
+        let (codes, dense) = adaptive_weights(out_dim, in_dim);
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            (
+                "weight_packed".to_string(),
+                Tensor::from_vec(codes, (out_dim, in_dim / 4, SCALES.len()), &device)?,
+            ),
+            ("scales".to_string(), Tensor::new(&SCALES, &device)?),
+        ]);
+        let vb = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let layer = AdaptiveBitLinear::load(in_dim, out_dim, vb, &device)?;
+
+        assert!(layer.reconstructed_weight.is_none());
+        let packed = layer.packed_bases.as_ref().unwrap();
+        assert_eq!(packed.num_bases(), 3);
+        assert_eq!(packed.data.dtype(), DType::U8);
+
+        // [Batch, Seq, In] input, as in the transformer blocks
+        let x = pattern(6, in_dim, 2.0).reshape((2, 3, in_dim))?;
+        let got = layer.forward(&x)?;
+        assert_eq!(got.dims(), &[2, 3, out_dim]);
+        let expected = x.broadcast_matmul(&dense.t()?)?;
+        let diff = max_rel_diff(&got, &expected)?;
+        assert!(diff < 1e-5, "diff {}", diff);
+        assert_eq!(max_rel_diff(&layer.effective_weight()?, &dense)?, 0.0);
+        Ok(())
+    }
+}
@@ -62,4 +62,73 @@ mod tests {
 
         Ok(())
     }
+
+    fn token(i: usize, heads: usize, dim: usize) -> Tensor {
+        let data: Vec<f32> = (0..heads * dim)
+            .map(|j| ((i * 17 + j) as f32 * 0.13).sin())
+            .collect();
+        Tensor::from_vec(data, (1, heads, 1, dim), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_kv_cache_grows_in_place_and_keeps_values() -> anyhow::Result<()> {
+        let (heads, dim) = (2, 8);
+        let mut cache = KVCache::new(1000);
+        let prefill = Tensor::cat(
+            &(0..300).map(|i| token(i, heads, dim)).collect::<Vec<_>>(),
+            2,
+        )?;
+        cache.append(&prefill, &prefill)?;
+        // 300 tokens on top of the initial 256 slots forces one reallocation
+        assert_eq!(cache.len(), 300);
+        assert!(cache.capacity() >= 300 && cache.capacity() <= 1000);
+
+        let (k, _) = cache.append(&token(300, heads, dim), &token(300, heads, dim))?;
+        assert_eq!(k.dims(), &[1, heads, 301, dim]);
+
+        // Q8 round trip error is bounded by half a quantization step
+        let expected = Tensor::cat(&[&prefill, &token(300, heads, dim)], 2)?;
+        let err = (k - expected)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(err < 1.0 / 127.0, "max error {}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kv_cache_ring_wraps_and_clones_deeply() -> anyhow::Result<()> {
+        let (heads, dim) = (1, 4);
+        let mut cache = KVCache::new(4);
+        for i in 0..4 {
+            cache.append(&token(i, heads, dim), &token(i, heads, dim))?;
+        }
+        let snapshot = cache.clone();
+
+        // Tokens 0..6 into 4 slots: keeps 2..6 in logical order for multi-token appends
+        let chunk = Tensor::cat(&[token(4, heads, dim), token(5, heads, dim)], 2)?;
+        let (k, _) = cache.append(&chunk, &chunk)?;
+        assert_eq!(cache.len(), 4);
+        let expected = Tensor::cat(&(2..6).map(|i| token(i, heads, dim)).collect::<Vec<_>>(), 2)?;
+        let err = (k - expected)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(err < 1.0 / 127.0);
+
+        // The clone still holds tokens 0..4
+        let mut snapshot = snapshot;
+        assert_eq!(snapshot.len(), 4);
+        let (k_snap, _) = snapshot.append(&token(9, heads, dim), &token(9, heads, dim))?;
+        let first = k_snap.narrow(2, 1, 1)?;
+        let err = (first - token(1, heads, dim))?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(err < 1.0 / 127.0);
+        Ok(())
+    }
 }
@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::autotune::{self, Autotuner, TuneMode};
+    use crate::kernels::backend;
+    use crate::kernels::cpu::ActivationQuant;
+    use crate::kernels::packing::{PackedTensor, WeightScale};
+    use crate::layers::BitLinear;
+    use crate::model::{BitLlama, ModelArch};
+    use crate::test_util::small_config;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+    use std::path::PathBuf;
+
+    fn weights(rows: usize, cols: usize) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32) * 0.37).sin())
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("autotune_{}_{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_autotune_caches_winners_per_cpu() -> anyhow::Result<()> {
+        let path = cache_path("cache");
+        let _ = std::fs::remove_file(&path);
+        let packed = PackedTensor::pack(&weights(48, 256))?;
+
+        let mut tuner = Autotuner::new(Some(path.clone()), TuneMode::Cached);
+        let winner = tuner.choose(&packed)?;
+        assert_ne!(winner, ActivationQuant::Auto);
+        assert_eq!(tuner.measurements(), 1);
+        // Same shape again: no new measurement
+        assert_eq!(tuner.choose(&packed)?, winner);
+        assert_eq!(tuner.measurements(), 1);
+
+        // The file is keyed by CPU model, then "in x out"
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(
+            serde_json::from_value::<ActivationQuant>(json[tuner.cpu()]["256x48"].clone())?,
+            winner
+        );
+
+        // A later load reuses the cached winner without measuring
+        let mut reloaded = Autotuner::new(Some(path.clone()), TuneMode::Cached);
+        assert_eq!(reloaded.cached(256, 48), Some(winner));
+        assert_eq!(reloaded.choose(&packed)?, winner);
+        assert_eq!(reloaded.measurements(), 0);
+
+        // Overrides: retune measures once per process, off never measures
+        let mut retune = Autotuner::new(Some(path.clone()), TuneMode::Retune);
+        retune.choose(&packed)?;
+        retune.choose(&packed)?;
+        assert_eq!(retune.measurements(), 1);
+        let mut off = Autotuner::new(Some(path.clone()), TuneMode::Off);
+        assert_eq!(off.choose(&packed)?, ActivationQuant::F32);
+        assert_eq!(off.measurements(), 0);
+
+        // The key names the kernel backend; an unwritable cache keeps the result in memory
+        assert!(tuner.cpu().contains(backend::selected()));
+        let mut unwritable = Autotuner::new(Some(path.join("autotune.json")), TuneMode::Cached);
+        let winner = unwritable.choose(&packed)?;
+        assert_eq!(unwritable.cached(256, 48), Some(winner));
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_autotune_skips_unsupported_layouts() -> anyhow::Result<()> {
+        // Groups of 64 columns have no int8 layout
+        let packed = PackedTensor::pack_with(&weights(16, 256), WeightScale::Group, 64)?;
+        let timings = autotune::benchmark(&packed)?;
+        let kernels: Vec<_> = timings.iter().map(|(k, _)| *k).collect();
+        assert_eq!(kernels, vec![ActivationQuant::F32, ActivationQuant::Lut]);
+
+        let mut tuner = Autotuner::new(None, TuneMode::Cached);
+        assert_ne!(tuner.choose(&packed)?, ActivationQuant::Int8);
+
+        assert_eq!("retune".parse::<TuneMode>(), Ok(TuneMode::Retune));
+        assert!("sometimes".parse::<TuneMode>().is_err());
+        assert_eq!(serde_json::from_str::<TuneMode>("\"off\"")?, TuneMode::Off);
+        assert_eq!("auto".parse::<ActivationQuant>(), Ok(ActivationQuant::Auto));
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_activation_quant_packs_tuned_layout() -> anyhow::Result<()> {
+        // The process-wide tuner must not write to the user's cache
+        let path = cache_path("global");
+        std::env::set_var(autotune::CACHE_ENV, &path);
+
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let mut linear = BitLinear::load(128, 24, vb, &device)?;
+        linear.precompute_packed(ActivationQuant::Auto.into())?;
+
+        let packed = linear.packed_params.as_ref().unwrap();
+        let layouts = (packed.int8_layout.is_some(), packed.lut_layout.is_some());
+        let expected = match autotune::choose(packed)? {
+            ActivationQuant::Int8 => (true, false),
+            ActivationQuant::Lut => (false, true),
+            _ => (false, false),
+        };
+        assert_eq!(layouts, expected);
+        assert_eq!(linear.forward(&weights(2, 128))?.dims(), &[2, 24]);
+
+        // Config settings take over from the environment
+        let configured = cache_path("configured");
+        let mut cfg = small_config(ModelArch::Llama);
+        cfg.activation_quant = ActivationQuant::Auto;
+        cfg.autotune = Some(TuneMode::Off);
+        autotune::configure(None, Some(configured.clone()));
+        let mut model = BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+        model.precompute_packed()?;
+        assert_eq!(autotune::mode(), TuneMode::Off);
+        let modules = model.linear_modules();
+        let packed: Vec<_> = modules
+            .iter()
+            .filter_map(|(_, _, m)| m.legacy_linear.as_ref()?.packed_params.as_ref())
+            .collect();
+        assert!(!packed.is_empty());
+        assert!(packed
+            .iter()
+            .all(|p| p.int8_layout.is_none() && p.lut_layout.is_none()));
+        assert!(!configured.exists());
+        autotune::configure(Some(TuneMode::Cached), Some(path.clone()));
+        assert_eq!(autotune::mode(), TuneMode::Cached);
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+}
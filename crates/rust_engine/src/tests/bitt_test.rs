@@ -0,0 +1,234 @@
+#[cfg(test)]
+mod tests {
+    use crate::model::bitt::{self, BITT_ALIGN};
+    use crate::model::{BitLlama, BitLlamaConfig, BittFile, BittMetadata, Llama};
+    use candle_core::{DType, Device};
+    use candle_nn::{VarBuilder, VarMap};
+    use std::path::{Path, PathBuf};
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::Tokenizer;
+
+    /// Word-level tokenizer over "t0" .. "t47"
+    fn tokenizer() -> anyhow::Result<Tokenizer> {
+        let vocab = (0..48).map(|i| (format!("t{}", i), i)).collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("t0".into())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        Ok(Tokenizer::new(model))
+    }
+
+    /// v1 .bitt (magic, header length, config + tokenizer JSON, weights)
+    fn write_bitt(path: &Path, cfg: &BitLlamaConfig, weights: &Path) -> anyhow::Result<()> {
+        let header = serde_json::to_vec(&serde_json::json!({
+            "config": cfg,
+            "tokenizer": serde_json::from_str::<serde_json::Value>(
+                &tokenizer()?.to_string(false).map_err(anyhow::Error::msg)?
+            )?,
+        }))?;
+        let mut bytes = b"BITT".to_vec();
+        bytes.extend((header.len() as u64).to_le_bytes());
+        bytes.extend(header);
+        bytes.extend(std::fs::read(weights)?);
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn logits(model: &BitLlama) -> anyhow::Result<Vec<f32>> {
+        let mut seq = model.new_sequence()?;
+        let mut out = Vec::new();
+        for token in [3u32, 17, 5, 40] {
+            let logits = model.forward_batch(&[token], &mut [&mut seq])?;
+            out.extend(logits.flatten_all()?.to_vec1::<f32>()?);
+        }
+        Ok(out)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bitt_test_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_load_bitt_matches_safetensors() -> anyhow::Result<()> {
+        let cfg = BitLlamaConfig::new(48, 64, 2, 0.1, None);
+        let varmap = VarMap::new();
+        let mut model = BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        model.precompute_packed()?;
+
+        let (dense, packed) = (
+            temp_path("dense.safetensors"),
+            temp_path("packed.safetensors"),
+        );
+        varmap.save(&dense)?;
+        model.save_packed(&packed)?;
+        let dir = temp_path("dir");
+        std::fs::create_dir_all(&dir)?;
+        let (dense_bitt, packed_bitt) = (dir.join("model.bitt"), temp_path("packed.bitt"));
+        write_bitt(&dense_bitt, &cfg, &dense)?;
+        write_bitt(&packed_bitt, &cfg, &packed)?;
+
+        let expected = logits(&BitLlama::load_checkpoint(cfg, &dense)?)?;
+
+        // Config and tokenizer come from the header
+        assert!(BittFile::is_bitt(&dense_bitt) && !BittFile::is_bitt(&dense));
+        let bitt = BittFile::open(&dense_bitt)?;
+        assert_eq!(bitt.config.vocab_size, 48);
+        assert_eq!(bitt.body(), std::fs::read(&dense)?.as_slice());
+        for llama in [
+            Llama::load_bitt(&dense_bitt)?,
+            Llama::load_auto(&dense_bitt)?,
+            Llama::load_auto(&dir)?,
+        ] {
+            assert_eq!(llama.tokenizer.token_to_id("t17"), Some(17));
+            assert_eq!(logits(&llama.model)?, expected);
+        }
+
+        // Packed weights are read from the container mapping at their offset
+        let mut from_bitt = BitLlama::load_checkpoint(cfg, &packed_bitt)?;
+        let mut from_file = BitLlama::load_checkpoint(cfg, &packed)?;
+        for (name, _, module) in from_bitt.linear_modules() {
+            let linear = module.legacy_linear.as_ref().unwrap();
+            assert!(
+                linear.packed_params.as_ref().unwrap().data.is_mapped(),
+                "{}",
+                name
+            );
+        }
+        from_bitt.precompute_packed()?;
+        from_file.precompute_packed()?;
+        assert_eq!(logits(&from_bitt)?, logits(&from_file)?);
+
+        for path in [&dense, &packed, &packed_bitt] {
+            let _ = std::fs::remove_file(path);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitt_rejects_bad_files() -> anyhow::Result<()> {
+        let path = temp_path("bad.bitt");
+        std::fs::write(&path, b"NOPE")?;
+        assert!(BittFile::open(&path).is_err());
+        // Header length past the end of the file
+        let mut bytes = b"BITT".to_vec();
+        bytes.extend(1000u64.to_le_bytes());
+        bytes.extend(b"{}");
+        std::fs::write(&path, &bytes)?;
+        assert!(BittFile::is_bitt(&path));
+        assert!(BittFile::open(&path).is_err());
+        // Header without a config
+        let mut bytes = b"BITT".to_vec();
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(b"{}");
+        std::fs::write(&path, &bytes)?;
+        assert!(Llama::load_bitt(&path).is_err());
+        // v2 magic with a version this build doesn't know
+        let mut bytes = b"BITT".to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(b"{}");
+        std::fs::write(&path, &bytes)?;
+        let err = BittFile::open(&path).err().unwrap().to_string();
+        assert!(err.contains("unsupported .bitt version 3"), "{}", err);
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitt_v2_sections_and_checksums() -> anyhow::Result<()> {
+        let cfg = BitLlamaConfig::new(48, 64, 2, 0.1, None);
+        let varmap = VarMap::new();
+        BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        let weights = temp_path("v2.safetensors");
+        varmap.save(&weights)?;
+        let expected = logits(&BitLlama::load_checkpoint(cfg, &weights)?)?;
+
+        // Soul snapshot of a model that has seen a few tokens
+        let mut source = Llama::load_bitt({
+            let v1 = temp_path("v2_source.bitt");
+            write_bitt(&v1, &cfg, &weights)?;
+            v1
+        })?;
+        let mut w_states = source.model.new_w_states();
+        for token in [3u32, 17, 5] {
+            let x = candle_core::Tensor::new(&[token], &Device::Cpu)?;
+            source.model.forward_one(&x, &mut w_states)?;
+        }
+        source.w_states = w_states;
+        let soul = temp_path("v2.soul");
+        source.save_memory(&soul)?;
+
+        let config = serde_json::to_vec(&cfg)?;
+        let tokenizer = tokenizer()?.to_string(false).map_err(anyhow::Error::msg)?;
+        let (body, soul_bytes) = (std::fs::read(&weights)?, std::fs::read(&soul)?);
+        let metadata = BittMetadata {
+            training_step: Some(1200),
+            source: Some("runs/test".to_string()),
+            ..Default::default()
+        };
+        let path = temp_path("v2.bitt");
+        BittFile::write(
+            &path,
+            &[
+                (bitt::SECTION_CONFIG, &config),
+                (bitt::SECTION_TOKENIZER, tokenizer.as_bytes()),
+                (bitt::SECTION_GENERATION_CONFIG, br#"{"temperature":0.7}"#),
+                (bitt::SECTION_CHAT_TEMPLATE, b"{{ messages }}"),
+                (bitt::SECTION_SOUL, &soul_bytes),
+                (bitt::SECTION_WEIGHTS, &body),
+            ],
+            &metadata,
+        )?;
+        assert!(BittFile::write(&path, &[(bitt::SECTION_CONFIG, &config)], &metadata).is_err());
+
+        let file = BittFile::open(&path)?;
+        assert_eq!(file.version, bitt::BITT_VERSION);
+        assert_eq!(file.metadata, metadata);
+        assert_eq!(file.sections.len(), 6);
+        for section in &file.sections {
+            assert_eq!(section.offset as usize % BITT_ALIGN, 0, "{}", section.name);
+        }
+        assert_eq!(file.body(), body.as_slice());
+        assert_eq!(
+            file.generation_config()?.unwrap()["temperature"],
+            serde_json::json!(0.7)
+        );
+        assert_eq!(file.chat_template()?.as_deref(), Some("{{ messages }}"));
+        assert!(file.quantization()?.is_none());
+        file.verify()?;
+        assert!(BittFile::open(temp_path("v2_source.bitt"))?
+            .verify()
+            .is_err());
+        drop(file);
+
+        // Same model, and the soul section restores the memory
+        let llama = Llama::load_auto(&path)?;
+        assert_eq!(llama.tokenizer.token_to_id("t17"), Some(17));
+        assert_eq!(logits(&llama.model)?, expected);
+        for (got, want) in llama.w_states.iter().zip(&source.w_states) {
+            let diff = (got - want)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert_eq!(diff, 0.0);
+        }
+
+        // A flipped weight byte fails verify (in that section only)
+        let mut bytes = std::fs::read(&path)?;
+        let at = BittFile::open(&path)?.sections.last().unwrap().offset as usize + body.len() - 1;
+        bytes[at] ^= 0xff;
+        std::fs::write(&path, &bytes)?;
+        let err = BittFile::open(&path)?.verify().err().unwrap().to_string();
+        assert!(err.contains("section(s): weights"), "{}", err);
+
+        for p in [weights, soul, path, temp_path("v2_source.bitt")] {
+            let _ = std::fs::remove_file(p);
+        }
+        Ok(())
+    }
+}
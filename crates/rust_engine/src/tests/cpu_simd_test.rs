@@ -0,0 +1,105 @@
+//! SIMD kernels vs the scalar reference.
+//!
+//! On x86_64 this checks AVX2, on AArch64 (cross-compiled and run under
+//! QEMU by the `aarch64` CI job; the `build` job only compiles it) the same
+//! tests check NEON.
+
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{BitLinearCpu, CpuSimd};
+    use crate::kernels::packing::PackedTensor;
+    use candle_core::{Device, Tensor};
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 5) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    /// (M, K, N): whole 32/128-weight chunks, remainders and K < 32
+    const SHAPES: [(usize, usize, usize); 4] = [(1, 256, 16), (3, 200, 9), (2, 36, 5), (4, 12, 3)];
+
+    #[test]
+    fn test_simd_detection() {
+        let simd = CpuSimd::detect();
+        assert!(simd.is_available(), "detected {:?} is not available", simd);
+        assert!(CpuSimd::Scalar.is_available());
+        #[cfg(target_arch = "x86_64")]
+        assert!(!CpuSimd::Neon.is_available());
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert_eq!(simd, CpuSimd::Neon);
+            assert!(!CpuSimd::Avx2.is_available());
+        }
+    }
+
+    #[test]
+    fn test_unavailable_simd_is_rejected() -> anyhow::Result<()> {
+        let missing = [CpuSimd::Avx2, CpuSimd::Neon]
+            .into_iter()
+            .find(|s| !s.is_available())
+            .unwrap();
+        let packed = PackedTensor::pack(&pattern(4, 64, 1.0))?;
+        let x = pattern(1, 64, 2.0);
+        assert!(BitLinearCpu::forward_with_simd(&x, &packed, missing).is_err());
+        let packed = packed.with_int8_layout()?;
+        assert!(BitLinearCpu::forward_int8_with_simd(&x, &packed, missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_f32_kernel_matches_scalar() -> anyhow::Result<()> {
+        let simd = CpuSimd::detect();
+        for (m, k, n) in SHAPES {
+            let x = pattern(m, k, 1.0);
+            let packed = PackedTensor::pack(&pattern(n, k, 2.0))?;
+            let got = BitLinearCpu::forward_with_simd(&x, &packed, simd)?;
+            let expected = BitLinearCpu::forward_with_simd(&x, &packed, CpuSimd::Scalar)?;
+            // Same products, different summation order
+            let diff = (&got - &expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            let scale = expected.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert!(
+                diff <= 1e-5 * scale.max(1.0),
+                "{:?} {}x{}x{}: diff {}",
+                simd,
+                m,
+                k,
+                n,
+                diff
+            );
+
+            // Scalar path vs dense matmul of the unpacked weights
+            let dense = x.matmul(&packed.unpack(&Device::Cpu)?.t()?)?;
+            let diff = (&expected - &dense)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert!(
+                diff <= 1e-4 * scale.max(1.0),
+                "scalar vs dense diff {}",
+                diff
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_int8_kernel_matches_scalar_exactly() -> anyhow::Result<()> {
+        let simd = CpuSimd::detect();
+        for (m, k, n) in SHAPES {
+            let x = pattern(m, k, 3.0);
+            let packed = PackedTensor::pack(&pattern(n, k, 4.0))?.with_int8_layout()?;
+            let got = BitLinearCpu::forward_int8_with_simd(&x, &packed, simd)?;
+            let expected = BitLinearCpu::forward_int8_with_simd(&x, &packed, CpuSimd::Scalar)?;
+            // Integer dot products: bit-identical
+            assert_eq!(
+                got.flatten_all()?.to_vec1::<f32>()?,
+                expected.flatten_all()?.to_vec1::<f32>()?,
+                "{:?} {}x{}x{}",
+                simd,
+                m,
+                k,
+                n
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::backend;
+    use crate::kernels::cpu::ActivationQuant;
+    use crate::layers::KvCacheDtype;
+    use crate::model::{BitLlama, BitLlamaConfig, DecodeWorkspace, ModelArch};
+    use crate::test_util::{max_rel_error, packed_model, small_config};
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts heap allocations of the threads that opted in (`TRACKED`), so
+    /// tests running in parallel don't disturb the count
+    struct CountingAlloc;
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static TRACKED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    fn count() {
+        if TRACKED.try_with(Cell::get).unwrap_or(false) {
+            ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    /// Two-layer model with every projection packed
+    fn model(arch: ModelArch, activations: ActivationQuant) -> anyhow::Result<BitLlama> {
+        let mut cfg = small_config(arch);
+        cfg.activation_quant = activations;
+        packed_model(cfg, &VarMap::new())
+    }
+
+    #[test]
+    fn test_workspace_matches_forward_one() -> anyhow::Result<()> {
+        for (arch, activations) in [
+            (ModelArch::TTT, ActivationQuant::F32),
+            (ModelArch::TTT, ActivationQuant::Int8),
+            (ModelArch::TTT, ActivationQuant::Lut),
+            (ModelArch::Llama, ActivationQuant::F32),
+            (ModelArch::Llama, ActivationQuant::Lut),
+        ] {
+            let mut model = model(arch, activations)?;
+            let mut ws = DecodeWorkspace::new(&model, 16)?;
+            let w = Tensor::zeros((1, 1, 16, 16), DType::F32, &Device::Cpu)?;
+            let mut w_states = vec![w; 2];
+
+            for token in [3u32, 17, 5, 40, 9, 17] {
+                let x = Tensor::new(&[token], &Device::Cpu)?;
+                let expected = model.forward_one(&x, &mut w_states)?;
+                let expected = expected.flatten_all()?.to_vec1::<f32>()?;
+                let got = model.forward_one_into(token, &mut ws)?;
+                let err = max_rel_error(got, &expected);
+                assert!(err < 1e-4, "{:?} {:?}: {}", arch, activations, err);
+            }
+            assert_eq!(ws.position(), 6);
+
+            // TTT states follow the tensor path
+            let mut states = vec![Tensor::zeros(0, DType::F32, &Device::Cpu)?; 2];
+            ws.store_w_states(&mut states)?;
+            if matches!(arch, ModelArch::TTT) {
+                for (got, expected) in states.iter().zip(&w_states) {
+                    let got = got.flatten_all()?.to_vec1::<f32>()?;
+                    let expected = expected.flatten_all()?.to_vec1::<f32>()?;
+                    assert!(max_rel_error(&got, &expected) < 1e-4);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_steady_state_decode_does_not_allocate() -> anyhow::Result<()> {
+        // The cross-check runs every call a second time through tensors
+        if std::env::var_os(backend::CHECK_ENV).is_some() {
+            return Ok(());
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .start_handler(|_| TRACKED.with(|t| t.set(true)))
+            .build()?;
+
+        for (arch, activations) in [
+            (ModelArch::TTT, ActivationQuant::Lut),
+            (ModelArch::TTT, ActivationQuant::F32),
+            (ModelArch::Llama, ActivationQuant::Int8),
+        ] {
+            let mut model = model(arch, activations)?;
+            let mut ws = DecodeWorkspace::new(&model, 32)?;
+            let (workspace, tensors) =
+                pool.install(|| -> candle_core::Result<(usize, usize)> {
+                    // First step sizes the kernel scratch
+                    model.forward_one_into(1, &mut ws)?;
+                    let start = ALLOCATIONS.load(Ordering::SeqCst);
+                    for token in 0..16u32 {
+                        model.forward_one_into(token * 3 % 48, &mut ws)?;
+                    }
+                    let workspace = ALLOCATIONS.load(Ordering::SeqCst) - start;
+
+                    let start = ALLOCATIONS.load(Ordering::SeqCst);
+                    let mut w_states = model.new_w_states();
+                    model.forward_one(&Tensor::new(&[1u32], &Device::Cpu)?, &mut w_states)?;
+                    Ok((workspace, ALLOCATIONS.load(Ordering::SeqCst) - start))
+                })?;
+            assert_eq!(workspace, 0, "{:?} {:?}", arch, activations);
+            assert!(tensors > 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_workspace_limits() -> anyhow::Result<()> {
+        let mut cfg = BitLlamaConfig::new(48, 64, 1, 0.1, None);
+        cfg.n_experts = Some(2);
+        let varmap = VarMap::new();
+        let moe = BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        let err = DecodeWorkspace::new(&moe, 8).err().unwrap().to_string();
+        assert!(err.contains("MoE"), "{}", err);
+
+        // Unpacked ternary weights would run the training path
+        let mut unpacked = model(ModelArch::TTT, ActivationQuant::F32)?;
+        for (_, _, module) in unpacked.linear_modules_mut() {
+            module.legacy_linear.as_mut().unwrap().packed_params = None;
+        }
+        let err = DecodeWorkspace::new(&unpacked, 8)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("precompute_packed"), "{}", err);
+
+        // Positions run out at max_len; reset starts over
+        let model = model(ModelArch::Llama, ActivationQuant::F32)?;
+        let mut ws = DecodeWorkspace::new(&model, 2)?;
+        let first = model.forward_one_into(7, &mut ws)?.to_vec();
+        model.forward_one_into(8, &mut ws)?;
+        assert!(model.forward_one_into(9, &mut ws).is_err());
+        ws.reset();
+        assert_eq!(model.forward_one_into(7, &mut ws)?, first.as_slice());
+        assert!(model.forward_one_into(48, &mut ws).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_workspace_grows_and_hands_over() -> anyhow::Result<()> {
+        for arch in [ModelArch::Llama, ModelArch::TTT] {
+            let mut model = model(arch, ActivationQuant::F32)?;
+            let tokens: Vec<u32> = (0..270u32).map(|t| t * 7 % 48).collect();
+            let mut w_states = model.new_w_states();
+            let mut expected = Vec::new();
+            for &token in &tokens {
+                let x = Tensor::new(&[token], &Device::Cpu)?;
+                expected = model
+                    .forward_one(&x, &mut w_states)?
+                    .flatten_all()?
+                    .to_vec1()?;
+            }
+
+            // K/V rows grow past the initial capacity, then the session moves
+            // into the caches of forward_one
+            model.reset_kv_cache();
+            let mut ws = DecodeWorkspace::new(&model, 264)?;
+            for &token in &tokens[..264] {
+                model.forward_one_into(token, &mut ws)?;
+            }
+            let mut w_states = model.new_w_states();
+            ws.hand_over(&mut model, &mut w_states)?;
+            assert_eq!(model.current_pos, 264);
+            let mut got = Vec::new();
+            for &token in &tokens[264..] {
+                let x = Tensor::new(&[token], &Device::Cpu)?;
+                got = model
+                    .forward_one(&x, &mut w_states)?
+                    .flatten_all()?
+                    .to_vec1()?;
+            }
+            let err = max_rel_error(&got, &expected);
+            assert!(err < 1e-4, "{:?}: {}", arch, err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_window_follows_kv_cache_dtype() -> anyhow::Result<()> {
+        let mut llama = model(ModelArch::Llama, ActivationQuant::F32)?;
+        assert_eq!(llama.decode_window(), 2048);
+        llama.config.kv_cache_dtype = KvCacheDtype::Q8;
+        assert_eq!(llama.decode_window(), 512);
+        llama.config.kv_cache_dtype = KvCacheDtype::Kivi;
+        assert_eq!(llama.decode_window(), 256);
+        // No K/V to bound
+        let mut ttt = model(ModelArch::TTT, ActivationQuant::F32)?;
+        ttt.config.kv_cache_dtype = KvCacheDtype::Q4;
+        assert_eq!(ttt.decode_window(), 2048);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::attention_cpu::FlashAttentionCpu;
+    use crate::layers::{BitAttention, QuantizedKVCache};
+    use crate::model::{BitLlama, BitLlamaConfig, ModelArch};
+    use candle_core::{DType, Device, Tensor, D};
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn pattern(shape: (usize, usize, usize, usize), seed: f32) -> Tensor {
+        let n = shape.0 * shape.1 * shape.2 * shape.3;
+        let data: Vec<f32> = (0..n).map(|i| ((i as f32 + seed) * 0.71).sin()).collect();
+        Tensor::from_vec(data, shape, &Device::Cpu).unwrap()
+    }
+
+    /// Materialized reference: repeat_kv + full score matrix + explicit causal mask
+    fn reference(q: &Tensor, k: &Tensor, v: &Tensor, scale: f64) -> anyhow::Result<Tensor> {
+        let (b, h, t, d) = q.dims4()?;
+        let (_, kvh, k_len, _) = k.dims4()?;
+        let rep = h / kvh;
+        let expand = |x: &Tensor| -> candle_core::Result<Tensor> {
+            x.unsqueeze(2)?
+                .expand((b, kvh, rep, k_len, d))?
+                .reshape((b, h, k_len, d))
+        };
+        let (k, v) = (expand(k)?, expand(v)?);
+        let att = (q.matmul(&k.t()?)? * scale)?;
+        let att = if t > 1 {
+            let past = k_len - t;
+            let mask: Vec<f32> = (0..t)
+                .flat_map(|i| {
+                    (0..k_len).map(move |j| {
+                        if j <= i + past {
+                            0.0
+                        } else {
+                            f32::NEG_INFINITY
+                        }
+                    })
+                })
+                .collect();
+            att.broadcast_add(&Tensor::from_vec(mask, (1, 1, t, k_len), &Device::Cpu)?)?
+        } else {
+            att
+        };
+        Ok(candle_nn::ops::softmax(&att, D::Minus1)?.matmul(&v)?)
+    }
+
+    #[test]
+    fn test_flash_attention_matches_reference() -> anyhow::Result<()> {
+        // (batch, heads, kv_heads, queries, keys, head_dim)
+        let cases = [
+            (1, 4, 4, 1, 70, 8),    // decode
+            (2, 4, 2, 37, 37, 8),   // GQA prefill, T not a tile multiple
+            (1, 6, 2, 20, 150, 16), // chunked prefill on top of a cache
+            (1, 2, 1, 130, 130, 4), // several K blocks, causal
+        ];
+        for (b, h, kvh, t, k_len, d) in cases {
+            let q = pattern((b, h, t, d), 1.0);
+            let k = pattern((b, kvh, k_len, d), 2.0);
+            let v = pattern((b, kvh, k_len, d), 3.0);
+            let scale = 1.0 / (d as f64).sqrt();
+
+            let got = FlashAttentionCpu::forward(&q, &k, &v, scale)?;
+            let expected = reference(&q, &k, &v, scale)?;
+            assert_eq!(got.dims(), expected.dims());
+            let diff = (got - expected)?
+                .abs()?
+                .flatten_all()?
+                .max(0)?
+                .to_scalar::<f32>()?;
+            assert!(
+                diff < 1e-5,
+                "case {:?}: max diff {}",
+                (b, h, kvh, t, k_len, d),
+                diff
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_flash_attention_accepts_strided_inputs() -> anyhow::Result<()> {
+        // [B, T, H, D] -> [B, H, T, D] view, as produced by the attention layer
+        let q = pattern((1, 9, 2, 8), 5.0).transpose(1, 2)?;
+        let k = pattern((1, 9, 2, 8), 6.0).transpose(1, 2)?;
+        let v = pattern((1, 9, 2, 8), 7.0).transpose(1, 2)?;
+        let got = FlashAttentionCpu::forward(&q, &k, &v, 0.35)?;
+        let expected = reference(&q.contiguous()?, &k.contiguous()?, &v.contiguous()?, 0.35)?;
+        let diff = (got - expected)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+        Ok(())
+    }
+
+    fn max_abs(t: Tensor) -> anyhow::Result<f32> {
+        Ok(t.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+    }
+
+    #[test]
+    fn test_q8_attention_matches_dequantized_f32() -> anyhow::Result<()> {
+        // GQA, prefill then decode, over the in-place cache views
+        let (h, kvh, d) = (4, 2, 16);
+        let mut cache = QuantizedKVCache::new(64);
+        let steps = [(12, 10.0), (1, 20.0), (1, 30.0), (5, 40.0)];
+        for (t, seed) in steps {
+            let q = pattern((1, h, t, d), seed);
+            let k = pattern((1, kvh, t, d), seed + 1.0);
+            let v = pattern((1, kvh, t, d), seed + 2.0);
+            let kv = cache.append_quantized(&k, &v)?;
+
+            let got = FlashAttentionCpu::forward_quantized(&q, &kv, 0.25)?;
+            let (k_f, v_f) = kv.dequantize()?;
+            let expected = FlashAttentionCpu::forward(&q, &k_f, &v_f, 0.25)?;
+            let diff = max_abs((got - expected)?)?;
+            assert!(diff < 1e-5, "t={}: diff {}", t, diff);
+        }
+        assert_eq!(cache.len(), 19);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantized_kv_attention_layer_accuracy() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        BitAttention::load(64, 4, 2, 10000.0, 64, vb.clone(), &device)?;
+        // Deterministic weights (VarMap init is random)
+        for (name, var) in varmap.data().lock().unwrap().iter() {
+            let seed = name.len() as f32;
+            let data: Vec<f32> = (0..var.elem_count())
+                .map(|i| ((i as f32 * 0.61 + seed) * 1.3).sin() * 0.125)
+                .collect();
+            var.set(&Tensor::from_vec(data, var.dims(), &device)?)?;
+        }
+        let f32_attn = BitAttention::load(64, 4, 2, 10000.0, 64, vb, &device)?;
+        let mut q8_attn = f32_attn.clone();
+        q8_attn.quantized_kv = true;
+
+        let mut f32_cache = Some(QuantizedKVCache::new(64));
+        let mut q8_cache = Some(QuantizedKVCache::new(64));
+        let mut no_cache = None;
+
+        let x = Tensor::from_vec(
+            (0..64 * 8)
+                .map(|i| ((i as f32) * 0.37).sin())
+                .collect::<Vec<f32>>(),
+            (1, 8, 64),
+            &device,
+        )?;
+        // Full-precision reference (no quantization at all) over the whole sequence
+        let reference = f32_attn.forward(&x, &mut no_cache, 0)?;
+
+        let mut pos = 0;
+        let mut outputs = Vec::new();
+        for len in [5, 1, 1, 1] {
+            let chunk = x.narrow(1, pos, len)?;
+            let a = f32_attn.forward(&chunk, &mut f32_cache, pos)?;
+            let b = q8_attn.forward(&chunk, &mut q8_cache, pos)?;
+            // Same quantized values either way: fused path must match the f32 path
+            let diff = max_abs((&a - &b)?)?;
+            assert!(diff < 1e-4, "pos {}: fused vs f32 diff {}", pos, diff);
+            outputs.push(b);
+            pos += len;
+        }
+
+        // Q8 error vs unquantized attention stays small
+        let fused = Tensor::cat(&outputs, 1)?;
+        let err = max_abs((fused - reference.narrow(1, 0, pos)?)?)?;
+        let scale = max_abs(reference)?;
+        assert!(
+            err < 0.05 * scale.max(1e-3),
+            "q8 error {} (scale {})",
+            err,
+            scale
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_attention_training_keeps_gradients() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let mut cfg = BitLlamaConfig::new(32, 64, 1, 0.1, None);
+        cfg.arch = ModelArch::Llama;
+        cfg.n_heads = 4;
+        cfg.n_kv_heads = 2;
+        let varmap = VarMap::new();
+        let model = BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+
+        // Training forward as in PyTrainer: chunkwise over unpacked weights
+        let x = Tensor::new(&[[1u32, 5, 9, 3, 7, 2]], &device)?;
+        let mut w_states = model.new_w_states();
+        let logits = model.forward_chunkwise(&x, &mut w_states, 4)?;
+        let grads = logits.sqr()?.mean_all()?.backward()?;
+
+        let data = varmap.data().lock().unwrap();
+        let q_proj = data
+            .iter()
+            .find(|(name, _)| name.contains("q_proj"))
+            .map(|(_, var)| var)
+            .expect("q_proj variable");
+        let grad = grads.get(q_proj).expect("q_proj gradient");
+        assert!(max_abs(grad.clone())? > 0.0);
+        Ok(())
+    }
+}
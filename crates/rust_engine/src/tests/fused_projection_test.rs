@@ -0,0 +1,194 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::backend;
+    use crate::kernels::cpu::{self, ActivationQuant, Epilogue, KernelScratch, RmsNormIn};
+    use crate::kernels::packing::{PackedTensor, WeightScale, LUT_ROWS};
+    use crate::layers::{AdaptiveBitLinear, FusedProjection};
+    use crate::model::ModelArch;
+    use crate::model::{BitLlama, DecodeWorkspace, LayerDispatch, MlpDispatch};
+    use crate::test_util::{max_rel_error, packed_model, small_config};
+    use candle_core::{Device, Tensor};
+    use candle_nn::VarMap;
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 7) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    fn with_layout(packed: PackedTensor, activations: ActivationQuant) -> PackedTensor {
+        match activations {
+            ActivationQuant::Int8 => packed.with_int8_layout().unwrap(),
+            ActivationQuant::Lut => packed.with_lut_layout().unwrap(),
+            _ => packed,
+        }
+    }
+
+    #[test]
+    fn test_fused_kernels_match_separate_projections() -> anyhow::Result<()> {
+        let (m, k) = (3, 256);
+        let x = pattern(m, k, 1.0).flatten_all()?.to_vec1::<f32>()?;
+        let norm_weight: Vec<f32> = (0..k).map(|i| 0.5 + (i % 5) as f32 * 0.25).collect();
+        let norm = RmsNormIn {
+            weight: &norm_weight,
+            eps: 1e-5,
+        };
+        let mut normed = vec![0.0; x.len()];
+        cpu::rms_norm_into(&x, norm, &mut normed)?;
+
+        let weights = [
+            pattern(64, k, 2.0),
+            pattern(32, k, 3.0),
+            pattern(32, k, 4.0),
+        ];
+        for name in ["scalar", "auto"] {
+            let kernel = backend::get(name)?;
+            for activations in [
+                ActivationQuant::F32,
+                ActivationQuant::Int8,
+                ActivationQuant::Lut,
+            ] {
+                for scale in [
+                    WeightScale::Tensor,
+                    WeightScale::Channel,
+                    WeightScale::Group,
+                ] {
+                    let parts: Vec<_> = weights
+                        .iter()
+                        .map(|w| {
+                            with_layout(
+                                PackedTensor::pack_with(w, scale, 128).unwrap(),
+                                activations,
+                            )
+                        })
+                        .collect();
+                    let mut scratch = KernelScratch::default();
+                    let case = format!("{} {:?} {:?}", name, activations, scale);
+
+                    // q/k/v: one call, stacked outputs
+                    let stacked = PackedTensor::concat_rows(&parts.iter().collect::<Vec<_>>())?;
+                    let stacked = with_layout(stacked, activations);
+                    let mut got = vec![0.0; m * 128];
+                    kernel.fused_matmul_into(
+                        &x,
+                        Some(norm),
+                        &stacked,
+                        Epilogue::Linear,
+                        &mut scratch,
+                        &mut got,
+                    )?;
+                    for (i, part) in parts.iter().enumerate() {
+                        let n = part.shape.dims2()?.0;
+                        let mut expected = vec![0.0; m * n];
+                        kernel.matmul_into(&normed, part, &mut scratch, &mut expected)?;
+                        let offset = [0, 64, 96][i];
+                        let got: Vec<f32> = got
+                            .chunks(128)
+                            .flat_map(|row| row[offset..offset + n].to_vec())
+                            .collect();
+                        let err = max_rel_error(&got, &expected);
+                        assert!(err < 1e-5, "{} part {}: {}", case, i, err);
+                    }
+
+                    // gate/up: stacked rows, SiLU epilogue
+                    let (gate, up) = (&parts[1], &parts[2]);
+                    let gate_up = with_layout(PackedTensor::concat_rows(&[gate, up])?, activations);
+                    let mut got = vec![0.0; m * 32];
+                    kernel.fused_matmul_into(
+                        &x,
+                        Some(norm),
+                        &gate_up,
+                        Epilogue::SwiGlu,
+                        &mut scratch,
+                        &mut got,
+                    )?;
+                    let (mut g, mut u) = (vec![0.0; m * 32], vec![0.0; m * 32]);
+                    kernel.matmul_into(&normed, gate, &mut scratch, &mut g)?;
+                    kernel.matmul_into(&normed, up, &mut scratch, &mut u)?;
+                    let expected: Vec<f32> =
+                        g.iter().zip(&u).map(|(&g, &u)| cpu::silu(g) * u).collect();
+                    let err = max_rel_error(&got, &expected);
+                    assert!(err < 1e-5, "{} swiglu: {}", case, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Llama model on the weights of `varmap`, all projections packed
+    fn model(varmap: &VarMap, fused: bool) -> anyhow::Result<BitLlama> {
+        let mut cfg = small_config(ModelArch::Llama);
+        cfg.activation_quant = ActivationQuant::Lut;
+        cfg.fused_projections = fused;
+        // Pack the attention projections too, then let the blocks fuse
+        let mut model = packed_model(cfg, varmap)?;
+        model.precompute_packed()?;
+        Ok(model)
+    }
+
+    /// Whether the codes and LUT layouts of `parts` point into `fused` (no copies)
+    fn shares_bytes(fused: &FusedProjection, parts: &[&AdaptiveBitLinear]) -> anyhow::Result<bool> {
+        let k = fused.weight.shape.dims2()?.1;
+        let (data, lut) = (
+            fused.weight.data.slice()?,
+            fused.weight.lut_layout.as_ref().unwrap().slice()?,
+        );
+        let (data, lut) = (data.as_slice()?, lut.as_slice()?);
+        let mut row = 0;
+        for part in parts {
+            let packed = part.legacy_linear.as_ref().unwrap();
+            let packed = packed.packed_params.as_ref().unwrap();
+            let part_data = packed.data.slice()?;
+            let part_lut = packed.lut_layout.as_ref().unwrap().slice()?;
+            let tile = row / LUT_ROWS * k.div_ceil(4) * LUT_ROWS;
+            if part_data.as_slice()?.as_ptr() != data[row * k / 4..].as_ptr()
+                || part_lut.as_slice()?.as_ptr() != lut[tile..].as_ptr()
+            {
+                return Ok(false);
+            }
+            row += part.out_features;
+        }
+        Ok(true)
+    }
+
+    #[test]
+    fn test_model_forward_with_fused_projections() -> anyhow::Result<()> {
+        let varmap = VarMap::new();
+        let mut fused = model(&varmap, true)?;
+        for layer in &fused.layers {
+            let LayerDispatch::Attention(a) = &layer.core else {
+                panic!("expected attention layers");
+            };
+            let qkv = a.qkv_fusion().unwrap();
+            assert!(shares_bytes(qkv, &[&a.q_proj, &a.k_proj, &a.v_proj])?);
+            let MlpDispatch::SwiGLU(mlp) = &layer.mlp else {
+                panic!("expected SwiGLU MLPs");
+            };
+            let gate_up = mlp.gate_up_fusion().unwrap();
+            assert!(shares_bytes(gate_up, &[&mlp.w1, &mlp.w3])?);
+        }
+
+        // Same weights without fusion give the same logits (tensor path and workspace)
+        let mut plain = model(&varmap, false)?;
+        assert!(plain.layers.iter().all(|l| match (&l.core, &l.mlp) {
+            (LayerDispatch::Attention(a), MlpDispatch::SwiGLU(m)) =>
+                a.fused_qkv.is_none() && m.fused_gate_up.is_none(),
+            _ => false,
+        }));
+
+        let mut ws = DecodeWorkspace::new(&fused, 8)?;
+        let (mut w_fused, mut w_plain) = (fused.new_w_states(), plain.new_w_states());
+        for token in [3u32, 17, 5, 40] {
+            let x = Tensor::new(&[token], &Device::Cpu)?;
+            let expected = plain.forward_one(&x, &mut w_plain)?;
+            let expected = expected.flatten_all()?.to_vec1::<f32>()?;
+            let got = fused.forward_one(&x, &mut w_fused)?;
+            let got = got.flatten_all()?.to_vec1::<f32>()?;
+            assert!(max_rel_error(&got, &expected) < 1e-4);
+            let got = fused.forward_one_into(token, &mut ws)?;
+            assert!(max_rel_error(got, &expected) < 1e-4);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{BitLinearCpu, CpuSimd};
+    use crate::kernels::packing::PackedTensor;
+    use candle_core::{Device, Tensor};
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 5) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_tiled_gemm_matches_reference() -> anyhow::Result<()> {
+        // Partial register tiles (M % 4, N % 16), K across slices (K > 256),
+        // several row / column tasks (M > 64, N > 64), tiny shapes
+        for (m, k, n) in [
+            (5, 300, 70),
+            (130, 64, 17),
+            (16, 512, 128),
+            (2, 8, 3),
+            (1, 40, 9),
+        ] {
+            let x = pattern(m, k, 1.0);
+            let packed = PackedTensor::pack(&pattern(n, k, 2.0))?;
+            let got = BitLinearCpu::forward_tiled(&x, &packed)?;
+            let expected = BitLinearCpu::forward_with_simd(&x, &packed, CpuSimd::Scalar)?;
+            assert_eq!(got.dims(), &[m, n]);
+
+            let diff = (&got - &expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            let scale = expected.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert!(
+                diff <= 1e-5 * scale.max(1.0),
+                "{}x{}x{}: diff {}",
+                m,
+                k,
+                n,
+                diff
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_forward_dispatches_batches_to_tiled_gemm() -> anyhow::Result<()> {
+        let x = pattern(8, 96, 3.0);
+        let packed = PackedTensor::pack(&pattern(24, 96, 4.0))?;
+        let batched = BitLinearCpu::forward(&x, &packed)?;
+        assert_eq!(
+            batched.flatten_all()?.to_vec1::<f32>()?,
+            BitLinearCpu::forward_tiled(&x, &packed)?
+                .flatten_all()?
+                .to_vec1::<f32>()?
+        );
+
+        // Row-by-row decode agrees with the batch
+        let scale = batched.abs()?.max_all()?.to_scalar::<f32>()?;
+        for i in 0..8 {
+            let row = BitLinearCpu::forward(&x.narrow(0, i, 1)?, &packed)?;
+            let diff = (row - batched.narrow(0, i, 1)?)?
+                .abs()?
+                .max_all()?
+                .to_scalar::<f32>()?;
+            assert!(diff <= 1e-5 * scale, "row {}: diff {}", i, diff);
+        }
+        Ok(())
+    }
+}
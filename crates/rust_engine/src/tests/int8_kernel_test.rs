@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{quantize_activations, ActivationQuant, BitLinearCpu};
+    use crate::kernels::packing::{PackedTensor, INT8_BLOCK};
+    use crate::layers::SwiGLU;
+    use crate::model::{BitLlama, BitLlamaConfig, MlpDispatch};
+    use crate::test_util::rel_error;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 7) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_int8_kernel_matches_quantized_reference() -> anyhow::Result<()> {
+        // K not a multiple of the block (padding), M > 1 (per-token scales)
+        for (m, k, n) in [(1, 256, 16), (3, 200, 9), (5, 132, 33)] {
+            let x = pattern(m, k, 1.0);
+            let w = pattern(n, k, 2.0);
+            let packed = PackedTensor::pack(&w)?.with_int8_layout()?;
+            let got = BitLinearCpu::forward_int8(&x, &packed)?;
+
+            // Same quantized activations, dense matmul
+            let k_pad = k.div_ceil(INT8_BLOCK) * INT8_BLOCK;
+            let (x_q, scales) = quantize_activations(&x.flatten_all()?.to_vec1::<f32>()?, k, k_pad);
+            let x_deq: Vec<f32> = x_q
+                .chunks(k_pad)
+                .zip(&scales)
+                .flat_map(|(row, &s)| row[..k].iter().map(move |&q| q as f32 * s))
+                .collect();
+            let x_deq = Tensor::from_vec(x_deq, (m, k), &Device::Cpu)?;
+            let expected = x_deq.matmul(&packed.unpack(&Device::Cpu)?.t()?)?;
+            let err = rel_error(&got, &expected)?;
+            assert!(err < 1e-5, "{}x{}x{}: error {}", m, k, n, err);
+
+            // Activation quantization error vs the f32 kernel stays small
+            let f32_out = x.matmul(&packed.unpack(&Device::Cpu)?.t()?)?;
+            let err = rel_error(&got, &f32_out)?;
+            assert!(err < 0.02, "{}x{}x{}: int8 vs f32 error {}", m, k, n, err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_int8_kernel_requires_layout_and_handles_zero_rows() -> anyhow::Result<()> {
+        let w = pattern(4, 128, 3.0);
+        let packed = PackedTensor::pack(&w)?;
+        let x = Tensor::zeros((2, 128), DType::F32, &Device::Cpu)?;
+        assert!(BitLinearCpu::forward_int8(&x, &packed).is_err());
+
+        let out = BitLinearCpu::forward_int8(&x, &packed.with_int8_layout()?)?;
+        assert_eq!(out.sum_all()?.to_scalar::<f32>()?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_activation_quant_selected_by_config() -> anyhow::Result<()> {
+        assert_eq!("int8".parse::<ActivationQuant>(), Ok(ActivationQuant::Int8));
+        assert!("int4".parse::<ActivationQuant>().is_err());
+
+        let device = Device::Cpu;
+        let cfg = BitLlamaConfig::new(32, 64, 2, 0.1, None);
+        let varmap = VarMap::new();
+        BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+        // Deterministic weights (VarMap init is random), unit norms
+        for (name, var) in varmap.data().lock().unwrap().iter() {
+            let dims = var.dims().to_vec();
+            if name.contains("norm") {
+                var.set(&Tensor::ones(dims, DType::F32, &device)?)?;
+                continue;
+            }
+            let seed = name.len() as f32;
+            let scale = 1.0 / (*dims.last().unwrap() as f32).sqrt();
+            let data: Vec<f32> = (0..var.elem_count())
+                .map(|i| ((i as f32 * 0.61 + seed) * 1.3).sin() * scale)
+                .collect();
+            var.set(&Tensor::from_vec(data, dims, &device)?)?;
+        }
+
+        let mut logits = Vec::new();
+        for activations in [ActivationQuant::F32, ActivationQuant::Int8] {
+            let mut cfg = cfg;
+            cfg.activation_quant = activations;
+            let mut model =
+                BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+            model.precompute_packed()?;
+
+            let MlpDispatch::SwiGLU(mlp) = &model.layers[0].mlp else {
+                panic!("dense MLP expected");
+            };
+            let SwiGLU { w1, .. } = mlp.as_ref();
+            let packed = w1.legacy_linear.as_ref().unwrap().packed_params.as_ref();
+            assert_eq!(
+                packed.unwrap().int8_layout.is_some(),
+                activations == ActivationQuant::Int8
+            );
+
+            let mut seq = model.new_sequence()?;
+            let mut out = Vec::new();
+            for t in [1u32, 5, 9, 3] {
+                out.push(model.forward_batch(&[t], &mut [&mut seq])?);
+            }
+            logits.push(Tensor::cat(&out, 0)?);
+        }
+        let err = rel_error(&logits[1], &logits[0])?;
+        assert!(err < 0.05, "int8 vs f32 logits error {}", err);
+        Ok(())
+    }
+}
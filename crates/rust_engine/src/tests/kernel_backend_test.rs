@@ -0,0 +1,202 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::backend::{self, PagedRead, ScalarKernel, TernaryKernel};
+    use crate::kernels::cpu::CpuSimd;
+    use crate::kernels::packing::{MultiBasePacked, PackedTensor};
+    use crate::layers::{KvView, QuantizedKV};
+    use crate::test_util::tensor_max_rel_error;
+    use candle_core::{Device, Result, Tensor};
+    use std::sync::Arc;
+
+    fn pattern(shape: &[usize], seed: f32) -> Tensor {
+        let n: usize = shape.iter().product();
+        let data: Vec<f32> = (0..n)
+            .map(|i| ((i as f32 + seed) * 0.53).sin() * (1.0 + (i % 5) as f32))
+            .collect();
+        Tensor::from_vec(data, shape, &Device::Cpu).unwrap()
+    }
+
+    /// CPU backends this machine can run
+    fn cpu_kernels() -> anyhow::Result<Vec<Arc<dyn TernaryKernel>>> {
+        let kernels = backend::available()
+            .into_iter()
+            .map(backend::get)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(kernels
+            .into_iter()
+            .filter(|k| k.supports(&Device::Cpu))
+            .collect())
+    }
+
+    #[test]
+    fn test_registry_lookup() -> anyhow::Result<()> {
+        let names = backend::available();
+        assert!(names.contains(&"scalar") && names.contains(&"portable"));
+        assert!(!names.contains(&"cuda") || candle_core::utils::cuda_is_available());
+
+        let auto = backend::get("auto")?;
+        assert_eq!(
+            auto.name(),
+            match CpuSimd::detect() {
+                CpuSimd::Scalar => "portable",
+                CpuSimd::Avx2 => "avx2",
+                CpuSimd::Neon => "neon",
+            }
+        );
+        let err = backend::get("tensor-cores").err().unwrap().to_string();
+        assert!(err.contains("expected auto, scalar"), "{}", err);
+        assert!(backend::select("cuda").is_err());
+
+        // Registering adds a backend by name without touching the selection
+        let selected = backend::selected();
+        backend::register(Arc::new(Offset("test-offset", 0.0)));
+        assert_eq!(backend::get("test-offset")?.name(), "test-offset");
+        assert_eq!(backend::selected(), selected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backends_match_scalar_reference() -> anyhow::Result<()> {
+        let cpu = cpu_kernels()?;
+        assert!(cpu.len() >= 2);
+
+        for (m, k, n) in [(1, 256, 40), (4, 320, 70)] {
+            let x = pattern(&[m, k], 1.0);
+            let packed = PackedTensor::pack(&pattern(&[n, k], 2.0))?;
+            let layouts = [
+                ("f32", packed.clone()),
+                ("int8", packed.clone().with_int8_layout()?),
+                ("lut", packed.with_lut_layout()?),
+            ];
+            for (layout, w) in &layouts {
+                let expected = ScalarKernel.matmul(&x, w)?;
+                for kernel in &cpu {
+                    let got = kernel.matmul(&x, w)?;
+                    let err = tensor_max_rel_error(&got, &expected)?;
+                    assert!(
+                        err < 1e-5,
+                        "{} {} {}x{}x{}: {}",
+                        kernel.name(),
+                        layout,
+                        m,
+                        k,
+                        n,
+                        err
+                    );
+                }
+            }
+
+            // Two bases, every 2-bit code (incl. the unused 11)
+            let codes: Vec<u8> = (0..n * k / 4 * 2).map(|i| (i * 37 % 256) as u8).collect();
+            let data = Tensor::from_vec(codes, (n, k / 4, 2), &Device::Cpu)?;
+            let multibase = MultiBasePacked::new(data, vec![0.7, 0.2], n, k)?;
+            let expected = ScalarKernel.adaptive_matmul(&x, &multibase)?;
+            for kernel in &cpu {
+                let got = kernel.adaptive_matmul(&x, &multibase)?;
+                assert!(
+                    tensor_max_rel_error(&got, &expected)? < 1e-5,
+                    "{} adaptive",
+                    kernel.name()
+                );
+            }
+        }
+
+        // GQA prefill over a longer cache; f32 and Q8 K/V
+        let q = pattern(&[1, 4, 9, 8], 4.0);
+        let (k, v) = (pattern(&[1, 2, 30, 8], 5.0), pattern(&[1, 2, 30, 8], 6.0));
+        let float = backend::float_kv(&k, &v);
+        let q8 = |x: &Tensor| -> anyhow::Result<KvView> {
+            let scale = (x.abs()?.max_keepdim(3)? / 127.0)?;
+            let data =
+                (x.broadcast_div(&scale)?.round()? + 128.0)?.to_dtype(candle_core::DType::U8)?;
+            Ok(KvView::Q8 { data, scale })
+        };
+        let quantized = QuantizedKV {
+            k: q8(&k)?,
+            v: q8(&v)?,
+        };
+        for kv in [&float, &quantized] {
+            let expected = ScalarKernel.attention(&q, kv, 0.35)?;
+            for kernel in &cpu {
+                let got = kernel.attention(&q, kv, 0.35)?;
+                assert!(
+                    tensor_max_rel_error(&got, &expected)? < 1e-5,
+                    "{} attention",
+                    kernel.name()
+                );
+            }
+        }
+
+        // Paged: 10 tokens in blocks of 4 scattered over a pool of 5 blocks
+        let q = pattern(&[1, 4, 1, 8], 7.0);
+        let read = PagedRead {
+            blocks: &[3, 0, 4],
+            block_size: 4,
+            len: 10,
+        };
+        let pool = backend::float_kv(&pattern(&[1, 2, 20, 8], 8.0), &pattern(&[1, 2, 20, 8], 9.0));
+        let expected = ScalarKernel.paged_attention(&q, &pool, read, 0.35)?;
+        for kernel in &cpu {
+            let got = kernel.paged_attention(&q, &pool, read, 0.35)?;
+            assert!(
+                tensor_max_rel_error(&got, &expected)? < 1e-5,
+                "{} paged",
+                kernel.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Scalar reference named `.0` whose matmul is off by `.1`
+    struct Offset(&'static str, f64);
+
+    impl TernaryKernel for Offset {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn supports(&self, device: &Device) -> bool {
+            device.is_cpu()
+        }
+
+        fn matmul(&self, x: &Tensor, w: &PackedTensor) -> Result<Tensor> {
+            ScalarKernel.matmul(x, w)? + self.1
+        }
+
+        fn adaptive_matmul(&self, x: &Tensor, w: &MultiBasePacked) -> Result<Tensor> {
+            ScalarKernel.adaptive_matmul(x, w)
+        }
+
+        fn attention(&self, q: &Tensor, kv: &QuantizedKV, scale: f64) -> Result<Tensor> {
+            ScalarKernel.attention(q, kv, scale)
+        }
+
+        fn paged_attention(
+            &self,
+            q: &Tensor,
+            kv: &QuantizedKV,
+            read: PagedRead,
+            scale: f64,
+        ) -> Result<Tensor> {
+            ScalarKernel.paged_attention(q, kv, read, scale)
+        }
+    }
+
+    #[test]
+    fn test_cross_check_reports_mismatch() -> anyhow::Result<()> {
+        let x = pattern(&[2, 64], 1.0);
+        let w = PackedTensor::pack(&pattern(&[16, 64], 2.0))?;
+
+        let exact = backend::cross_checked(Arc::new(Offset("exact", 0.0)));
+        assert!(exact.matmul(&x, &w).is_ok());
+
+        let broken = backend::cross_checked(Arc::new(Offset("broken", 0.5)));
+        let err = broken.matmul(&x, &w).err().unwrap().to_string();
+        assert!(err.contains("kernel broken matmul"), "{}", err);
+        // Other ops are still verified (and agree)
+        let q = pattern(&[1, 2, 3, 8], 3.0);
+        let kv = backend::float_kv(&pattern(&[1, 1, 5, 8], 4.0), &pattern(&[1, 1, 5, 8], 5.0));
+        assert!(broken.attention(&q, &kv, 0.3).is_ok());
+        Ok(())
+    }
+}
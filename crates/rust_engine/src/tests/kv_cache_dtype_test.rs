@@ -0,0 +1,238 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::attention_cpu::FlashAttentionCpu;
+    use crate::layers::kv_quant::{fp8_encode, fp8_table};
+    use crate::layers::{KvCacheDtype, KvView, QuantizedKVCache};
+    use crate::model::{BitLlama, BitLlamaConfig, ModelArch};
+    use crate::test_util::rel_error;
+    use candle_core::{DType, Device, Tensor, D};
+    use candle_nn::{VarBuilder, VarMap};
+
+    const ALL: [KvCacheDtype; 6] = [
+        KvCacheDtype::F32,
+        KvCacheDtype::F16,
+        KvCacheDtype::Q8,
+        KvCacheDtype::Q4,
+        KvCacheDtype::Fp8,
+        KvCacheDtype::Kivi,
+    ];
+
+    /// Smooth values with a few large-magnitude channels (typical of keys)
+    fn keys(t: usize, seed: f32) -> Tensor {
+        let (h, d) = (2, 16);
+        let data: Vec<f32> = (0..h * t * d)
+            .map(|i| {
+                let x = ((i as f32 + seed) * 0.37).sin();
+                if i % d == 3 || i % d == 11 {
+                    8.0 + x
+                } else {
+                    x
+                }
+            })
+            .collect();
+        Tensor::from_vec(data, (1, h, t, d), &Device::Cpu).unwrap()
+    }
+
+    /// Fill a cache in uneven chunks (crosses KIVI group boundaries)
+    fn fill(dtype: KvCacheDtype) -> anyhow::Result<(Tensor, Tensor)> {
+        let mut cache = QuantizedKVCache::with_dtype(128, dtype);
+        let mut out = None;
+        for (t, seed) in [(40, 1.0), (1, 2.0), (30, 3.0), (1, 4.0)] {
+            let k = keys(t, seed);
+            out = Some(cache.append(&k, &k)?);
+        }
+        assert_eq!(cache.len(), 72);
+        Ok(out.unwrap())
+    }
+
+    #[test]
+    fn test_kv_cache_dtype_reconstruction_error() -> anyhow::Result<()> {
+        let expected = Tensor::cat(
+            &[keys(40, 1.0), keys(1, 2.0), keys(30, 3.0), keys(1, 4.0)],
+            2,
+        )?;
+        let mut errors = Vec::new();
+        for dtype in ALL {
+            let (k, v) = fill(dtype)?;
+            assert_eq!(k.dims(), &[1, 2, 72, 16]);
+            let (k_err, v_err) = (rel_error(&k, &expected)?, rel_error(&v, &expected)?);
+            println!(
+                "{:?}: key error {:.5}, value error {:.5}",
+                dtype, k_err, v_err
+            );
+            errors.push((dtype, k_err, v_err));
+        }
+        let err = |d: KvCacheDtype| errors.iter().find(|e| e.0 == d).unwrap();
+
+        assert_eq!(err(KvCacheDtype::F32).1, 0.0);
+        assert!(err(KvCacheDtype::F16).1 < 1e-3);
+        assert!(err(KvCacheDtype::Q8).1 < 0.01);
+        assert!(err(KvCacheDtype::Fp8).1 < 0.05);
+        assert!(err(KvCacheDtype::Q4).1 < 0.2);
+        // Per-channel keys absorb the outlier channels that per-token Q4 cannot
+        assert!(err(KvCacheDtype::Kivi).1 < err(KvCacheDtype::Q4).1 / 2.0);
+        // KIVI values are per-token Q4
+        assert_eq!(err(KvCacheDtype::Kivi).2, err(KvCacheDtype::Q4).2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_q8_cache_keeps_no_f32_mirror() -> anyhow::Result<()> {
+        let mut cache = QuantizedKVCache::with_dtype(128, KvCacheDtype::Q8);
+        for (t, seed) in [(40, 1.0), (1, 2.0), (1, 3.0)] {
+            let k = keys(t, seed);
+            let (k_out, _) = cache.append(&k, &k)?;
+            assert_eq!(k_out.dtype(), DType::F32);
+        }
+        // K and V: u8 codes plus one f32 scale per token-head, nothing else
+        let (h, d) = (2, 16);
+        let q8 = 2 * h * cache.capacity() * (d + 4);
+        assert_eq!(cache.bytes(), q8);
+        assert!(cache.bytes() < 2 * h * cache.capacity() * d * 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fp8_e4m3_codes() {
+        let table = fp8_table();
+        assert_eq!(table[0x38], 1.0);
+        assert_eq!(table[0x7E], 448.0);
+        assert_eq!(table[0x01], 2f32.powi(-9));
+        assert_eq!(table[0xB8], -1.0);
+        assert!(table[0x7F].is_nan());
+        for code in (0u8..0x7F).chain(0x81..0xFF) {
+            assert_eq!(fp8_encode(table[code as usize]), code);
+        }
+        // Saturation and rounding to nearest
+        assert_eq!(fp8_encode(1000.0), 0x7E);
+        assert_eq!(fp8_encode(-1000.0), 0xFE);
+        assert_eq!(table[fp8_encode(1.06) as usize], 1.0);
+        assert_eq!(table[fp8_encode(1.07) as usize], 1.125);
+    }
+
+    #[test]
+    fn test_fused_attention_matches_dequantized_for_every_dtype() -> anyhow::Result<()> {
+        for dtype in ALL {
+            let mut fused = QuantizedKVCache::with_dtype(48, dtype);
+            let mut mirrored = QuantizedKVCache::with_dtype(48, dtype);
+            // Wraps the ring (KIVI rounds the window up to 64 slots, i.e. whole groups)
+            let steps = [
+                (33, 10.0),
+                (1, 20.0),
+                (14, 30.0),
+                (1, 40.0),
+                (20, 50.0),
+                (1, 60.0),
+            ];
+            for (t, seed) in steps {
+                let q = keys(t, seed + 5.0).repeat((1, 2, 1, 1))?;
+                let k = keys(t, seed);
+                let v = keys(t, seed + 1.0);
+                let kv = fused.append_quantized(&k, &v)?;
+                let (k_f, v_f) = mirrored.append(&k, &v)?;
+
+                let (k_deq, v_deq) = kv.dequantize()?;
+                assert!(rel_error(&k_deq, &k_f)? < 1e-6, "{:?}: key views", dtype);
+                assert!(rel_error(&v_deq, &v_f)? < 1e-6, "{:?}: value views", dtype);
+
+                let got = FlashAttentionCpu::forward_quantized(&q, &kv, 0.25)?;
+                let expected = FlashAttentionCpu::forward(&q, &k_f, &v_f, 0.25)?;
+                let err = rel_error(&got, &expected)?;
+                assert!(err < 1e-5, "{:?} t={}: error {}", dtype, t, err);
+            }
+            if dtype == KvCacheDtype::Kivi {
+                let kv = fused.append_quantized(&keys(1, 70.0), &keys(1, 71.0))?;
+                assert!(matches!(kv.k, KvView::Q4Channel { .. }));
+            }
+        }
+        Ok(())
+    }
+
+    /// exp(mean NLL) of `tokens[1..]` with the given cache format
+    fn perplexity(
+        model: &mut BitLlama,
+        dtype: KvCacheDtype,
+        tokens: &[u32],
+    ) -> anyhow::Result<f32> {
+        model.config.kv_cache_dtype = dtype;
+        let mut seq = model.new_sequence()?;
+        let mut nll = 0.0;
+        for w in tokens.windows(2) {
+            let logits = model.forward_batch(&[w[0]], &mut [&mut seq])?;
+            let log_p = candle_nn::ops::log_softmax(&logits, D::Minus1)?;
+            nll -= log_p.get(0)?.get(w[1] as usize)?.to_scalar::<f32>()?;
+        }
+        Ok((nll / (tokens.len() - 1) as f32).exp())
+    }
+
+    #[test]
+    fn test_kv_cache_dtype_perplexity_impact() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let mut cfg = BitLlamaConfig::new(32, 64, 2, 0.1, None);
+        cfg.arch = ModelArch::Llama;
+        cfg.n_heads = 4;
+        cfg.n_kv_heads = 2;
+        cfg.max_position_embeddings = 64;
+        let varmap = VarMap::new();
+        BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+        // Deterministic weights (VarMap init is random), unit norms
+        for (name, var) in varmap.data().lock().unwrap().iter() {
+            let dims = var.dims().to_vec();
+            if name.contains("norm") {
+                var.set(&Tensor::ones(dims, DType::F32, &device)?)?;
+                continue;
+            }
+            let seed = name.len() as f32;
+            let scale = 1.0 / (*dims.last().unwrap() as f32).sqrt();
+            let data: Vec<f32> = (0..var.elem_count())
+                .map(|i| ((i as f32 * 0.61 + seed) * 1.3).sin() * scale)
+                .collect();
+            var.set(&Tensor::from_vec(data, dims, &device)?)?;
+        }
+        let mut model = BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+        // Same weights, attention fused over the stored cache
+        cfg.quantized_kv_attention = true;
+        let mut fused_model =
+            BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+
+        // Greedy continuation of the f32 model
+        let mut tokens = vec![1u32, 7, 3];
+        let mut seq = model.new_sequence()?;
+        for &t in &tokens[..2] {
+            model.forward_batch(&[t], &mut [&mut seq])?;
+        }
+        while tokens.len() < 48 {
+            let logits = model.forward_batch(&[*tokens.last().unwrap()], &mut [&mut seq])?;
+            tokens.push(logits.get(0)?.argmax(0)?.to_scalar::<u32>()?);
+        }
+
+        let base = perplexity(&mut model, KvCacheDtype::F32, &tokens)?;
+        for dtype in ALL {
+            let ppl = perplexity(&mut model, dtype, &tokens)?;
+            let fused = perplexity(&mut fused_model, dtype, &tokens)?;
+            let delta = (ppl - base).abs() / base;
+            println!(
+                "{:?}: perplexity {:.4} (f32 {:.4}, delta {:.3}%)",
+                dtype,
+                ppl,
+                base,
+                delta * 100.0
+            );
+            assert!(
+                (fused - ppl).abs() / ppl < 1e-3,
+                "{:?}: fused {} vs {}",
+                dtype,
+                fused,
+                ppl
+            );
+            let bound = match dtype {
+                KvCacheDtype::F32 => 0.0,
+                KvCacheDtype::F16 | KvCacheDtype::Q8 => 0.002,
+                KvCacheDtype::Fp8 => 0.01,
+                KvCacheDtype::Q4 | KvCacheDtype::Kivi => 0.03,
+            };
+            assert!(delta <= bound, "{:?}: perplexity delta {}", dtype, delta);
+        }
+        Ok(())
+    }
+}
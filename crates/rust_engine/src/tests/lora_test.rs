@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::ActivationQuant;
+    use crate::layers::{AdaptiveBitLinear, LoraAdapter, LoraConfig, LoraTarget};
+    use crate::model::{BitLlama, BitLlamaConfig};
+    use crate::test_util::max_abs_diff;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+    use std::collections::HashMap;
+
+    fn pattern(rows: usize, cols: usize, seed: f32, device: &Device) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.61).sin())
+            .collect();
+        Tensor::from_vec(data, (rows, cols), device).unwrap()
+    }
+
+    fn ternary_linear(device: &Device) -> AdaptiveBitLinear {
+        let mut tensors = HashMap::new();
+        tensors.insert("weight".to_string(), pattern(6, 8, 1.0, device));
+        let vb = VarBuilder::from_tensors(tensors, DType::F32, device);
+        let mut linear = AdaptiveBitLinear::load(8, 6, vb, device).unwrap();
+        linear
+            .precompute_packed(ActivationQuant::F32.into())
+            .unwrap();
+        linear
+    }
+
+    #[test]
+    fn test_lora_parse_targets() -> anyhow::Result<()> {
+        let t = LoraTarget::parse_list("q_proj, v,gate,ttt,q")?;
+        assert_eq!(
+            t,
+            vec![
+                LoraTarget::Q,
+                LoraTarget::V,
+                LoraTarget::Gate,
+                LoraTarget::Ttt
+            ]
+        );
+        assert_eq!(LoraTarget::parse_list("all")?.len(), LoraTarget::ALL.len());
+        assert!(LoraTarget::parse_list("lm_head").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lora_fresh_adapter_is_noop_and_merge_matches() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let mut linear = ternary_linear(&device);
+        let x = pattern(3, 8, 5.0, &device);
+        let base = linear.forward(&x)?;
+
+        // Fresh adapter (B = 0) must not change the output
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let cfg = LoraConfig {
+            rank: 2,
+            alpha: 4.0,
+            targets: vec![LoraTarget::Q],
+        };
+        linear.lora = Some(LoraAdapter::init(8, 6, &cfg, vb)?);
+        assert!(max_abs_diff(&base, &linear.forward(&x)?)? < 1e-6);
+
+        // Non-trivial adapter, then merge into dense weights
+        let a = pattern(2, 8, 9.0, &device);
+        let b = pattern(6, 2, 3.0, &device);
+        linear.lora = Some(LoraAdapter::new(a, b, cfg.scaling())?);
+        let adapted = linear.forward(&x)?;
+        assert!(max_abs_diff(&base, &adapted)? > 1e-3);
+
+        linear.merge_lora()?;
+        assert!(linear.lora.is_none());
+        assert!(linear.legacy_linear.is_none());
+        assert!(max_abs_diff(&adapted, &linear.forward(&x)?)? < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lora_save_load_roundtrip() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let cfg = BitLlamaConfig::new(32, 16, 2, 0.1, None);
+        let base_map = VarMap::new();
+        let mut model =
+            BitLlama::load(cfg, VarBuilder::from_varmap(&base_map, DType::F32, &device))?;
+
+        let lora_map = VarMap::new();
+        let lora_cfg = LoraConfig {
+            rank: 2,
+            alpha: 2.0,
+            targets: vec![LoraTarget::Ttt, LoraTarget::Down],
+        };
+        let n = model.attach_lora(
+            &lora_cfg,
+            VarBuilder::from_varmap(&lora_map, DType::F32, &device),
+        )?;
+        // 2 layers x (ttt.down + ttt.up + mlp.down_proj)
+        assert_eq!(n, 6);
+        // Only adapter variables live in the adapter VarMap
+        assert_eq!(lora_map.all_vars().len(), 12);
+
+        let path =
+            std::env::temp_dir().join(format!("lora_test_{}.safetensors", std::process::id()));
+        model.save_lora(&path, &lora_cfg)?;
+
+        model.clear_lora();
+        let loaded = model.load_lora(&path)?;
+        assert_eq!(loaded.rank, 2);
+        assert_eq!(loaded.targets, lora_cfg.targets);
+        assert_eq!(
+            model
+                .linear_modules()
+                .iter()
+                .filter(|(_, _, m)| m.lora.is_some())
+                .count(),
+            6
+        );
+
+        // Adapters of another model size or rank fail with the module name
+        let other = BitLlamaConfig::new(32, 24, 2, 0.1, None);
+        let mut other = BitLlama::load(
+            other,
+            VarBuilder::from_varmap(&VarMap::new(), DType::F32, &device),
+        )?;
+        let err = other.load_lora(&path).err().unwrap().to_string();
+        assert!(
+            err.contains("LoRA layers.") && err.contains("projection"),
+            "{}",
+            err
+        );
+        crate::model::lora::write_lora_config(
+            &path,
+            &LoraConfig {
+                rank: 4,
+                ..lora_cfg
+            },
+        )?;
+        let err = model.load_lora(&path).err().unwrap().to_string();
+        assert!(
+            err.contains("rank 2 but the adapter config says 4"),
+            "{}",
+            err
+        );
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(crate::model::lora::lora_config_path(&path));
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{ActivationQuant, BitLinearCpu, CpuSimd};
+    use crate::kernels::packing::PackedTensor;
+    use crate::layers::BitLinear;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 7) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    /// (M, K, N): partial row tiles, K not a multiple of 4, K > LUT_FLUSH blocks
+    const SHAPES: [(usize, usize, usize); 4] =
+        [(1, 256, 32), (3, 201, 45), (2, 6, 3), (2, 1030, 70)];
+
+    #[test]
+    fn test_lut_kernel_matches_int8_kernel_exactly() -> anyhow::Result<()> {
+        for (m, k, n) in SHAPES {
+            let x = pattern(m, k, 1.0);
+            let packed = PackedTensor::pack(&pattern(n, k, 2.0))?
+                .with_int8_layout()?
+                .with_lut_layout()?;
+            // Same quantized activations, same integer dot products
+            let expected = BitLinearCpu::forward_int8(&x, &packed)?.flatten_all()?;
+            for simd in [CpuSimd::Scalar, CpuSimd::detect()] {
+                let got = BitLinearCpu::forward_lut_with_simd(&x, &packed, simd)?;
+                assert_eq!(
+                    got.flatten_all()?.to_vec1::<f32>()?,
+                    expected.to_vec1::<f32>()?,
+                    "{:?} {}x{}x{}",
+                    simd,
+                    m,
+                    k,
+                    n
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lut_kernel_requires_layout() -> anyhow::Result<()> {
+        let packed = PackedTensor::pack(&pattern(4, 64, 3.0))?;
+        let x = pattern(1, 64, 4.0);
+        assert!(BitLinearCpu::forward_lut(&x, &packed).is_err());
+        assert!(BitLinearCpu::forward_lut(&x, &packed.with_lut_layout()?).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lut_selected_by_activation_quant() -> anyhow::Result<()> {
+        assert_eq!("lut".parse::<ActivationQuant>(), Ok(ActivationQuant::Lut));
+        assert_eq!(
+            serde_json::from_str::<ActivationQuant>("\"lut\"")?,
+            ActivationQuant::Lut
+        );
+
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let mut linear = BitLinear::load(96, 40, vb, &device)?;
+        let x = pattern(3, 96, 5.0);
+
+        linear.precompute_packed(ActivationQuant::F32.into())?;
+        let reference = linear.forward(&x)?;
+
+        linear.precompute_packed(ActivationQuant::Lut.into())?;
+        let packed = linear.packed_params.as_ref().unwrap();
+        assert!(packed.lut_layout.is_some() && packed.int8_layout.is_none());
+        let got = linear.forward(&x)?;
+        let err = (&got - &reference)?.sqr()?.sum_all()?.to_scalar::<f32>()?
+            / reference.sqr()?.sum_all()?.to_scalar::<f32>()?;
+        assert!(err.sqrt() < 0.02, "LUT vs f32 error {}", err.sqrt());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests {
+    use crate::layers::MoE;
+    use candle_core::{DType, Device, Module, Tensor, D};
+    use candle_nn::VarBuilder;
+    use std::collections::HashMap;
+
+    const HIDDEN: usize = 8;
+    const INTER: usize = 16;
+    const N_EXPERTS: usize = 4;
+
+    /// Deterministic pseudo-random weights
+    fn pattern(rows: usize, cols: usize, seed: f32, device: &Device) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * 0.5)
+            .collect();
+        Tensor::from_vec(data, (rows, cols), device).unwrap()
+    }
+
+    fn mixtral_tensors(gate: Tensor, device: &Device) -> HashMap<String, Tensor> {
+        let mut tensors = HashMap::new();
+        tensors.insert("gate.weight".to_string(), gate);
+        for e in 0..N_EXPERTS {
+            let s = e as f32 * 10.0;
+            let prefix = format!("experts.{}", e);
+            tensors.insert(
+                format!("{}.w1.weight", prefix),
+                pattern(INTER, HIDDEN, s + 1.0, device),
+            );
+            tensors.insert(
+                format!("{}.w2.weight", prefix),
+                pattern(HIDDEN, INTER, s + 2.0, device),
+            );
+            tensors.insert(
+                format!("{}.w3.weight", prefix),
+                pattern(INTER, HIDDEN, s + 3.0, device),
+            );
+        }
+        tensors
+    }
+
+    #[test]
+    fn test_moe_matches_dense_reference() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let gate = pattern(N_EXPERTS, HIDDEN, 42.0, &device);
+        let vb = VarBuilder::from_tensors(mixtral_tensors(gate, &device), DType::F32, &device);
+        let moe = MoE::load(HIDDEN, INTER, N_EXPERTS, 2, vb, &device)?;
+
+        let x = pattern(5, HIDDEN, 7.0, &device).reshape((1, 5, HIDDEN))?;
+        let out = moe.forward(&x)?;
+        assert_eq!(out.dims(), &[1, 5, HIDDEN]);
+
+        // Reference: run every expert densely, keep only the top-2 per token
+        let x_flat = x.reshape((5, HIDDEN))?;
+        let probs =
+            candle_nn::ops::softmax(&moe.gate.forward(&x_flat)?, D::Minus1)?.to_vec2::<f32>()?;
+        let expert_outs: Vec<Vec<Vec<f32>>> = moe
+            .experts
+            .iter()
+            .map(|e| e.forward(&x_flat).unwrap().to_vec2::<f32>().unwrap())
+            .collect();
+
+        let got = out.reshape((5, HIDDEN))?.to_vec2::<f32>()?;
+        for t in 0..5 {
+            let mut order: Vec<usize> = (0..N_EXPERTS).collect();
+            order.sort_by(|&a, &b| probs[t][b].total_cmp(&probs[t][a]));
+            let norm: f32 = order[..2].iter().map(|&e| probs[t][e]).sum();
+            for d in 0..HIDDEN {
+                let expected: f32 = order[..2]
+                    .iter()
+                    .map(|&e| probs[t][e] / norm * expert_outs[e][t][d])
+                    .sum();
+                assert!(
+                    (got[t][d] - expected).abs() < 1e-4,
+                    "token {} dim {}: {} vs {}",
+                    t,
+                    d,
+                    got[t][d],
+                    expected
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_moe_aux_loss_uniform_router() -> anyhow::Result<()> {
+        // A zero router gives uniform probabilities, where E * sum(f_i * P_i) == 1
+        let device = Device::Cpu;
+        let gate = Tensor::zeros((N_EXPERTS, HIDDEN), DType::F32, &device)?;
+        let vb = VarBuilder::from_tensors(mixtral_tensors(gate, &device), DType::F32, &device);
+        let moe = MoE::load(HIDDEN, INTER, N_EXPERTS, 2, vb, &device)?;
+
+        let x = pattern(6, HIDDEN, 3.0, &device);
+        let (_, aux) = moe.forward_with_aux(&x)?;
+        let aux = aux.to_scalar::<f32>()?;
+        assert!((aux - 1.0).abs() < 1e-5, "aux loss = {}", aux);
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::ActivationQuant;
+    use crate::kernels::packing::WeightScale;
+    use crate::model::{BitLlama, BitLlamaConfig, PackedCheckpoint};
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn config(activations: ActivationQuant, scale: WeightScale, group: usize) -> BitLlamaConfig {
+        let mut cfg = BitLlamaConfig::new(48, 64, 2, 0.1, None);
+        cfg.activation_quant = activations;
+        cfg.weight_scale = scale;
+        cfg.weight_scale_group = group;
+        cfg
+    }
+
+    /// Logits of a short decode, token by token
+    fn logits(model: &mut BitLlama) -> anyhow::Result<Vec<f32>> {
+        // TTT state per layer, batched like the [1, 1, hidden] decode input
+        let d_small = model.config.hidden_dim / 4;
+        let w = Tensor::zeros((1, 1, d_small, d_small), DType::F32, &Device::Cpu)?;
+        let mut w_states = vec![w; model.layers.len()];
+        let mut out = Vec::new();
+        for token in [3u32, 17, 5, 40] {
+            let x = Tensor::new(&[token], &Device::Cpu)?;
+            let logits = model.forward_one(&x, &mut w_states)?;
+            out.extend(logits.flatten_all()?.to_vec1::<f32>()?);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn test_packed_checkpoint_roundtrip() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        for (activations, scale, group) in [
+            (ActivationQuant::F32, WeightScale::Channel, 128),
+            (ActivationQuant::Int8, WeightScale::Tensor, 128),
+            (ActivationQuant::Lut, WeightScale::Group, 32),
+        ] {
+            let cfg = config(activations, scale, group);
+            let varmap = VarMap::new();
+            let mut model =
+                BitLlama::load(cfg, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+            model.precompute_packed()?;
+            let expected = logits(&mut model)?;
+
+            let tag = format!("{:?}_{}", activations, std::process::id());
+            let dense = std::env::temp_dir().join(format!("packed_test_dense_{}.safetensors", tag));
+            let packed = std::env::temp_dir().join(format!("packed_test_{}.safetensors", tag));
+            varmap.save(&dense)?;
+            model.save_packed(&packed)?;
+
+            let checkpoint = PackedCheckpoint::open(&packed)?;
+            assert!(checkpoint.is_packed());
+            assert!(!PackedCheckpoint::open(&dense)?.is_packed());
+            // Ternary weights take 2 bits instead of 32
+            let size = |p: &std::path::Path| std::fs::metadata(p).map(|m| m.len());
+            assert!(size(&packed)? < size(&dense)?, "{:?}", activations);
+
+            let mut loaded = BitLlama::load_checkpoint(cfg, &packed)?;
+            let linears = loaded.linear_modules();
+            assert!(!linears.is_empty());
+            for (name, _, module) in linears {
+                let linear = module.legacy_linear.as_ref().unwrap();
+                let weights = linear.packed_params.as_ref().unwrap();
+                assert!(linear.prepacked && weights.data.is_mapped(), "{}", name);
+                assert_eq!(linear.weight.elem_count(), 0, "{}", name);
+            }
+            // Kernel layouts come from the file; nothing is re-packed
+            loaded.precompute_packed()?;
+            assert_eq!(logits(&mut loaded)?, expected, "{:?}", activations);
+
+            let _ = std::fs::remove_file(&dense);
+            let _ = std::fs::remove_file(&packed);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_checkpoint_follows_config_kernel() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let mut model = BitLlama::load(config(ActivationQuant::Lut, WeightScale::Tensor, 128), vb)?;
+        model.precompute_packed()?;
+        let path = std::env::temp_dir().join(format!(
+            "packed_test_kernel_{}.safetensors",
+            std::process::id()
+        ));
+        model.save_packed(&path)?;
+
+        // A LUT checkpoint served with the f32 kernel matches an f32-packed model
+        let f32_cfg = config(ActivationQuant::F32, WeightScale::Tensor, 128);
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let mut reference = BitLlama::load(f32_cfg, vb)?;
+        reference.precompute_packed()?;
+        let mut loaded = BitLlama::load_checkpoint(f32_cfg, &path)?;
+        loaded.precompute_packed()?;
+        for (_, _, module) in loaded.linear_modules() {
+            let weights = module
+                .legacy_linear
+                .as_ref()
+                .unwrap()
+                .packed_params
+                .as_ref();
+            assert!(weights.unwrap().lut_layout.is_none());
+        }
+        assert_eq!(logits(&mut loaded)?, logits(&mut reference)?);
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_checkpoint_rejects_bad_layout_length() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let cfg = config(ActivationQuant::Lut, WeightScale::Tensor, 128);
+        let mut model = BitLlama::load(cfg, vb)?;
+        model.precompute_packed()?;
+        let path = std::env::temp_dir().join(format!(
+            "packed_test_truncated_{}.safetensors",
+            std::process::id()
+        ));
+        model.save_packed(&path)?;
+
+        // Drop the last byte of one LUT layout
+        let mut tensors = candle_core::safetensors::load(&path, &device)?;
+        let name = tensors
+            .keys()
+            .find(|k| k.ends_with(".packed_lut"))
+            .cloned()
+            .unwrap();
+        let layout = &tensors[&name];
+        let truncated = layout.narrow(0, 0, layout.dim(0)? - 1)?;
+        tensors.insert(name.clone(), truncated);
+        candle_core::safetensors::save(&tensors, &path)?;
+
+        let err = BitLlama::load_checkpoint(cfg, &path)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains(&name), "{}", err);
+        assert!(err.contains("bytes, expected"), "{}", err);
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+}
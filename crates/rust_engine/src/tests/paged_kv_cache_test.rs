@@ -0,0 +1,139 @@
+#[cfg(test)]
+mod tests {
+    use crate::layers::{BlockTable, KvCacheDtype, PagedKVCache};
+    use crate::model::{BitLlama, ModelArch};
+    use crate::test_util::{fix_weights, max_abs_diff, small_config};
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn pool(num_blocks: usize, block_size: usize) -> anyhow::Result<PagedKVCache> {
+        let specs = [Some((2, 8, Device::Cpu)), None];
+        Ok(PagedKVCache::new(
+            &specs,
+            num_blocks,
+            block_size,
+            KvCacheDtype::Q8,
+        )?)
+    }
+
+    fn step(cache: &mut PagedKVCache, table: &mut BlockTable, n: usize) -> anyhow::Result<()> {
+        cache.reserve(table, n)?;
+        let kv = Tensor::ones((1, 2, n, 8), DType::F32, &Device::Cpu)?;
+        cache.write(0, table, &kv, &kv)?;
+        cache.advance(table, n);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_allocator_copy_on_write() -> anyhow::Result<()> {
+        let mut cache = pool(4, 4)?;
+        assert!(cache.pool_view(1).is_err());
+
+        let mut a = BlockTable::default();
+        step(&mut cache, &mut a, 6)?;
+        assert_eq!((a.len(), a.blocks().len()), (6, 2));
+        assert_eq!(cache.used_blocks(), 2);
+        assert_eq!(cache.utilization(), 0.5);
+
+        // Fork shares both blocks; the partial tail is copied on the next write
+        let mut b = cache.fork(&a);
+        assert_eq!(cache.shared_blocks(), 2);
+        step(&mut cache, &mut b, 1)?;
+        assert_eq!(cache.used_blocks(), 3);
+        assert_eq!(cache.shared_blocks(), 1);
+        assert_eq!(a.blocks()[0], b.blocks()[0]);
+        assert_ne!(a.blocks()[1], b.blocks()[1]);
+
+        // The original keeps writing its own (no longer shared) tail
+        step(&mut cache, &mut a, 2)?;
+        assert_eq!(cache.used_blocks(), 3);
+
+        // Pool exhausted: 3 + 2 blocks needed, nothing is taken
+        let mut c = BlockTable::default();
+        assert!(cache.reserve(&mut c, 8).is_err());
+        assert!(c.blocks().is_empty());
+        assert_eq!(cache.free_blocks(), 1);
+
+        cache.free(&mut a);
+        assert!(a.is_empty());
+        assert_eq!((cache.used_blocks(), cache.shared_blocks()), (2, 0));
+        cache.free(&mut b);
+        cache.free(&mut c);
+        assert_eq!(cache.free_blocks(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kivi_is_rejected() {
+        let specs = [Some((2, 8, Device::Cpu))];
+        assert!(PagedKVCache::new(&specs, 4, 4, KvCacheDtype::Kivi).is_err());
+    }
+
+    fn model(quantized_kv: bool, dtype: KvCacheDtype) -> anyhow::Result<BitLlama> {
+        let mut cfg = small_config(ModelArch::Llama);
+        cfg.vocab_size = 32;
+        cfg.max_position_embeddings = 64;
+        cfg.quantized_kv_attention = quantized_kv;
+        cfg.kv_cache_dtype = dtype;
+        let varmap = VarMap::new();
+        BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?;
+        fix_weights(&varmap)?;
+        Ok(BitLlama::load(
+            cfg,
+            VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu),
+        )?)
+    }
+
+    #[test]
+    fn test_forward_paged_matches_dense_with_shared_prefix() -> anyhow::Result<()> {
+        let prompt = [1u32, 7, 3, 9, 4, 12];
+        let tails = [[5u32, 8, 2], [11, 6, 10]];
+        for (quantized_kv, dtype) in [
+            (true, KvCacheDtype::Q8),
+            (true, KvCacheDtype::Q4),
+            (false, KvCacheDtype::F32),
+        ] {
+            let model = model(quantized_kv, dtype)?;
+            let mut cache = model.new_paged_cache(8, 4)?;
+
+            // Shared prompt, then two diverging continuations
+            let mut parent = model.new_paged_sequence()?;
+            for &t in &prompt {
+                model.forward_paged(&[t], &mut [&mut parent], &mut cache)?;
+            }
+            let mut child = model.fork_sequence(&parent, &mut cache);
+            assert_eq!(cache.used_blocks(), 2);
+            assert_eq!(cache.shared_blocks(), 2);
+
+            let mut dense: Vec<_> = (0..2)
+                .map(|_| model.new_sequence())
+                .collect::<candle_core::Result<_>>()?;
+            for &t in &prompt {
+                for seq in dense.iter_mut() {
+                    model.forward_batch(&[t], &mut [seq])?;
+                }
+            }
+
+            for (a, b) in tails[0].iter().zip(&tails[1]) {
+                let paged =
+                    model.forward_paged(&[*a, *b], &mut [&mut parent, &mut child], &mut cache)?;
+                let (d0, d1) = dense.split_at_mut(1);
+                let expected = model.forward_batch(&[*a, *b], &mut [&mut d0[0], &mut d1[0]])?;
+                let diff = max_abs_diff(&paged, &expected)?;
+                assert!(diff < 1e-4, "{:?}: paged vs dense {}", dtype, diff);
+            }
+            assert_eq!((parent.pos, child.pos), (9, 9));
+            // Prefix block stays shared; each tail got its own blocks
+            assert_eq!(cache.shared_blocks(), 1);
+            assert_eq!(cache.used_blocks(), 5);
+
+            cache.free(&mut parent.block_table);
+            cache.free(&mut child.block_table);
+            assert_eq!(cache.utilization(), 0.0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+#[cfg(test)]
+mod tests {
+    use crate::layers::{DensePrecision, FloatDtype};
+    use crate::model::{BitLlama, BitLlamaConfig};
+    use crate::test_util::max_rel_error;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::{VarBuilder, VarMap};
+
+    /// Logits of a short decode, token by token
+    fn logits(model: &mut BitLlama) -> anyhow::Result<Vec<f32>> {
+        let d_small = model.config.hidden_dim / 4;
+        let w = Tensor::zeros((1, 1, d_small, d_small), DType::F32, &Device::Cpu)?;
+        let mut w_states = vec![w; model.layers.len()];
+        let mut out = Vec::new();
+        for token in [3u32, 17, 5, 40] {
+            let x = Tensor::new(&[token], &Device::Cpu)?;
+            let logits = model.forward_one(&x, &mut w_states)?;
+            assert_eq!(logits.dtype(), DType::F32);
+            out.extend(logits.flatten_all()?.to_vec1::<f32>()?);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn test_half_storage_matches_f32() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let base = BitLlamaConfig::new(48, 64, 2, 0.1, None);
+        let varmap = VarMap::new();
+        let mut reference =
+            BitLlama::load(base, VarBuilder::from_varmap(&varmap, DType::F32, &device))?;
+        let expected = logits(&mut reference)?;
+
+        let path =
+            std::env::temp_dir().join(format!("precision_test_{}.safetensors", std::process::id()));
+        varmap.save(&path)?;
+
+        for (storage, compute, lm_head) in [
+            (FloatDtype::BF16, FloatDtype::F32, None),
+            (FloatDtype::F16, FloatDtype::F32, None),
+            (FloatDtype::BF16, FloatDtype::BF16, Some(FloatDtype::F32)),
+            (FloatDtype::F16, FloatDtype::F16, None),
+        ] {
+            let mut cfg = base;
+            cfg.storage_dtype = storage;
+            cfg.compute_dtype = compute;
+            cfg.lm_head_dtype = lm_head;
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&path], DType::F32, &device)? };
+            let mut model = BitLlama::load(cfg, vb)?;
+
+            assert_eq!(model.embedding.dense().unwrap().dtype(), storage.dtype());
+            assert_eq!(model.lm_head.dense().unwrap().dtype(), storage.dtype());
+            assert_eq!(model.norm.weight.dtype(), storage.dtype());
+            assert_eq!(model.layers[0].norm1.weight.dtype(), storage.dtype());
+
+            let err = max_rel_error(&logits(&mut model)?, &expected);
+            assert!(err < 2e-2, "{:?}/{:?}: {}", storage, compute, err);
+        }
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dense_precision_accumulates_in_compute_dtype() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let x = Tensor::randn(0f32, 1.0, (3, 96), &device)?;
+        let w = Tensor::randn(0f32, 0.1, (40, 96), &device)?;
+        let expected = x.matmul(&w.t()?)?.flatten_all()?.to_vec1::<f32>()?;
+
+        for (storage, compute) in [
+            (DType::BF16, DType::F32),
+            (DType::F16, DType::F32),
+            (DType::BF16, DType::BF16),
+        ] {
+            let precision = DensePrecision { storage, compute };
+            let stored = precision.store(&w)?;
+            assert_eq!(stored.dtype(), storage);
+            let y = precision.forward(&x, &stored)?;
+            // Back in the caller's dtype
+            assert_eq!(y.dtype(), DType::F32);
+            let err = max_rel_error(&y.flatten_all()?.to_vec1::<f32>()?, &expected);
+            assert!(err < 2e-2, "{:?}/{:?}: {}", storage, compute, err);
+
+            if compute == DType::F32 {
+                // Half weights read in place: same as widening W, for any input rank
+                let widened = x.matmul(&stored.to_dtype(DType::F32)?.t()?)?;
+                let y3 = precision.forward(&x.unsqueeze(0)?, &stored)?;
+                assert_eq!(y3.dims(), &[1, 3, 40]);
+                let err = max_rel_error(
+                    &y3.flatten_all()?.to_vec1::<f32>()?,
+                    &widened.flatten_all()?.to_vec1::<f32>()?,
+                );
+                assert!(err < 1e-5, "{:?}: {}", storage, err);
+            }
+        }
+
+        assert_eq!("bf16".parse::<FloatDtype>(), Ok(FloatDtype::BF16));
+        assert_eq!("FP16".parse::<FloatDtype>(), Ok(FloatDtype::F16));
+        assert!("f8".parse::<FloatDtype>().is_err());
+        let cfg: BitLlamaConfig = serde_json::from_str(
+            r#"{"vocab_size": 64, "hidden_dim": 48, "num_layers": 1, "n_heads": 4, "n_kv_heads": 4,
+                "storage_dtype": "bf16", "lm_head_dtype": "f32"}"#,
+        )?;
+        assert_eq!(cfg.storage_dtype, FloatDtype::BF16);
+        assert_eq!(cfg.compute_dtype, FloatDtype::F32);
+        assert_eq!(cfg.lm_head_precision().compute, DType::F32);
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod tests {
+    use crate::runtime::RuntimeConfig;
+
+    #[test]
+    fn test_parse_cores() -> anyhow::Result<()> {
+        assert_eq!(
+            RuntimeConfig::parse_cores("0-3,8, 10-11")?,
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(RuntimeConfig::parse_cores("")?, Vec::<usize>::new());
+        assert!(RuntimeConfig::parse_cores("3-1").is_err());
+        assert!(RuntimeConfig::parse_cores("a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_runtime_pools() -> anyhow::Result<()> {
+        // Default: rayon's global pool
+        let global = RuntimeConfig::default().build()?;
+        assert_eq!(global.decode_threads(), rayon::current_num_threads());
+        assert!(!global.is_split());
+
+        let shared = RuntimeConfig {
+            threads: Some(2),
+            ..Default::default()
+        }
+        .build()?;
+        assert_eq!((shared.prefill_threads(), shared.decode_threads()), (2, 2));
+        assert!(!shared.is_split());
+
+        let split = RuntimeConfig {
+            threads: Some(3),
+            decode_threads: Some(1),
+            ..Default::default()
+        }
+        .build()?;
+        assert_eq!((split.prefill_threads(), split.decode_threads()), (3, 1));
+        assert!(split.is_split());
+        let name = split.decode(|| std::thread::current().name().map(str::to_string));
+        assert_eq!(name.as_deref(), Some("bit-ttt-decode-0"));
+
+        let zero = RuntimeConfig {
+            prefill_threads: Some(0),
+            ..Default::default()
+        };
+        assert!(zero.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_runtime_pins_workers() -> anyhow::Result<()> {
+        let Some(core) = core_affinity::get_core_ids().and_then(|ids| ids.last().copied()) else {
+            return Ok(());
+        };
+        let pinned = RuntimeConfig {
+            pin_cores: vec![core.id],
+            ..Default::default()
+        }
+        .build()?;
+        // One worker per pinned core by default
+        assert_eq!(pinned.prefill_threads(), 1);
+        let ids = pinned.prefill(core_affinity::get_core_ids);
+        assert_eq!(
+            ids.map(|ids| ids.iter().map(|c| c.id).collect()),
+            Some(vec![core.id])
+        );
+
+        let missing = RuntimeConfig {
+            pin_cores: vec![usize::MAX],
+            ..Default::default()
+        };
+        assert!(missing.build().is_err());
+        Ok(())
+    }
+}
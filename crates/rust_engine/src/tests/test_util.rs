@@ -0,0 +1,78 @@
+//! Helpers shared by the test modules: error metrics and small model fixtures
+
+use crate::layers::KvCacheDtype;
+use crate::model::{BitLlama, BitLlamaConfig, ModelArch};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+
+/// max |got - expected| relative to max |expected|
+pub fn max_rel_error(got: &[f32], expected: &[f32]) -> f32 {
+    let diff = got
+        .iter()
+        .zip(expected)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0f32, f32::max);
+    diff / expected.iter().map(|v| v.abs()).fold(0f32, f32::max)
+}
+
+/// `max_rel_error` of two tensors
+pub fn tensor_max_rel_error(got: &Tensor, expected: &Tensor) -> anyhow::Result<f32> {
+    let values = |t: &Tensor| t.flatten_all()?.to_vec1::<f32>();
+    Ok(max_rel_error(&values(got)?, &values(expected)?))
+}
+
+/// ||got - expected|| / ||expected|| (L2 over all elements)
+pub fn rel_error(got: &Tensor, expected: &Tensor) -> anyhow::Result<f32> {
+    let err = (got - expected)?.sqr()?.sum_all()?.to_scalar::<f32>()?;
+    let norm = expected.sqr()?.sum_all()?.to_scalar::<f32>()?;
+    Ok((err / norm).sqrt())
+}
+
+/// max |a - b|
+pub fn max_abs_diff(a: &Tensor, b: &Tensor) -> anyhow::Result<f32> {
+    Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+}
+
+/// Two-layer `arch` model with 48 tokens, hidden 64, 4 heads / 2 KV heads
+/// and an F32 KV cache
+pub fn small_config(arch: ModelArch) -> BitLlamaConfig {
+    let mut cfg = BitLlamaConfig::new(48, 64, 2, 0.1, None);
+    cfg.arch = arch;
+    cfg.n_heads = 4;
+    cfg.n_kv_heads = 2;
+    cfg.kv_cache_dtype = KvCacheDtype::F32;
+    cfg
+}
+
+/// `cfg` on the weights of `varmap` with every projection packed (attention
+/// included, which `precompute_packed` leaves on the STE path)
+pub fn packed_model(cfg: BitLlamaConfig, varmap: &VarMap) -> anyhow::Result<BitLlama> {
+    let mut model = BitLlama::load(
+        cfg,
+        VarBuilder::from_varmap(varmap, DType::F32, &Device::Cpu),
+    )?;
+    for (_, _, module) in model.linear_modules_mut() {
+        module.precompute_packed(cfg.pack_options())?;
+    }
+    Ok(model)
+}
+
+/// Replace the random `VarMap` init with fixed weights (unit norms) so that
+/// tests with tolerances don't depend on the draw. Call after loading a model
+/// on `varmap`, then load again to see the new values.
+pub fn fix_weights(varmap: &VarMap) -> anyhow::Result<()> {
+    for (name, var) in varmap.data().lock().unwrap().iter() {
+        let dims = var.dims().to_vec();
+        if name.contains("norm") {
+            var.set(&Tensor::ones(dims, DType::F32, &Device::Cpu)?)?;
+            continue;
+        }
+        let seed = name.len() as f32;
+        let scale = 1.0 / (*dims.last().unwrap() as f32).sqrt();
+        let data: Vec<f32> = (0..var.elem_count())
+            .map(|i| ((i as f32 * 0.61 + seed) * 1.3).sin() * scale)
+            .collect();
+        var.set(&Tensor::from_vec(data, dims, &Device::Cpu)?)?;
+    }
+    Ok(())
+}
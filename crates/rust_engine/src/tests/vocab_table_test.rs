@@ -0,0 +1,193 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::backend;
+    use crate::kernels::cpu::{self, ActivationQuant, CpuSimd, KernelScratch};
+    use crate::layers::{QuantizedTable, TableQuant, VocabTable};
+    use crate::model::ModelArch;
+    use crate::model::{BitLlama, BitLlamaConfig, DecodeWorkspace};
+    use crate::test_util::{fix_weights, max_rel_error, packed_model, small_config};
+    use candle_core::{Device, Tensor};
+    use candle_nn::VarMap;
+
+    fn pattern(rows: usize, cols: usize, seed: f32) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| ((i as f32 + seed) * 0.37).sin() * (1.0 + (i % 7) as f32))
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_quantized_tables_match_dequantized_weights() -> anyhow::Result<()> {
+        // cols not a multiple of the int8 block
+        let (rows, cols, m) = (40, 200, 3);
+        let w = pattern(rows, cols, 1.0);
+        let x = pattern(m, cols, 2.0).flatten_all()?.to_vec1::<f32>()?;
+        let options = BitLlamaConfig::new(rows, cols, 1, 0.1, None).pack_options();
+        let dense = VocabTable::Dense(w.clone());
+
+        for quant in [TableQuant::Int8, TableQuant::Ternary] {
+            let table = dense.quantize(quant, options)?;
+            assert_eq!(table.quant(), quant);
+            assert_eq!(table.dims()?, (rows, cols));
+            assert!(table.size_bytes() < dense.size_bytes() / 2);
+            let VocabTable::Quantized(q) = &table else {
+                panic!("{:?} table stayed dense", quant);
+            };
+
+            // Rows are the dequantized weights, close to the originals
+            let deq = q.dequantize()?;
+            let mut row = vec![0.0; cols];
+            q.row_into(7, &mut row)?;
+            assert_eq!(row, deq.get(7)?.to_vec1::<f32>()?);
+            let ids = Tensor::new(&[[7u32, 0], [39, 7]], &Device::Cpu)?;
+            let embedded = table.embed(&ids)?;
+            assert_eq!(embedded.dims(), &[2, 2, cols]);
+            assert_eq!(embedded.get(1)?.get(1)?.to_vec1::<f32>()?, row);
+            assert!(q.row_into(rows, &mut row).is_err());
+            if quant == TableQuant::Int8 {
+                let err = max_rel_error(
+                    &deq.flatten_all()?.to_vec1::<f32>()?,
+                    &w.flatten_all()?.to_vec1::<f32>()?,
+                );
+                assert!(err < 1e-2, "int8 dequantization: {}", err);
+            }
+
+            // Logits against the dequantized weights (int8 adds activation rounding)
+            let x_t = Tensor::from_vec(x.clone(), (m, cols), &Device::Cpu)?;
+            let expected = x_t.matmul(&deq.t()?)?.flatten_all()?.to_vec1::<f32>()?;
+            let mut got = vec![0.0; m * rows];
+            q.matmul_into(&x, &mut KernelScratch::default(), &mut got)?;
+            assert!(max_rel_error(&got, &expected) < 2e-2, "{:?}", quant);
+            let logits = table.logits(&x_t, lm_head_precision())?;
+            assert_eq!(logits.flatten_all()?.to_vec1::<f32>()?, got);
+        }
+
+        // Quantized tables can't change storage again
+        let int8 = dense.quantize(TableQuant::Int8, options)?;
+        assert!(int8.quantize(TableQuant::Ternary, options).is_err());
+        assert_eq!(int8.to_dense()?.dims2()?, (rows, cols));
+        Ok(())
+    }
+
+    fn lm_head_precision() -> crate::layers::DensePrecision {
+        BitLlamaConfig::new(8, 8, 1, 0.1, None).lm_head_precision()
+    }
+
+    #[test]
+    fn test_int8_rows_kernel_matches_scalar() -> anyhow::Result<()> {
+        let (n, k, m) = (24, 200, 2);
+        let Some(QuantizedTable::Int8 { data, scales, .. }) = QuantizedTable::quantize(
+            &pattern(n, k, 3.0),
+            TableQuant::Int8,
+            BitLlamaConfig::new(8, 8, 1, 0.1, None).pack_options(),
+        )?
+        else {
+            panic!("expected an int8 table");
+        };
+        let x = pattern(m, k, 4.0).flatten_all()?.to_vec1::<f32>()?;
+        let mut scratch = KernelScratch::default();
+        let mut expected = vec![0.0; m * n];
+        cpu::int8_rows_into(
+            CpuSimd::Scalar,
+            &x,
+            &data,
+            &scales,
+            &mut scratch,
+            &mut expected,
+        )?;
+        // Every CPU backend (and the cross-checked one) runs the int8 rows
+        let mut kernels = vec![backend::cross_checked(backend::get("auto")?)];
+        for name in backend::available() {
+            kernels.push(backend::get(name)?);
+        }
+        for kernel in kernels.iter().filter(|k| k.supports(&Device::Cpu)) {
+            let mut got = vec![0.0; m * n];
+            kernel.int8_rows_into(&x, &data, &scales, &mut scratch, &mut got)?;
+            assert_eq!(got, expected, "{}", kernel.name());
+        }
+        let mut bad = vec![0.0; m * n + 1];
+        let kernel = backend::for_device(&Device::Cpu)?;
+        assert!(kernel
+            .int8_rows_into(&x, &data, &scales, &mut scratch, &mut bad)
+            .is_err());
+        Ok(())
+    }
+
+    /// Small attention model, attention projections packed
+    fn model(varmap: &VarMap) -> anyhow::Result<BitLlama> {
+        let mut cfg = small_config(ModelArch::Llama);
+        cfg.activation_quant = ActivationQuant::Int8;
+        packed_model(cfg, varmap)
+    }
+
+    #[test]
+    fn test_model_with_quantized_tables() -> anyhow::Result<()> {
+        let varmap = VarMap::new();
+        let mut model = model(&varmap)?;
+        model.config.embedding_quant = TableQuant::Ternary;
+        model.config.lm_head_quant = TableQuant::Int8;
+        model.precompute_packed()?;
+        assert_eq!(model.embedding.quant(), TableQuant::Ternary);
+        assert_eq!(model.lm_head.quant(), TableQuant::Int8);
+
+        // Tensor path and decode workspace agree
+        let mut ws = DecodeWorkspace::new(&model, 8)?;
+        let mut w_states = model.new_w_states();
+        for token in [3u32, 17, 5, 40] {
+            let x = Tensor::new(&[token], &Device::Cpu)?;
+            let expected = model.forward_one(&x, &mut w_states)?;
+            let expected = expected.flatten_all()?.to_vec1::<f32>()?;
+            assert_eq!(expected.len(), 48);
+            let got = model.forward_one_into(token, &mut ws)?;
+            assert!(max_rel_error(got, &expected) < 1e-4);
+        }
+        assert!(model
+            .quantize_tables(TableQuant::Dense, TableQuant::Int8)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_quant_report() -> anyhow::Result<()> {
+        // Fixed weights: the perplexity bounds below must not depend on the init
+        let varmap = VarMap::new();
+        model(&varmap)?;
+        fix_weights(&varmap)?;
+        let mut model = model(&varmap)?;
+        model.precompute_packed()?;
+        let sequences: Vec<Vec<u32>> = (0..3)
+            .map(|s| (0..6).map(|t| (s * 7 + t * 5) % 48).collect())
+            .collect();
+        let dense = model.perplexity(&sequences)?;
+        assert!(dense.is_finite() && dense > 1.0);
+        // Ragged sequences: only real tokens count
+        let mut ragged = sequences.clone();
+        ragged[1].truncate(2);
+        assert!(model.perplexity(&ragged)?.is_finite());
+        assert!(model.perplexity(&[vec![1]]).is_err());
+
+        let reports = model.table_quant_report(&sequences)?;
+        assert_eq!(reports.len(), 9);
+        assert_eq!(
+            (reports[0].embedding, reports[0].lm_head),
+            (TableQuant::Dense, TableQuant::Dense)
+        );
+        assert!((reports[0].perplexity - dense).abs() < 1e-3 * dense);
+        for r in &reports {
+            assert!(r.perplexity.is_finite() && r.ratio > 0.0, "{:?}", r);
+            // int8 barely moves the logits (ternary on random weights does)
+            if !matches!(r.embedding, TableQuant::Ternary)
+                && !matches!(r.lm_head, TableQuant::Ternary)
+            {
+                assert!((r.ratio - 1.0).abs() < 0.05, "{:?}", r);
+            }
+            if r.embedding != TableQuant::Dense || r.lm_head != TableQuant::Dense {
+                assert!(r.bytes < reports[0].bytes);
+            }
+        }
+        // The dense tables are back
+        assert_eq!(model.embedding.quant(), TableQuant::Dense);
+        assert_eq!(model.config.lm_head_quant, TableQuant::Dense);
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernels::cpu::{BitLinearCpu, CpuSimd};
+    use crate::kernels::packing::{PackedScales, PackedTensor, WeightScale};
+    use crate::test_util::rel_error;
+    use candle_core::{Device, Tensor};
+    use std::collections::HashMap;
+
+    /// Rows whose magnitude grows ~100x from first to last, and whose second
+    /// half of columns is 8x larger than the first
+    fn uneven(rows: usize, cols: usize) -> Tensor {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|i| {
+                let (r, c) = (i / cols, i % cols);
+                let row_gain = 0.05 * (1.0 + r as f32 * 99.0 / rows as f32);
+                let col_gain = if c < cols / 2 { 1.0 } else { 8.0 };
+                (i as f32 * 0.61).sin() * row_gain * col_gain
+            })
+            .collect();
+        Tensor::from_vec(data, (rows, cols), &Device::Cpu).unwrap()
+    }
+
+    fn reconstruction_error(w: &Tensor, scale: WeightScale, group: usize) -> anyhow::Result<f32> {
+        let packed = PackedTensor::pack_with(w, scale, group)?;
+        rel_error(&packed.unpack(&Device::Cpu)?, w)
+    }
+
+    #[test]
+    fn test_finer_scales_reduce_reconstruction_error() -> anyhow::Result<()> {
+        let w = uneven(48, 256);
+        let tensor = reconstruction_error(&w, WeightScale::Tensor, 0)?;
+        let channel = reconstruction_error(&w, WeightScale::Channel, 0)?;
+        let group128 = reconstruction_error(&w, WeightScale::Group, 128)?;
+        let group64 = reconstruction_error(&w, WeightScale::Group, 64)?;
+        assert!(
+            channel < tensor && group128 < channel && group64 <= group128,
+            "tensor {} channel {} group128 {} group64 {}",
+            tensor,
+            channel,
+            group128,
+            group64
+        );
+        assert!(group64 < 0.6, "group64 error {}", group64);
+
+        // Per-tensor packing is unchanged
+        let legacy = PackedTensor::pack(&w)?;
+        let packed = PackedTensor::pack_with(&w, WeightScale::Tensor, 64)?;
+        assert_eq!(packed.scales, legacy.scales);
+        assert_eq!(packed.data.to_vec()?, legacy.data.to_vec()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scale_layouts() -> anyhow::Result<()> {
+        let w = uneven(6, 200);
+        let channel = PackedTensor::pack_with(&w, WeightScale::Channel, 0)?;
+        assert!(matches!(&channel.scales, PackedScales::Channel(s) if s.len() == 6));
+        // 200 columns in groups of 64 -> 4 groups per row (last one partial)
+        let group = PackedTensor::pack_with(&w, WeightScale::Group, 64)?;
+        assert!(
+            matches!(&group.scales, PackedScales::Group { size: 64, scales } if scales.len() == 24)
+        );
+        assert_eq!(group.scales.row(1, 200).0.len(), 4);
+
+        assert!(PackedTensor::pack_with(&w, WeightScale::Group, 30).is_err());
+        assert!(PackedTensor::pack_with(&w, WeightScale::Group, 0).is_err());
+        assert!(PackedTensor::pack(&w)?
+            .with_scales(PackedScales::Channel(vec![1.0; 5]))
+            .is_err());
+        // The int8 layout only takes groups of whole blocks
+        assert!(group.clone().with_int8_layout().is_err());
+        assert!(PackedTensor::pack_with(&w, WeightScale::Group, 128)?
+            .with_int8_layout()
+            .is_ok());
+        assert_eq!("group".parse::<WeightScale>(), Ok(WeightScale::Group));
+        assert_eq!(
+            serde_json::from_str::<WeightScale>("\"channel\"")?,
+            WeightScale::Channel
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_kernels_apply_channel_and_group_scales() -> anyhow::Result<()> {
+        // (M, K, N): single token, tiled batch with partial tiles, K not a multiple of 128
+        for (m, k, n) in [(1, 256, 40), (5, 320, 70)] {
+            let x = uneven(m, k);
+            for (scale, group) in [
+                (WeightScale::Channel, 0),
+                (WeightScale::Group, 64),
+                (WeightScale::Group, 128),
+            ] {
+                let packed = PackedTensor::pack_with(&uneven(n, k), scale, group)?;
+                let reference = x.matmul(&packed.unpack(&Device::Cpu)?.t()?)?;
+                let label = format!("{:?}/{} {}x{}x{}", scale, group, m, k, n);
+
+                for simd in [CpuSimd::Scalar, CpuSimd::detect()] {
+                    let got = BitLinearCpu::forward_with_simd(&x, &packed, simd)?;
+                    assert!(
+                        rel_error(&got, &reference)? < 1e-5,
+                        "f32 {:?} {}",
+                        simd,
+                        label
+                    );
+                }
+                let got = BitLinearCpu::forward_tiled(&x, &packed)?;
+                assert!(rel_error(&got, &reference)? < 1e-5, "tiled {}", label);
+
+                // int8 activations: quantization noise only
+                let lut = packed.clone().with_lut_layout()?;
+                for simd in [CpuSimd::Scalar, CpuSimd::detect()] {
+                    let got = BitLinearCpu::forward_lut_with_simd(&x, &lut, simd)?;
+                    assert!(
+                        rel_error(&got, &reference)? < 0.02,
+                        "lut {:?} {}",
+                        simd,
+                        label
+                    );
+                }
+                if group != 64 {
+                    let int8 = packed.with_int8_layout()?;
+                    for simd in [CpuSimd::Scalar, CpuSimd::detect()] {
+                        let got = BitLinearCpu::forward_int8_with_simd(&x, &int8, simd)?;
+                        let expected = BitLinearCpu::forward_lut_with_simd(&x, &lut, simd)?;
+                        // Same integer dot products, same group order
+                        assert_eq!(
+                            got.flatten_all()?.to_vec1::<f32>()?,
+                            expected.flatten_all()?.to_vec1::<f32>()?,
+                            "int8 {:?} {}",
+                            simd,
+                            label
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_tensor_serialization_roundtrip() -> anyhow::Result<()> {
+        let w = uneven(10, 96);
+        for (scale, group) in [
+            (WeightScale::Tensor, 0),
+            (WeightScale::Channel, 0),
+            (WeightScale::Group, 32),
+        ] {
+            let packed = PackedTensor::pack_with(&w, scale, group)?;
+            let tensors: HashMap<String, Tensor> =
+                packed.to_tensors("layers.0.w1")?.into_iter().collect();
+            let loaded = PackedTensor::from_tensors(
+                "layers.0.w1",
+                w.shape().clone(),
+                &Device::Cpu,
+                |name| Ok(tensors[name].clone()),
+            )?;
+            assert_eq!(loaded.scales, packed.scales, "{:?}", scale);
+            assert_eq!(
+                loaded
+                    .unpack(&Device::Cpu)?
+                    .flatten_all()?
+                    .to_vec1::<f32>()?,
+                packed
+                    .unpack(&Device::Cpu)?
+                    .flatten_all()?
+                    .to_vec1::<f32>()?
+            );
+        }
+        Ok(())
+    }
+}